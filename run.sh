@@ -1,5 +1,6 @@
 #!/bin/sh
 
+# Output frame size: turing.width*cell_width by turing.height*cell_height.
 WIDTH=1024
 HEIGHT=768
 
@@ -11,4 +12,6 @@ HEIGHT=768
 
 # Play directly:
 #./target/turing | ffmpeg -y -f rawvideo -s ${WIDTH}x${HEIGHT} -pix_fmt rgb24 -i - -an -f mpeg2video - | vlc -
+# With a y4m output to stdout ([[output]] format = "y4m") players need no flags:
+#./target/release/turing | mpv -
 ./target/release/turing | vlc --demux rawvideo --rawvid-fps=25 --rawvid-width $WIDTH --rawvid-height $HEIGHT --rawvid-chroma RV24 -
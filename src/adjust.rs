@@ -0,0 +1,60 @@
+use std::num::Float;
+
+
+/// Adjusts the colors of frames on their way out, since raw palette colors
+/// often look blown out on video. Contrast scales each channel about the
+/// middle gray, brightness is added, and the gamma curve is applied last.
+/// All three are worked out once into a table from each channel value to
+/// the adjusted one, so a frame costs a lookup per byte.
+pub struct ColorAdjust {
+  table: [u8, ..256],
+  frame: Vec<u8>,
+}
+
+impl ColorAdjust {
+  /// 'gamma' above 1 brightens the mid tones, 'brightness' from -1 to 1
+  /// shifts every channel by that fraction of full scale and 'contrast'
+  /// above 1 spreads channels away from the middle. 1, 0 and 1 leave frames
+  /// as they are.
+  pub fn new(gamma: f64, brightness: f64, contrast: f64) -> ColorAdjust {
+    let mut table = [0u8, ..256];
+    for (value, entry) in table.iter_mut().enumerate() {
+      let v = (value as f64 / 255.0 - 0.5) * contrast + 0.5 + brightness;
+      let v = v.max(0.0).min(1.0).powf(1.0 / gamma);
+      *entry = (v * 255.0).round() as u8;
+    }
+    ColorAdjust { table: table, frame: Vec::new() }
+  }
+
+  /// A copy of 'image' with its colors adjusted.
+  pub fn apply<'a>(&'a mut self, image: &[u8]) -> &'a [u8] {
+    self.frame.clear();
+    let table = &self.table;
+    self.frame.extend(image.iter().map(|&value| table[value as uint]));
+    self.frame.as_slice()
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::ColorAdjust;
+
+  #[test]
+  fn neutral_settings_change_nothing() {
+    let mut adjust = ColorAdjust::new(1.0, 0.0, 1.0);
+    let image: Vec<u8> = range(0u, 256).map(|value| value as u8).collect();
+    assert_eq!(adjust.apply(image.as_slice()), image.as_slice());
+  }
+
+  #[test]
+  fn each_setting_moves_channels_its_way() {
+    let image = [0u8, 64, 128, 255];
+    let mut gamma = ColorAdjust::new(2.0, 0.0, 1.0);
+    assert_eq!(gamma.apply(image.as_slice()), [0, 128, 181, 255].as_slice());
+    let mut brightness = ColorAdjust::new(1.0, 0.25, 1.0);
+    assert_eq!(brightness.apply(image.as_slice()), [64, 128, 192, 255].as_slice());
+    let mut contrast = ColorAdjust::new(1.0, 0.0, 0.5);
+    assert_eq!(contrast.apply(image.as_slice()), [64, 96, 128, 191].as_slice());
+  }
+}
@@ -0,0 +1,197 @@
+use std::io::{IoError, IoResult, OtherIoError};
+use toml;
+
+use turing::error::Error;
+use output::Outputs;
+use runner::FrameSink;
+
+
+// Work for the writer task: a frame in one of the buffers (only for the
+// outputs that keep alive, if set), checking the outputs for keys, reopening
+// the output that failed, or the end of the run.
+enum Job {
+  Frame(u64, Vec<u8>, uint, uint, bool),
+  PollInput,
+  Reopen(toml::Value),
+  Finish,
+}
+
+
+/// Writes frames to the outputs on a background task, so the machine keeps
+/// stepping while a slow reader (stdout to vlc, a pipe to ffmpeg) takes a
+/// frame. Frames are copied into one of two buffers: while the task writes
+/// one, the next frame goes into the other. Only when both are busy does
+/// writing a frame wait.
+///
+/// Errors from writing a frame are returned by a later 'write_frame' or by
+/// 'finish'. 'finish' and 'reopen_failed' wait for the frames queued before
+/// them.
+pub struct BackgroundOutputs {
+  jobs: Sender<Job>,
+  // Buffers back from the task with how writing their frame went. 'finish'
+  // gets None for a buffer.
+  done: Receiver<(Option<Vec<u8>>, IoResult<()>)>,
+  // Buffers free for the next frame.
+  free: Vec<Vec<u8>>,
+  // Frames the task failed to write since 'dropped_frames' was last called.
+  dropped: u64,
+}
+
+impl BackgroundOutputs {
+  /// Starts the writer task, which opens the outputs with 'open'.
+  pub fn start(open: proc() -> Result<Outputs, Error>) -> Result<BackgroundOutputs, Error> {
+    let (jobs, job_receiver) = channel::<Job>();
+    let (done_sender, done) = channel();
+    let (opened_sender, opened) = channel();
+    spawn(proc() {
+      let mut outputs = match open() {
+        Ok(outputs) => {
+          opened_sender.send(Ok(()));
+          outputs
+        },
+        Err(why) => {
+          opened_sender.send(Err(why));
+          return;
+        },
+      };
+      for job in job_receiver.iter() {
+        let result = match job {
+          Frame(frame, image, width, height, keep_alive) => {
+            let written = if keep_alive {
+              outputs.keep_alive(frame, image.as_slice(), width, height)
+            } else {
+              outputs.write(frame, image.as_slice(), width, height)
+            };
+            (Some(image), written)
+          },
+          PollInput => {
+            outputs.poll_input();
+            continue;
+          },
+          Reopen(config) => (None, outputs.reopen_failed(&config)),
+          Finish => (None, outputs.finish()),
+        };
+        if done_sender.send_opt(result).is_err() {
+          break;
+        }
+      }
+    });
+    try!(opened.recv());
+    Ok(BackgroundOutputs { jobs: jobs, done: done, free: vec!(Vec::new(), Vec::new()),
+                          dropped: 0 })
+  }
+
+  // Queues a frame for the task in a free buffer, waiting for one if both
+  // are busy.
+  fn queue(&mut self, frame: u64, image: &[u8], width: uint, height: uint, keep_alive: bool) -> IoResult<()> {
+    loop {
+      match self.done.try_recv() {
+        Ok((buffer, written)) => try!(self.take_back(buffer, written)),
+        Err(_) => break,
+      }
+    }
+    while self.free.is_empty() {
+      match self.done.recv_opt() {
+        Ok((buffer, written)) => try!(self.take_back(buffer, written)),
+        Err(()) => return Err(stopped()),
+      }
+    }
+    let mut buffer = self.free.pop().unwrap();
+    buffer.clear();
+    buffer.push_all(image);
+    self.jobs.send_opt(Frame(frame, buffer, width, height, keep_alive)).map_err(|_| stopped())
+  }
+
+  // Takes back a buffer the task is done with, reporting its write error.
+  fn take_back(&mut self, buffer: Option<Vec<u8>>, written: IoResult<()>) -> IoResult<()> {
+    match buffer {
+      Some(buffer) => {
+        self.free.push(buffer);
+        if written.is_err() {
+          self.dropped += 1;
+        }
+      },
+      None => {},
+    }
+    written
+  }
+}
+
+impl FrameSink for BackgroundOutputs {
+  fn write_frame(&mut self, frame: u64, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    self.queue(frame, image, width, height, false)
+  }
+
+  fn keep_alive(&mut self, frame: u64, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    self.queue(frame, image, width, height, true)
+  }
+
+  fn finish(&mut self) -> IoResult<()> {
+    try!(self.jobs.send_opt(Finish).map_err(|_| stopped()));
+    // The frames still queued are written first.
+    loop {
+      match self.done.recv_opt() {
+        Ok((None, finished)) => return finished,
+        Ok((buffer, written)) => try!(self.take_back(buffer, written)),
+        Err(()) => return Err(stopped()),
+      }
+    }
+  }
+
+  fn reopen_failed(&mut self, config: &toml::Value) -> IoResult<()> {
+    try!(self.jobs.send_opt(Reopen(config.clone())).map_err(|_| stopped()));
+    // Frames queued before the reopen skip the failed output, so their
+    // errors are from others and come up again with the next frame.
+    loop {
+      match self.done.recv_opt() {
+        Ok((None, reopened)) => return reopened,
+        Ok((buffer, written)) => { let _ = self.take_back(buffer, written); },
+        Err(()) => return Err(stopped()),
+      }
+    }
+  }
+
+  fn poll_input(&mut self) {
+    let _ = self.jobs.send_opt(PollInput);
+  }
+
+  fn dropped_frames(&mut self) -> u64 {
+    let dropped = self.dropped;
+    self.dropped = 0;
+    dropped
+  }
+}
+
+
+// The error for a writer task that has gone away.
+fn stopped() -> IoError {
+  IoError { kind: OtherIoError, desc: "the frame writer stopped", detail: None }
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::io::File;
+
+  use output::{Outputs, Output, RawSink, ImageSink};
+  use runner::FrameSink;
+
+  use super::BackgroundOutputs;
+
+  #[test]
+  fn frames_are_written_in_order() {
+    let path = ::std::os::tmpdir().join(format!("turing-frames-{}.raw", ::std::os::getpid()));
+    let file_path = path.clone();
+    let mut outputs = BackgroundOutputs::start(proc() {
+      let file = File::create(&file_path).unwrap();
+      Ok(Outputs::new(vec!(Output::new(box RawSink::new(box file as Box<Writer>) as Box<ImageSink>, 1, 1))))
+    }).ok().unwrap();
+    for frame in range(0u8, 5) {
+      outputs.write_frame(frame as u64, [frame, frame, frame].as_slice(), 1, 1).unwrap();
+    }
+    outputs.finish().unwrap();
+    let written = File::open(&path).read_to_end();
+    let _ = ::std::io::fs::unlink(&path);
+    assert_eq!(written.unwrap(), vec!(0u8, 0, 0, 1, 1, 1, 2, 2, 2, 3, 3, 3, 4, 4, 4));
+  }
+}
@@ -0,0 +1,72 @@
+use std::io::{File, IoResult, USER_RWX};
+use std::io::fs::mkdir_recursive;
+use toml;
+
+use turing::Machine;
+use turing::error::{Error, invalid};
+use turing::rng::SessionRng;
+use super::{build_machine, load_palette};
+use event_log::fingerprint;
+use image::write_ppm;
+
+
+/// 'turing render-batch': simulates 'machines' random machines for 'steps'
+/// steps each without any live output, and writes the final image, the
+/// machine (in session format, so it can go in a kiosk playlist) and a short
+/// report for each into 'out'.
+pub fn render_batch(config: &toml::Value, machines: uint, steps: u64, out: &Path) -> Result<(), Error> {
+  if config.lookup("turing.tape").and_then(|v| v.as_str()) == Some("sparse") {
+    return Err(invalid("render-batch saves each machine whole, which sparse tapes can't".to_string()));
+  }
+  let mut rng = try!(SessionRng::from_config(config));
+  let mut machine = try!(build_machine(config, &mut rng));
+  machine.set_palette(&load_palette(config));
+  write_machines(&mut *machine, &mut rng, machines, steps, out)
+    .map_err(|why| Error::io(format!("Error writing to {}", out.display()), why))
+}
+
+
+fn write_machines(machine: &mut Machine, rng: &mut SessionRng, machines: uint, steps: u64,
+                  out: &Path) -> IoResult<()> {
+  try!(mkdir_recursive(out, USER_RWX));
+  for n in range(0, machines) {
+    let draws = if n == 0 { 0 } else { rng.draws() };
+    if n > 0 {
+      machine.reset(rng);
+    }
+
+    let mut changes = 0u64;
+    for _ in range(0, steps) {
+      if machine.step() {
+        changes += 1;
+      }
+    }
+
+    let name = format!("machine_{:04u}", n);
+    let (width, height) = machine.image_size();
+    try!(write_ppm(&out.join(format!("{}.ppm", name)), machine.image(), width, height));
+    {
+      let mut file = try!(File::create(&out.join(format!("{}.toml", name))));
+      try!(writeln!(file, "[machine]"));
+      try!(file.write_str(machine.save_state().as_slice()));
+    }
+    {
+      let mut file = try!(File::create(&out.join(format!("{}.txt", name))));
+      try!(writeln!(file, "fingerprint: {:016x}", fingerprint(machine.table_text().as_slice())));
+      try!(writeln!(file, "seed: {}", rng.seed()));
+      try!(writeln!(file, "draws: {}", draws));
+      try!(writeln!(file, "steps: {}", steps));
+      try!(writeln!(file, "changed pixels: {}", changes));
+      try!(writeln!(file, "table: {}", machine.table_text()));
+    }
+    println!("{}: {} changed pixels", name, changes);
+  }
+  Ok(())
+}
+
+
+/// Parses a count that may use '_' as a digit separator, like "2_000_000".
+pub fn parse_count(text: &str) -> Option<u64> {
+  let digits: String = text.chars().filter(|&c| c != '_').collect();
+  from_str(digits.as_slice())
+}
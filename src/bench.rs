@@ -0,0 +1,182 @@
+use std::io::IoResult;
+use std::io::util::NullWriter;
+use time::precise_time_ns;
+use toml;
+
+use turing::{Machine, Color};
+use turing::error::Error;
+use turing::rng::{SessionRng, Algorithm};
+use super::{build_machine, load_palette};
+use background::BackgroundOutputs;
+use jpeg::JpegEncoder;
+use output::{Outputs, Output, ImageSink, RawSink};
+use runner::FrameSink;
+use verify::fnv1a;
+
+
+/// A way of turning the tape into frames.
+enum Renderer {
+  /// The image patched by 'step' as cells change (what the main loop uses).
+  Incremental,
+  /// The whole tape painted again cell by cell for every frame.
+  Full,
+  /// The whole tape drawn again for every frame in a single pass through the
+  /// palette's lookup table, for one-pixel cells.
+  Lut,
+  /// The patched image, written on a background thread as with
+  /// 'turing.render_threads = 2'.
+  Background,
+}
+
+static RENDERERS: [(&'static str, Renderer), ..4] =
+  [("incremental", Incremental), ("full", Full), ("lut", Lut), ("background", Background)];
+
+
+/// 'turing bench --compare': runs the same seeded machine through every
+/// renderer, writing the frames as raw video to a null sink, and reports the
+/// time per frame of each along with whether they produced identical frames.
+pub fn compare(config: &toml::Value, seed: u64, frames: uint, picture_steps: u64,
+               out: &mut Writer) -> Result<(), Error> {
+  let algorithm = try!(Algorithm::from_config(config));
+  let mut machines = Vec::new();
+  let mut sinks = Vec::new();
+  for &(_, ref renderer) in RENDERERS.iter() {
+    machines.push(try!(build_machine(config, &mut SessionRng::with_algorithm(algorithm, seed))));
+    sinks.push(match *renderer {
+      Background => box try!(BackgroundOutputs::start(proc() { Ok(null_outputs()) })) as Box<FrameSink>,
+      _ => box null_outputs() as Box<FrameSink>,
+    });
+  }
+  run_renderers(machines, sinks, &load_palette(config), frames, picture_steps, out)
+    .map_err(|why| Error::io("Error writing to stdout".to_string(), why))
+}
+
+
+// Outputs writing raw frames to nowhere.
+fn null_outputs() -> Outputs {
+  Outputs::new(vec!(Output::new(box RawSink::new(box NullWriter as Box<Writer>) as Box<ImageSink>, 1, 1)))
+}
+
+
+// Runs each machine, built the same way, through the renderer and into the
+// sink at the same position in RENDERERS. Stepping is timed apart from
+// rendering, so the render time of the incremental renderers is the
+// patching 'step' does rather than anything here.
+fn run_renderers(machines: Vec<Box<Machine>>, sinks: Vec<Box<FrameSink>>, palette: &Vec<Color>, frames: uint,
+                 picture_steps: u64, out: &mut Writer) -> IoResult<()> {
+  let mut reference = None;
+  try!(writeln!(out, "{:<12} {:>12} {:>12} {:>12} {:>18}", "renderer", "step ms", "render ms", "write ms",
+                "checksum"));
+  for ((&(name, ref renderer), mut machine), mut sink) in RENDERERS.iter().zip(machines.into_iter())
+                                                                  .zip(sinks.into_iter()) {
+    machine.set_palette(palette);
+    let (width, height) = machine.image_size();
+    let (mut step_ns, mut render_ns, mut write_ns) = (0u64, 0u64, 0u64);
+    let mut checksum = 0u64;
+
+    for frame in range(0, frames) {
+      let start = precise_time_ns();
+      for _ in range(0, picture_steps) {
+        machine.step();
+      }
+      let stepped = precise_time_ns();
+      match *renderer {
+        Incremental | Background => {},
+        Full => machine.redraw(false),
+        Lut => machine.redraw(true),
+      }
+      let rendered = precise_time_ns();
+      try!(sink.write_frame(frame as u64, machine.image(), width, height));
+      write_ns += precise_time_ns() - rendered;
+      render_ns += rendered - stepped;
+      step_ns += stepped - start;
+      checksum = (checksum ^ fnv1a(machine.image())) * 0x100000001b3;
+    }
+    // Frames still queued on a background thread count as writing time.
+    let finishing = precise_time_ns();
+    try!(sink.finish());
+    write_ns += precise_time_ns() - finishing;
+
+    let matches = match reference {
+      Some(expected) if expected != checksum => "  MISMATCH",
+      _ => "",
+    };
+    if reference.is_none() {
+      reference = Some(checksum);
+    }
+    let frames = if frames == 0 { 1 } else { frames } as f64;
+    try!(writeln!(out, "{:<12} {:>12.3} {:>12.3} {:>12.3} {:>18}{}", name,
+                  step_ns as f64 / frames / 1e6, render_ns as f64 / frames / 1e6,
+                  write_ns as f64 / frames / 1e6, format!("{:016x}", checksum), matches));
+  }
+  Ok(())
+}
+
+
+/// How '--bench' encodes frames.
+pub enum Encoding {
+  /// Raw bgr24 frames, as written to a video player.
+  Raw,
+  /// JPEG frames at the given quality, as served by the "mjpeg" output.
+  Jpeg(uint),
+}
+
+
+/// 'turing --bench': runs the configured machine, seeded with 'seed', for
+/// 'steps' steps, encoding a frame every 'picture_steps' steps and throwing
+/// it away, and reports the steps per second, the time spent encoding and
+/// the bytes the frames would have taken.
+pub fn throughput(config: &toml::Value, seed: u64, steps: u64, picture_steps: u64, encoding: Encoding,
+                  out: &mut Writer) -> Result<(), Error> {
+  let algorithm = try!(Algorithm::from_config(config));
+  let mut machine = try!(build_machine(config, &mut SessionRng::with_algorithm(algorithm, seed)));
+  machine.set_palette(&load_palette(config));
+  run_throughput(&mut *machine, steps, picture_steps, encoding, out)
+    .map_err(|why| Error::io("Error writing to stdout".to_string(), why))
+}
+
+
+fn run_throughput(machine: &mut Machine, steps: u64, picture_steps: u64, encoding: Encoding,
+                  out: &mut Writer) -> IoResult<()> {
+  let picture_steps = if picture_steps == 0 { 1 } else { picture_steps };
+  let (width, height) = machine.image_size();
+  let mut raw = RawSink::new(box NullWriter as Box<Writer>);
+  let encoder = match encoding {
+    Jpeg(quality) => Some(JpegEncoder::new(quality)),
+    Raw => None,
+  };
+  let mut jpeg = Vec::new();
+  let (mut step_ns, mut encode_ns, mut frames, mut bytes) = (0u64, 0u64, 0u64, 0u64);
+  let mut done = 0;
+  while done < steps {
+    let batch = if steps - done < picture_steps { steps - done } else { picture_steps };
+    let start = precise_time_ns();
+    for _ in range(0, batch) {
+      machine.step();
+    }
+    let stepped = precise_time_ns();
+    bytes += match encoder {
+      Some(ref encoder) => {
+        try!(encoder.encode(machine.image(), width, height, &mut jpeg));
+        jpeg.len() as u64
+      },
+      None => {
+        try!(raw.write_frame(machine.image(), width, height));
+        machine.image().len() as u64
+      },
+    };
+    encode_ns += precise_time_ns() - stepped;
+    step_ns += stepped - start;
+    frames += 1;
+    done += batch;
+  }
+
+  let seconds = |ns: u64| if ns == 0 { 1e-9 } else { ns as f64 / 1e9 };
+  let per_frame = if frames == 0 { 1 } else { frames } as f64;
+  try!(writeln!(out, "steps          {}", steps));
+  try!(writeln!(out, "steps/sec      {:.0}", steps as f64 / seconds(step_ns)));
+  try!(writeln!(out, "frames         {}", frames));
+  try!(writeln!(out, "encode ms      {:.3} per frame", encode_ns as f64 / per_frame / 1e6));
+  try!(writeln!(out, "frames/sec     {:.1}", frames as f64 / seconds(step_ns + encode_ns)));
+  writeln!(out, "bytes written  {}", bytes)
+}
@@ -0,0 +1,64 @@
+/// Blends each frame with the one written before it, softening the strobing
+/// that single cell updates cause at high steps per frame. The previous
+/// frame keeps 'alpha' of its weight, so a change fades in over a few
+/// frames and old ones fade out the same way.
+pub struct FrameBlend {
+  // The previous frame's weight in 256ths.
+  alpha: uint,
+  frame: Vec<u8>,
+}
+
+impl FrameBlend {
+  /// 'alpha' from 0 (frames as they are) to just under 1 (changes take
+  /// many frames to show).
+  pub fn new(alpha: f64) -> FrameBlend {
+    FrameBlend { alpha: (alpha * 256.0) as uint, frame: Vec::new() }
+  }
+
+  /// 'image' blended with the frame before it. The first frame, and the
+  /// first after the frame size changes, are shown as they are.
+  pub fn apply<'a>(&'a mut self, image: &[u8]) -> &'a [u8] {
+    if self.frame.len() != image.len() {
+      self.frame.clear();
+      self.frame.push_all(image);
+      return self.frame.as_slice();
+    }
+    let alpha = self.alpha;
+    for (previous, &current) in self.frame.iter_mut().zip(image.iter()) {
+      let blended = ((current as uint * (256 - alpha) + *previous as uint * alpha + 128) >> 8) as u8;
+      // Rounding could stall a value one short of a still frame; move on.
+      *previous = if blended == *previous && blended != current {
+        if current > blended { blended + 1 } else { blended - 1 }
+      } else {
+        blended
+      };
+    }
+    self.frame.as_slice()
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::FrameBlend;
+
+  #[test]
+  fn changes_fade_in_and_settle() {
+    let mut blend = FrameBlend::new(0.5);
+    assert_eq!(blend.apply([0u8, 200].as_slice()), [0, 200].as_slice());
+    assert_eq!(blend.apply([100u8, 200].as_slice()), [50, 200].as_slice());
+    for _ in range(0u, 20) {
+      blend.apply([100u8, 200].as_slice());
+    }
+    assert_eq!(blend.apply([100u8, 200].as_slice()), [100, 200].as_slice());
+    // A new size starts over.
+    assert_eq!(blend.apply([7u8].as_slice()), [7].as_slice());
+  }
+
+  #[test]
+  fn zero_alpha_passes_frames_through() {
+    let mut blend = FrameBlend::new(0.0);
+    blend.apply([0u8, 0, 0].as_slice());
+    assert_eq!(blend.apply([1u8, 128, 255].as_slice()), [1, 128, 255].as_slice());
+  }
+}
@@ -0,0 +1,88 @@
+use std::io::{IoResult, USER_RWX};
+use std::io::fs::mkdir_recursive;
+
+use turing::{Machine, TuringMachine, DirectionSet};
+use turing::error::{Error, invalid};
+use turing::rng::SessionRng;
+use turing::table::Table;
+use session;
+
+
+/// '--breed a.toml b.toml': writes 'children' tables to 'out', each a
+/// crossover of the tables saved in 'a' and 'b' with 'mutations' entries
+/// redrawn, as 'child_NNNN.toml'. 'machine' is a machine of the configured
+/// shape, for reading and writing the tables.
+pub fn run(machine: &mut TuringMachine, a: &Path, b: &Path, children: uint, mutations: uint, halting: bool,
+           rng: &mut SessionRng, out: &Path) -> Result<(), Error> {
+  let parent_a = try!(read_table(machine, a));
+  let parent_b = try!(read_table(machine, b));
+  let children = try!(offspring(&parent_a, &parent_b, children, mutations, machine.directions(), halting, rng)
+                      .ok_or(invalid("The parents' tables have different shapes".to_string())));
+  write_children(machine, children.as_slice(), out)
+    .map_err(|why| Error::io(format!("Error writing to {}", out.display()), why))
+}
+
+
+/// 'count' children of 'a' and 'b', each taking every entry from one parent
+/// or the other and then having 'mutations' entries redrawn. None if the
+/// parents' shapes differ.
+pub fn offspring(a: &Table, b: &Table, count: uint, mutations: uint, directions: DirectionSet, halting: bool,
+                 rng: &mut SessionRng) -> Option<Vec<Table>> {
+  let mut children = Vec::with_capacity(count);
+  for _ in range(0, count) {
+    let mut child = match a.crossover(b, rng) {
+      Some(child) => child,
+      None => return None,
+    };
+    for _ in range(0, mutations) {
+      child.mutate(rng, directions, halting);
+    }
+    children.push(child);
+  }
+  Some(children)
+}
+
+
+// The table saved at 'path', checked against 'machine'.
+fn read_table(machine: &mut TuringMachine, path: &Path) -> Result<Table, Error> {
+  let table = try!(session::load_table(path).map_err(|why| invalid(why.to_string())));
+  try!(machine.load_table(&table).map_err(|why| invalid(format!("Unable to load {}: {}", path.display(), why))));
+  Ok(machine.table().clone())
+}
+
+
+fn write_children(machine: &mut TuringMachine, children: &[Table], out: &Path) -> IoResult<()> {
+  try!(mkdir_recursive(out, USER_RWX));
+  for (n, child) in children.iter().enumerate() {
+    *machine.table_mut() = child.clone();
+    let path = out.join(format!("child_{:04u}.toml", n));
+    try!(machine.save_table(&path));
+    println!("{}", path.display());
+  }
+  Ok(())
+}
+
+
+#[cfg(test)]
+mod test {
+  use turing::Cardinal;
+  use turing::rng::SessionRng;
+  use turing::table::Table;
+
+  use super::offspring;
+
+  #[test]
+  fn children_come_from_both_parents() {
+    let mut rng = SessionRng::new(4);
+    let (a, b) = (Table::random(&mut rng, 4, 3, 1, Cardinal), Table::random(&mut rng, 4, 3, 1, Cardinal));
+    let children = offspring(&a, &b, 5, 0, Cardinal, false, &mut rng).unwrap();
+    assert_eq!(children.len(), 5);
+    for child in children.iter() {
+      assert!(child.entries().iter().enumerate().all(|(i, entry)| {
+        *entry == a.entries()[i] || *entry == b.entries()[i]
+      }));
+    }
+    let other = Table::random(&mut rng, 2, 3, 1, Cardinal);
+    assert!(offspring(&a, &other, 1, 1, Cardinal, false, &mut rng).is_none());
+  }
+}
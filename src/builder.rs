@@ -0,0 +1,224 @@
+use super::{TuringMachine, PositionFeature, NoFeature, DirectionSet, Cardinal, Boundary, Wrap, Topology, Square,
+            HeadCollision, Sequential, RenderMode, Symbols};
+use error::{MachineError, BadTable};
+use rng::SessionRng;
+use table::Table;
+
+
+/// Builds a 'TuringMachine' an option at a time, so new options don't break
+/// existing callers. Options left alone are those of a new machine, on a
+/// 64x48 tape with 4 states and 2 symbols.
+///
+/// ```no_run
+/// use turing::builder::TuringMachineBuilder;
+/// use turing::Reflect;
+///
+/// let machine = TuringMachineBuilder::new().size(320, 240).states(6).symbols(3).boundary(Reflect)
+///   .seed(42).build().unwrap();
+/// ```
+pub struct TuringMachineBuilder {
+  width: uint,
+  height: uint,
+  states: u8,
+  symbols: u8,
+  seed: u64,
+  table: Option<Table>,
+  position_feature: PositionFeature,
+  directions: DirectionSet,
+  boundary: Boundary,
+  topology: Topology,
+  halting: bool,
+  heads: uint,
+  head_collision: HeadCollision,
+  cell_width: uint,
+  cell_height: uint,
+  render_mode: RenderMode,
+  // (world width, world height, fit) of a sparse tape.
+  sparse: Option<(uint, uint, bool)>,
+}
+
+impl TuringMachineBuilder {
+  pub fn new() -> TuringMachineBuilder {
+    TuringMachineBuilder {
+      width: 64,
+      height: 48,
+      states: 4,
+      symbols: 2,
+      seed: 0,
+      table: None,
+      position_feature: NoFeature,
+      directions: Cardinal,
+      boundary: Wrap,
+      topology: Square,
+      halting: false,
+      heads: 1,
+      head_collision: Sequential,
+      cell_width: 1,
+      cell_height: 1,
+      render_mode: Symbols,
+      sparse: None,
+    }
+  }
+
+  /// The size of the tape in cells.
+  pub fn size<'a>(&'a mut self, width: uint, height: uint) -> &'a mut TuringMachineBuilder {
+    self.width = width;
+    self.height = height;
+    self
+  }
+
+  pub fn states<'a>(&'a mut self, states: u8) -> &'a mut TuringMachineBuilder {
+    self.states = states;
+    self
+  }
+
+  pub fn symbols<'a>(&'a mut self, symbols: u8) -> &'a mut TuringMachineBuilder {
+    self.symbols = symbols;
+    self
+  }
+
+  /// The seed 'build' draws the random table from. Ignored by 'build_with'.
+  pub fn seed<'a>(&'a mut self, seed: u64) -> &'a mut TuringMachineBuilder {
+    self.seed = seed;
+    self
+  }
+
+  /// Runs 'table' instead of a random table. It must have the machine's
+  /// states, symbols and position feature.
+  pub fn table<'a>(&'a mut self, table: Table) -> &'a mut TuringMachineBuilder {
+    self.table = Some(table);
+    self
+  }
+
+  pub fn position_feature<'a>(&'a mut self, position_feature: PositionFeature) -> &'a mut TuringMachineBuilder {
+    self.position_feature = position_feature;
+    self
+  }
+
+  /// The directions random tables move in.
+  pub fn directions<'a>(&'a mut self, directions: DirectionSet) -> &'a mut TuringMachineBuilder {
+    self.directions = directions;
+    self
+  }
+
+  pub fn boundary<'a>(&'a mut self, boundary: Boundary) -> &'a mut TuringMachineBuilder {
+    self.boundary = boundary;
+    self
+  }
+
+  /// See 'TuringMachine::set_topology'.
+  pub fn topology<'a>(&'a mut self, topology: Topology) -> &'a mut TuringMachineBuilder {
+    self.topology = topology;
+    self
+  }
+
+  /// Whether random tables have transitions to HALT.
+  pub fn halting<'a>(&'a mut self, halting: bool) -> &'a mut TuringMachineBuilder {
+    self.halting = halting;
+    self
+  }
+
+  /// See 'TuringMachine::set_heads'.
+  pub fn heads<'a>(&'a mut self, heads: uint, collision: HeadCollision) -> &'a mut TuringMachineBuilder {
+    self.heads = heads;
+    self.head_collision = collision;
+    self
+  }
+
+  /// The size of each cell in pixels.
+  pub fn cell_size<'a>(&'a mut self, cell_width: uint, cell_height: uint) -> &'a mut TuringMachineBuilder {
+    self.cell_width = cell_width;
+    self.cell_height = cell_height;
+    self
+  }
+
+  pub fn render_mode<'a>(&'a mut self, render_mode: RenderMode) -> &'a mut TuringMachineBuilder {
+    self.render_mode = render_mode;
+    self
+  }
+
+  /// Runs on a sparse 'world_width'x'world_height' tape, showing 'size' cells
+  /// of it (see 'TuringMachine::set_sparse_tape').
+  pub fn sparse_tape<'a>(&'a mut self, world_width: uint, world_height: uint, fit: bool)
+                         -> &'a mut TuringMachineBuilder {
+    self.sparse = Some((world_width, world_height, fit));
+    self
+  }
+
+  /// Builds the machine, drawing its table from a generator seeded with
+  /// 'seed'.
+  pub fn build(&self) -> Result<Box<TuringMachine>, MachineError> {
+    self.build_with(&mut SessionRng::new(self.seed))
+  }
+
+  /// Builds the machine, drawing its table from 'rng' as a session does.
+  /// Draws the same numbers as creating the machine with 'TuringMachine::new'
+  /// and setting the options, so seeded runs are unchanged.
+  pub fn build_with(&self, rng: &mut SessionRng) -> Result<Box<TuringMachine>, MachineError> {
+    if self.states == 0 || self.symbols == 0 {
+      return Err(BadTable(format!("A machine needs at least one state and symbol, not {} and {}", self.states,
+                                  self.symbols)));
+    }
+    let mut machine = TuringMachine::new(self.width, self.height, self.states, self.symbols,
+                                         self.position_feature, self.directions, rng);
+    machine.set_cell_size(self.cell_width, self.cell_height);
+    machine.set_heads(self.heads, self.head_collision);
+    machine.set_boundary(self.boundary);
+    // Only halting machines draw their table again.
+    if self.halting {
+      machine.set_halting(true, rng);
+    }
+    if self.topology != Square {
+      machine.set_topology(self.topology);
+    }
+    if self.render_mode != Symbols {
+      machine.set_render_mode(self.render_mode);
+    }
+    match self.sparse {
+      Some((world_width, world_height, fit)) => machine.set_sparse_tape(world_width, world_height, fit),
+      None => {},
+    }
+    match self.table {
+      Some(ref table) => {
+        let entries = table.entries().to_vec();
+        *machine.table_mut() = try!(Table::from_entries(self.states, self.symbols, self.position_feature.count(),
+                                                        entries));
+      },
+      None => {},
+    }
+    Ok(machine)
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::super::{TuringMachine, Machine, NoFeature, Parity, Cardinal, Reflect};
+  use rng::SessionRng;
+  use table::Table;
+
+  use super::TuringMachineBuilder;
+
+  #[test]
+  fn builds_like_new() {
+    let built = TuringMachineBuilder::new().size(8, 6).states(3).symbols(4).seed(7).build().unwrap();
+    let made = TuringMachine::new(8, 6, 3, 4, NoFeature, Cardinal, &mut SessionRng::new(7));
+    assert_eq!(built.table_text(), made.table_text());
+    assert_eq!(built.image_size(), (8, 6));
+  }
+
+  #[test]
+  fn checks_the_table_shape() {
+    let table = Table::random(&mut SessionRng::new(1), 3, 2, 1, Cardinal);
+    let mut builder = TuringMachineBuilder::new();
+    builder.size(4, 4).states(3).symbols(2).boundary(Reflect).table(table.clone());
+    assert_eq!(builder.build().unwrap().table_text(), table.text());
+    assert!(builder.position_feature(Parity).build().is_err());
+  }
+
+  #[test]
+  fn rejects_no_states_or_symbols() {
+    assert!(TuringMachineBuilder::new().states(0).build().is_err());
+    assert!(TuringMachineBuilder::new().symbols(0).build().is_err());
+  }
+}
@@ -0,0 +1,855 @@
+use std::io::File;
+use std::io::fs::PathExtensions;
+use toml;
+
+use turing::error::{Error, ConfigMissing, Usage, invalid};
+use turing::{palette, preset};
+use turing::hybrid::LifeRule;
+use turing::sparse::MAX_WORLD;
+
+
+/// The configuration file: the one given with '--config', or turing.toml.
+pub fn path(args: &[String]) -> Result<Path, Error> {
+  match args.iter().position(|arg| arg.as_slice() == "--config") {
+    Some(index) => match args.get(index + 1) {
+      Some(path) => Ok(Path::new(path.as_slice())),
+      None => Err(Error::new(Usage, "--config needs a file".to_string())),
+    },
+    None => Ok(Path::new("turing.toml")),
+  }
+}
+
+
+/// Reads the configuration file at 'path', applies the overrides in 'args'
+/// (see 'apply_overrides') and the preset, and checks the result.
+pub fn load(path: &Path, args: &[String]) -> Result<toml::Value, Error> {
+  if !path.exists() {
+    return Err(Error::new(ConfigMissing, format!("Config file {} not found", path.display())));
+  }
+  let data = match File::open(path).read_to_string() {
+    Ok(data) => data,
+    Err(why) => return Err(Error::new(ConfigMissing,
+                                      format!("Unable to read config file {}: {}", path.display(), why.desc))),
+  };
+  let config = match from_str(data.as_slice()) {
+    Some(config) => config,
+    None => return Err(invalid(format!("{} is not valid TOML", path.display()))),
+  };
+  let config = apply_preset(try!(apply_overrides(config, args)));
+  try!(check(&config));
+  Ok(config)
+}
+
+
+/// Sets the keys of the [turing] section given as '--key value' in 'args',
+/// e.g. '--width 320' or '--reset-steps 1000000' (dashes stand for
+/// underscores). Other arguments are left for the commands to read.
+pub fn apply_overrides(config: toml::Value, args: &[String]) -> Result<toml::Value, Error> {
+  let mut config = config;
+  let mut i = 0;
+  while i < args.len() {
+    let arg = args[i].as_slice();
+    i += 1;
+    if !arg.starts_with("--") {
+      continue;
+    }
+    let name = format!("turing.{}", arg.slice_from(2).replace("-", "_"));
+    let kind = match key_kind(name.as_slice()) {
+      Some(kind) => kind,
+      None => continue,
+    };
+    let text = match args.get(i) {
+      Some(text) => text.as_slice(),
+      None => return Err(Error::new(Usage, format!("{} needs a value", arg))),
+    };
+    i += 1;
+    let value = match kind {
+      IntegerKey => from_str::<i64>(text.replace("_", "").as_slice()).map(|n| toml::Integer(n)),
+      FloatKey => from_str::<f64>(text).map(|n| toml::Float(n)),
+      BooleanKey => from_str::<bool>(text).map(|b| toml::Boolean(b)),
+      StringKey => Some(toml::String(text.to_string())),
+    };
+    match value {
+      Some(value) => set(&mut config, name.as_slice(), value),
+      None => return Err(Error::new(Usage, format!("Invalid value for {}: {}", arg, text))),
+    }
+  }
+  Ok(config)
+}
+
+
+/// Sets the keys the machine of 'turing.preset' needs: its mode, states and
+/// symbols, one tape and no position feature, interleaving or montage.
+pub fn apply_preset(config: toml::Value) -> toml::Value {
+  // Unknown names are left for 'check' to report.
+  let preset = match config.lookup("turing.preset").and_then(|v| v.as_str())
+                           .and_then(|name| preset::find(name)) {
+    Some(preset) => preset,
+    None => return config,
+  };
+  let mut config = config;
+  set(&mut config, "turing.mode", toml::String(preset.mode.to_string()));
+  set(&mut config, "turing.states", toml::Integer(preset.states as i64));
+  set(&mut config, "turing.symbols", toml::Integer(preset.symbols as i64));
+  set(&mut config, "turing.tapes", toml::Integer(1));
+  set(&mut config, "turing.position_feature", toml::String("none".to_string()));
+  set(&mut config, "turing.interleave", toml::String("none".to_string()));
+  set(&mut config, "turing.montage_columns", toml::Integer(1));
+  set(&mut config, "turing.montage_rows", toml::Integer(1));
+  set(&mut config, "turing.topology", toml::String("square".to_string()));
+  set(&mut config, "turing.halting", toml::Boolean(false));
+  config
+}
+
+
+/// Sets 'name' ("section.key") to 'value', adding the section if needed.
+pub fn set(config: &mut toml::Value, name: &str, value: toml::Value) {
+  let (section, key) = match name.find('.') {
+    Some(dot) => (name.slice_to(dot), name.slice_from(dot + 1)),
+    None => return,
+  };
+  match *config {
+    toml::Table(ref mut root) => {
+      if !root.contains_key(&section.to_string()) {
+        root.insert(section.to_string(), toml::Table(::std::collections::TreeMap::new()));
+      }
+      match root.find_mut(&section.to_string()) {
+        Some(&toml::Table(ref mut table)) => { table.insert(key.to_string(), value); },
+        _ => {},
+      }
+    },
+    _ => {},
+  }
+}
+
+
+enum KeyKind {
+  IntegerKey,
+  FloatKey,
+  BooleanKey,
+  StringKey,
+}
+
+// The type of the known key 'name', if it is one.
+fn key_kind(name: &str) -> Option<KeyKind> {
+  if REQUIRED_INTEGERS.iter().chain(INTEGERS.iter()).any(|&(key, _)| key == name) {
+    Some(IntegerKey)
+  } else if FLOATS.iter().any(|&(key, _, _)| key == name) {
+    Some(FloatKey)
+  } else if BOOLEANS.contains(&name) {
+    Some(BooleanKey)
+  } else if STRINGS.iter().any(|&(key, _)| key == name) {
+    Some(StringKey)
+  } else {
+    None
+  }
+}
+
+
+// Integer keys that must be present, and the smallest value each may have.
+static REQUIRED_INTEGERS: &'static [(&'static str, i64)] = &[
+  ("turing.states", 1),
+  ("turing.symbols", 1),
+  ("turing.width", 1),
+  ("turing.height", 1),
+  ("turing.picture_steps", 1),
+  ("turing.reset_steps", 1),
+];
+
+// Optional integer keys and their smallest values.
+static INTEGERS: &'static [(&'static str, i64)] = &[
+  ("turing.cell_width", 1),
+  ("turing.cell_height", 1),
+  ("turing.scale", 1),
+  ("turing.tapes", 1),
+  ("turing.heads", 1),
+  ("turing.halt_hold_frames", 0),
+  ("turing.interleave_size", 1),
+  ("turing.trail_frames", 1),
+  ("turing.head_marker_size", 1),
+  ("turing.viewport_width", 1),
+  ("turing.viewport_height", 1),
+  ("turing.viewport_x", 0),
+  ("turing.viewport_y", 0),
+  ("turing.zoom", -8),
+  ("turing.follow_deadzone", 0),
+  ("turing.follow_smoothing", 1),
+  ("turing.search_population", 2),
+  ("turing.search_generations", 1),
+  ("turing.mutate_steps", 0),
+  ("turing.mutations_per_event", 1),
+  ("turing.hall_of_fame_window", 1),
+  ("turing.hall_of_fame_windows", 1),
+  ("turing.hall_of_fame_rate", 0),
+  ("turing.montage_columns", 1),
+  ("turing.montage_rows", 1),
+  ("turing.min_picture_steps", 1),
+  ("turing.max_picture_steps", 1),
+  ("turing.target_changes", 1),
+  ("turing.energy", 1),
+  ("turing.energy_drain", 0),
+  ("turing.energy_recharge", 0),
+  ("turing.stats_interval", 0),
+  ("turing.nice", 0),
+  ("turing.throttle_ms", 0),
+  ("turing.fps", 0),
+  ("turing.events_port", 0),
+  ("turing.control_port", 0),
+  ("turing.grpc_port", 0),
+  ("turing.frame_fd", 0),
+  ("turing.render_threads", 1),
+  ("turing.record_every", 1),
+  ("turing.session_save_frames", 0),
+  ("turing.replay_window", 1),
+  ("turing.stagnant_frames", 0),
+  ("turing.cycle_period", 0),
+  ("turing.cycle_repeats", 1),
+  ("turing.batch_steps", 0),
+  ("turing.min_interest", 0),
+  ("turing.interest_steps", 1),
+  ("turing.interest_attempts", 1),
+  ("turing.replay_slowdown", 1),
+  ("turing.memory_limit_mb", 0),
+  ("turing.seed", 0),
+  ("turing.world_size", 1),
+  ("turing.eca_rule", 0),
+  ("turing.ca_steps", 1),
+  ("kiosk.random_quota", 0),
+  ("kiosk.quiet_start", 0),
+  ("kiosk.quiet_end", 0),
+  ("kiosk.heartbeat_interval", 1),
+];
+
+// Number keys and the range of values each may have.
+static FLOATS: &'static [(&'static str, f64, f64)] = &[
+  ("render.gamma", 0.1, 10.0),
+  ("render.brightness", -1.0, 1.0),
+  ("render.contrast", 0.0, 10.0),
+  ("render.blend", 0.0, 0.99),
+];
+
+static BOOLEANS: &'static [&'static str] = &[
+  "turing.adaptive_picture_steps",
+  "turing.terminal_preview",
+  "turing.chain",
+  "turing.keyboard",
+  "turing.kiosk",
+  "turing.replay",
+  "turing.halting",
+  "turing.follow_head",
+  "turing.search",
+];
+
+// String keys and the values they may have. Keys without a list take any
+// string (paths).
+static STRINGS: &'static [(&'static str, &'static [&'static str])] = &[
+  ("turing.mode", &["turing", "turmite", "spacetime", "eca", "hybrid"]),
+  ("turing.position_feature", &["none", "parity", "quadrant"]),
+  ("turing.directions", &["cardinal", "eightway", "all"]),
+  ("turing.head_collision", &["sequential", "wait"]),
+  ("turing.boundary", &["wrap", "reflect", "clamp", "halt"]),
+  ("turing.topology", &["square", "hex"]),
+  ("turing.tape", &["dense", "sparse"]),
+  ("turing.sparse_view", &["fixed", "fit"]),
+  ("turing.ca_rule", &[]),
+  ("turing.render", &["symbols", "heatmap", "trails", "states"]),
+  ("turing.head_marker", &["none", "block", "crosshair"]),
+  ("turing.head_marker_color", &[]),
+  ("turing.two_tape_display", &["side-by-side", "combined"]),
+  ("turing.interleave", &["none", "stripes", "checkerboard"]),
+  ("turing.reset_policy", &["steps", "energy"]),
+  ("turing.fps_mode", &["sleep", "adapt"]),
+  ("turing.pixel_format", &["bgr24", "rgb24", "rgba", "gray8"]),
+  ("turing.control", &["none", "stdio"]),
+  ("turing.on_closed_output", &["exit", "wait"]),
+  ("turing.memory_policy", &["refuse", "downscale"]),
+  ("turing.rng", &["small", "std", "chacha"]),
+  ("turing.palette", &["curated", "generated", "cycled"]),
+  ("turing.session_file", &[]),
+  ("turing.shutdown_table", &[]),
+  ("turing.record_dir", &[]),
+  ("turing.hall_of_fame", &[]),
+  ("turing.reset_log", &[]),
+  ("turing.table_file", &[]),
+  ("turing.preset", &[]),
+  ("turing.event_log", &[]),
+  ("turing.control_host", &[]),
+  ("turing.playlist", &[]),
+  ("kiosk.playlist", &[]),
+  ("kiosk.heartbeat_file", &[]),
+];
+
+
+/// Checks every known key has a value of the right type and range, so the
+/// rest of the program can read the config without checking again.
+pub fn check(config: &toml::Value) -> Result<(), Error> {
+  for &(name, min) in REQUIRED_INTEGERS.iter() {
+    if config.lookup(name).is_none() {
+      return Err(invalid(format!("Missing {}", name)));
+    }
+    try!(check_integer(config, name, min));
+  }
+  for &(name, min) in INTEGERS.iter() {
+    try!(check_integer(config, name, min));
+  }
+  for &(name, min, max) in FLOATS.iter() {
+    match config.lookup(name) {
+      Some(value) => match number(value) {
+        Some(n) if n >= min && n <= max => {},
+        Some(n) => return Err(invalid(format!("{} must be from {} to {}, not {}", name, min, max, n))),
+        None => return Err(invalid(format!("{} must be a number", name))),
+      },
+      None => {},
+    }
+  }
+  for &name in BOOLEANS.iter() {
+    match config.lookup(name) {
+      Some(value) if value.as_bool().is_none() => return Err(invalid(format!("{} must be true or false", name))),
+      _ => {},
+    }
+  }
+  for &(name, choices) in STRINGS.iter() {
+    match config.lookup(name) {
+      Some(value) => match value.as_str() {
+        Some(text) if choices.is_empty() || choices.contains(&text) => {},
+        Some(text) => return Err(invalid(format!("Unknown {}: {} (expected one of {})",
+                                                 name, text, choices.connect(", ")))),
+        None => return Err(invalid(format!("{} must be a string", name))),
+      },
+      None => {},
+    }
+  }
+
+  let symbols = config.lookup("turing.symbols").and_then(|v| v.as_integer()).unwrap();
+  let states = config.lookup("turing.states").and_then(|v| v.as_integer()).unwrap();
+  if symbols > 255 || states > 255 {
+    return Err(invalid("states and symbols must be at most 255".to_string()));
+  }
+  match config.lookup("turing.tapes").and_then(|v| v.as_integer()) {
+    Some(tapes) if tapes > 2 => return Err(invalid(format!("Unsupported number of tapes: {}", tapes))),
+    _ => {},
+  }
+  let turmite = config.lookup("turing.mode").and_then(|v| v.as_str()) == Some("turmite");
+  let single_tape = config.lookup("turing.tapes").and_then(|v| v.as_integer()).unwrap_or(1) == 1;
+  if turmite && !single_tape {
+    return Err(invalid("Turmites have a single tape".to_string()));
+  }
+  let int_or = |name: &str| config.lookup(name).and_then(|v| v.as_integer()).unwrap_or(1);
+  let montage = int_or("turing.montage_columns") * int_or("turing.montage_rows") > 1;
+  let interleaved = config.lookup("turing.interleave").and_then(|v| v.as_str()).unwrap_or("none") != "none";
+  if montage && (turmite || !single_tape || interleaved) {
+    return Err(invalid("Montages are of single tape machines in \"turing\" mode without interleave".to_string()));
+  }
+  if config.lookup("turing.mode").and_then(|v| v.as_str()) == Some("spacetime") {
+    try!(check_spacetime(config, single_tape && !interleaved && !montage));
+  }
+  if config.lookup("turing.mode").and_then(|v| v.as_str()) == Some("eca") {
+    try!(check_eca(config, single_tape && !interleaved && !montage));
+  }
+  if config.lookup("turing.mode").and_then(|v| v.as_str()) == Some("hybrid") {
+    try!(check_hybrid(config, single_tape && !interleaved && !montage));
+  }
+  match config.lookup("turing.min_interest").and_then(|v| v.as_integer()) {
+    Some(min) if min > 100 => return Err(invalid(format!("min_interest ({}) is a percentage, at most 100", min))),
+    _ => {},
+  }
+  match config.lookup("turing.zoom").and_then(|v| v.as_integer()) {
+    Some(zoom) if zoom > 8 => return Err(invalid(format!("zoom ({}) is at most 8", zoom))),
+    _ => {},
+  }
+  if config.lookup("turing.viewport_width").is_some() != config.lookup("turing.viewport_height").is_some() {
+    return Err(invalid("viewport_width and viewport_height go together".to_string()));
+  }
+  if config.lookup("turing.follow_head").and_then(|v| v.as_bool()).unwrap_or(false)
+      && config.lookup("turing.viewport_width").is_none() {
+    return Err(invalid("follow_head needs a viewport (viewport_width and viewport_height)".to_string()));
+  }
+  match config.lookup("turing.hall_of_fame_rate").and_then(|v| v.as_integer()) {
+    Some(rate) if rate > 100 => {
+      return Err(invalid(format!("hall_of_fame_rate ({}) is a percentage, at most 100", rate)));
+    },
+    _ => {},
+  }
+  match config.lookup("turing.follow_deadzone").and_then(|v| v.as_integer()) {
+    Some(deadzone) if deadzone > 100 => {
+      return Err(invalid(format!("follow_deadzone ({}) is a percentage, at most 100", deadzone)));
+    },
+    _ => {},
+  }
+  match config.lookup("turing.trail_frames").and_then(|v| v.as_integer()) {
+    Some(frames) if frames > 255 => return Err(invalid(format!("trail_frames ({}) is at most 255", frames))),
+    _ => {},
+  }
+  match config.lookup("turing.render_threads").and_then(|v| v.as_integer()) {
+    Some(threads) if threads > 2 => return Err(invalid(format!("render_threads ({}) is at most 2", threads))),
+    _ => {},
+  }
+  let halting = config.lookup("turing.halting").and_then(|v| v.as_bool()).unwrap_or(false);
+  if halting && (turmite || !single_tape) {
+    return Err(invalid("Only single tape machines in \"turing\" mode have a HALT state".to_string()));
+  }
+  if config.lookup("turing.heads").and_then(|v| v.as_integer()).unwrap_or(1) > 1 && (turmite || !single_tape) {
+    return Err(invalid("heads is for single tape machines in \"turing\" mode".to_string()));
+  }
+  if config.lookup("turing.topology").and_then(|v| v.as_str()) == Some("hex") {
+    if turmite || !single_tape || interleaved || montage {
+      return Err(invalid("Hex tapes are for single tape machines in \"turing\" mode without interleave or montage"
+                         .to_string()));
+    }
+    if config.lookup("turing.directions").and_then(|v| v.as_str()).unwrap_or("cardinal") != "cardinal" {
+      return Err(invalid("directions is for square tapes; hex machines move in the six hex directions".to_string()));
+    }
+    if config.lookup("turing.height").and_then(|v| v.as_integer()).unwrap() % 2 == 1 {
+      return Err(invalid("Hex tapes need an even height so the offset rows line up".to_string()));
+    }
+  }
+  if config.lookup("turing.mutate_steps").and_then(|v| v.as_integer()).unwrap_or(0) > 0
+      && (turmite || !single_tape) {
+    return Err(invalid("mutate_steps is for single tape machines in \"turing\" mode".to_string()));
+  }
+  if config.lookup("turing.search").and_then(|v| v.as_bool()).unwrap_or(false)
+      && (turmite || !single_tape || interleaved || montage) {
+    return Err(invalid("search is for single tape machines in \"turing\" mode without interleave or montage"
+                       .to_string()));
+  }
+  if config.lookup("turing.render").and_then(|v| v.as_str()).unwrap_or("symbols") != "symbols"
+      && (turmite || !single_tape || interleaved || montage) {
+    return Err(invalid("Render modes other than \"symbols\" are for single tape machines in \"turing\" mode \
+                        without interleave or montage".to_string()));
+  }
+  if config.lookup("turing.tape").and_then(|v| v.as_str()) == Some("sparse") {
+    try!(check_sparse(config, !turmite && single_tape && !interleaved && !montage));
+  }
+  match config.lookup("turing.preset").and_then(|v| v.as_str()) {
+    Some(name) if preset::find(name).is_none() => {
+      let names: Vec<&str> = preset::PRESETS.iter().map(|preset| preset.name).collect();
+      return Err(invalid(format!("Unknown preset: {} (expected one of {})", name, names.connect(", "))));
+    },
+    _ => {},
+  }
+  match config.lookup("turing.head_marker_color") {
+    Some(color) if palette::parse_color(color).is_none() => {
+      return Err(invalid(format!("Invalid head_marker_color: {}", color)));
+    },
+    _ => {},
+  }
+  match config.lookup("palette.colors") {
+    Some(colors) => try!(palette::parse(colors).map_err(|why| invalid(format!("Invalid palette: {}", why)))),
+    None => {},
+  }
+  try!(check_timing(config));
+  check_sizes(config)
+}
+
+
+// Checks the step counts make a run that shows something: machines live for
+// at least one frame.
+fn check_timing(config: &toml::Value) -> Result<(), Error> {
+  let int = |name: &str| config.lookup(name).and_then(|v| v.as_integer());
+  let (picture_steps, reset_steps) = (int("turing.picture_steps").unwrap(), int("turing.reset_steps").unwrap());
+  let energy = config.lookup("turing.reset_policy").and_then(|v| v.as_str()) == Some("energy");
+  try!(check_steps(picture_steps as u64, reset_steps as u64, energy));
+  let adapt_fps = config.lookup("turing.fps_mode").and_then(|v| v.as_str()) == Some("adapt");
+  if adapt_fps && config.lookup("turing.adaptive_picture_steps").and_then(|v| v.as_bool()) == Some(true) {
+    return Err(invalid("fps_mode = \"adapt\" and adaptive_picture_steps both set picture_steps; use one".to_string()));
+  }
+  match (int("turing.min_picture_steps"), int("turing.max_picture_steps")) {
+    (Some(min), Some(max)) if min > max => {
+      return Err(invalid(format!("min_picture_steps ({}) is more than max_picture_steps ({})", min, max)));
+    },
+    _ => {},
+  }
+  Ok(())
+}
+
+
+/// Checks 'picture_steps' and 'reset_steps' are at least 1 and, unless
+/// machines are reset by 'energy', that machines live for at least one frame.
+/// Applies to the configuration and to changes by control requests.
+pub fn check_steps(picture_steps: u64, reset_steps: u64, energy: bool) -> Result<(), Error> {
+  if picture_steps < 1 || reset_steps < 1 {
+    return Err(invalid("picture_steps and reset_steps must be at least 1".to_string()));
+  }
+  if !energy && picture_steps > reset_steps {
+    return Err(invalid(format!("picture_steps ({}) is more than reset_steps ({}), so no frame would ever be shown",
+                               picture_steps, reset_steps)));
+  }
+  Ok(())
+}
+
+
+// Checks a spacetime machine is a plain machine on a square dense tape,
+// moving east and west as it has to, and not searched for.
+fn check_spacetime(config: &toml::Value, plain: bool) -> Result<(), Error> {
+  let str_or = |name: &str, default: &'static str| config.lookup(name).and_then(|v| v.as_str()).unwrap_or(default);
+  if !plain || str_or("turing.topology", "square") != "square" || str_or("turing.tape", "dense") != "dense" {
+    return Err(invalid("Spacetime machines have a single square dense tape, without interleave or montage"
+                       .to_string()));
+  }
+  if str_or("turing.directions", "cardinal") != "cardinal" {
+    return Err(invalid("directions is for two dimensional tapes; spacetime machines move east and west"
+                       .to_string()));
+  }
+  if config.lookup("turing.search").and_then(|v| v.as_bool()).unwrap_or(false) {
+    return Err(invalid("search is for machines in \"turing\" mode".to_string()));
+  }
+  Ok(())
+}
+
+
+// Checks an elementary cellular automaton's rule, and that nothing asks
+// for a head or a tape it hasn't got.
+fn check_eca(config: &toml::Value, plain: bool) -> Result<(), Error> {
+  match config.lookup("turing.eca_rule").and_then(|v| v.as_integer()) {
+    Some(rule) if rule > 255 => return Err(invalid(format!("eca_rule ({}) is at most 255", rule))),
+    _ => {},
+  }
+  let str_is = |name: &str, unset: &str| config.lookup(name).and_then(|v| v.as_str()).unwrap_or(unset) == unset;
+  let int_is = |name: &str, unset: i64| config.lookup(name).and_then(|v| v.as_integer()).unwrap_or(unset) == unset;
+  let bool_is = |name: &str| !config.lookup(name).and_then(|v| v.as_bool()).unwrap_or(false);
+  if !plain || !str_is("turing.topology", "square") || !str_is("turing.tape", "dense")
+      || !str_is("turing.boundary", "wrap") || !str_is("turing.directions", "cardinal")
+      || !str_is("turing.position_feature", "none") || !str_is("turing.render", "symbols")
+      || !int_is("turing.heads", 1) || !int_is("turing.mutate_steps", 0)
+      || !bool_is("turing.halting") || !bool_is("turing.search") {
+    return Err(invalid("Elementary automata have one wrapping row of cells and no head, so they don't go with \
+                        tapes, topology, tape, boundary, directions, position_feature, render, heads, \
+                        mutate_steps, halting, search, interleave or montage".to_string()));
+  }
+  Ok(())
+}
+
+
+// Checks a hybrid machine's substrate rule, and that its tape is one the
+// substrate can update as a whole.
+fn check_hybrid(config: &toml::Value, plain: bool) -> Result<(), Error> {
+  let str_or = |name: &str, default: &'static str| config.lookup(name).and_then(|v| v.as_str()).unwrap_or(default);
+  let rule = str_or("turing.ca_rule", "B3/S23");
+  if LifeRule::parse(rule).is_none() {
+    return Err(invalid(format!("ca_rule ({}) should be in B/S notation, like \"B3/S23\"", rule)));
+  }
+  if !plain || str_or("turing.topology", "square") != "square" || str_or("turing.tape", "dense") != "dense" {
+    return Err(invalid("Hybrid machines have a single square dense tape, without interleave or montage"
+                       .to_string()));
+  }
+  Ok(())
+}
+
+
+// Checks a sparse tape goes with a plain machine drawn by symbol, the world
+// holds the image, and nothing needs the whole tape saved.
+fn check_sparse(config: &toml::Value, plain: bool) -> Result<(), Error> {
+  let str_or = |name: &str, default: &'static str| config.lookup(name).and_then(|v| v.as_str()).unwrap_or(default);
+  if !plain || str_or("turing.topology", "square") != "square" || str_or("turing.render", "symbols") != "symbols" {
+    return Err(invalid("Sparse tapes are for square single tape machines in \"turing\" mode drawn by symbol, \
+                        without interleave or montage".to_string()));
+  }
+  let int = |name: &str| config.lookup(name).and_then(|v| v.as_integer());
+  let world = int("turing.world_size").unwrap_or(MAX_WORLD as i64);
+  let (width, height) = (int("turing.width").unwrap(), int("turing.height").unwrap());
+  if world > MAX_WORLD as i64 || world < width || world < height {
+    return Err(invalid(format!("world_size ({}) must be from the larger of width and height ({}) to {}",
+                               world, ::std::cmp::max(width, height), MAX_WORLD)));
+  }
+  let saves_states = config.lookup("turing.replay").and_then(|v| v.as_bool()).unwrap_or(false)
+    || config.lookup("turing.search").and_then(|v| v.as_bool()).unwrap_or(false)
+    || int("turing.min_interest").unwrap_or(0) > 0
+    || config.lookup("turing.session_file").is_some();
+  if saves_states {
+    return Err(invalid("Sparse tapes can't be saved whole, so they don't go with replay, search, min_interest \
+                        or session_file".to_string()));
+  }
+  Ok(())
+}
+
+
+// Checks the tape, image and table sizes can be computed without overflow.
+// Cell indexes are stored as u32 (see NeighborTable), so the tape must have
+// fewer than 2^32 cells, and the image must fit in memory.
+fn check_sizes(config: &toml::Value) -> Result<(), Error> {
+  let int = |name: &str, default: i64| {
+    config.lookup(name).and_then(|v| v.as_integer()).unwrap_or(default) as u64
+  };
+  let (width, height) = (int("turing.width", 1), int("turing.height", 1));
+  let cells = match width.checked_mul(&height) {
+    Some(cells) if cells <= ::std::u32::MAX as u64 => cells,
+    _ => return Err(invalid(format!("A {}x{} tape has too many cells (at most {})",
+                                    width, height, ::std::u32::MAX))),
+  };
+  // Two tapes side by side make the image twice as wide, a montage has a
+  // tape per tile.
+  let columns = if int("turing.tapes", 1) == 2 { 2 } else { 1 };
+  let image_bytes = Some(cells)
+    .and_then(|n| n.checked_mul(&int("turing.cell_width", 1)))
+    .and_then(|n| n.checked_mul(&int("turing.cell_height", 1)))
+    .and_then(|n| n.checked_mul(&int("turing.montage_columns", 1)))
+    .and_then(|n| n.checked_mul(&int("turing.montage_rows", 1)))
+    .and_then(|n| n.checked_mul(&(3 * columns)));
+  match image_bytes {
+    Some(bytes) if bytes <= ::std::uint::MAX as u64 => {},
+    _ => return Err(invalid(format!("The {}x{} tape with cell size {}x{} makes too large an image",
+                                    width, height, int("turing.cell_width", 1), int("turing.cell_height", 1)))),
+  }
+  // At most 4 feature values, 255 states and 255 symbols (squared with two
+  // tapes), so table sizes always fit; the index arithmetic is done in uint.
+  Ok(())
+}
+
+
+/// The value of a number key, which may be written without a decimal point.
+pub fn number(value: &toml::Value) -> Option<f64> {
+  value.as_float().or(value.as_integer().map(|n| n as f64))
+}
+
+
+fn check_integer(config: &toml::Value, name: &str, min: i64) -> Result<(), Error> {
+  match config.lookup(name) {
+    Some(value) => match value.as_integer() {
+      Some(n) if n >= min => Ok(()),
+      Some(n) => Err(invalid(format!("{} must be at least {}, not {}", name, min, n))),
+      None => Err(invalid(format!("{} must be an integer", name))),
+    },
+    None => Ok(()),
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use toml;
+
+  use super::{check, check_steps, apply_overrides, apply_preset};
+
+  fn config(extra: &str) -> toml::Value {
+    from_str(format!("[turing]\nstates = 4\nsymbols = 6\npicture_steps = 10\nreset_steps = 100\n{}",
+                     extra).as_slice()).unwrap()
+  }
+
+  #[test]
+  fn accepts_normal_sizes() {
+    assert!(check(&config("width = 1024\nheight = 768\ncell_width = 2")).is_ok());
+  }
+
+  #[test]
+  fn rejects_too_many_cells() {
+    assert!(check(&config("width = 100000\nheight = 100000")).is_err());
+  }
+
+  #[test]
+  fn rejects_overflowing_images() {
+    assert!(check(&config("width = 65536\nheight = 65535\ncell_width = 4294967296\n\
+                           cell_height = 4294967296")).is_err());
+  }
+
+  fn timing(picture_steps: i64, reset_steps: i64, extra: &str) -> toml::Value {
+    from_str(format!("[turing]\nstates = 4\nsymbols = 6\nwidth = 8\nheight = 8\n\
+                      picture_steps = {}\nreset_steps = {}\n{}", picture_steps, reset_steps, extra).as_slice()).unwrap()
+  }
+
+  #[test]
+  fn rejects_zero_steps() {
+    assert!(check(&timing(0, 100, "")).is_err());
+    assert!(check(&timing(10, 0, "")).is_err());
+  }
+
+  #[test]
+  fn rejects_frames_longer_than_machines() {
+    assert!(check(&timing(100, 100, "")).is_ok());
+    assert!(check(&timing(101, 100, "")).is_err());
+    // With the energy policy reset_steps doesn't end machines.
+    assert!(check(&timing(101, 100, "reset_policy = \"energy\"")).is_ok());
+  }
+
+  #[test]
+  fn step_changes_follow_the_config_limits() {
+    assert!(check_steps(100, 100, false).is_ok());
+    assert!(check_steps(0, 100, false).is_err());
+    assert!(check_steps(10, 0, true).is_err());
+    assert!(check_steps(101, 100, false).is_err());
+    assert!(check_steps(101, 100, true).is_ok());
+  }
+
+  #[test]
+  fn accepts_steps_beyond_u32() {
+    // Step counters are u64, so runs can last longer than 2^32 steps.
+    assert!(check(&timing(10, 10000000000, "")).is_ok());
+    assert!(check(&timing(10, 100, "max_picture_steps = 4294967296")).is_ok());
+  }
+
+  #[test]
+  fn rejects_inverted_adaptive_range() {
+    assert!(check(&timing(10, 100, "min_picture_steps = 50\nmax_picture_steps = 50")).is_ok());
+    assert!(check(&timing(10, 100, "min_picture_steps = 51\nmax_picture_steps = 50")).is_err());
+  }
+
+  #[test]
+  fn rejects_too_many_symbols() {
+    assert!(check(&config("width = 8\nheight = 8")).is_ok());
+    let mut too_many = String::from_str("[turing]\nstates = 4\nsymbols = 300\nwidth = 8\nheight = 8\n");
+    too_many.push_str("picture_steps = 10\nreset_steps = 100\n");
+    assert!(check(&from_str(too_many.as_slice()).unwrap()).is_err());
+  }
+
+  #[test]
+  fn checks_palette_colors() {
+    assert!(check(&config("width = 8\nheight = 8\n[palette]\ncolors = [\"#102030\", \"#ffffff\"]")).is_ok());
+    assert!(check(&config("width = 8\nheight = 8\n[palette]\ncolors = [\"#zz0000\"]")).is_err());
+    assert!(check(&config("width = 8\nheight = 8\n[palette]\ncolors = \"red\"")).is_err());
+  }
+
+  #[test]
+  fn turmites_have_one_tape() {
+    assert!(check(&config("width = 8\nheight = 8\nmode = \"turmite\"")).is_ok());
+    assert!(check(&config("width = 8\nheight = 8\nmode = \"turmite\"\ntapes = 2")).is_err());
+  }
+
+  #[test]
+  fn presets_set_the_machine_keys() {
+    let ant = apply_preset(config("width = 8\nheight = 8\ntapes = 2\npreset = \"langtons-ant\""));
+    assert!(check(&ant).is_ok());
+    assert_eq!(ant.lookup("turing.mode").and_then(|v| v.as_str()), Some("turmite"));
+    assert_eq!(ant.lookup("turing.symbols").and_then(|v| v.as_integer()), Some(2));
+    assert_eq!(ant.lookup("turing.tapes").and_then(|v| v.as_integer()), Some(1));
+    assert!(check(&apply_preset(config("width = 8\nheight = 8\npreset = \"nope\""))).is_err());
+  }
+
+  #[test]
+  fn montages_need_plain_machines() {
+    let grid = "width = 8\nheight = 8\nmontage_columns = 2\nmontage_rows = 2\n";
+    assert!(check(&config(grid)).is_ok());
+    assert!(check(&config(format!("{}tapes = 2", grid).as_slice())).is_err());
+    assert!(check(&config(format!("{}interleave = \"stripes\"", grid).as_slice())).is_err());
+  }
+
+  #[test]
+  fn only_turing_mode_halts() {
+    assert!(check(&config("width = 8\nheight = 8\nhalting = true")).is_ok());
+    assert!(check(&config("width = 8\nheight = 8\nhalting = true\ntapes = 2")).is_err());
+    assert!(check(&config("width = 8\nheight = 8\nhalting = true\nmode = \"turmite\"")).is_err());
+  }
+
+  #[test]
+  fn only_turing_mode_has_more_heads() {
+    assert!(check(&config("width = 8\nheight = 8\nheads = 3")).is_ok());
+    assert!(check(&config("width = 8\nheight = 8\nheads = 3\ntapes = 2")).is_err());
+    assert!(check(&config("width = 8\nheight = 8\nheads = 3\nmode = \"turmite\"")).is_err());
+  }
+
+  #[test]
+  fn hex_tapes_need_plain_machines() {
+    assert!(check(&config("width = 8\nheight = 8\ntopology = \"hex\"")).is_ok());
+    assert!(check(&config("width = 8\nheight = 7\ntopology = \"hex\"")).is_err());
+    assert!(check(&config("width = 8\nheight = 8\ntopology = \"hex\"\nmode = \"turmite\"")).is_err());
+    assert!(check(&config("width = 8\nheight = 8\ntopology = \"hex\"\ndirections = \"eightway\"")).is_err());
+  }
+
+  #[test]
+  fn sparse_tapes_need_plain_unsaved_machines() {
+    let sparse = "width = 8\nheight = 8\ntape = \"sparse\"\n";
+    assert!(check(&config(sparse)).is_ok());
+    assert!(check(&config(format!("{}world_size = 100\nsparse_view = \"fit\"", sparse).as_slice())).is_ok());
+    assert!(check(&config(format!("{}world_size = 4", sparse).as_slice())).is_err());
+    assert!(check(&config(format!("{}world_size = 65536", sparse).as_slice())).is_err());
+    assert!(check(&config(format!("{}render = \"heatmap\"", sparse).as_slice())).is_err());
+    assert!(check(&config(format!("{}tapes = 2", sparse).as_slice())).is_err());
+    assert!(check(&config(format!("{}replay = true", sparse).as_slice())).is_err());
+  }
+
+  #[test]
+  fn spacetime_machines_need_plain_dense_tapes() {
+    let spacetime = "width = 8\nheight = 8\nmode = \"spacetime\"\n";
+    assert!(check(&config(format!("{}halting = true\nheads = 2", spacetime).as_slice())).is_ok());
+    assert!(check(&config(format!("{}tapes = 2", spacetime).as_slice())).is_err());
+    assert!(check(&config(format!("{}tape = \"sparse\"", spacetime).as_slice())).is_err());
+    assert!(check(&config(format!("{}directions = \"eightway\"", spacetime).as_slice())).is_err());
+    assert!(check(&config(format!("{}montage_columns = 2", spacetime).as_slice())).is_err());
+  }
+
+  #[test]
+  fn elementary_automata_have_no_head() {
+    let eca = "width = 8\nheight = 8\nmode = \"eca\"\n";
+    assert!(check(&config(format!("{}eca_rule = 110", eca).as_slice())).is_ok());
+    assert!(check(&config(format!("{}eca_rule = 256", eca).as_slice())).is_err());
+    assert!(check(&config(format!("{}heads = 2", eca).as_slice())).is_err());
+    assert!(check(&config(format!("{}boundary = \"reflect\"", eca).as_slice())).is_err());
+    assert!(check(&config(format!("{}tapes = 2", eca).as_slice())).is_err());
+  }
+
+  #[test]
+  fn hybrid_machines_need_a_rule_and_a_dense_tape() {
+    let hybrid = "width = 8\nheight = 8\nmode = \"hybrid\"\n";
+    let highlife = format!("{}ca_rule = \"B36/S23\"\nca_steps = 10\nheads = 2", hybrid);
+    assert!(check(&config(highlife.as_slice())).is_ok());
+    assert!(check(&config(format!("{}ca_rule = \"life\"", hybrid).as_slice())).is_err());
+    assert!(check(&config(format!("{}ca_steps = 0", hybrid).as_slice())).is_err());
+    assert!(check(&config(format!("{}tape = \"sparse\"", hybrid).as_slice())).is_err());
+    assert!(check(&config(format!("{}topology = \"hex\"", hybrid).as_slice())).is_err());
+  }
+
+  #[test]
+  fn color_adjustments_are_numbers_in_range() {
+    assert!(check(&config("width = 8\nheight = 8\n[render]\ngamma = 2.2\nbrightness = -0.1\ncontrast = 1")).is_ok());
+    assert!(check(&config("width = 8\nheight = 8\n[render]\ngamma = 0.0")).is_err());
+    assert!(check(&config("width = 8\nheight = 8\n[render]\nbrightness = 2")).is_err());
+    assert!(check(&config("width = 8\nheight = 8\n[render]\ncontrast = \"high\"")).is_err());
+    assert!(check(&config("width = 8\nheight = 8\n[render]\nblend = 0.8")).is_ok());
+    assert!(check(&config("width = 8\nheight = 8\n[render]\nblend = 1")).is_err());
+  }
+
+  #[test]
+  fn viewports_need_both_sizes() {
+    assert!(check(&config("width = 8\nheight = 8\nviewport_width = 4\nviewport_height = 4\nzoom = -2")).is_ok());
+    assert!(check(&config("width = 8\nheight = 8\nviewport_width = 4")).is_err());
+    assert!(check(&config("width = 8\nheight = 8\nviewport_width = 4\nviewport_height = 4\nzoom = 9")).is_err());
+  }
+
+  #[test]
+  fn following_needs_a_viewport() {
+    assert!(check(&config("width = 8\nheight = 8\nviewport_width = 4\nviewport_height = 4\nfollow_head = true"))
+            .is_ok());
+    assert!(check(&config("width = 8\nheight = 8\nfollow_head = true")).is_err());
+  }
+
+  #[test]
+  fn heatmaps_need_plain_machines() {
+    assert!(check(&config("width = 8\nheight = 8\nrender = \"heatmap\"")).is_ok());
+    assert!(check(&config("width = 8\nheight = 8\nrender = \"heatmap\"\ntapes = 2")).is_err());
+    assert!(check(&config("width = 8\nheight = 8\nrender = \"heatmap\"\nmontage_columns = 2")).is_err());
+    assert!(check(&config("width = 8\nheight = 8\nrender = \"heatmap\"\nmode = \"turmite\"")).is_err());
+  }
+
+  fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+  }
+
+  #[test]
+  fn flags_override_keys() {
+    let overridden = apply_overrides(config("width = 8\nheight = 8"),
+                                     args(&["--width", "320", "--reset-steps", "1_000", "--chain", "true",
+                                           "--rng", "chacha", "--trace", "5"]).as_slice()).unwrap();
+    let lookup = |name: &str| overridden.lookup(name).unwrap().clone();
+    assert_eq!(lookup("turing.width"), toml::Integer(320));
+    assert_eq!(lookup("turing.height"), toml::Integer(8));
+    assert_eq!(lookup("turing.reset_steps"), toml::Integer(1000));
+    assert_eq!(lookup("turing.chain"), toml::Boolean(true));
+    assert_eq!(lookup("turing.rng"), toml::String("chacha".to_string()));
+    // Flags that aren't keys are left alone.
+    assert!(overridden.lookup("turing.trace").is_none());
+    assert!(check(&overridden).is_ok());
+  }
+
+  #[test]
+  fn bad_override_values_are_errors() {
+    assert!(apply_overrides(config(""), args(&["--width", "wide"]).as_slice()).is_err());
+    assert!(apply_overrides(config(""), args(&["--chain", "yes"]).as_slice()).is_err());
+    assert!(apply_overrides(config(""), args(&["--states"]).as_slice()).is_err());
+  }
+
+  #[test]
+  fn overrides_can_replace_a_missing_section() {
+    let empty: toml::Value = from_str("").unwrap();
+    let overridden = apply_overrides(empty, args(&["--states", "2", "--symbols", "2", "--width", "4", "--height", "4",
+                                                  "--picture-steps", "1", "--reset-steps", "1"]).as_slice());
+    assert!(check(&overridden.unwrap()).is_ok());
+  }
+}
@@ -0,0 +1,361 @@
+use std::io::{BufferedReader, IoError, IoResult, LineBufferedWriter, Listener, Acceptor, TcpListener, TcpStream};
+use std::io::stdio::{stdin, stdout, StdWriter};
+use serialize::json;
+use serialize::json::Json;
+
+use turing::error::Error;
+use grpc;
+use grpc::FrameStreams;
+use output::ImageSink;
+
+
+/// A request from a controlling process.
+#[deriving(Show)]
+pub enum Command {
+  /// Stop stepping until resumed.
+  Pause,
+  Resume,
+  /// Replace the machine with a new random one.
+  Reset,
+  SetPictureSteps(u64),
+  SetResetSteps(u64),
+  /// Write the current frame as a PPM image to the given path.
+  Snapshot(Path),
+  /// Save the transition table to the given path, for 'turing.table_file'.
+  SaveTable(Path),
+  /// Answer with the transition table instead of the status.
+  GetTable,
+  /// Just answer with the status.
+  Status,
+}
+
+
+/// Where the answer to a request goes.
+pub enum ReplyTo {
+  /// A JSON-RPC response with this id on stdout.
+  Stdio(Json),
+  /// The task serving an HTTP or gRPC client, which sends the result or
+  /// why the request was turned down.
+  Task(Sender<Result<String, Refusal>>),
+}
+
+
+/// Why the main loop turned a request down.
+#[deriving(Show)]
+pub enum Refusal {
+  /// The request asked for something invalid, such as step counts that
+  /// would never show a frame.
+  Invalid(String),
+  /// Carrying the request out failed, e.g. a snapshot couldn't be written.
+  Failed(String),
+}
+
+
+/// The answer to 'Status' and most other commands.
+#[deriving(Encodable)]
+pub struct StatusReply {
+  pub paused: bool,
+  pub step: u64,
+  pub picture_steps: u64,
+  pub reset_steps: u64,
+}
+
+
+/// The answer to 'GetTable': the table as 'Machine::table_json' encodes it.
+#[deriving(Encodable)]
+pub struct TableReply {
+  pub symbols: u8,
+  pub table: Json,
+}
+
+
+/// A command along with where to answer it.
+pub struct Request {
+  pub reply_to: ReplyTo,
+  pub command: Command,
+}
+
+
+/// Control by another process: JSON-RPC 2.0 over stdin/stdout, a small
+/// HTTP API and/or gRPC (see 'grpc'). Requests are read on background tasks
+/// and handled by the main loop between frames. JSON-RPC requests are read
+/// one per line from stdin and answered one per line on stdout. See the
+/// README for the schemas.
+pub struct Control {
+  receiver: Receiver<Request>,
+  out: LineBufferedWriter<StdWriter>,
+  // The frames of gRPC 'Frames' calls.
+  frames: Option<Box<FrameStreams>>,
+}
+
+impl Control {
+  /// Starts reading JSON-RPC requests from stdin with 'stdio', and serving
+  /// HTTP and gRPC requests on 'http_port' and 'grpc_port' of the interface
+  /// with address 'host' unless they are None.
+  pub fn start(stdio: bool, host: &str, http_port: Option<u16>, grpc_port: Option<u16>)
+      -> Result<Control, Error> {
+    let listen_error = |port: u16, why: IoError| {
+      Error::io(format!("Unable to listen on {} port {}", host, port), why)
+    };
+    let (sender, receiver) = channel();
+    match http_port {
+      Some(port) => try!(serve_http(host, port, sender.clone()).map_err(|why| listen_error(port, why))),
+      None => {},
+    }
+    let frames = match grpc_port {
+      Some(port) => Some(try!(grpc::serve(host, port, sender.clone()).map_err(|why| listen_error(port, why)))),
+      None => None,
+    };
+    if stdio {
+      read_stdio(sender);
+    }
+    Ok(Control { receiver: receiver, out: stdout(), frames: frames })
+  }
+
+  /// A sink for the frames of gRPC 'Frames' calls, to be written every
+  /// frame, if gRPC is served. Each call gives a sink for the same clients.
+  pub fn frame_sink(&self) -> Option<Box<ImageSink + Send>> {
+    self.frames.as_ref().map(|frames| frames.sink())
+  }
+
+  /// The next pending request, if any.
+  pub fn try_recv(&self) -> Option<Request> {
+    self.receiver.try_recv().ok()
+  }
+
+  /// Waits for the next request. Returns None once nothing could send one,
+  /// e.g. stdin is closed and there is no HTTP API.
+  pub fn recv(&self) -> Option<Request> {
+    self.receiver.recv_opt().ok()
+  }
+
+  /// Answers 'request' with a successful 'result' (a JSON value).
+  pub fn reply(&mut self, request: &Request, result: String) {
+    match request.reply_to {
+      Stdio(ref id) => { let _ = respond(&mut self.out, id, Ok(result)); },
+      Task(ref sender) => { let _ = sender.send_opt(Ok(result)); },
+    }
+  }
+
+  /// Answers 'request' with an error: carrying it out failed.
+  pub fn reply_error(&mut self, request: &Request, message: String) {
+    match request.reply_to {
+      Stdio(ref id) => { let _ = respond(&mut self.out, id, Err((-32000, message))); },
+      Task(ref sender) => { let _ = sender.send_opt(Err(Failed(message))); },
+    }
+  }
+
+  /// Answers 'request' with an error: it asked for something invalid.
+  pub fn reply_invalid(&mut self, request: &Request, message: String) {
+    match request.reply_to {
+      Stdio(ref id) => { let _ = respond(&mut self.out, id, Err((-32602, format!("Invalid params: {}", message)))); },
+      Task(ref sender) => { let _ = sender.send_opt(Err(Invalid(message))); },
+    }
+  }
+}
+
+
+// Reads JSON-RPC requests from stdin on a background task.
+fn read_stdio(sender: Sender<Request>) {
+  spawn(proc() {
+    let mut out = stdout();
+    for line in BufferedReader::new(stdin()).lines() {
+      let line = match line {
+        Ok(line) => line,
+        Err(_) => break,
+      };
+      if line.as_slice().trim().is_empty() {
+        continue;
+      }
+      match parse_request(line.as_slice()) {
+        Ok(request) => sender.send(request),
+        Err((id, code, message)) => {
+          let _ = respond(&mut out, &id, Err((code, message)));
+        },
+      }
+    }
+  });
+}
+
+
+// Parses one JSON-RPC request. Errors carry the id (if known), the JSON-RPC
+// error code and a message.
+fn parse_request(line: &str) -> Result<Request, (Json, i64, String)> {
+  let message = match json::from_str(line) {
+    Ok(message) => message,
+    Err(why) => return Err((json::Null, -32700, format!("Parse error: {}", why))),
+  };
+  let id = match message.find(&"id".to_string()) {
+    Some(id) => id.clone(),
+    None => json::Null,
+  };
+  let method = match message.find(&"method".to_string()).and_then(|m| m.as_string()) {
+    Some(method) => method.to_string(),
+    None => return Err((id, -32600, "Invalid request: missing method".to_string())),
+  };
+  let param = |name: &str| {
+    message.find(&"params".to_string()).and_then(|params| params.find(&name.to_string()))
+  };
+  let command = match method.as_slice() {
+    "pause" => Pause,
+    "resume" => Resume,
+    "reset" => Reset,
+    "status" => Status,
+    "table" => GetTable,
+    "set" => {
+      match (param("picture_steps").and_then(|v| v.as_u64()),
+             param("reset_steps").and_then(|v| v.as_u64())) {
+        (Some(steps), _) if steps > 0 => SetPictureSteps(steps),
+        (_, Some(steps)) if steps > 0 => SetResetSteps(steps),
+        _ => return Err((id, -32602, "Invalid params: expected picture_steps or reset_steps".to_string())),
+      }
+    },
+    "snapshot" => {
+      match param("path").and_then(|v| v.as_string()) {
+        Some(path) => Snapshot(Path::new(path)),
+        None => return Err((id, -32602, "Invalid params: expected path".to_string())),
+      }
+    },
+    "save_table" => {
+      match param("path").and_then(|v| v.as_string()) {
+        Some(path) => SaveTable(Path::new(path)),
+        None => return Err((id, -32602, "Invalid params: expected path".to_string())),
+      }
+    },
+    other => return Err((id, -32601, format!("Method not found: {}", other))),
+  };
+  Ok(Request { reply_to: Stdio(id), command: command })
+}
+
+
+// Serves the HTTP API on 'port' of 'host' in the background. Each client
+// gets a task that turns its request into a command, waits for the main
+// loop's answer and writes it as a JSON response.
+fn serve_http(host: &str, port: u16, sender: Sender<Request>) -> IoResult<()> {
+  let listener = try!(TcpListener::bind(host, port));
+  let mut acceptor = try!(listener.listen());
+  spawn(proc() {
+    for client in acceptor.incoming() {
+      match client {
+        Ok(client) => {
+          let sender = sender.clone();
+          spawn(proc() {
+            let _ = answer_http(client, sender);
+          });
+        },
+        Err(_) => {},
+      }
+    }
+  });
+  Ok(())
+}
+
+
+fn answer_http(client: TcpStream, sender: Sender<Request>) -> IoResult<()> {
+  let mut reader = BufferedReader::new(client.clone());
+  let request_line = try!(reader.read_line());
+  // Skip the headers; requests have no body.
+  loop {
+    if try!(reader.read_line()).as_slice().trim().is_empty() {
+      break;
+    }
+  }
+  let mut out = client;
+  let words: Vec<&str> = request_line.as_slice().split(' ').collect();
+  let (method, target) = match words.as_slice() {
+    [method, target, ..] => (method, target),
+    _ => return http_respond(&mut out, 400, "Bad Request", Err("Malformed request".to_string())),
+  };
+  let command = match parse_http(method, target) {
+    Ok(command) => command,
+    Err((status, reason, message)) => return http_respond(&mut out, status, reason, Err(message)),
+  };
+  let (reply_sender, reply) = channel();
+  if sender.send_opt(Request { reply_to: Task(reply_sender), command: command }).is_err() {
+    return http_respond(&mut out, 503, "Service Unavailable", Err("Stopping".to_string()));
+  }
+  match reply.recv_opt() {
+    Ok(Ok(result)) => http_respond(&mut out, 200, "OK", Ok(result)),
+    Ok(Err(Invalid(message))) => http_respond(&mut out, 400, "Bad Request", Err(message)),
+    Ok(Err(Failed(message))) => http_respond(&mut out, 500, "Internal Server Error", Err(message)),
+    Err(()) => http_respond(&mut out, 503, "Service Unavailable", Err("Stopping".to_string())),
+  }
+}
+
+
+// The command for an HTTP request. Reading is done with GET, changes with
+// POST. Errors carry the HTTP status and a message.
+fn parse_http(method: &str, target: &str) -> Result<Command, (uint, &'static str, String)> {
+  let (path, query) = match target.find('?') {
+    Some(index) => (target.slice_to(index), target.slice_from(index + 1)),
+    None => (target, ""),
+  };
+  let param = |name: &str| {
+    query.split('&').filter_map(|pair| {
+      let mut parts = pair.splitn(1, '=');
+      match (parts.next(), parts.next()) {
+        (Some(key), Some(value)) if key == name => from_str::<u64>(value),
+        _ => None,
+      }
+    }).next()
+  };
+  let (command, post) = match path {
+    "/" | "/status" => (Status, false),
+    "/table" => (GetTable, false),
+    "/pause" => (Pause, true),
+    "/resume" => (Resume, true),
+    "/reset" => (Reset, true),
+    "/set" => match (param("picture_steps"), param("reset_steps")) {
+      (Some(steps), _) if steps > 0 => (SetPictureSteps(steps), true),
+      (_, Some(steps)) if steps > 0 => (SetResetSteps(steps), true),
+      _ => return Err((400, "Bad Request", "Expected picture_steps or reset_steps".to_string())),
+    },
+    other => return Err((404, "Not Found", format!("No such endpoint: {}", other))),
+  };
+  match (method, post) {
+    ("GET", false) | ("POST", true) => Ok(command),
+    _ => Err((405, "Method Not Allowed", format!("{} {} is not allowed", method, path))),
+  }
+}
+
+
+fn http_respond(out: &mut Writer, status: uint, reason: &str, result: Result<String, String>) -> IoResult<()> {
+  let body = match result {
+    Ok(result) => result,
+    Err(message) => format!("{{\"error\":{}}}", json::String(message).to_string()),
+  };
+  try!(write!(out, "HTTP/1.1 {} {}\r\n\
+                    Content-Type: application/json\r\n\
+                    Content-Length: {}\r\n\
+                    Connection: close\r\n\r\n", status, reason, body.len()));
+  try!(out.write_str(body.as_slice()));
+  out.flush()
+}
+
+
+fn respond(out: &mut Writer, id: &Json, result: Result<String, (i64, String)>) -> IoResult<()> {
+  let body = match result {
+    Ok(result) => format!("\"result\":{}", result),
+    Err((code, message)) => format!("\"error\":{{\"code\":{},\"message\":{}}}",
+                                    code, json::String(message).to_string()),
+  };
+  try!(writeln!(out, "{{\"jsonrpc\":\"2.0\",\"id\":{},{}}}", id.to_string(), body));
+  out.flush()
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::{parse_http, Pause, Status, GetTable, SetPictureSteps};
+
+  #[test]
+  fn http_requests_map_to_commands() {
+    assert!(match parse_http("GET", "/") { Ok(Status) => true, _ => false });
+    assert!(match parse_http("GET", "/table") { Ok(GetTable) => true, _ => false });
+    assert!(match parse_http("POST", "/pause") { Ok(Pause) => true, _ => false });
+    assert!(match parse_http("POST", "/set?picture_steps=500") { Ok(SetPictureSteps(500)) => true, _ => false });
+    assert!(match parse_http("GET", "/pause") { Err((405, _, _)) => true, _ => false });
+    assert!(match parse_http("POST", "/set?picture_steps=0") { Err((400, _, _)) => true, _ => false });
+    assert!(match parse_http("GET", "/stop") { Err((404, _, _)) => true, _ => false });
+  }
+}
@@ -0,0 +1,95 @@
+/// Detects machines caught in a short loop, repeating the same few steps
+/// while the picture stays frozen, from the 'Machine::local_hash' of every
+/// step. A cycle of at most 'max_period' steps that comes round 'repeats'
+/// times in a row is reported.
+///
+/// The search is Brent's: each step is compared with one remembered step,
+/// which moves on after 1, 2, 4, ... steps, so a step costs one comparison
+/// whatever the period.
+pub struct CycleDetector {
+  max_period: u64,
+  repeats: u64,
+  // The remembered step's hash and the steps since it.
+  remembered: Option<u64>,
+  since: u64,
+  // Steps before the remembered step moves on.
+  power: u64,
+  // The period of the last repeat and how many repeats in a row had it.
+  period: u64,
+  matches: u64,
+}
+
+impl CycleDetector {
+  pub fn new(max_period: u64, repeats: u64) -> CycleDetector {
+    CycleDetector { max_period: max_period, repeats: repeats, remembered: None, since: 0, power: 1,
+                    period: 0, matches: 0 }
+  }
+
+  /// Accounts for a step that ended with local hash 'hash'. Returns true
+  /// once the machine is cycling.
+  pub fn step(&mut self, hash: u64) -> bool {
+    self.since += 1;
+    if self.remembered == Some(hash) {
+      if self.since == self.period {
+        self.matches += 1;
+      } else {
+        self.period = self.since;
+        self.matches = 1;
+      }
+      self.since = 0;
+      return self.matches >= self.repeats;
+    }
+    if self.since >= self.power {
+      // Remember this step instead, waiting twice as long for it to come
+      // round, or starting over once periods get too long to matter.
+      self.remembered = Some(hash);
+      self.since = 0;
+      self.matches = 0;
+      self.power = if self.power >= self.max_period { 1 } else { self.power * 2 };
+    }
+    false
+  }
+
+  /// Starts over for a new machine.
+  pub fn reset(&mut self) {
+    *self = CycleDetector::new(self.max_period, self.repeats);
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::CycleDetector;
+
+  // Feeds 'hashes' and returns the step (counting from 1) a cycle was
+  // reported at, if any.
+  fn detect(detector: &mut CycleDetector, hashes: &[u64]) -> Option<uint> {
+    hashes.iter().position(|&hash| detector.step(hash)).map(|i| i + 1)
+  }
+
+  #[test]
+  fn finds_a_cycle_after_its_repeats() {
+    let mut detector = CycleDetector::new(16, 3);
+    let hashes: Vec<u64> = range(0u64, 100).map(|i| i % 5).collect();
+    // Not before three periods, and soon after the remembered step waits
+    // long enough.
+    let found = detect(&mut detector, hashes.as_slice()).unwrap();
+    assert!(found >= 3 * 5 && found <= 8 + 4 * 5, "{}", found);
+  }
+
+  #[test]
+  fn ignores_long_cycles_and_no_cycles() {
+    let mut detector = CycleDetector::new(4, 2);
+    let long: Vec<u64> = range(0u64, 1000).map(|i| i % 9).collect();
+    assert_eq!(detect(&mut detector, long.as_slice()), None);
+    detector.reset();
+    let fresh: Vec<u64> = range(0u64, 1000).collect();
+    assert_eq!(detect(&mut detector, fresh.as_slice()), None);
+  }
+
+  #[test]
+  fn a_machine_standing_still_cycles_at_once() {
+    let mut detector = CycleDetector::new(8, 4);
+    assert_eq!(detect(&mut detector, [7u64, ..10].as_slice()), Some(5));
+  }
+}
@@ -0,0 +1,264 @@
+use std::hash::hash;
+use std::rand::Rng;
+use serialize::json;
+use serialize::json::Json;
+use toml;
+
+use super::{Machine, Color, BLACK, paint_cell, distinct_symbols};
+use error::{MachineError, BadTable, BadState};
+use palette;
+use rng::SessionRng;
+use session;
+use spacetime::Scroll;
+use trace;
+
+
+/// A Wolfram elementary cellular automaton: a row of two symbol cells, each
+/// of which becomes at every step bit 4·left + 2·cell + right of the rule,
+/// its neighbors wrapping around the ends. It is drawn like a spacetime
+/// machine, the current row at the bottom and one row per step above it.
+pub struct EcaMachine {
+  width: uint,
+  rule: u8,
+  // The rule 'chain' keeps, if not a random one.
+  fixed_rule: Option<u8>,
+  cells: Vec<u8>,
+
+  cell_width: uint,
+  cell_height: uint,
+  palette: Vec<Color>,
+  // The image of 'cells', kept up to date by 'step'.
+  row: Vec<u8>,
+  scroll: Scroll,
+}
+
+impl EcaMachine {
+  /// Creates an automaton with a random rule, 'width' cells wide and showing
+  /// the last 'rows' generations. It starts from a single 1 in the middle.
+  pub fn new(width: uint, rows: uint, rng: &mut SessionRng) -> Box<EcaMachine> {
+    let row = Vec::from_elem(width * 3, 0u8);
+    let scroll = Scroll::new(row.as_slice(), rows);
+    let mut machine = box EcaMachine {
+      width: width,
+      rule: 0,
+      fixed_rule: None,
+      cells: EcaMachine::start(width),
+      cell_width: 1,
+      cell_height: 1,
+      palette: vec!(BLACK, BLACK),
+      row: row,
+      scroll: scroll,
+    };
+    machine.chain(rng);
+    machine
+  }
+
+  // A single 1 in the middle, where the classic diagrams start.
+  fn start(width: uint) -> Vec<u8> {
+    let mut cells = Vec::from_elem(width, 0u8);
+    *cells.get_mut(width / 2) = 1;
+    cells
+  }
+
+  /// The rule, 0 to 255.
+  pub fn rule(&self) -> u8 {
+    self.rule
+  }
+
+  /// Runs 'rule' from now on, for this and every chained automaton.
+  pub fn set_rule(&mut self, rule: u8) {
+    self.rule = rule;
+    self.fixed_rule = Some(rule);
+  }
+
+  /// Sets the pixel size of each cell in the output image.
+  pub fn set_cell_size(&mut self, cell_width: uint, cell_height: uint) {
+    self.cell_width = cell_width;
+    self.cell_height = cell_height;
+    self.row = Vec::from_elem(self.width * cell_width * cell_height * 3, 0u8);
+    self.paint_row();
+    self.scroll = Scroll::new(self.row.as_slice(), self.scroll.rows());
+  }
+
+  // The cells of the next generation.
+  fn next_generation(&self) -> Vec<u8> {
+    let width = self.width;
+    Vec::from_fn(width, |i| {
+      let left = *self.cells.get((i + width - 1) % width);
+      let right = *self.cells.get((i + 1) % width);
+      let neighborhood = left << 2 | *self.cells.get(i) << 1 | right;
+      (self.rule >> neighborhood as uint) & 1
+    })
+  }
+
+  fn paint_row(&mut self) {
+    let row_bytes = self.width * self.cell_width * 3;
+    for (x, &symbol) in self.cells.iter().enumerate() {
+      paint_cell(&mut self.row, row_bytes, x, 0, self.cell_width, self.cell_height,
+                 *self.palette.get(symbol as uint));
+    }
+  }
+
+  // Draws the current generation, and every row before it as the same.
+  fn restart_picture(&mut self) {
+    self.paint_row();
+    self.scroll.fill(self.row.as_slice());
+  }
+}
+
+impl Machine for EcaMachine {
+  fn step(&mut self) -> bool {
+    let next = self.next_generation();
+    let changed = next != self.cells;
+    self.cells = next;
+    self.paint_row();
+    self.scroll.push(self.row.as_slice());
+    changed
+  }
+
+  // The rows before can't be redrawn in the new colors, so the diagram
+  // starts again from the current row.
+  fn set_palette(&mut self, palette: &Vec<Color>) {
+    self.palette = palette::complete(palette, 2);
+    self.restart_picture();
+  }
+
+  fn image<'a>(&'a self) -> &'a [u8] {
+    self.scroll.image()
+  }
+
+  fn image_size(&self) -> (uint, uint) {
+    (self.width * self.cell_width, self.scroll.rows() * self.cell_height)
+  }
+
+  fn tape_size(&self) -> (uint, uint) {
+    (self.width, 1)
+  }
+
+  fn symbols(&self) -> u8 {
+    2
+  }
+
+  fn fill_tape(&mut self, symbol: |uint| -> u8) {
+    for (i, cell) in self.cells.iter_mut().enumerate() {
+      *cell = symbol(i) % 2;
+    }
+    self.restart_picture();
+  }
+
+  fn reset(&mut self, rng: &mut SessionRng) {
+    self.chain(rng);
+    self.cells = EcaMachine::start(self.width);
+    self.restart_picture();
+  }
+
+  fn chain(&mut self, rng: &mut SessionRng) {
+    self.rule = match self.fixed_rule {
+      Some(rule) => rule,
+      None => rng.gen::<u8>(),
+    };
+  }
+
+  // The table of an automaton is its rule number.
+  fn table_text(&self) -> String {
+    format!("{}", self.rule)
+  }
+
+  fn table_json(&self) -> Json {
+    json::U64(self.rule as u64)
+  }
+
+  fn save_state(&self) -> String {
+    format!("rule = {}\ntape = \"{}\"\n", self.rule, session::encode_tape(self.cells.as_slice()))
+  }
+
+  fn load_state(&mut self, saved: &toml::Value) -> Result<(), MachineError> {
+    let rule = try!(session::int(saved, "rule"));
+    if rule < 0 || rule > 255 {
+      return Err(BadState(format!("Saved rule out of range: {}", rule)));
+    }
+    let cells = match saved.lookup("tape").and_then(|tape| tape.as_str()) {
+      Some(tape) => try!(session::decode_tape(tape, self.width, 2)),
+      None => return Err(BadState("Missing tape".to_string())),
+    };
+    self.rule = rule as u8;
+    self.cells = cells;
+    self.restart_picture();
+    Ok(())
+  }
+
+  fn load_table(&mut self, table: &toml::Value) -> Result<(), MachineError> {
+    match table.as_integer() {
+      Some(rule) if rule >= 0 && rule <= 255 => {
+        self.rule = rule as u8;
+        Ok(())
+      },
+      _ => Err(BadTable(format!("Expected a rule from 0 to 255, found {}", table))),
+    }
+  }
+
+  fn explain_step(&self) -> String {
+    let next = self.next_generation();
+    format!("rule {}: {} → {}", self.rule, trace::grid_text(self.cells.as_slice(), self.width, &[]).trim(),
+            trace::grid_text(next.as_slice(), self.width, &[]).trim())
+  }
+
+  fn tape_text(&self) -> String {
+    trace::grid_text(self.cells.as_slice(), self.width, &[])
+  }
+
+  fn symbols_used(&self) -> uint {
+    distinct_symbols(&[self.cells.as_slice()])
+  }
+
+  // The whole row decides what comes next, so a repeated row is a cycle.
+  fn local_hash(&self) -> Option<u64> {
+    Some(hash(&(self.rule, &self.cells)))
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use toml;
+
+  use super::super::{Machine, BLACK, WHITE};
+  use rng::SessionRng;
+
+  use super::EcaMachine;
+
+  fn rows(machine: &EcaMachine) -> Vec<Vec<u8>> {
+    let (width, _) = machine.image_size();
+    machine.image().chunks(width * 3).map(|row| row.chunks(3).map(|pixel| pixel[0] / 255).collect()).collect()
+  }
+
+  #[test]
+  fn rule_90_draws_a_sierpinski_triangle() {
+    let mut machine = EcaMachine::new(7, 4, &mut SessionRng::new(0));
+    machine.set_rule(90);
+    machine.set_palette(&vec!(BLACK, WHITE));
+    for _ in range(0u, 3) {
+      machine.step();
+    }
+    assert_eq!(rows(&*machine), vec!(vec!(0, 0, 0, 1, 0, 0, 0),
+                                     vec!(0, 0, 1, 0, 1, 0, 0),
+                                     vec!(0, 1, 0, 0, 0, 1, 0),
+                                     vec!(1, 0, 1, 0, 1, 0, 1)));
+    assert_eq!(machine.table_text(), "90".to_string());
+  }
+
+  #[test]
+  fn fixed_rules_survive_chaining_and_saving() {
+    let mut rng = SessionRng::new(0);
+    let mut machine = EcaMachine::new(8, 2, &mut rng);
+    machine.set_rule(110);
+    machine.reset(&mut rng);
+    assert_eq!(machine.rule(), 110);
+    machine.step();
+    let saved = machine.save_state();
+    let mut loaded = EcaMachine::new(8, 2, &mut rng);
+    loaded.load_state(&from_str(saved.as_slice()).unwrap()).unwrap();
+    assert_eq!((loaded.rule(), loaded.tape_text()), (110, machine.tape_text()));
+    assert!(loaded.load_table(&toml::Integer(256)).is_err());
+  }
+}
@@ -0,0 +1,184 @@
+use std::io::{IoResult, Command, InheritFd};
+use std::io::stdio::{stdin_raw, stdout};
+
+use turing::{TuringMachine, Machine, Color};
+use turing::rng::SessionRng;
+use output::ImageSink;
+use preview::{TerminalPreview, terminal_size, too_small};
+
+
+// Steps run between preview refreshes.
+static STEPS_PER_REFRESH: uint = 20000;
+
+
+/// A keyboard command in the editor.
+enum Key {
+  Up,
+  Down,
+  Left,
+  Right,
+  Char(u8),
+}
+
+
+/// Interactive table editor. Shows the transition table as a grid (rows are
+/// states, columns are symbols) above a live preview of the machine, and lets
+/// the selected entry be changed with the keyboard:
+///
+/// - arrows or h/j/k/l: select an entry
+/// - n/N: next state up/down, w/W: written symbol up/down, d: next of the machine's directions
+/// - f: next position feature block
+/// - r: random table, c: clear the tape, x: save to 'save_path', q: quit
+pub fn run(machine: &mut TuringMachine, palette: &Vec<Color>, rng: &mut SessionRng,
+           save_path: &Path) -> IoResult<()> {
+  machine.set_palette(palette);
+  try!(set_raw_mode(true));
+  let result = edit(machine, rng, save_path);
+  try!(set_raw_mode(false));
+  result
+}
+
+
+fn edit(machine: &mut TuringMachine, rng: &mut SessionRng, save_path: &Path) -> IoResult<()> {
+  // The table takes one line per state plus a header and a status line.
+  let table_rows = machine.states() as uint + 2;
+  let (columns, rows) = terminal_size();
+  let preview_rows = match rows.checked_sub(&(table_rows + 2)) {
+    Some(preview_rows) if preview_rows > 0 && columns > 0 => preview_rows,
+    _ => return Err(too_small()),
+  };
+  let keys = read_keys();
+  let mut out = stdout();
+  try!(out.write_str("\x1b[2J\x1b[?25l"));
+  let mut preview = TerminalPreview::with_area(box stdout() as Box<Writer>, table_rows + 2, columns, preview_rows);
+  let (mut state, mut symbol, mut feature) = (0u, 0u, 0u);
+  let mut status = String::from_str("arrows: select  n/N w/W d: edit  f: feature  r: random  c: clear  x: save  q: quit");
+
+  loop {
+    loop {
+      let key = match keys.try_recv() {
+        Ok(key) => key,
+        Err(_) => break,
+      };
+      let states = machine.states() as uint;
+      let symbols = machine.symbols() as uint;
+      let (next_state, write_symbol, direction) = machine.table().get(feature, state as u8, symbol as u8);
+      // The new value of the selected entry, if the key edits it.
+      let edited = match key {
+        Char(b'n') => Some((((next_state as uint + 1) % states) as u8, write_symbol, direction)),
+        Char(b'N') => Some((((next_state as uint + states - 1) % states) as u8, write_symbol, direction)),
+        Char(b'w') => Some((next_state, ((write_symbol as uint + 1) % symbols) as u8, direction)),
+        Char(b'W') => Some((next_state, ((write_symbol as uint + symbols - 1) % symbols) as u8, direction)),
+        Char(b'd') => Some((next_state, write_symbol, machine.directions().after(direction))),
+        _ => None,
+      };
+      match edited {
+        Some(transition) => machine.table_mut().set(feature, state as u8, symbol as u8, transition),
+        None => {},
+      }
+      match key {
+        Up | Char(b'k') => state = (state + states - 1) % states,
+        Down | Char(b'j') => state = (state + 1) % states,
+        Left | Char(b'h') => symbol = (symbol + symbols - 1) % symbols,
+        Right | Char(b'l') => symbol = (symbol + 1) % symbols,
+        Char(b'f') => feature = (feature + 1) % machine.position_feature().count(),
+        Char(b'r') => machine.chain(rng),
+        Char(b'c') => machine.fill_tape(|_| 0),
+        Char(b'x') => status = match save(machine, save_path) {
+          Ok(()) => format!("Saved to {}", save_path.display()),
+          Err(why) => format!("Unable to save: {}", why.desc),
+        },
+        Char(b'q') | Char(3) => return Ok(()),
+        _ => {},
+      }
+    }
+
+    for _ in range(0, STEPS_PER_REFRESH) {
+      machine.step();
+    }
+    try!(draw_table(&mut out, machine, state, symbol, feature, status.as_slice()));
+    let (width, height) = machine.image_size();
+    try!(preview.write_frame(machine.image(), width, height));
+  }
+}
+
+
+// Draws the table with the selected entry highlighted.
+fn draw_table(out: &mut Writer, machine: &TuringMachine, selected_state: uint, selected_symbol: uint,
+              feature: uint, status: &str) -> IoResult<()> {
+  let states = machine.states() as uint;
+  let symbols = machine.symbols() as uint;
+  let mut text = String::from_str("\x1b[1;1H\x1b[2K");
+  text.push_str(format!("feature {}   read symbol →", feature).as_slice());
+  for symbol in range(0, symbols) {
+    text.push_str(format!("{:>10}", symbol).as_slice());
+  }
+  for state in range(0, states) {
+    text.push_str(format!("\x1b[{};1H\x1b[2Kstate {:>3}             ", state + 2, state).as_slice());
+    for symbol in range(0, symbols) {
+      let (next_state, write_symbol, direction) = machine.table().get(feature, state as u8, symbol as u8);
+      let entry = format!("{}/{}/{}", next_state, write_symbol, direction.abbreviation());
+      if state == selected_state && symbol == selected_symbol {
+        text.push_str(format!("  \x1b[7m{:>8}\x1b[0m", entry).as_slice());
+      } else {
+        text.push_str(format!("{:>10}", entry).as_slice());
+      }
+    }
+  }
+  text.push_str(format!("\x1b[{};1H\x1b[2K{}", states + 3, status).as_slice());
+  try!(out.write_str(text.as_slice()));
+  out.flush()
+}
+
+
+// Writes the machine in the session [machine] format, so it can be used in a
+// playlist.
+fn save(machine: &TuringMachine, path: &Path) -> IoResult<()> {
+  let mut file = try!(::std::io::File::create(path));
+  try!(writeln!(file, "[machine]"));
+  try!(file.write_str(machine.save_state().as_slice()));
+  Ok(())
+}
+
+
+// Reads keys from stdin on a background task.
+fn read_keys() -> Receiver<Key> {
+  let (sender, receiver) = channel();
+  spawn(proc() {
+    let mut input = stdin_raw();
+    loop {
+      let key = match input.read_byte() {
+        // Arrow keys arrive as ESC [ A..D.
+        Ok(27) => match (input.read_byte(), input.read_byte()) {
+          (Ok(b'['), Ok(b'A')) => Up,
+          (Ok(b'['), Ok(b'B')) => Down,
+          (Ok(b'['), Ok(b'C')) => Right,
+          (Ok(b'['), Ok(b'D')) => Left,
+          _ => continue,
+        },
+        Ok(byte) => Char(byte),
+        Err(_) => break,
+      };
+      if sender.send_opt(key).is_err() {
+        break;
+      }
+    }
+  });
+  receiver
+}
+
+
+// Switches the terminal in and out of raw mode with stty.
+fn set_raw_mode(raw: bool) -> IoResult<()> {
+  let mut stty = Command::new("stty");
+  if raw {
+    stty.arg("raw").arg("-echo");
+  } else {
+    stty.arg("sane");
+  }
+  try!(stty.stdin(InheritFd(0)).status());
+  if !raw {
+    try!(stdout().write_str("\x1b[0m\x1b[2J\x1b[H\x1b[?25h"));
+  }
+  Ok(())
+}
@@ -0,0 +1,85 @@
+use std::fmt;
+use std::io::IoError;
+
+
+/// The kinds of errors that stop the program. Each has its own exit code
+/// (from sysexits.h) so supervisors and scripts can tell them apart.
+#[deriving(PartialEq,Eq,Show)]
+pub enum ErrorKind {
+  /// Bad command line arguments.
+  Usage,
+  /// The configuration file doesn't exist or can't be read.
+  ConfigMissing,
+  /// The configuration has a missing key or a bad value.
+  InvalidConfig,
+  /// Reading or writing files, pipes or sockets failed.
+  Io,
+}
+
+
+/// An error from 'run', with a message for the user.
+pub struct Error {
+  pub kind: ErrorKind,
+  pub message: String,
+}
+
+impl Error {
+  pub fn new(kind: ErrorKind, message: String) -> Error {
+    Error { kind: kind, message: message }
+  }
+
+  /// An 'Io' error: 'context' followed by the reason.
+  pub fn io(context: String, why: IoError) -> Error {
+    Error::new(Io, format!("{}: {}", context, why.desc))
+  }
+
+  pub fn exit_code(&self) -> int {
+    match self.kind {
+      Usage => 64,
+      ConfigMissing => 66,
+      InvalidConfig => 78,
+      Io => 74,
+    }
+  }
+}
+
+impl fmt::Show for Error {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.message)
+  }
+}
+
+
+/// Shorthand for an 'InvalidConfig' error.
+pub fn invalid(message: String) -> Error {
+  Error::new(InvalidConfig, message)
+}
+
+
+/// Why the library turned down a table or saved machine, so embedders can
+/// tell the kinds apart. Each carries a message for the user.
+#[deriving(PartialEq,Eq,Clone)]
+pub enum MachineError {
+  /// A transition table with invalid entries, or that doesn't fit the
+  /// machine.
+  BadTable(String),
+  /// A saved state whose heads, states or tapes don't fit the machine.
+  BadState(String),
+  /// A session or table file that can't be read or isn't valid TOML.
+  BadFile(String),
+}
+
+impl MachineError {
+  /// The message for the user.
+  pub fn message<'a>(&'a self) -> &'a str {
+    match *self {
+      BadTable(ref message) | BadState(ref message) | BadFile(ref message) => message.as_slice(),
+    }
+  }
+}
+
+impl fmt::Show for MachineError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.message())
+  }
+}
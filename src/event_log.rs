@@ -0,0 +1,49 @@
+use std::hash::hash;
+use std::io::{File, IoResult, Append, Write};
+use time::get_time;
+
+use turing::Machine;
+use turing::rng::SessionRng;
+
+
+/// An append-only JSON-lines history of every machine shown: one line when a
+/// machine is created and one when it is replaced.
+pub struct EventLog {
+  file: File,
+}
+
+impl EventLog {
+  /// Opens 'path' for appending, creating it if needed.
+  pub fn open(path: &Path) -> IoResult<EventLog> {
+    let file = try!(File::open_mode(path, Append, Write));
+    Ok(EventLog { file: file })
+  }
+
+  /// Records a new machine whose table was drawn from 'rng' after 'draws'
+  /// numbers, 'start_step' steps into the run. Returns its fingerprint.
+  pub fn created(&mut self, machine: &Machine, rng: &SessionRng, draws: u64,
+                 start_step: u64) -> IoResult<u64> {
+    let table = machine.table_text();
+    let fingerprint = fingerprint(table.as_slice());
+    try!(writeln!(self.file,
+        "{{\"event\":\"create\",\"time\":{},\"fingerprint\":\"{:016x}\",\"rng\":\"{}\",\"seed\":{},\"draws\":{},\
+         \"start_step\":{},\"table\":{}}}",
+        get_time().sec, fingerprint, rng.algorithm().name(), rng.seed(), draws, start_step, table));
+    try!(self.file.flush());
+    Ok(fingerprint)
+  }
+
+  /// Records the end of the machine with 'fingerprint' after 'lifetime' steps.
+  pub fn destroyed(&mut self, fingerprint: u64, reason: &str, lifetime: u64) -> IoResult<()> {
+    try!(writeln!(self.file,
+        "{{\"event\":\"destroy\",\"time\":{},\"fingerprint\":\"{:016x}\",\"reason\":\"{}\",\"lifetime\":{}}}",
+        get_time().sec, fingerprint, reason, lifetime));
+    self.file.flush()
+  }
+}
+
+
+/// A short identifier for a transition table.
+pub fn fingerprint(table: &str) -> u64 {
+  hash(&table)
+}
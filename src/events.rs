@@ -0,0 +1,89 @@
+use std::comm::RecvDisconnected;
+use std::io::{IoResult, Listener, Acceptor, TcpListener, TcpStream};
+
+
+// Events queued for a client that is still writing earlier ones. A client
+// further behind misses events rather than holding up the others.
+static BACKLOG: uint = 64;
+
+
+/// A Server-Sent Events endpoint. Every HTTP client that connects gets an
+/// 'text/event-stream' response and receives each event sent afterwards as a
+/// 'data:' line, so a web page can subscribe with 'new EventSource(url)'.
+pub struct EventStream {
+  sender: Sender<String>,
+}
+
+impl EventStream {
+  /// Starts serving events on 'host' ('turing.control_host') and 'port' in
+  /// the background.
+  pub fn listen(host: &str, port: u16) -> IoResult<EventStream> {
+    let listener = try!(TcpListener::bind(host, port));
+    let mut acceptor = try!(listener.listen());
+
+    let (client_sender, client_receiver) = channel::<TcpStream>();
+    spawn(proc() {
+      for client in acceptor.incoming() {
+        match client {
+          Ok(client) => {
+            if client_sender.send_opt(client).is_err() {
+              break;
+            }
+          },
+          Err(_) => {},
+        }
+      }
+    });
+
+    // Hands each event to a task per client, like 'MjpegSink' does with
+    // frames, so a slow client only holds up itself.
+    let (sender, receiver) = channel::<String>();
+    spawn(proc() {
+      let mut clients: Vec<SyncSender<String>> = Vec::new();
+      for event in receiver.iter() {
+        loop {
+          match client_receiver.try_recv() {
+            Ok(client) => {
+              let (events, client_events) = sync_channel(BACKLOG);
+              spawn(proc() {
+                let _ = stream_to(client, client_events);
+              });
+              clients.push(events);
+            },
+            Err(_) => break,
+          }
+        }
+        // Drop clients that have gone away; a full queue drops the event.
+        clients.retain(|client| {
+          match client.try_send(event.clone()) {
+            Err(RecvDisconnected(_)) => false,
+            _ => true,
+          }
+        });
+      }
+    });
+
+    Ok(EventStream { sender: sender })
+  }
+
+  /// Sends an event (a JSON object) to all connected clients.
+  pub fn send(&self, event: String) {
+    self.sender.send(event);
+  }
+}
+
+
+// Writes the response headers to 'client', then each event from 'events',
+// until the client goes away.
+fn stream_to(mut client: TcpStream, events: Receiver<String>) -> IoResult<()> {
+  try!(client.write_str("HTTP/1.1 200 OK\r\n\
+                         Content-Type: text/event-stream\r\n\
+                         Cache-Control: no-cache\r\n\
+                         Access-Control-Allow-Origin: *\r\n\r\n"));
+  try!(client.flush());
+  for event in events.iter() {
+    try!(write!(client, "data: {}\n\n", event));
+    try!(client.flush());
+  }
+  Ok(())
+}
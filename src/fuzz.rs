@@ -0,0 +1,203 @@
+use std::io::{File, IoResult};
+use std::rand::Rng;
+use std::str::from_utf8;
+use std::task;
+use toml;
+
+use turing::{Machine, TuringMachine, NoFeature, Cardinal};
+use turing::rng::SessionRng;
+use turing::turmite::TurmiteMachine;
+use turing::two_tape::{TwoTapeMachine, SideBySide};
+use config;
+use replay;
+
+
+// A fuzzer for the parsers of user files, in place of cargo-fuzz targets.
+// It is only built for tests: 'cargo test' runs a short round of every
+// target, and the environment variables of 'parsers_survive_mutated_input'
+// make longer ones.
+
+
+/// A fuzz target: feeds arbitrary bytes to one of the parsers of user files.
+/// Malformed input must come back as an error; a target fails if it panics.
+pub type Target = fn(&[u8]);
+
+/// The targets, by name.
+pub static TARGETS: &'static [&'static str] = &["config", "table", "state"];
+
+pub fn target(name: &str) -> Option<Target> {
+  match name {
+    "config" => Some(config_target as Target),
+    "table" => Some(table_target as Target),
+    "state" => Some(state_target as Target),
+    _ => None,
+  }
+}
+
+
+/// The config loader, without the file: parse and check.
+fn config_target(data: &[u8]) {
+  match from_utf8(data).and_then(|text| from_str::<toml::Value>(text)) {
+    Some(config) => { let _ = config::check(&config); },
+    None => {},
+  }
+}
+
+
+/// Tables as read from snapshots and playlists, for both kinds of machine.
+fn table_target(data: &[u8]) {
+  let text = match from_utf8(data) {
+    Some(text) => text,
+    None => return,
+  };
+  let table: toml::Value = match from_str(format!("table = {}", text).as_slice()) {
+    Some(table) => table,
+    None => return,
+  };
+  let table = table.lookup("table").unwrap();
+  let mut rng = SessionRng::new(0);
+  let _ = TuringMachine::new(4, 4, 3, 3, NoFeature, Cardinal, &mut rng).load_table(table);
+  let _ = TwoTapeMachine::new(4, 4, 2, 2, SideBySide, 1, 1, Cardinal, &mut rng).load_table(table);
+  let _ = TurmiteMachine::new(4, 4, 3, 3, &mut rng).load_table(table);
+}
+
+
+/// Saved machine states, as restored by replays and sessions.
+fn state_target(data: &[u8]) {
+  match from_utf8(data) {
+    Some(text) => {
+      let mut rng = SessionRng::new(0);
+      let _ = replay::restore(&mut *TuringMachine::new(4, 4, 3, 3, NoFeature, Cardinal, &mut rng), text);
+      let _ = replay::restore(&mut *TwoTapeMachine::new(4, 4, 2, 2, SideBySide, 1, 1, Cardinal, &mut rng), text);
+      let _ = replay::restore(&mut *TurmiteMachine::new(4, 4, 3, 3, &mut rng), text);
+    },
+    None => {},
+  }
+}
+
+
+/// Valid inputs for 'name' to start mutating from when no corpus is given.
+pub fn seeds(name: &str) -> Vec<Vec<u8>> {
+  let seeds: &[&str] = match name {
+    "config" => &[
+      "[turing]\nstates = 4\nsymbols = 6\nwidth = 64\nheight = 48\npicture_steps = 10\nreset_steps = 100\n",
+      "[turing]\nstates = 2\nsymbols = 2\nwidth = 8\nheight = 8\npicture_steps = 1\nreset_steps = 1\n\
+       tapes = 2\ncell_width = 4\ninterleave = \"stripes\"\nchain = true\n",
+    ],
+    "table" => &[
+      "[[1, 2, 0], [0, 1, 3], [2, 0, 1], [1, 1, 1], [0, 0, 2], [2, 2, 0], [1, 0, 3], [0, 2, 1], [2, 1, 2]]",
+      "[[0, 1, 1, 0, 2], [1, 0, 0, 1, 3]]",
+    ],
+    _ => &[
+      "position = 5\nstate = 1\ntape = \"00010200000000000000000000000000\"\n\
+       table = [[1, 2, 0], [0, 1, 3], [2, 0, 1], [1, 1, 1], [0, 0, 2], [2, 2, 0], [1, 0, 3], [0, 2, 1], [2, 1, 2]]\n",
+      "positions = [0, 15]\nstate = 1\ntape_a = \"00010000000000000000000000000001\"\n\
+       tape_b = \"01000000000000000000000000000000\"\ntable = []\n",
+    ],
+  };
+  seeds.iter().map(|seed| seed.as_bytes().to_vec()).collect()
+}
+
+
+// Byte strings that tend to reach new parser states when spliced in.
+static TOKENS: &'static [&'static str] = &[
+  "[", "]", "[[", "]]", "=", "\"", "'", ",", ".", "\n", "#", "-1", "0", "256",
+  "9223372036854775807", "-9223372036854775808", "4294967296", "1e999", "true", "\"\"\"", "\\u",
+  "turing.", "[turing]", "table", "tape", "position", "state",
+];
+
+
+/// Makes 1 to 4 random edits to 'input': byte flips, insertions, deletions,
+/// duplicated ranges and spliced tokens.
+pub fn mutate(input: &mut Vec<u8>, rng: &mut SessionRng) {
+  for _ in range(0, rng.gen_range(1u, 5)) {
+    let len = input.len();
+    match rng.gen_range(0u, 5) {
+      0 if len > 0 => {
+        let i = rng.gen_range(0, len);
+        *input.get_mut(i) ^= 1 << rng.gen_range(0u, 8);
+      },
+      1 => input.insert(rng.gen_range(0, len + 1), rng.gen()),
+      2 if len > 0 => { input.remove(rng.gen_range(0, len)); },
+      3 if len > 0 => {
+        let start = rng.gen_range(0, len);
+        let end = rng.gen_range(start, len) + 1;
+        let copy = input.slice(start, end).to_vec();
+        let at = rng.gen_range(0, len + 1);
+        for (offset, &byte) in copy.iter().enumerate() {
+          input.insert(at + offset, byte);
+        }
+      },
+      _ => {
+        let token = TOKENS[rng.gen_range(0, TOKENS.len())].as_bytes();
+        let at = rng.gen_range(0, len + 1);
+        for (offset, &byte) in token.iter().enumerate() {
+          input.insert(at + offset, byte);
+        }
+      },
+    }
+  }
+}
+
+
+/// Runs 'target' on 'runs' mutations of the inputs in 'corpus', each in its
+/// own task so a panic is caught. Inputs that make it panic are written to
+/// 'crash-<name>-<run>' in the current directory. Returns the number of
+/// crashes.
+pub fn run(name: &str, target: Target, corpus: &[Vec<u8>], runs: u64, seed: u64,
+           out: &mut Writer) -> IoResult<uint> {
+  let mut rng = SessionRng::new(seed);
+  let mut crashes = 0u;
+  for run in range(0, runs) {
+    let mut input = if corpus.is_empty() {
+      Vec::new()
+    } else {
+      corpus[rng.gen_range(0, corpus.len())].clone()
+    };
+    mutate(&mut input, &mut rng);
+    let data = input.clone();
+    if task::try(proc() target(data.as_slice())).is_err() {
+      crashes += 1;
+      let path = Path::new(format!("crash-{}-{}", name, run));
+      try!(File::create(&path).write(input.as_slice()));
+      try!(writeln!(out, "Crash on run {}, input saved to {}", run, path.display()));
+    }
+  }
+  try!(writeln!(out, "{}: {} runs, {} crashes", name, runs, crashes));
+  Ok(crashes)
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::io::stdout;
+  use std::os::getenv;
+  use toml;
+
+  use batch;
+  use super::{TARGETS, target, seeds, run};
+
+  // A short round of every target with each 'cargo test'. Longer rounds, or
+  // ones from another seed, are set with TURING_FUZZ_RUNS and
+  // TURING_FUZZ_SEED.
+  #[test]
+  fn parsers_survive_mutated_input() {
+    let setting = |name: &str, default: u64| {
+      getenv(name).and_then(|value| batch::parse_count(value.as_slice())).unwrap_or(default)
+    };
+    let (runs, seed) = (setting("TURING_FUZZ_RUNS", 2000), setting("TURING_FUZZ_SEED", 1));
+    for &name in TARGETS.iter() {
+      let crashes = run(name, target(name).unwrap(), seeds(name).as_slice(), runs, seed, &mut stdout());
+      assert_eq!(crashes.unwrap(), 0);
+    }
+  }
+
+  // Mutating from inputs that parse reaches deeper than random bytes.
+  #[test]
+  fn config_seeds_are_valid() {
+    for input in seeds("config").iter() {
+      let config: toml::Value = from_str(::std::str::from_utf8(input.as_slice()).unwrap()).unwrap();
+      assert!(::config::check(&config).is_ok());
+    }
+  }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::io::{IoResult, IoError, OtherIoError};
+
+use turing::Color;
+
+
+// Codes are at most 12 bits. The last code is left unused so decoders that
+// add an entry for the code before a clear never overflow the table.
+static MAX_CODES: u16 = 4095;
+
+
+/// Writes an animated GIF of 'frames', each a 'width'x'height' image of
+/// indices into 'palette' (at most 256 colors), shown for 'delay' hundredths
+/// of a second each. The animation loops forever. Fails for images over
+/// 65535 pixels on a side, which GIF can't describe.
+pub fn write_gif(out: &mut Writer, width: uint, height: uint, palette: &[Color],
+                 frames: &[Vec<u8>], delay: u16) -> IoResult<()> {
+  if width > 0xffff || height > 0xffff {
+    return Err(IoError { kind: OtherIoError, desc: "GIF images are at most 65535 pixels on a side", detail: None });
+  }
+  // The color table has 2^(bits) entries, at least 2.
+  let mut bits = 1u;
+  while (1u << bits) < palette.len() {
+    bits += 1;
+  }
+  try!(out.write(b"GIF89a"));
+  try!(out.write_le_u16(width as u16));
+  try!(out.write_le_u16(height as u16));
+  // Global color table, 8 bits per primary, no background or aspect ratio.
+  try!(out.write(&[0xf0 | (bits - 1) as u8, 0, 0]));
+  for i in range(0, 1u << bits) {
+    let color = if i < palette.len() { palette[i] } else { [0, 0, 0] };
+    try!(out.write(&color));
+  }
+  // Loop forever.
+  try!(out.write(&[0x21, 0xff, 11]));
+  try!(out.write(b"NETSCAPE2.0"));
+  try!(out.write(&[3, 1, 0, 0, 0]));
+
+  let min_code_size = if bits < 2 { 2 } else { bits };
+  for frame in frames.iter() {
+    try!(out.write(&[0x21, 0xf9, 4, 0]));
+    try!(out.write_le_u16(delay));
+    try!(out.write(&[0, 0]));
+    try!(out.write_u8(0x2c));
+    try!(out.write_le_u16(0));
+    try!(out.write_le_u16(0));
+    try!(out.write_le_u16(width as u16));
+    try!(out.write_le_u16(height as u16));
+    try!(out.write_u8(0));
+    try!(out.write_u8(min_code_size as u8));
+    let data = lzw(min_code_size, frame.as_slice());
+    for block in data.as_slice().chunks(255) {
+      try!(out.write_u8(block.len() as u8));
+      try!(out.write(block));
+    }
+    try!(out.write_u8(0));
+  }
+  out.write_u8(0x3b)
+}
+
+
+// Packs variable width codes, least significant bit first.
+struct BitWriter {
+  out: Vec<u8>,
+  bits: u32,
+  count: uint,
+}
+
+impl BitWriter {
+  fn write(&mut self, code: u16, size: uint) {
+    self.bits |= (code as u32) << self.count;
+    self.count += size;
+    while self.count >= 8 {
+      self.out.push(self.bits as u8);
+      self.bits >>= 8;
+      self.count -= 8;
+    }
+  }
+
+  fn finish(mut self) -> Vec<u8> {
+    if self.count > 0 {
+      self.out.push(self.bits as u8);
+    }
+    self.out
+  }
+}
+
+
+/// LZW compresses 'indices' the way GIF image data is: codes start at
+/// 'min_code_size' + 1 bits and grow with the dictionary, which is cleared
+/// when it is full.
+fn lzw(min_code_size: uint, indices: &[u8]) -> Vec<u8> {
+  let clear = 1u16 << min_code_size;
+  let end = clear + 1;
+  let mut out = BitWriter { out: Vec::new(), bits: 0, count: 0 };
+  let mut size = min_code_size + 1;
+  let mut next = end + 1;
+  let mut codes: HashMap<(u16, u8), u16> = HashMap::new();
+  out.write(clear, size);
+  if indices.is_empty() {
+    out.write(end, size);
+    return out.finish();
+  }
+
+  let mut prefix = indices[0] as u16;
+  for &index in indices.slice_from(1).iter() {
+    match codes.find(&(prefix, index)) {
+      Some(&code) => {
+        prefix = code;
+        continue;
+      },
+      None => {},
+    }
+    out.write(prefix, size);
+    if next < MAX_CODES {
+      codes.insert((prefix, index), next);
+      next += 1;
+      if next as uint > (1u << size) && size < 12 {
+        size += 1;
+      }
+    } else {
+      out.write(clear, size);
+      codes.clear();
+      next = end + 1;
+      size = min_code_size + 1;
+    }
+    prefix = index as u16;
+  }
+  out.write(prefix, size);
+  out.write(end, size);
+  out.finish()
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::collections::HashMap;
+  use std::io::MemWriter;
+
+  use super::{lzw, write_gif};
+
+  // Decodes GIF image data the way decoders do, growing codes one step
+  // behind the encoder.
+  fn unlzw(min_code_size: uint, data: &[u8]) -> Vec<u8> {
+    let clear = 1u << min_code_size;
+    let end = clear + 1;
+    let (mut bits, mut count, mut offset) = (0u32, 0u, 0u);
+    let mut size = min_code_size + 1;
+    let mut table: HashMap<uint, Vec<u8>> = HashMap::new();
+    let mut next = end + 1;
+    let mut previous: Option<uint> = None;
+    let mut out = Vec::new();
+    loop {
+      while count < size {
+        bits |= (data[offset] as u32) << count;
+        offset += 1;
+        count += 8;
+      }
+      let code = (bits & ((1 << size) - 1)) as uint;
+      bits >>= size;
+      count -= size;
+      if code == clear {
+        table.clear();
+        for i in range(0, clear) {
+          table.insert(i, vec!(i as u8));
+        }
+        next = end + 1;
+        size = min_code_size + 1;
+        previous = None;
+        continue;
+      }
+      if code == end {
+        return out;
+      }
+      let entry = match (table.find(&code), previous) {
+        (Some(entry), _) => entry.clone(),
+        (None, Some(previous)) => {
+          let mut entry = table.find(&previous).unwrap().clone();
+          let first = *entry.get(0);
+          entry.push(first);
+          entry
+        },
+        (None, None) => fail!("invalid first code {}", code),
+      };
+      out.push_all(entry.as_slice());
+      match previous {
+        Some(previous) if next < 4096 => {
+          let mut added = table.find(&previous).unwrap().clone();
+          added.push(*entry.get(0));
+          table.insert(next, added);
+          next += 1;
+          if next >= (1 << size) && size < 12 {
+            size += 1;
+          }
+        },
+        _ => {},
+      }
+      previous = Some(code);
+    }
+  }
+
+  #[test]
+  fn lzw_round_trips() {
+    let runs: Vec<u8> = range(0u, 3000).map(|i| (i / 7 % 3) as u8).collect();
+    // Enough distinct sequences to fill and clear the dictionary.
+    let mut seed = 1u32;
+    let noise: Vec<u8> = range(0u, 50000).map(|_| {
+      seed = seed * 1103515245 + 12345;
+      (seed >> 16) as u8 % 16
+    }).collect();
+    for &(min_code_size, ref indices) in [(2u, vec!(0u8)), (2, runs.clone()), (4, noise.clone())].iter() {
+      assert_eq!(unlzw(min_code_size, lzw(min_code_size, indices.as_slice()).as_slice()), *indices);
+    }
+  }
+
+  #[test]
+  fn writes_header_and_trailer() {
+    let mut out = MemWriter::new();
+    write_gif(&mut out, 2, 1, &[[0, 0, 0], [255, 255, 255], [255, 0, 0]],
+              &[vec!(0, 1), vec!(2, 1)], 4).unwrap();
+    let gif = out.unwrap();
+    assert_eq!(gif.as_slice().slice_to(6), b"GIF89a");
+    // 2x1, a 4 color table.
+    assert_eq!(gif.as_slice().slice(6, 11), [2u8, 0, 1, 0, 0xf1].as_slice());
+    assert_eq!(gif.as_slice().slice(13, 25), [0u8, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 0].as_slice());
+    assert_eq!(gif.last(), Some(&0x3b));
+  }
+
+  #[test]
+  fn rejects_images_too_big_to_describe() {
+    let mut out = MemWriter::new();
+    assert!(write_gif(&mut out, 65536, 1, &[[0, 0, 0]], &[], 4).is_err());
+    assert!(out.unwrap().is_empty());
+  }
+}
@@ -0,0 +1,1172 @@
+#[cfg(not(feature = "grpc"))]
+use std::io::{IoError, OtherIoError};
+use std::io::IoResult;
+
+use control::Request;
+use output::ImageSink;
+
+
+/// Hands out a sink for the frames of gRPC 'Frames' calls every time the
+/// outputs are opened. All of them send to the same clients.
+pub trait FrameStreams: Send {
+  fn sink(&self) -> Box<ImageSink + Send>;
+}
+
+
+/// Serves the service of 'proto/turing.proto' on 'port' of 'host' in the
+/// background. Control calls become requests on 'requests', answered by the
+/// main loop like those of the HTTP API, and 'Frames' calls are sent every
+/// frame written to the returned sinks. Only available when built with
+/// '--features grpc'.
+#[cfg(feature = "grpc")]
+pub fn serve(host: &str, port: u16, requests: Sender<Request>) -> IoResult<Box<FrameStreams>> {
+  server::serve(host, port, requests).map(|clients| box clients as Box<FrameStreams>)
+}
+
+#[cfg(not(feature = "grpc"))]
+pub fn serve(_host: &str, _port: u16, _requests: Sender<Request>) -> IoResult<Box<FrameStreams>> {
+  Err(IoError { kind: OtherIoError, desc: "grpc_port needs a build with --features grpc", detail: None })
+}
+
+
+// gRPC runs over HTTP/2, which is spoken here as far as gRPC clients need
+// it: cleartext with prior knowledge, header blocks decoded with HPACK but
+// written without compression, and flow control on the frames sent.
+#[cfg(feature = "grpc")]
+mod server {
+  use std::cmp::min;
+  use std::collections::HashMap;
+  use std::io::{BufferedReader, BufferedWriter, IoError, IoResult, OtherIoError};
+  use std::io::{Listener, Acceptor, TcpListener, TcpStream};
+  use std::iter::range_step;
+  use std::sync::{Arc, Mutex};
+  use serialize::json;
+  use serialize::json::Json;
+
+  use control::{Request, Task, Invalid, Failed};
+  use control::{Pause, Resume, Reset, SetPictureSteps, SetResetSteps, GetTable, Status};
+  use output::ImageSink;
+
+  use super::FrameStreams;
+
+
+  // What every HTTP/2 connection starts with.
+  static PREFACE: &'static [u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+  // Packet types.
+  static DATA: u8 = 0x0;
+  static HEADERS: u8 = 0x1;
+  static RST_STREAM: u8 = 0x3;
+  static SETTINGS: u8 = 0x4;
+  static PING: u8 = 0x6;
+  static GOAWAY: u8 = 0x7;
+  static WINDOW_UPDATE: u8 = 0x8;
+  static CONTINUATION: u8 = 0x9;
+
+  // Packet flags. ACK is for SETTINGS and PING, the others for DATA and
+  // HEADERS.
+  static END_STREAM: u8 = 0x1;
+  static ACK: u8 = 0x1;
+  static END_HEADERS: u8 = 0x4;
+  static PADDED: u8 = 0x8;
+  static PRIORITY: u8 = 0x20;
+
+  // Settings.
+  static MAX_CONCURRENT_STREAMS: u16 = 0x3;
+  static INITIAL_WINDOW_SIZE: u16 = 0x4;
+  static MAX_FRAME_SIZE: u16 = 0x5;
+
+  // HTTP/2 error codes.
+  static PROTOCOL_ERROR: u32 = 0x1;
+  static FLOW_CONTROL_ERROR: u32 = 0x3;
+  static FRAME_SIZE_ERROR: u32 = 0x6;
+  static REFUSED_STREAM: u32 = 0x7;
+  static CANCEL: u32 = 0x8;
+  static COMPRESSION_ERROR: u32 = 0x9;
+
+  // gRPC status codes.
+  static OK: u32 = 0;
+  static INVALID_ARGUMENT: u32 = 3;
+  static RESOURCE_EXHAUSTED: u32 = 8;
+  static UNIMPLEMENTED: u32 = 12;
+  static INTERNAL: u32 = 13;
+  static UNAVAILABLE: u32 = 14;
+
+  // The defaults of the protocol, which we don't change for our side.
+  static DEFAULT_WINDOW: i64 = 65535;
+  static DEFAULT_MAX_PACKET: uint = 16384;
+  static DEFAULT_TABLE_SIZE: uint = 4096;
+  // Header blocks are a few hundred bytes; anything much bigger is abuse.
+  static MAX_HEADER_BLOCK: uint = 65536;
+  // Requests are a few bytes, so this is just as generous.
+  static MAX_REQUEST: uint = 65536;
+  // Calls open at once on a connection, the least HTTP/2 recommends.
+  static MAX_CALLS: uint = 100;
+  // The largest packet and window either side may ask for.
+  static MAX_PACKET_LIMIT: u32 = 0xff_ffff;
+  static MAX_WINDOW: u32 = 0x7fff_ffff;
+
+  static GET_TABLE: &'static str = "/turing.Turing/GetTable";
+
+
+  pub fn serve(host: &str, port: u16, requests: Sender<Request>) -> IoResult<FrameClients> {
+    let listener = try!(TcpListener::bind(host, port));
+    let mut acceptor = try!(listener.listen());
+    let clients = FrameClients::new();
+    let accepted = clients.clone();
+    spawn(proc() {
+      for client in acceptor.incoming() {
+        match client {
+          Ok(client) => {
+            let (requests, clients) = (requests.clone(), accepted.clone());
+            spawn(proc() {
+              let _ = serve_client(client, requests, clients);
+            });
+          },
+          Err(_) => {},
+        }
+      }
+    });
+    Ok(clients)
+  }
+
+
+  // A frame of HTTP/2, called a packet here so as not to mix it up with the
+  // frames of the video.
+  struct Packet {
+    kind: u8,
+    flags: u8,
+    stream: u32,
+    payload: Vec<u8>,
+  }
+
+  fn read_packet(reader: &mut Reader) -> IoResult<Packet> {
+    let length = try!(reader.read_be_uint_n(3)) as uint;
+    let kind = try!(reader.read_u8());
+    let flags = try!(reader.read_u8());
+    let stream = try!(reader.read_be_u32()) & 0x7fff_ffff;
+    if length > DEFAULT_MAX_PACKET {
+      return Err(IoError { kind: OtherIoError, desc: "HTTP/2 frame over the maximum size", detail: None });
+    }
+    let payload = try!(reader.read_exact(length));
+    Ok(Packet { kind: kind, flags: flags, stream: stream, payload: payload })
+  }
+
+  fn write_packet(out: &mut Writer, kind: u8, flags: u8, stream: u32, payload: &[u8]) -> IoResult<()> {
+    try!(out.write_be_uint_n(payload.len() as u64, 3));
+    try!(out.write_u8(kind));
+    try!(out.write_u8(flags));
+    try!(out.write_be_u32(stream));
+    out.write(payload)
+  }
+
+  // The payload of a DATA or HEADERS packet without its padding.
+  fn unpad<'a>(packet: &'a Packet) -> Result<&'a [u8], &'static str> {
+    let payload = packet.payload.as_slice();
+    if packet.flags & PADDED == 0 {
+      return Ok(payload);
+    }
+    if payload.is_empty() || payload[0] as uint >= payload.len() {
+      return Err("Bad padding");
+    }
+    Ok(payload.slice(1, payload.len() - payload[0] as uint))
+  }
+
+  fn be32(value: u32) -> [u8, ..4] {
+    [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+  }
+
+  fn u32_at(bytes: &[u8], at: uint) -> u32 {
+    range(at, at + 4).fold(0, |value, i| value << 8 | bytes[i] as u32)
+  }
+
+  // Checks the value of a setting from the client, giving the error code to
+  // go away with if it is out of range.
+  fn check_setting(id: u16, value: u32) -> Result<(), (u32, &'static str)> {
+    if id == INITIAL_WINDOW_SIZE && value > MAX_WINDOW {
+      Err((FLOW_CONTROL_ERROR, "INITIAL_WINDOW_SIZE over 2^31-1"))
+    } else if id == MAX_FRAME_SIZE && (value < DEFAULT_MAX_PACKET as u32 || value > MAX_PACKET_LIMIT) {
+      Err((PROTOCOL_ERROR, "MAX_FRAME_SIZE outside 16384 to 2^24-1"))
+    } else {
+      Ok(())
+    }
+  }
+
+
+  // The events a connection handles, one at a time.
+  enum Event {
+    // A packet from the client, or None once it has gone away.
+    Received(Option<Packet>),
+    // The main loop's answer to a call's request, or a gRPC status and
+    // message.
+    Answered(u32, Result<String, (u32, String)>),
+    // A new frame is waiting for the 'Frames' calls.
+    FrameReady,
+  }
+
+
+  // The newest frame a connection hasn't taken yet, as a gRPC message.
+  type Newest = Arc<Mutex<Option<Arc<Vec<u8>>>>>;
+
+  // A connection with 'Frames' calls.
+  struct Watcher {
+    id: uint,
+    newest: Newest,
+    // Where to say a frame is waiting.
+    events: Sender<Event>,
+  }
+
+  struct Watchers {
+    list: Vec<Watcher>,
+    next_id: uint,
+    // Frames written since the server started.
+    frames: u64,
+  }
+
+  /// Sends each frame written to it to the 'Frames' calls of every client.
+  /// A connection still sending a frame only gets the newest of those
+  /// written meanwhile, so a slow client misses frames rather than holding
+  /// up the others. Frames are only encoded while someone is watching.
+  #[deriving(Clone)]
+  pub struct FrameClients {
+    watchers: Arc<Mutex<Watchers>>,
+  }
+
+  impl FrameClients {
+    fn new() -> FrameClients {
+      FrameClients { watchers: Arc::new(Mutex::new(Watchers { list: Vec::new(), next_id: 0, frames: 0 })) }
+    }
+
+    // Adds a connection, which is sent FrameReady whenever 'newest' gets a
+    // frame after being empty. Returns its id and 'newest'.
+    fn add(&self, events: Sender<Event>) -> (uint, Newest) {
+      let mut watchers = self.watchers.lock();
+      let id = watchers.next_id;
+      watchers.next_id += 1;
+      let newest = Arc::new(Mutex::new(None));
+      watchers.list.push(Watcher { id: id, newest: newest.clone(), events: events });
+      (id, newest)
+    }
+
+    fn remove(&self, id: uint) {
+      self.watchers.lock().list.retain(|watcher| watcher.id != id);
+    }
+  }
+
+  impl FrameStreams for FrameClients {
+    fn sink(&self) -> Box<ImageSink + Send> {
+      box self.clone() as Box<ImageSink + Send>
+    }
+  }
+
+  impl ImageSink for FrameClients {
+    fn write_frame(&mut self, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+      let mut watchers = self.watchers.lock();
+      let number = watchers.frames;
+      watchers.frames += 1;
+      if watchers.list.is_empty() {
+        return Ok(());
+      }
+      let mut frame = Message::new();
+      frame.varint(1, number);
+      frame.varint(2, width as u64);
+      frame.varint(3, height as u64);
+      frame.bytes(4, image);
+      let frame = Arc::new(frame.finish());
+      // Drop connections that have gone away.
+      watchers.list.retain(|watcher| {
+        let mut newest = watcher.newest.lock();
+        let told = newest.is_some();
+        *newest = Some(frame.clone());
+        told || watcher.events.send_opt(FrameReady).is_ok()
+      });
+      Ok(())
+    }
+  }
+
+
+  // Talks to one client until it goes away: a task reads its packets and
+  // this one handles them along with answers from the main loop and frames.
+  fn serve_client(client: TcpStream, requests: Sender<Request>, clients: FrameClients) -> IoResult<()> {
+    let mut reader = BufferedReader::new(client.clone());
+    if try!(reader.read_exact(PREFACE.len())).as_slice() != PREFACE {
+      return Err(IoError { kind: OtherIoError, desc: "Not an HTTP/2 client", detail: None });
+    }
+    let (events, received) = channel();
+    let packets = events.clone();
+    spawn(proc() {
+      let mut reader = reader;
+      loop {
+        let packet = read_packet(&mut reader).ok();
+        let closed = packet.is_none();
+        if packets.send_opt(Received(packet)).is_err() || closed {
+          break;
+        }
+      }
+    });
+    let mut socket = client.clone();
+    let mut connection = Connection::new(client, requests, events, clients);
+    let served = connection.serve(received);
+    connection.close();
+    // Stops the reader.
+    let _ = socket.close_read();
+    served
+  }
+
+
+  // A call, i.e. an HTTP/2 stream.
+  struct Call {
+    path: Vec<u8>,
+    // The request as far as it has arrived.
+    body: Vec<u8>,
+    // The bytes the client takes on this call before a WINDOW_UPDATE.
+    window: i64,
+    // A message being sent and how much of it is sent.
+    sending: Option<(Arc<Vec<u8>>, uint)>,
+    // Whether the call ends once 'sending' is sent, as unary calls do.
+    last: bool,
+    frames: bool,
+  }
+
+  struct Connection {
+    out: BufferedWriter<TcpStream>,
+    requests: Sender<Request>,
+    events: Sender<Event>,
+    clients: FrameClients,
+    decoder: HeaderDecoder,
+    calls: HashMap<u32, Call>,
+    // The highest stream started by the client.
+    last_stream: u32,
+    // A header block waiting for CONTINUATION packets: its stream, whether
+    // the stream ends with it and the block so far.
+    continued: Option<(u32, bool, Vec<u8>)>,
+    // The bytes the client takes on all calls before a WINDOW_UPDATE.
+    window: i64,
+    initial_window: i64,
+    max_packet: uint,
+    // Set with the first 'Frames' call: the id among the watchers and the
+    // newest frame.
+    watching: Option<(uint, Newest)>,
+  }
+
+  impl Connection {
+    fn new(client: TcpStream, requests: Sender<Request>, events: Sender<Event>, clients: FrameClients)
+        -> Connection {
+      Connection {
+        out: BufferedWriter::new(client),
+        requests: requests,
+        events: events,
+        clients: clients,
+        decoder: HeaderDecoder::new(),
+        calls: HashMap::new(),
+        last_stream: 0,
+        continued: None,
+        window: DEFAULT_WINDOW,
+        initial_window: DEFAULT_WINDOW,
+        max_packet: DEFAULT_MAX_PACKET,
+        watching: None,
+      }
+    }
+
+    fn serve(&mut self, events: Receiver<Event>) -> IoResult<()> {
+      // Our settings are the defaults but for the calls open at once.
+      let mut settings = vec!(0, MAX_CONCURRENT_STREAMS as u8);
+      settings.push_all(be32(MAX_CALLS as u32).as_slice());
+      try!(write_packet(&mut self.out, SETTINGS, 0, 0, settings.as_slice()));
+      try!(self.out.flush());
+      for event in events.iter() {
+        match event {
+          Received(Some(packet)) => {
+            if !try!(self.received(packet)) {
+              return Ok(());
+            }
+          },
+          Received(None) => return Ok(()),
+          Answered(stream, answer) => try!(self.answered(stream, answer)),
+          FrameReady => self.frame_ready(),
+        }
+        try!(self.send());
+      }
+      Ok(())
+    }
+
+    fn close(&mut self) {
+      match self.watching {
+        Some((id, _)) => self.clients.remove(id),
+        None => {},
+      }
+    }
+
+    // Handles a packet from the client. Returns false once the connection
+    // is to be closed.
+    fn received(&mut self, packet: Packet) -> IoResult<bool> {
+      // Nothing may come between the packets of a header block.
+      if self.continued.is_some() != (packet.kind == CONTINUATION) {
+        return self.go_away(PROTOCOL_ERROR, "Expected CONTINUATION only after HEADERS");
+      }
+      if packet.kind == DATA {
+        if !packet.payload.is_empty() {
+          // Requests are small, so only the connection's window runs out.
+          try!(write_packet(&mut self.out, WINDOW_UPDATE, 0, 0, be32(packet.payload.len() as u32).as_slice()));
+        }
+        let data = match unpad(&packet) {
+          Ok(data) => data,
+          Err(why) => return self.go_away(PROTOCOL_ERROR, why),
+        };
+        let (ended, too_big) = match self.calls.find_mut(&packet.stream) {
+          Some(call) => {
+            call.body.push_all(data);
+            (packet.flags & END_STREAM != 0, call.body.len() > MAX_REQUEST)
+          },
+          // Ended or reset already.
+          None => (false, false),
+        };
+        if too_big {
+          try!(self.fail(packet.stream, RESOURCE_EXHAUSTED, "Request too large"));
+          // Tells the client to stop sending the rest.
+          try!(write_packet(&mut self.out, RST_STREAM, 0, packet.stream, be32(CANCEL).as_slice()));
+        } else if ended {
+          try!(self.dispatch(packet.stream));
+        }
+      } else if packet.kind == HEADERS {
+        let block = match unpad(&packet) {
+          Ok(block) => block,
+          Err(why) => return self.go_away(PROTOCOL_ERROR, why),
+        };
+        // Priorities make no difference here.
+        let block = if packet.flags & PRIORITY == 0 {
+          block
+        } else if block.len() >= 5 {
+          block.slice_from(5)
+        } else {
+          return self.go_away(FRAME_SIZE_ERROR, "HEADERS too short for its priority");
+        };
+        let end_stream = packet.flags & END_STREAM != 0;
+        if packet.flags & END_HEADERS != 0 {
+          return self.headers(packet.stream, end_stream, block);
+        }
+        self.continued = Some((packet.stream, end_stream, block.to_vec()));
+      } else if packet.kind == CONTINUATION {
+        let (stream, end_stream, mut block) = self.continued.take().unwrap();
+        if packet.stream != stream || block.len() + packet.payload.len() > MAX_HEADER_BLOCK {
+          return self.go_away(PROTOCOL_ERROR, "Bad CONTINUATION");
+        }
+        block.push_all(packet.payload.as_slice());
+        if packet.flags & END_HEADERS != 0 {
+          return self.headers(stream, end_stream, block.as_slice());
+        }
+        self.continued = Some((stream, end_stream, block));
+      } else if packet.kind == RST_STREAM {
+        self.calls.remove(&packet.stream);
+      } else if packet.kind == SETTINGS {
+        if packet.flags & ACK != 0 {
+          return Ok(true);
+        }
+        if packet.payload.len() % 6 != 0 {
+          return self.go_away(FRAME_SIZE_ERROR, "Bad SETTINGS");
+        }
+        for setting in packet.payload.as_slice().chunks(6) {
+          let (id, value) = ((setting[0] as u16) << 8 | setting[1] as u16, u32_at(setting, 2));
+          match check_setting(id, value) {
+            Ok(()) => {},
+            Err((code, why)) => return self.go_away(code, why),
+          }
+          if id == INITIAL_WINDOW_SIZE {
+            // The windows of open calls change by the difference.
+            let difference = value as i64 - self.initial_window;
+            self.initial_window = value as i64;
+            for (_, call) in self.calls.iter_mut() {
+              call.window += difference;
+            }
+          } else if id == MAX_FRAME_SIZE {
+            self.max_packet = value as uint;
+          }
+        }
+        try!(write_packet(&mut self.out, SETTINGS, ACK, 0, &[]));
+      } else if packet.kind == PING {
+        if packet.flags & ACK == 0 {
+          try!(write_packet(&mut self.out, PING, ACK, 0, packet.payload.as_slice()));
+        }
+      } else if packet.kind == GOAWAY {
+        return Ok(false);
+      } else if packet.kind == WINDOW_UPDATE {
+        if packet.payload.len() != 4 {
+          return self.go_away(FRAME_SIZE_ERROR, "Bad WINDOW_UPDATE");
+        }
+        let increment = (u32_at(packet.payload.as_slice(), 0) & 0x7fff_ffff) as i64;
+        if packet.stream == 0 {
+          self.window += increment;
+        } else {
+          match self.calls.find_mut(&packet.stream) {
+            Some(call) => call.window += increment,
+            None => {},
+          }
+        }
+      }
+      // Other packets (PRIORITY, unknown types) are ignored.
+      Ok(true)
+    }
+
+    // Handles a whole header block: it starts a call, or ends one with
+    // trailers.
+    fn headers(&mut self, stream: u32, end_stream: bool, block: &[u8]) -> IoResult<bool> {
+      let headers = match self.decoder.decode(block) {
+        Ok(headers) => headers,
+        Err(why) => return self.go_away(COMPRESSION_ERROR, why),
+      };
+      if stream > self.last_stream && stream % 2 == 1 {
+        self.last_stream = stream;
+        if self.calls.len() >= MAX_CALLS {
+          try!(write_packet(&mut self.out, RST_STREAM, 0, stream, be32(REFUSED_STREAM).as_slice()));
+          return Ok(true);
+        }
+        let mut path = Vec::new();
+        for &(ref name, ref value) in headers.iter() {
+          if name.as_slice() == ":path".as_bytes() {
+            path = value.clone();
+          }
+        }
+        self.calls.insert(stream, Call { path: path, body: Vec::new(), window: self.initial_window, sending: None,
+                                         last: false, frames: false });
+      }
+      if end_stream {
+        try!(self.dispatch(stream));
+      }
+      Ok(true)
+    }
+
+    // Starts answering a call whose request has all arrived.
+    fn dispatch(&mut self, stream: u32) -> IoResult<()> {
+      let (path, steps) = match self.calls.find(&stream) {
+        Some(call) => (String::from_utf8(call.path.clone()).ok().unwrap_or(String::new()),
+                       uint_field(call.body.as_slice(), 1)),
+        None => return Ok(()),
+      };
+      let command = match path.as_slice() {
+        "/turing.Turing/GetStatus" => Status,
+        "/turing.Turing/GetTable" => GetTable,
+        "/turing.Turing/Pause" => Pause,
+        "/turing.Turing/Resume" => Resume,
+        "/turing.Turing/Reset" => Reset,
+        "/turing.Turing/SetPictureSteps" => match steps {
+          Some(steps) if steps > 0 => SetPictureSteps(steps),
+          _ => return self.fail(stream, INVALID_ARGUMENT, "Expected picture_steps above 0"),
+        },
+        "/turing.Turing/SetResetSteps" => match steps {
+          Some(steps) if steps > 0 => SetResetSteps(steps),
+          _ => return self.fail(stream, INVALID_ARGUMENT, "Expected reset_steps above 0"),
+        },
+        "/turing.Turing/Frames" => return self.watch(stream),
+        other => return self.fail(stream, UNIMPLEMENTED, format!("No such method: {}", other).as_slice()),
+      };
+      let (reply_sender, reply) = channel();
+      if self.requests.send_opt(Request { reply_to: Task(reply_sender), command: command }).is_err() {
+        return self.fail(stream, UNAVAILABLE, "Stopping");
+      }
+      // The answer comes between frames; wait for it on a task of its own so
+      // the connection carries on meanwhile.
+      let events = self.events.clone();
+      spawn(proc() {
+        let answer = match reply.recv_opt() {
+          Ok(Ok(result)) => Ok(result),
+          Ok(Err(Invalid(message))) => Err((INVALID_ARGUMENT, message)),
+          Ok(Err(Failed(message))) => Err((INTERNAL, message)),
+          Err(()) => Err((UNAVAILABLE, "Stopping".to_string())),
+        };
+        let _ = events.send_opt(Answered(stream, answer));
+      });
+      Ok(())
+    }
+
+    // Sends the main loop's answer on a unary call.
+    fn answered(&mut self, stream: u32, answer: Result<String, (u32, String)>) -> IoResult<()> {
+      let table = match self.calls.find(&stream) {
+        Some(call) => call.path.as_slice() == GET_TABLE.as_bytes(),
+        // Reset by the client meanwhile.
+        None => return Ok(()),
+      };
+      let message = match answer {
+        Ok(result) => match json::from_str(result.as_slice()) {
+          Ok(ref reply) if table => table_message(reply),
+          Ok(ref reply) => status_message(reply),
+          Err(_) => return self.fail(stream, INTERNAL, "Unreadable answer"),
+        },
+        Err((status, message)) => return self.fail(stream, status, message.as_slice()),
+      };
+      try!(write_headers(&mut self.out, stream, false, response_headers()));
+      match self.calls.find_mut(&stream) {
+        Some(call) => {
+          call.sending = Some((Arc::new(message), 0));
+          call.last = true;
+        },
+        None => {},
+      }
+      Ok(())
+    }
+
+    // Starts a 'Frames' call. Like all of the connection's, it is sent the
+    // next frame once it has sent the last.
+    fn watch(&mut self, stream: u32) -> IoResult<()> {
+      if self.watching.is_none() {
+        self.watching = Some(self.clients.add(self.events.clone()));
+      }
+      match self.calls.find_mut(&stream) {
+        Some(call) => call.frames = true,
+        None => {},
+      }
+      write_headers(&mut self.out, stream, false, response_headers())
+    }
+
+    fn frame_ready(&mut self) {
+      let frame = match self.watching {
+        Some((_, ref newest)) => newest.lock().take(),
+        None => None,
+      };
+      match frame {
+        Some(frame) => {
+          for (_, call) in self.calls.iter_mut() {
+            if call.frames && call.sending.is_none() {
+              call.sending = Some((frame.clone(), 0));
+            }
+          }
+        },
+        None => {},
+      }
+    }
+
+    // Sends as much of each call's message as the windows allow, and ends
+    // the unary calls whose answer is sent.
+    fn send(&mut self) -> IoResult<()> {
+      let mut ended = Vec::new();
+      for (&stream, call) in self.calls.iter_mut() {
+        loop {
+          let (message, sent) = match call.sending {
+            Some((ref message, sent)) => (message.clone(), sent),
+            None => break,
+          };
+          let room = min(self.window, call.window);
+          if room <= 0 {
+            break;
+          }
+          let size = min(min(message.len() - sent, self.max_packet), room as uint);
+          try!(write_packet(&mut self.out, DATA, 0, stream, message.slice(sent, sent + size)));
+          self.window -= size as i64;
+          call.window -= size as i64;
+          if sent + size < message.len() {
+            call.sending = Some((message, sent + size));
+            continue;
+          }
+          call.sending = None;
+          if call.last {
+            let mut trailers = Vec::new();
+            put_header(&mut trailers, "grpc-status", format!("{}", OK).as_slice());
+            try!(write_headers(&mut self.out, stream, true, trailers));
+            ended.push(stream);
+          }
+        }
+      }
+      for stream in ended.iter() {
+        self.calls.remove(stream);
+      }
+      self.out.flush()
+    }
+
+    // Ends a call with an error status and no messages.
+    fn fail(&mut self, stream: u32, status: u32, message: &str) -> IoResult<()> {
+      self.calls.remove(&stream);
+      let mut block = response_headers();
+      put_header(&mut block, "grpc-status", format!("{}", status).as_slice());
+      put_header(&mut block, "grpc-message", percent_encode(message).as_slice());
+      write_headers(&mut self.out, stream, true, block)
+    }
+
+    fn go_away(&mut self, code: u32, reason: &str) -> IoResult<bool> {
+      let mut payload = be32(self.last_stream).to_vec();
+      payload.push_all(be32(code).as_slice());
+      payload.push_all(reason.as_bytes());
+      try!(write_packet(&mut self.out, GOAWAY, 0, 0, payload.as_slice()));
+      try!(self.out.flush());
+      Ok(false)
+    }
+  }
+
+
+  fn write_headers(out: &mut Writer, stream: u32, end_stream: bool, block: Vec<u8>) -> IoResult<()> {
+    let flags = END_HEADERS | if end_stream { END_STREAM } else { 0 };
+    write_packet(out, HEADERS, flags, stream, block.as_slice())
+  }
+
+  // The headers that start every response.
+  fn response_headers() -> Vec<u8> {
+    let mut block = Vec::new();
+    put_header(&mut block, ":status", "200");
+    put_header(&mut block, "content-type", "application/grpc");
+    block
+  }
+
+  // Appends a header as a literal with a new name, not indexed, so nothing
+  // depends on the client's table.
+  fn put_header(block: &mut Vec<u8>, name: &str, value: &str) {
+    block.push(0);
+    for string in [name, value].iter() {
+      put_integer(block, string.len(), 7, 0);
+      block.push_all(string.as_bytes());
+    }
+  }
+
+  // Appends an HPACK integer with a 'prefix_bits' bit prefix, the rest of the
+  // first byte being 'flags'.
+  fn put_integer(block: &mut Vec<u8>, value: uint, prefix_bits: uint, flags: u8) {
+    let limit = (1u << prefix_bits) - 1;
+    if value < limit {
+      block.push(flags | value as u8);
+      return;
+    }
+    block.push(flags | limit as u8);
+    let mut rest = value - limit;
+    while rest >= 0x80 {
+      block.push((rest & 0x7f) as u8 | 0x80);
+      rest >>= 7;
+    }
+    block.push(rest as u8);
+  }
+
+  // A grpc-message value: printable ASCII but '%' as it is, the rest as %XX.
+  fn percent_encode(message: &str) -> String {
+    let mut encoded = String::new();
+    for &byte in message.as_bytes().iter() {
+      if byte >= 0x20 && byte <= 0x7e && byte != b'%' {
+        encoded.push(byte as char);
+      } else {
+        encoded.push_str(format!("%{:02X}", byte).as_slice());
+      }
+    }
+    encoded
+  }
+
+
+  // A protobuf message being written, framed as a gRPC message: a byte
+  // saying it isn't compressed and its length in four come first.
+  struct Message {
+    encoded: Vec<u8>,
+  }
+
+  impl Message {
+    fn new() -> Message {
+      Message { encoded: vec!(0, 0, 0, 0, 0) }
+    }
+
+    fn varint(&mut self, field: u32, value: u64) {
+      put_varint(&mut self.encoded, (field << 3) as u64);
+      put_varint(&mut self.encoded, value);
+    }
+
+    fn bytes(&mut self, field: u32, value: &[u8]) {
+      put_varint(&mut self.encoded, (field << 3 | 2) as u64);
+      put_varint(&mut self.encoded, value.len() as u64);
+      self.encoded.push_all(value);
+    }
+
+    // Writes 'message' as a field of this one.
+    fn message(&mut self, field: u32, message: &Message) {
+      self.bytes(field, message.encoded.slice_from(5));
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+      let length = be32((self.encoded.len() - 5) as u32);
+      for (i, &byte) in length.iter().enumerate() {
+        *self.encoded.get_mut(i + 1) = byte;
+      }
+      self.encoded
+    }
+  }
+
+  fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+      out.push((value & 0x7f) as u8 | 0x80);
+      value >>= 7;
+    }
+    out.push(value as u8);
+  }
+
+  fn read_varint(bytes: &[u8], at: &mut uint) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in range_step(0u, 64, 7) {
+      if *at >= bytes.len() {
+        return None;
+      }
+      let byte = bytes[*at];
+      *at += 1;
+      value |= (byte & 0x7f) as u64 << shift;
+      if byte & 0x80 == 0 {
+        return Some(value);
+      }
+    }
+    None
+  }
+
+  // The value of varint field 'field' in the gRPC message 'body', None if it
+  // is missing or the message is malformed. The fields of our requests are
+  // all varints.
+  fn uint_field(body: &[u8], field: u64) -> Option<u64> {
+    // Clients only compress when told they may, which they never are.
+    if body.len() < 5 || body[0] != 0 {
+      return None;
+    }
+    let message = body.slice_from(5);
+    let (mut at, mut found) = (0, None);
+    while at < message.len() {
+      let key = match read_varint(message, &mut at) {
+        Some(key) => key,
+        None => return None,
+      };
+      let skip = match key & 7 {
+        0 => match read_varint(message, &mut at) {
+          Some(value) => {
+            if key >> 3 == field {
+              found = Some(value);
+            }
+            0
+          },
+          None => return None,
+        },
+        1 => 8,
+        2 => match read_varint(message, &mut at) {
+          Some(length) => length as uint,
+          None => return None,
+        },
+        5 => 4,
+        _ => return None,
+      };
+      if skip > message.len() - at {
+        return None;
+      }
+      at += skip;
+    }
+    found
+  }
+
+  // The Status message for the main loop's status (see 'StatusReply').
+  fn status_message(reply: &Json) -> Vec<u8> {
+    let number = |name: &str| reply.find(&name.to_string()).and_then(|value| value.as_u64()).unwrap_or(0);
+    let paused = reply.find(&"paused".to_string()).and_then(|value| value.as_boolean()).unwrap_or(false);
+    let mut message = Message::new();
+    message.varint(1, if paused { 1 } else { 0 });
+    message.varint(2, number("step"));
+    message.varint(3, number("picture_steps"));
+    message.varint(4, number("reset_steps"));
+    message.finish()
+  }
+
+  // The Table message for the main loop's 'TableReply'. Tables that aren't a
+  // list of [next state, write symbol, direction] entries, such as those of
+  // elementary cellular automata and two tape machines, only come as text.
+  fn table_message(reply: &Json) -> Vec<u8> {
+    let mut message = Message::new();
+    message.varint(1, reply.find(&"symbols".to_string()).and_then(|value| value.as_u64()).unwrap_or(0));
+    let table = reply.find(&"table".to_string()).map(|table| table.clone()).unwrap_or(json::Null);
+    let entries: Vec<Vec<u64>> = match table.as_list() {
+      Some(entries) => entries.iter().map(|entry| match entry.as_list() {
+        Some(parts) => parts.iter().filter_map(|part| part.as_u64()).collect(),
+        None => Vec::new(),
+      }).collect(),
+      None => Vec::new(),
+    };
+    if entries.iter().all(|entry| entry.len() == 3) {
+      for entry in entries.iter() {
+        let mut transition = Message::new();
+        for (field, &value) in entry.iter().enumerate() {
+          transition.varint(field as u32 + 1, value);
+        }
+        message.message(2, &transition);
+      }
+    }
+    message.bytes(3, table.to_string().as_bytes());
+    message.finish()
+  }
+
+
+  // Decodes HPACK header blocks (RFC 7541) from one client. The table of
+  // headers seen carries over from block to block.
+  struct HeaderDecoder {
+    // The newest last.
+    table: Vec<(Vec<u8>, Vec<u8>)>,
+    size: uint,
+    max_size: uint,
+    huffman: Huffman,
+  }
+
+  impl HeaderDecoder {
+    fn new() -> HeaderDecoder {
+      HeaderDecoder { table: Vec::new(), size: 0, max_size: DEFAULT_TABLE_SIZE, huffman: Huffman::new() }
+    }
+
+    fn decode(&mut self, block: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>, &'static str> {
+      let mut headers = Vec::new();
+      let mut at = 0;
+      while at < block.len() {
+        let first = block[at];
+        if first & 0x80 != 0 {
+          let index = try!(integer(block, &mut at, 7));
+          headers.push(try!(self.entry(index)));
+        } else if first & 0x40 != 0 {
+          let header = try!(self.literal(block, &mut at, 6));
+          self.insert(header.clone());
+          headers.push(header);
+        } else if first & 0x20 != 0 {
+          let size = try!(integer(block, &mut at, 5));
+          if size > DEFAULT_TABLE_SIZE {
+            return Err("Header table size over the maximum");
+          }
+          self.max_size = size;
+          self.evict(0);
+        } else {
+          // Not indexed, or never indexed: the same for us.
+          headers.push(try!(self.literal(block, &mut at, 4)));
+        }
+      }
+      Ok(headers)
+    }
+
+    // The header at 'index': the static table of RFC 7541 appendix A, then
+    // ours, newest first.
+    fn entry(&self, index: uint) -> Result<(Vec<u8>, Vec<u8>), &'static str> {
+      if index == 0 {
+        Err("Header index 0")
+      } else if index <= STATIC_TABLE.len() {
+        let (name, value) = STATIC_TABLE[index - 1];
+        Ok((name.as_bytes().to_vec(), value.as_bytes().to_vec()))
+      } else if index - STATIC_TABLE.len() <= self.table.len() {
+        Ok(self.table.get(self.table.len() + STATIC_TABLE.len() - index).clone())
+      } else {
+        Err("Header index out of range")
+      }
+    }
+
+    fn literal(&self, block: &[u8], at: &mut uint, prefix_bits: uint) -> Result<(Vec<u8>, Vec<u8>), &'static str> {
+      let name = match try!(integer(block, at, prefix_bits)) {
+        0 => try!(self.string(block, at)),
+        index => {
+          let (name, _) = try!(self.entry(index));
+          name
+        },
+      };
+      Ok((name, try!(self.string(block, at))))
+    }
+
+    fn string(&self, block: &[u8], at: &mut uint) -> Result<Vec<u8>, &'static str> {
+      if *at >= block.len() {
+        return Err("Header block ends in a header");
+      }
+      let huffman = block[*at] & 0x80 != 0;
+      let length = try!(integer(block, at, 7));
+      if length > block.len() - *at {
+        return Err("Header block ends in a string");
+      }
+      let string = block.slice(*at, *at + length);
+      *at += length;
+      if huffman { self.huffman.decode(string) } else { Ok(string.to_vec()) }
+    }
+
+    fn insert(&mut self, header: (Vec<u8>, Vec<u8>)) {
+      let size = entry_size(&header);
+      self.evict(size);
+      // An entry bigger than the table empties it and isn't added.
+      if size <= self.max_size {
+        self.size += size;
+        self.table.push(header);
+      }
+    }
+
+    // Drops the oldest entries until 'room' more bytes fit.
+    fn evict(&mut self, room: uint) {
+      while !self.table.is_empty() && self.size + room > self.max_size {
+        let oldest = self.table.remove(0).unwrap();
+        self.size -= entry_size(&oldest);
+      }
+    }
+  }
+
+  fn entry_size(&(ref name, ref value): &(Vec<u8>, Vec<u8>)) -> uint {
+    name.len() + value.len() + 32
+  }
+
+  fn integer(block: &[u8], at: &mut uint, prefix_bits: uint) -> Result<uint, &'static str> {
+    let limit = (1u << prefix_bits) - 1;
+    if *at >= block.len() {
+      return Err("Header block ends in an integer");
+    }
+    let mut value = block[*at] as uint & limit;
+    *at += 1;
+    if value < limit {
+      return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+      if *at >= block.len() || shift > 21 {
+        return Err("Bad integer in a header block");
+      }
+      let byte = block[*at] as uint;
+      *at += 1;
+      value += (byte & 0x7f) << shift;
+      shift += 7;
+      if byte & 0x80 == 0 {
+        return Ok(value);
+      }
+    }
+  }
+
+
+  // The Huffman code of RFC 7541 appendix B is canonical, so it is decoded
+  // the way inflate decodes: from the number of codes of each length and
+  // the symbols in order of their codes.
+  struct Huffman {
+    counts: [uint, ..31],
+    symbols: Vec<uint>,
+  }
+
+  impl Huffman {
+    fn new() -> Huffman {
+      let mut counts = [0u, ..31];
+      for &length in HUFFMAN_LENGTHS.iter() {
+        counts[length as uint] += 1;
+      }
+      let mut symbols: Vec<uint> = range(0u, HUFFMAN_LENGTHS.len()).collect();
+      symbols.sort_by(|&a, &b| (HUFFMAN_LENGTHS[a], a).cmp(&(HUFFMAN_LENGTHS[b], b)));
+      Huffman { counts: counts, symbols: symbols }
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, &'static str> {
+      let mut decoded = Vec::new();
+      // The code read so far, its length, and the first code of that length
+      // along with its place in 'symbols'.
+      let (mut code, mut length, mut first, mut index) = (0u, 0u, 0u, 0u);
+      for &byte in bytes.iter() {
+        for bit in range(0u, 8).rev() {
+          code = code << 1 | (byte as uint >> bit) & 1;
+          length += 1;
+          if length >= self.counts.len() {
+            return Err("Bad Huffman code");
+          }
+          let count = self.counts[length];
+          if code < first + count {
+            match *self.symbols.get(index + code - first) {
+              EOS => return Err("Huffman end of string in a string"),
+              symbol => decoded.push(symbol as u8),
+            }
+            code = 0;
+            length = 0;
+            first = 0;
+            index = 0;
+          } else {
+            index += count;
+            first = (first + count) << 1;
+          }
+        }
+      }
+      // The last byte is padded with the start of EOS, all ones.
+      if length > 7 || code != (1 << length) - 1 {
+        return Err("Bad Huffman padding");
+      }
+      Ok(decoded)
+    }
+  }
+
+  static EOS: uint = 256;
+
+  // The length of the code of each byte value, and of EOS.
+  static HUFFMAN_LENGTHS: [u8, ..257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 30, 28,
+    28, 28, 28, 28, 28, 28, 28, 28, 6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6,
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10, 13, 6, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6,
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5, 6, 7, 6, 5, 5, 6, 7, 7,
+    7, 7, 7, 15, 11, 14, 13, 28, 20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24, 22, 21, 20, 22, 22, 23, 23, 21,
+    23, 22, 22, 24, 21, 22, 23, 23, 21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25, 19, 21, 26, 27, 27, 26, 27, 24,
+    21, 21, 26, 26, 28, 27, 27, 27, 20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26, 30,
+  ];
+
+  static STATIC_TABLE: [(&'static str, &'static str), ..61] = [
+    (":authority", ""), (":method", "GET"), (":method", "POST"), (":path", "/"), (":path", "/index.html"),
+    (":scheme", "http"), (":scheme", "https"), (":status", "200"), (":status", "204"), (":status", "206"),
+    (":status", "304"), (":status", "400"), (":status", "404"), (":status", "500"), ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"), ("accept-language", ""), ("accept-ranges", ""), ("accept", ""),
+    ("access-control-allow-origin", ""), ("age", ""), ("allow", ""), ("authorization", ""),
+    ("cache-control", ""), ("content-disposition", ""), ("content-encoding", ""), ("content-language", ""),
+    ("content-length", ""), ("content-location", ""), ("content-range", ""), ("content-type", ""),
+    ("cookie", ""), ("date", ""), ("etag", ""), ("expect", ""), ("expires", ""), ("from", ""), ("host", ""),
+    ("if-match", ""), ("if-modified-since", ""), ("if-none-match", ""), ("if-range", ""),
+    ("if-unmodified-since", ""), ("last-modified", ""), ("link", ""), ("location", ""), ("max-forwards", ""),
+    ("proxy-authenticate", ""), ("proxy-authorization", ""), ("range", ""), ("referer", ""), ("refresh", ""),
+    ("retry-after", ""), ("server", ""), ("set-cookie", ""), ("strict-transport-security", ""),
+    ("transfer-encoding", ""), ("user-agent", ""), ("vary", ""), ("via", ""), ("www-authenticate", ""),
+  ];
+
+
+  #[cfg(test)]
+  mod test {
+    use super::{HeaderDecoder, Message, uint_field, put_header, check_setting};
+    use super::{INITIAL_WINDOW_SIZE, MAX_FRAME_SIZE, PROTOCOL_ERROR, FLOW_CONTROL_ERROR};
+
+    fn decode(decoder: &mut HeaderDecoder, block: &[u8]) -> Vec<(String, String)> {
+      decoder.decode(block).unwrap().into_iter().map(|(name, value)| {
+        (String::from_utf8(name).unwrap(), String::from_utf8(value).unwrap())
+      }).collect()
+    }
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+      (name.to_string(), value.to_string())
+    }
+
+    // The Huffman coded requests of RFC 7541 appendix C.4, the second using
+    // the table left by the first.
+    #[test]
+    fn header_blocks_decode_with_their_table() {
+      let mut decoder = HeaderDecoder::new();
+      let first = [0x82u8, 0x86, 0x84, 0x41, 0x8c, 0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4,
+                   0xff];
+      assert_eq!(decode(&mut decoder, first.as_slice()),
+                 vec!(pair(":method", "GET"), pair(":scheme", "http"), pair(":path", "/"),
+                      pair(":authority", "www.example.com")));
+      let second = [0x82u8, 0x86, 0x84, 0xbe, 0x58, 0x86, 0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf];
+      assert_eq!(decode(&mut decoder, second.as_slice()),
+                 vec!(pair(":method", "GET"), pair(":scheme", "http"), pair(":path", "/"),
+                      pair(":authority", "www.example.com"), pair("cache-control", "no-cache")));
+      // Index 70 is past both tables.
+      assert!(decoder.decode([0xc6u8].as_slice()).is_err());
+
+      let mut block = Vec::new();
+      put_header(&mut block, "grpc-status", "0");
+      assert_eq!(decode(&mut decoder, block.as_slice()), vec!(pair("grpc-status", "0")));
+    }
+
+    #[test]
+    fn messages_round_trip() {
+      let mut message = Message::new();
+      message.varint(2, 300);
+      message.bytes(3, [7u8, 8].as_slice());
+      message.varint(1, 50_000);
+      let encoded = message.finish();
+      assert_eq!(encoded.slice_to(5), [0u8, 0, 0, 0, 11].as_slice());
+      assert_eq!(uint_field(encoded.as_slice(), 1), Some(50_000));
+      assert_eq!(uint_field(encoded.as_slice(), 2), Some(300));
+      assert_eq!(uint_field(encoded.as_slice(), 4), None);
+      // Cut off in the middle of a field.
+      assert_eq!(uint_field(encoded.slice_to(6), 2), None);
+    }
+
+    #[test]
+    fn settings_out_of_range_are_errors() {
+      let code = |id: u16, value: u32| check_setting(id, value).err().map(|(code, _)| code);
+      assert!(check_setting(MAX_FRAME_SIZE, 16384).is_ok());
+      assert!(check_setting(MAX_FRAME_SIZE, 0xff_ffff).is_ok());
+      assert_eq!(code(MAX_FRAME_SIZE, 0), Some(PROTOCOL_ERROR));
+      assert_eq!(code(MAX_FRAME_SIZE, 0x100_0000), Some(PROTOCOL_ERROR));
+      assert!(check_setting(INITIAL_WINDOW_SIZE, 0).is_ok());
+      assert_eq!(code(INITIAL_WINDOW_SIZE, 0x8000_0000), Some(FLOW_CONTROL_ERROR));
+    }
+  }
+}
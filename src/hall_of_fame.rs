@@ -0,0 +1,177 @@
+use std::cmp::max;
+use std::collections::HashSet;
+use std::io::IoResult;
+use std::io::fs::readdir;
+use time::get_time;
+
+use turing::Machine;
+use event_log;
+use png;
+
+
+// The longer side of thumbnails, in pixels.
+static THUMBNAIL_SIZE: uint = 160;
+
+
+/// Saves machines worth keeping before the loop moves on: a machine whose
+/// last 'windows' windows of 'window_frames' frames each had at least
+/// 'min_rate' percent of steps change a cell has its table and a PNG
+/// thumbnail written to the hall of fame directory, once. Tables already in
+/// the directory, from this run or an earlier one, aren't saved again.
+pub struct HallOfFame {
+  dir: Path,
+  window_frames: u64,
+  windows: u64,
+  min_rate: u64,
+  // The current window's frames, steps and changes so far.
+  frames: u64,
+  steps: u64,
+  changes: u64,
+  // Busy windows in a row so far.
+  busy_windows: u64,
+  saved: bool,
+  // The fingerprints of the tables in 'dir'.
+  fingerprints: HashSet<u64>,
+}
+
+impl HallOfFame {
+  pub fn new(dir: Path, window_frames: u64, windows: u64, min_rate: u64) -> HallOfFame {
+    let fingerprints = saved_fingerprints(&dir);
+    HallOfFame { dir: dir, window_frames: window_frames, windows: windows, min_rate: min_rate, frames: 0,
+                 steps: 0, changes: 0, busy_windows: 0, saved: false, fingerprints: fingerprints }
+  }
+
+  /// Starts watching a new machine.
+  pub fn begin(&mut self) {
+    self.frames = 0;
+    self.steps = 0;
+    self.changes = 0;
+    self.busy_windows = 0;
+    self.saved = false;
+  }
+
+  /// Accounts for a frame of 'steps' steps that changed 'changes' pixels,
+  /// saving 'machine' if it has earned it and its table isn't saved yet.
+  /// Returns the path of the saved table.
+  pub fn frame(&mut self, machine: &Machine, steps: u64, changes: u64) -> IoResult<Option<Path>> {
+    self.frames += 1;
+    self.steps += steps;
+    self.changes += changes;
+    if self.frames < self.window_frames {
+      return Ok(None);
+    }
+    if self.steps > 0 && self.changes * 100 >= self.min_rate * self.steps {
+      self.busy_windows += 1;
+    } else {
+      self.busy_windows = 0;
+    }
+    self.frames = 0;
+    self.steps = 0;
+    self.changes = 0;
+    if self.saved || self.busy_windows < self.windows {
+      return Ok(None);
+    }
+    self.saved = true;
+    let fingerprint = event_log::fingerprint(machine.table_text().as_slice());
+    if !self.fingerprints.insert(fingerprint) {
+      return Ok(None);
+    }
+    self.save(machine, fingerprint).map(|path| Some(path))
+  }
+
+  // Writes the table as '<time>-<fingerprint>.toml' and the thumbnail next
+  // to it.
+  fn save(&self, machine: &Machine, fingerprint: u64) -> IoResult<Path> {
+    let name = format!("{}-{:016x}", get_time().sec, fingerprint);
+    let table = self.dir.join(format!("{}.toml", name));
+    try!(machine.save_table(&table));
+    let (width, height) = machine.image_size();
+    let (thumbnail, thumbnail_width, thumbnail_height) = thumbnail(machine.image(), width, height);
+    try!(png::write_png(&self.dir.join(format!("{}.png", name)), thumbnail.as_slice(), thumbnail_width,
+                        thumbnail_height, &[]));
+    Ok(table)
+  }
+}
+
+
+// The fingerprints of the '<time>-<fingerprint>.toml' tables in 'dir'.
+fn saved_fingerprints(dir: &Path) -> HashSet<u64> {
+  let names = readdir(dir).unwrap_or(Vec::new());
+  names.iter().filter_map(|path| path.filename_str()).filter_map(|name| {
+    match name.find('-') {
+      Some(dash) if name.ends_with(".toml") => {
+        ::std::num::from_str_radix::<u64>(name.slice(dash + 1, name.len() - ".toml".len()), 16)
+      },
+      _ => None,
+    }
+  }).collect()
+}
+
+
+// 'image' shrunk by nearest neighbor sampling so its longer side is at most
+// THUMBNAIL_SIZE pixels, and its size.
+fn thumbnail(image: &[u8], width: uint, height: uint) -> (Vec<u8>, uint, uint) {
+  let longer = max(width, height);
+  if longer <= THUMBNAIL_SIZE {
+    return (image.to_vec(), width, height);
+  }
+  let (thumbnail_width, thumbnail_height) = (max(1, width * THUMBNAIL_SIZE / longer),
+                                             max(1, height * THUMBNAIL_SIZE / longer));
+  let mut thumbnail = Vec::with_capacity(thumbnail_width * thumbnail_height * 3);
+  for y in range(0, thumbnail_height) {
+    let row = y * height / thumbnail_height * width;
+    for x in range(0, thumbnail_width) {
+      let p = (row + x * width / thumbnail_width) * 3;
+      thumbnail.push_all(image.slice(p, p + 3));
+    }
+  }
+  (thumbnail, thumbnail_width, thumbnail_height)
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::io::USER_RWX;
+  use std::io::fs::{mkdir, readdir, rmdir_recursive};
+
+  use turing::{TuringMachine, NoFeature, Cardinal};
+  use turing::rng::SessionRng;
+
+  use super::{HallOfFame, thumbnail};
+
+  #[test]
+  fn thumbnails_keep_the_aspect_ratio() {
+    let image = Vec::from_elem(320 * 40 * 3, 7u8);
+    let (small, width, height) = thumbnail(image.as_slice(), 320, 40);
+    assert_eq!((width, height), (160, 20));
+    assert_eq!(small.len(), 160 * 20 * 3);
+    assert_eq!(thumbnail(&[1, 2, 3], 1, 1), (vec!(1, 2, 3), 1, 1));
+  }
+
+  #[test]
+  fn saves_busy_machines_once() {
+    let dir = ::std::os::tmpdir().join(format!("turing-hall-of-fame-{}", ::std::os::getpid()));
+    mkdir(&dir, USER_RWX).unwrap();
+    let machine = TuringMachine::new(4, 4, 2, 2, NoFeature, Cardinal, &mut SessionRng::new(0));
+    let mut hall = HallOfFame::new(dir.clone(), 2, 2, 50);
+    // A quiet window breaks the run of busy ones.
+    let frames = [(10, 10), (10, 10), (10, 1), (10, 1), (10, 6), (10, 5), (10, 9), (10, 9)];
+    let saved: Vec<bool> = frames.iter().map(|&(steps, changes)| {
+      hall.frame(&*machine, steps, changes).unwrap().is_some()
+    }).collect();
+    assert_eq!(saved, vec!(false, false, false, false, false, false, false, true));
+    assert!(hall.frame(&*machine, 10, 10).unwrap().is_none());
+    assert!(hall.frame(&*machine, 10, 10).unwrap().is_none());
+    // Nor is the same table saved for a later machine, or in a later run.
+    hall.begin();
+    assert!(hall.frame(&*machine, 10, 10).unwrap().is_none());
+    assert!(hall.frame(&*machine, 10, 10).unwrap().is_none());
+    assert!(hall.frame(&*machine, 10, 10).unwrap().is_none());
+    assert!(hall.frame(&*machine, 10, 10).unwrap().is_none());
+    let mut hall = HallOfFame::new(dir.clone(), 1, 1, 50);
+    assert!(hall.frame(&*machine, 10, 10).unwrap().is_none());
+    let files = readdir(&dir).unwrap();
+    rmdir_recursive(&dir).unwrap();
+    assert_eq!(files.len(), 2);
+  }
+}
@@ -0,0 +1,277 @@
+use serialize::json::Json;
+use toml;
+
+use super::{Machine, TuringMachine, Color, NORTH, EAST, SOUTH, WEST, NORTHEAST, SOUTHEAST, SOUTHWEST, NORTHWEST};
+use error::{MachineError, BadState};
+use neighbors::OFF_TAPE;
+use rng::SessionRng;
+use session;
+
+
+/// A Life-like cellular automaton rule in B/S notation: a dead cell is born
+/// with a number of live neighbors in 'birth', and a live one survives with
+/// a number in 'survive'. Conway's Life is "B3/S23".
+#[deriving(PartialEq)]
+pub struct LifeRule {
+  birth: [bool, ..9],
+  survive: [bool, ..9],
+}
+
+impl LifeRule {
+  /// Parses "B<digits>/S<digits>", either part possibly empty, e.g. "B36/S23"
+  /// or "B2/S". Letters may be lower case.
+  pub fn parse(text: &str) -> Option<LifeRule> {
+    let parts: Vec<&str> = text.split('/').collect();
+    if parts.len() != 2 {
+      return None;
+    }
+    match (counts(*parts.get(0), 'B'), counts(*parts.get(1), 'S')) {
+      (Some(birth), Some(survive)) => Some(LifeRule { birth: birth, survive: survive }),
+      _ => None,
+    }
+  }
+
+  /// Whether a cell is alive next generation, given whether it is alive now
+  /// and its live neighbors.
+  pub fn alive(&self, alive: bool, neighbors: uint) -> bool {
+    if alive { self.survive[neighbors] } else { self.birth[neighbors] }
+  }
+}
+
+
+// The neighbor counts after 'letter' in one part of a B/S rule.
+fn counts(part: &str, letter: char) -> Option<[bool, ..9]> {
+  let mut chars = part.chars();
+  match chars.next() {
+    Some(c) if c.to_uppercase() == letter => {},
+    _ => return None,
+  }
+  let mut counts = [false, ..9];
+  for c in chars {
+    match c.to_digit(10) {
+      Some(count) if count < 9 => counts[count] = true,
+      _ => return None,
+    }
+  }
+  Some(counts)
+}
+
+
+static MOORE: [super::Direction, ..8] = [NORTH, NORTHEAST, EAST, SOUTHEAST, SOUTH, SOUTHWEST, WEST, NORTHWEST];
+
+
+/// A single tape machine whose tape is also the substrate of a Life-like
+/// cellular automaton: every 'ca_steps' steps the whole tape moves on a
+/// generation under 'rule', while the heads keep writing. Cells with a
+/// symbol other than 0 are alive; surviving cells keep their symbol, born
+/// ones get symbol 1 and dying ones 0. Neighbors follow the tape's boundary,
+/// cells off a tape with edges being dead.
+pub struct HybridMachine {
+  machine: Box<TuringMachine>,
+  rule: LifeRule,
+  ca_steps: uint,
+  // Steps since the last generation.
+  since: uint,
+  // The eight neighbors of each cell, OFF_TAPE for those off the tape, looked
+  // up once since the tape's edges don't change.
+  neighbors: Vec<uint>,
+  // The tape as the last generation found it, kept between generations.
+  cells: Vec<u8>,
+}
+
+impl HybridMachine {
+  /// Runs 'machine', which must have a dense square tape with its boundary
+  /// set, on a substrate moving on a generation every 'ca_steps' steps.
+  pub fn new(machine: Box<TuringMachine>, rule: LifeRule, ca_steps: uint) -> Box<HybridMachine> {
+    let len = machine.tape.len();
+    let mut neighbors = Vec::with_capacity(len * MOORE.len());
+    for position in range(0, len) {
+      for &direction in MOORE.iter() {
+        neighbors.push(match machine.tape.neighbor(position, direction) {
+          // A reflected or clamped move can land back on the cell itself.
+          neighbor if neighbor == position => OFF_TAPE,
+          neighbor => neighbor,
+        });
+      }
+    }
+    box HybridMachine { machine: machine, rule: rule, ca_steps: ca_steps, since: 0, neighbors: neighbors,
+                        cells: Vec::from_elem(len, 0u8) }
+  }
+
+  // Moves the substrate on a generation, writing and repainting only the
+  // cells that change. Returns true if one did.
+  fn evolve(&mut self) -> bool {
+    self.machine.tape.copy_cells(self.cells.as_mut_slice());
+    let (machine, rule, cells) = (&mut self.machine, &self.rule, self.cells.as_slice());
+    let mut changed = false;
+    for (position, around) in self.neighbors.as_slice().chunks(MOORE.len()).enumerate() {
+      let symbol = cells[position];
+      let neighbors = around.iter().filter(|&&neighbor| neighbor != OFF_TAPE && cells[neighbor] != 0).count();
+      let next = match (symbol != 0, rule.alive(symbol != 0, neighbors)) {
+        (true, true) => symbol,
+        (false, true) => 1,
+        _ => 0,
+      };
+      if next != symbol {
+        machine.write_cell(position, next);
+        changed = true;
+      }
+    }
+    changed
+  }
+}
+
+impl Machine for HybridMachine {
+  fn step(&mut self) -> bool {
+    let mut changed = self.machine.step();
+    self.since += 1;
+    if self.since == self.ca_steps {
+      self.since = 0;
+      changed = self.evolve() || changed;
+    }
+    changed
+  }
+
+  fn set_palette(&mut self, palette: &Vec<Color>) {
+    self.machine.set_palette(palette);
+  }
+
+  fn redraw(&mut self, lut: bool) {
+    self.machine.redraw(lut);
+  }
+
+  fn image<'a>(&'a self) -> &'a [u8] {
+    self.machine.image()
+  }
+
+  fn image_size(&self) -> (uint, uint) {
+    self.machine.image_size()
+  }
+
+  fn tape_size(&self) -> (uint, uint) {
+    self.machine.tape_size()
+  }
+
+  fn symbols(&self) -> u8 {
+    self.machine.symbols()
+  }
+
+  fn fill_tape(&mut self, symbol: |uint| -> u8) {
+    self.machine.fill_tape(symbol);
+  }
+
+  fn reset(&mut self, rng: &mut SessionRng) {
+    self.machine.reset(rng);
+    self.since = 0;
+  }
+
+  fn chain(&mut self, rng: &mut SessionRng) {
+    self.machine.chain(rng);
+  }
+
+  fn mutate(&mut self, rng: &mut SessionRng, count: uint) {
+    self.machine.mutate(rng, count);
+  }
+
+  fn table_text(&self) -> String {
+    self.machine.table_text()
+  }
+
+  fn table_json(&self) -> Json {
+    self.machine.table_json()
+  }
+
+  fn save_state(&self) -> String {
+    format!("{}ca_since = {}\n", self.machine.save_state(), self.since)
+  }
+
+  fn load_state(&mut self, saved: &toml::Value) -> Result<(), MachineError> {
+    let since = try!(session::int(saved, "ca_since"));
+    if since < 0 || since >= self.ca_steps as i64 {
+      return Err(BadState(format!("Saved ca_since out of range: {}", since)));
+    }
+    try!(self.machine.load_state(saved));
+    self.since = since as uint;
+    Ok(())
+  }
+
+  fn load_table(&mut self, table: &toml::Value) -> Result<(), MachineError> {
+    self.machine.load_table(table)
+  }
+
+  fn explain_step(&self) -> String {
+    let explained = self.machine.explain_step();
+    if self.since + 1 == self.ca_steps {
+      format!("{}, then the substrate moves on a generation", explained)
+    } else {
+      explained
+    }
+  }
+
+  fn tape_text(&self) -> String {
+    self.machine.tape_text()
+  }
+
+  fn symbols_used(&self) -> uint {
+    self.machine.symbols_used()
+  }
+
+  fn halted(&self) -> bool {
+    self.machine.halted()
+  }
+
+  fn head_cells(&self) -> Vec<(uint, uint, uint, uint)> {
+    self.machine.head_cells()
+  }
+
+  fn end_frame(&mut self) {
+    self.machine.end_frame();
+  }
+
+  // The substrate can change any cell, so the heads' surroundings don't
+  // tell a cycle.
+  fn local_hash(&self) -> Option<u64> {
+    None
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::super::{Machine, TuringMachine, NoFeature, Cardinal, STAY};
+  use rng::SessionRng;
+  use table::Table;
+
+  use super::{HybridMachine, LifeRule};
+
+  #[test]
+  fn rules_parse_in_b_s_notation() {
+    let life = LifeRule::parse("B3/S23").unwrap();
+    assert!(life.alive(false, 3) && life.alive(true, 2) && !life.alive(true, 4) && !life.alive(false, 2));
+    assert!(LifeRule::parse("b2/s") == LifeRule::parse("B2/S"));
+    assert!(LifeRule::parse("B9/S23").is_none());
+    assert!(LifeRule::parse("S23/B3").is_none());
+    assert!(LifeRule::parse("B3S23").is_none());
+  }
+
+  #[test]
+  fn blinkers_blink_every_ca_steps() {
+    // A head that stays put on a blank cell, writing nothing.
+    let mut machine = TuringMachine::new(5, 5, 1, 3, NoFeature, Cardinal, &mut SessionRng::new(0));
+    *machine.table_mut() = Table::from_entries(1, 3, 1, vec!((0, 0, STAY), (0, 1, STAY), (0, 2, STAY))).unwrap();
+    machine.fill_tape(|position| if position >= 11 && position <= 13 { 2 } else { 0 });
+    let mut hybrid = HybridMachine::new(machine, LifeRule::parse("B3/S23").unwrap(), 2);
+    assert!(!hybrid.step());
+    assert!(hybrid.step());
+    // The middle survives with its symbol, the ends are born with 1.
+    assert_eq!(hybrid.machine.tape(), vec!(0, 0, 0, 0, 0,
+                                           0, 0, 1, 0, 0,
+                                           0, 0, 2, 0, 0,
+                                           0, 0, 1, 0, 0,
+                                           0, 0, 0, 0, 0));
+    let saved = hybrid.save_state();
+    hybrid.step();
+    hybrid.load_state(&from_str(saved.as_slice()).unwrap()).unwrap();
+    assert_eq!(hybrid.since, 0);
+  }
+}
@@ -0,0 +1,15 @@
+use std::io::{File, IoResult};
+
+
+/// Writes a bgr24 image as a binary PPM (P6) file.
+pub fn write_ppm(path: &Path, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+  let mut file = try!(File::create(path));
+  try!(write!(file, "P6\n{} {}\n255\n", width, height));
+  let mut rgb = Vec::with_capacity(image.len());
+  for pixel in image.chunks(3) {
+    rgb.push(pixel[2]);
+    rgb.push(pixel[1]);
+    rgb.push(pixel[0]);
+  }
+  file.write(rgb.as_slice())
+}
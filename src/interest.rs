@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::num::Float;
+
+use turing::Machine;
+use turing::rng::SessionRng;
+use replay;
+
+
+/// Screens random machines before they are shown. Many random tables only
+/// draw a dot or a single line; a warm-up run scores each new machine and
+/// ones scoring below the minimum are replaced before any of their frames
+/// are written.
+pub struct Interest {
+  // Warm-up steps per machine.
+  steps: u64,
+  // The smallest score kept, in percent.
+  min: u64,
+  // Machines tried before settling for the last one.
+  attempts: u64,
+}
+
+impl Interest {
+  pub fn new(steps: u64, min: u64, attempts: u64) -> Interest {
+    Interest { steps: steps, min: min, attempts: attempts }
+  }
+
+  /// Replaces 'machine' with new random ones from 'rng', like a reset (or a
+  /// chain, with 'chain'), until one scores at least the minimum or the
+  /// attempts run out. The kept machine is left as it was before its
+  /// warm-up. Returns the draws from 'rng' before the kept machine's table,
+  /// if it isn't the one passed in.
+  pub fn screen(&self, machine: &mut Machine, rng: &mut SessionRng, chain: bool) -> Option<u64> {
+    let mut draws = None;
+    for attempt in range(0, self.attempts) {
+      let start = machine.save_state();
+      let score = score(machine, self.steps);
+      let _ = replay::restore(machine, start.as_slice());
+      if score * 100.0 >= self.min as f64 || attempt + 1 == self.attempts {
+        break;
+      }
+      draws = Some(rng.draws());
+      if chain {
+        machine.chain(rng);
+      } else {
+        machine.reset(rng);
+      }
+    }
+    draws
+  }
+}
+
+
+/// Runs 'machine' for 'steps' steps and scores how interesting it looks,
+/// from 0 to 1: the share of steps that changed a cell, times the entropy of
+/// the image's colors relative to the most the symbols could have.
+pub fn score(machine: &mut Machine, steps: u64) -> f64 {
+  let mut changes = 0u64;
+  for _ in range(0, steps) {
+    if machine.step() {
+      changes += 1;
+    }
+  }
+  let rate = changes as f64 / steps as f64;
+  if machine.symbols() < 2 {
+    return 0.0;
+  }
+  rate * color_entropy(machine.image()) / (machine.symbols() as f64).log2()
+}
+
+
+// The Shannon entropy in bits of the colors of a bgr24 image's pixels.
+fn color_entropy(image: &[u8]) -> f64 {
+  let mut counts: HashMap<u32, uint> = HashMap::new();
+  for pixel in image.chunks(3) {
+    let color = pixel[0] as u32 << 16 | pixel[1] as u32 << 8 | pixel[2] as u32;
+    match counts.find_mut(&color) {
+      Some(count) => {
+        *count += 1;
+        continue;
+      },
+      None => {},
+    }
+    counts.insert(color, 1);
+  }
+  let pixels = (image.len() / 3) as f64;
+  counts.values().fold(0.0, |entropy, &count| {
+    let p = count as f64 / pixels;
+    entropy - p * p.log2()
+  })
+}
+
+
+#[cfg(test)]
+mod test {
+  use turing::{TuringMachine, Machine, NoFeature, Cardinal, BLACK, WHITE};
+  use turing::rng::SessionRng;
+
+  use super::{Interest, score, color_entropy};
+
+  fn machine(seed: u64) -> Box<TuringMachine> {
+    let mut machine = TuringMachine::new(16, 16, 3, 2, NoFeature, Cardinal, &mut SessionRng::new(seed));
+    machine.set_palette(&vec!(BLACK, WHITE));
+    machine
+  }
+
+  #[test]
+  fn entropy_counts_colors() {
+    assert_eq!(color_entropy([0u8, 0, 0, 0, 0, 0].as_slice()), 0.0);
+    assert_eq!(color_entropy([0u8, 0, 0, 255, 255, 255].as_slice()), 1.0);
+  }
+
+  #[test]
+  fn scores_are_between_zero_and_one() {
+    // With one symbol every step writes what is already there.
+    let mut dull = TuringMachine::new(16, 16, 3, 1, NoFeature, Cardinal, &mut SessionRng::new(0));
+    assert_eq!(score(&mut *dull, 100), 0.0);
+    for seed in range(0u64, 10) {
+      let score = score(&mut *machine(seed), 1000);
+      assert!(score >= 0.0 && score <= 1.0);
+    }
+  }
+
+  #[test]
+  fn screening_restores_the_kept_machine() {
+    let mut machine = machine(1);
+    let start = machine.save_state();
+    // Nothing scores over 100%, so every attempt is rejected but the last.
+    let mut rng = SessionRng::new(2);
+    assert!(Interest::new(50, 101, 3).screen(&mut *machine, &mut rng, false).is_some());
+    assert!(machine.save_state() != start);
+    let kept = machine.save_state();
+    assert!(Interest::new(50, 0, 3).screen(&mut *machine, &mut rng, false).is_none());
+    assert_eq!(machine.save_state(), kept);
+  }
+}
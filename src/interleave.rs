@@ -0,0 +1,233 @@
+use std::hash::hash;
+use serialize::json;
+use serialize::json::Json;
+use toml;
+
+use super::{Machine, TuringMachine, Color, distinct_symbols};
+use error::{MachineError, BadTable, BadState};
+use rng::SessionRng;
+
+
+/// How the regions of the frame are divided between the two machines.
+#[deriving(PartialEq,Eq,Show)]
+pub enum RegionPattern {
+  /// Vertical stripes 'size' cells wide, alternating between the machines.
+  Stripes,
+  /// A checkerboard of 'size'x'size' cell squares.
+  Checkerboard,
+}
+
+
+/// Two single tape machines running side by side on tapes of the same size,
+/// drawn into one frame with alternating regions taken from each. Where the
+/// machines' patterns meet the regions produce interference-like figures.
+pub struct Interleaved {
+  a: Box<TuringMachine>,
+  b: Box<TuringMachine>,
+  pattern: RegionPattern,
+  // Region size in cells.
+  size: uint,
+  // Composed from both machines' images. Kept up to date by 'step'.
+  image: Vec<u8>,
+}
+
+impl Interleaved {
+  pub fn new(a: Box<TuringMachine>, b: Box<TuringMachine>, pattern: RegionPattern,
+             size: uint) -> Box<Interleaved> {
+    let len = a.image.len();
+    let mut interleaved = box Interleaved {
+      a: a,
+      b: b,
+      pattern: pattern,
+      size: size,
+      image: Vec::from_elem(len, 0u8),
+    };
+    interleaved.rebuild_image();
+    interleaved
+  }
+
+  // True if the cell at 'position' is shown from machine A.
+  fn shows_a(&self, position: uint) -> bool {
+    let x = position % self.a.width / self.size;
+    let y = position / self.a.width / self.size;
+    match self.pattern {
+      Stripes => x % 2 == 0,
+      Checkerboard => (x + y) % 2 == 0,
+    }
+  }
+
+  // Copies the pixels of the cell at 'position' from whichever machine shows it.
+  fn copy_cell(&mut self, position: uint) {
+    let source = if self.shows_a(position) { &self.a.image } else { &self.b.image };
+    let (cell_width, cell_height) = (self.a.cell_width, self.a.cell_height);
+    let row_bytes = self.a.width * cell_width * 3;
+    let x = position % self.a.width * cell_width * 3;
+    let y = position / self.a.width * cell_height;
+    for dy in range(0, cell_height) {
+      let start = (y + dy) * row_bytes + x;
+      for p in range(start, start + cell_width * 3) {
+        *self.image.get_mut(p) = *source.get(p);
+      }
+    }
+  }
+
+  fn rebuild_image(&mut self) {
+    for position in range(0, self.a.width * self.a.height) {
+      self.copy_cell(position);
+    }
+  }
+
+  // 'step' for machines with several heads, where the cell of every head
+  // may change.
+  fn step_heads(&mut self) -> bool {
+    let (heads_a, heads_b) = (self.a.head_positions(), self.b.head_positions());
+    let changed_a = self.a.step();
+    let changed_b = self.b.step();
+    let mut changed = false;
+    for &position in heads_a.iter() {
+      if changed_a && self.shows_a(position) {
+        self.copy_cell(position);
+        changed = true;
+      }
+    }
+    for &position in heads_b.iter() {
+      if changed_b && !self.shows_a(position) {
+        self.copy_cell(position);
+        changed = true;
+      }
+    }
+    changed
+  }
+}
+
+impl Machine for Interleaved {
+  fn step(&mut self) -> bool {
+    if self.a.heads() > 1 || self.b.heads() > 1 {
+      return self.step_heads();
+    }
+    let (position_a, position_b) = (self.a.position, self.b.position);
+    let changed_a = self.a.step() && self.shows_a(position_a);
+    let changed_b = self.b.step() && !self.shows_a(position_b);
+    if changed_a {
+      self.copy_cell(position_a);
+    }
+    if changed_b {
+      self.copy_cell(position_b);
+    }
+    changed_a || changed_b
+  }
+
+  fn set_palette(&mut self, palette: &Vec<Color>) {
+    self.a.set_palette(palette);
+    self.b.set_palette(palette);
+    self.rebuild_image();
+  }
+
+  fn redraw(&mut self, lut: bool) {
+    self.a.redraw(lut);
+    self.b.redraw(lut);
+    self.rebuild_image();
+  }
+
+  fn image<'a>(&'a self) -> &'a [u8] {
+    self.image.as_slice()
+  }
+
+  fn image_size(&self) -> (uint, uint) {
+    self.a.image_size()
+  }
+
+  fn tape_size(&self) -> (uint, uint) {
+    self.a.tape_size()
+  }
+
+  fn symbols(&self) -> u8 {
+    self.a.symbols
+  }
+
+  fn fill_tape(&mut self, symbol: |uint| -> u8) {
+    let len = self.a.width * self.a.height;
+    let tape: Vec<u8> = range(0, len).map(|i| symbol(i)).collect();
+    self.a.fill_tape(|i| *tape.get(i));
+    self.b.fill_tape(|i| *tape.get(i));
+    self.rebuild_image();
+  }
+
+  fn reset(&mut self, rng: &mut SessionRng) {
+    self.a.reset(rng);
+    self.b.reset(rng);
+    self.rebuild_image();
+  }
+
+  fn chain(&mut self, rng: &mut SessionRng) {
+    self.a.chain(rng);
+    self.b.chain(rng);
+  }
+
+  fn mutate(&mut self, rng: &mut SessionRng, count: uint) {
+    self.a.mutate(rng, count);
+    self.b.mutate(rng, count);
+  }
+
+  fn table_text(&self) -> String {
+    format!("[{}, {}]", self.a.table_text(), self.b.table_text())
+  }
+
+  fn table_json(&self) -> Json {
+    json::List(vec!(self.a.table_json(), self.b.table_json()))
+  }
+
+  // Each machine gets a subsection of the caller's section.
+  fn save_state(&self) -> String {
+    format!("\n[machine.a]\n{}\n[machine.b]\n{}", self.a.save_state(), self.b.save_state())
+  }
+
+  fn load_state(&mut self, saved: &toml::Value) -> Result<(), MachineError> {
+    match (saved.lookup("a"), saved.lookup("b")) {
+      (Some(a), Some(b)) => {
+        try!(self.a.load_state(a));
+        try!(self.b.load_state(b));
+      },
+      _ => return Err(BadState("Expected saved machines a and b".to_string())),
+    }
+    self.rebuild_image();
+    Ok(())
+  }
+
+  fn load_table(&mut self, table: &toml::Value) -> Result<(), MachineError> {
+    match table.as_slice() {
+      Some([ref a, ref b]) => {
+        try!(self.a.load_table(a));
+        self.b.load_table(b)
+      },
+      _ => Err(BadTable("Expected the tables of machines a and b".to_string())),
+    }
+  }
+
+  fn explain_step(&self) -> String {
+    format!("A: {}; B: {}", self.a.explain_step(), self.b.explain_step())
+  }
+
+  fn tape_text(&self) -> String {
+    format!("Machine A:\n{}Machine B:\n{}", self.a.tape_text(), self.b.tape_text())
+  }
+
+  fn halted(&self) -> bool {
+    self.a.halted() || self.b.halted()
+  }
+
+  fn symbols_used(&self) -> uint {
+    distinct_symbols(&[self.a.tape().as_slice(), self.b.tape().as_slice()])
+  }
+
+  // The heads of both machines, wherever they are shown.
+  fn head_cells(&self) -> Vec<(uint, uint, uint, uint)> {
+    let mut cells = self.a.head_cells();
+    cells.push_all(self.b.head_cells().as_slice());
+    cells
+  }
+
+  fn local_hash(&self) -> Option<u64> {
+    Some(hash(&(self.a.local_hash(), self.b.local_hash())))
+  }
+}
@@ -0,0 +1,381 @@
+use std::f32::consts::PI;
+use std::io::{IoResult, IoError, OtherIoError};
+use std::iter::range_step;
+use std::num::Float;
+
+
+// The order coefficients are written in: the natural (row major) index of
+// each zigzag position.
+static ZIGZAG: [uint, ..64] = [
+   0,  1,  8, 16,  9,  2,  3, 10, 17, 24, 32, 25, 18, 11,  4,  5,
+  12, 19, 26, 33, 40, 48, 41, 34, 27, 20, 13,  6,  7, 14, 21, 28,
+  35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51,
+  58, 59, 52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+// The example quantization tables of the standard (Annex K), in natural
+// order, for quality 50.
+static LUMA_QUANT: [u16, ..64] = [
+  16,  11,  10,  16,  24,  40,  51,  61,
+  12,  12,  14,  19,  26,  58,  60,  55,
+  14,  13,  16,  24,  40,  57,  69,  56,
+  14,  17,  22,  29,  51,  87,  80,  62,
+  18,  22,  37,  56,  68, 109, 103,  77,
+  24,  35,  55,  64,  81, 104, 113,  92,
+  49,  64,  78,  87, 103, 121, 120, 101,
+  72,  92,  95,  98, 112, 100, 103,  99,
+];
+static CHROMA_QUANT: [u16, ..64] = [
+  17, 18, 24, 47, 99, 99, 99, 99,
+  18, 21, 26, 66, 99, 99, 99, 99,
+  24, 26, 56, 99, 99, 99, 99, 99,
+  47, 66, 99, 99, 99, 99, 99, 99,
+  99, 99, 99, 99, 99, 99, 99, 99,
+  99, 99, 99, 99, 99, 99, 99, 99,
+  99, 99, 99, 99, 99, 99, 99, 99,
+  99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+// The example Huffman tables of the standard: the number of codes of each
+// length from 1 to 16 bits, then the symbols in code order.
+static LUMA_DC_BITS: [u8, ..16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+static LUMA_DC_SYMBOLS: [u8, ..12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+static CHROMA_DC_BITS: [u8, ..16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+static CHROMA_DC_SYMBOLS: [u8, ..12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+static LUMA_AC_BITS: [u8, ..16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+static LUMA_AC_SYMBOLS: [u8, ..162] = [
+  0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+  0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+  0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+  0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+  0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+  0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+  0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+  0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+  0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+  0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+  0xf9, 0xfa,
+];
+static CHROMA_AC_BITS: [u8, ..16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+static CHROMA_AC_SYMBOLS: [u8, ..162] = [
+  0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+  0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+  0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+  0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+  0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+  0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+  0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+  0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+  0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+  0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+  0xf9, 0xfa,
+];
+
+
+/// Encodes bgr24 images as baseline JPEG, with the standard's example
+/// tables scaled to a quality from 1 to 100 (as libjpeg does). Chroma is
+/// kept at full resolution: the hard edges between cells smear badly when
+/// it is halved.
+pub struct JpegEncoder {
+  // Quantization tables in natural order.
+  luma_quant: [u16, ..64],
+  chroma_quant: [u16, ..64],
+  // The (code, length) of each symbol.
+  luma_dc: Vec<(u16, u8)>,
+  luma_ac: Vec<(u16, u8)>,
+  chroma_dc: Vec<(u16, u8)>,
+  chroma_ac: Vec<(u16, u8)>,
+  // cos((2x + 1)uπ/16) times the DCT's scale for u, by [u][x].
+  cosines: [[f32, ..8], ..8],
+}
+
+impl JpegEncoder {
+  pub fn new(quality: uint) -> JpegEncoder {
+    let quality = ::std::cmp::max(1, ::std::cmp::min(quality, 100));
+    let mut cosines = [[0f32, ..8], ..8];
+    for u in range(0u, 8) {
+      let scale = if u == 0 { (0.125f32).sqrt() } else { 0.5 };
+      for x in range(0u, 8) {
+        cosines[u][x] = scale * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos();
+      }
+    }
+    JpegEncoder {
+      luma_quant: scale_quant(&LUMA_QUANT, quality),
+      chroma_quant: scale_quant(&CHROMA_QUANT, quality),
+      luma_dc: huffman_codes(&LUMA_DC_BITS, &LUMA_DC_SYMBOLS),
+      luma_ac: huffman_codes(&LUMA_AC_BITS, &LUMA_AC_SYMBOLS),
+      chroma_dc: huffman_codes(&CHROMA_DC_BITS, &CHROMA_DC_SYMBOLS),
+      chroma_ac: huffman_codes(&CHROMA_AC_BITS, &CHROMA_AC_SYMBOLS),
+      cosines: cosines,
+    }
+  }
+
+  /// Encodes a 'width'x'height' bgr24 image as a JPEG file in 'out'. Fails
+  /// for images over 65535 pixels on a side, which JPEG can't describe.
+  pub fn encode(&self, image: &[u8], width: uint, height: uint, out: &mut Vec<u8>) -> IoResult<()> {
+    out.clear();
+    if width > 0xffff || height > 0xffff {
+      return Err(IoError { kind: OtherIoError, desc: "JPEG images are at most 65535 pixels on a side", detail: None });
+    }
+    self.write_headers(width, height, out);
+
+    {
+      let mut bits = BitWriter { out: &mut *out, buffer: 0, count: 0 };
+      let mut previous_dc = [0i32, ..3];
+      let mut blocks = [[0f32, ..64], ..3];
+      for block_y in range_step(0, height, 8) {
+        for block_x in range_step(0, width, 8) {
+          // Edge blocks repeat the last column and row.
+          for y in range(0u, 8) {
+            let row = ::std::cmp::min(block_y + y, height - 1) * width;
+            for x in range(0u, 8) {
+              let p = (row + ::std::cmp::min(block_x + x, width - 1)) * 3;
+              let (b, g, r) = (image[p] as f32, image[p + 1] as f32, image[p + 2] as f32);
+              blocks[0][y * 8 + x] = 0.299 * r + 0.587 * g + 0.114 * b - 128.0;
+              blocks[1][y * 8 + x] = -0.168736 * r - 0.331264 * g + 0.5 * b;
+              blocks[2][y * 8 + x] = 0.5 * r - 0.418688 * g - 0.081312 * b;
+            }
+          }
+          for component in range(0u, 3) {
+            let (quant, dc, ac) = if component == 0 {
+              (&self.luma_quant, &self.luma_dc, &self.luma_ac)
+            } else {
+              (&self.chroma_quant, &self.chroma_dc, &self.chroma_ac)
+            };
+            let coefficients = self.transform(&blocks[component], quant);
+            write_block(&mut bits, &coefficients, &mut previous_dc[component], dc.as_slice(), ac.as_slice());
+          }
+        }
+      }
+      bits.flush();
+    }
+    out.push_all(&[0xff, 0xd9]);
+    Ok(())
+  }
+
+  // The quantized DCT of a level shifted block, in zigzag order.
+  fn transform(&self, block: &[f32, ..64], quant: &[u16, ..64]) -> [i32, ..64] {
+    // Rows, then columns.
+    let mut rows = [0f32, ..64];
+    for y in range(0u, 8) {
+      for u in range(0u, 8) {
+        let mut sum = 0f32;
+        for x in range(0u, 8) {
+          sum += block[y * 8 + x] * self.cosines[u][x];
+        }
+        rows[y * 8 + u] = sum;
+      }
+    }
+    let mut coefficients = [0i32, ..64];
+    for (k, &natural) in ZIGZAG.iter().enumerate() {
+      let (v, u) = (natural / 8, natural % 8);
+      let mut sum = 0f32;
+      for y in range(0u, 8) {
+        sum += rows[y * 8 + u] * self.cosines[v][y];
+      }
+      coefficients[k] = (sum / quant[natural] as f32).round() as i32;
+    }
+    coefficients
+  }
+
+  fn write_headers(&self, width: uint, height: uint, out: &mut Vec<u8>) {
+    out.push_all(&[0xff, 0xd8]);
+    // JFIF 1.1, no density or thumbnail.
+    out.push_all(&[0xff, 0xe0, 0, 16]);
+    out.push_all(b"JFIF\0");
+    out.push_all(&[1, 1, 0, 0, 1, 0, 1, 0, 0]);
+
+    out.push_all(&[0xff, 0xdb, 0, 132]);
+    for (id, &quant) in [&self.luma_quant, &self.chroma_quant].iter().enumerate() {
+      out.push(id as u8);
+      for &natural in ZIGZAG.iter() {
+        out.push(quant[natural] as u8);
+      }
+    }
+
+    // Baseline, 8 bit samples, three components without subsampling.
+    out.push_all(&[0xff, 0xc0, 0, 17, 8]);
+    push_u16(out, height as u16);
+    push_u16(out, width as u16);
+    out.push_all(&[3, 1, 0x11, 0, 2, 0x11, 1, 3, 0x11, 1]);
+
+    let tables: [(u8, &[u8], &[u8]), ..4] = [
+      (0x00, LUMA_DC_BITS.as_slice(), LUMA_DC_SYMBOLS.as_slice()),
+      (0x10, LUMA_AC_BITS.as_slice(), LUMA_AC_SYMBOLS.as_slice()),
+      (0x01, CHROMA_DC_BITS.as_slice(), CHROMA_DC_SYMBOLS.as_slice()),
+      (0x11, CHROMA_AC_BITS.as_slice(), CHROMA_AC_SYMBOLS.as_slice()),
+    ];
+    for &(class_id, bits, symbols) in tables.iter() {
+      out.push_all(&[0xff, 0xc4]);
+      push_u16(out, (3 + bits.len() + symbols.len()) as u16);
+      out.push(class_id);
+      out.push_all(bits);
+      out.push_all(symbols);
+    }
+
+    // One scan of all components and coefficients.
+    out.push_all(&[0xff, 0xda, 0, 12, 3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+  }
+}
+
+
+// Writes a block's coefficients: the change in DC from the last block of the
+// component, then runs of zeros and the AC values.
+fn write_block(bits: &mut BitWriter, coefficients: &[i32, ..64], previous_dc: &mut i32,
+               dc: &[(u16, u8)], ac: &[(u16, u8)]) {
+  let difference = coefficients[0] - *previous_dc;
+  *previous_dc = coefficients[0];
+  let size = category(difference);
+  bits.write_symbol(dc, size);
+  bits.write_value(difference, size);
+
+  let mut zeros = 0u8;
+  for &coefficient in coefficients.as_slice().slice_from(1).iter() {
+    if coefficient == 0 {
+      zeros += 1;
+      continue;
+    }
+    while zeros >= 16 {
+      bits.write_symbol(ac, 0xf0);
+      zeros -= 16;
+    }
+    let size = category(coefficient);
+    bits.write_symbol(ac, zeros << 4 | size);
+    bits.write_value(coefficient, size);
+    zeros = 0;
+  }
+  if zeros > 0 {
+    // End of block.
+    bits.write_symbol(ac, 0x00);
+  }
+}
+
+
+// The number of bits in the magnitude of 'value'.
+fn category(value: i32) -> u8 {
+  let mut magnitude = if value < 0 { -value } else { value };
+  let mut bits = 0u8;
+  while magnitude > 0 {
+    magnitude >>= 1;
+    bits += 1;
+  }
+  bits
+}
+
+
+// The codes for a table's symbols, by symbol: codes of each length count up
+// from twice the code after the last shorter one.
+fn huffman_codes(bits: &[u8], symbols: &[u8]) -> Vec<(u16, u8)> {
+  let mut codes = Vec::from_elem(256, (0u16, 0u8));
+  let mut code = 0u16;
+  let mut next = symbols.iter();
+  for (length, &count) in bits.iter().enumerate() {
+    for _ in range(0, count) {
+      let &symbol = next.next().unwrap();
+      *codes.get_mut(symbol as uint) = (code, (length + 1) as u8);
+      code += 1;
+    }
+    code <<= 1;
+  }
+  codes
+}
+
+
+// 'base' scaled for 'quality', as libjpeg's quality setting does.
+fn scale_quant(base: &[u16, ..64], quality: uint) -> [u16, ..64] {
+  let scale = if quality < 50 { 5000 / quality } else { 200 - 2 * quality };
+  let mut quant = [0u16, ..64];
+  for (scaled, &value) in quant.iter_mut().zip(base.iter()) {
+    *scaled = ::std::cmp::max(1, ::std::cmp::min((value as uint * scale + 50) / 100, 255)) as u16;
+  }
+  quant
+}
+
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+  out.push((value >> 8) as u8);
+  out.push(value as u8);
+}
+
+
+// Packs codes most significant bit first, stuffing a zero byte after each
+// 0xff so it isn't read as a marker.
+struct BitWriter<'a> {
+  out: &'a mut Vec<u8>,
+  buffer: u32,
+  count: uint,
+}
+
+impl<'a> BitWriter<'a> {
+  fn write_bits(&mut self, bits: u32, count: uint) {
+    self.buffer = self.buffer << count | (bits & ((1 << count) - 1));
+    self.count += count;
+    while self.count >= 8 {
+      self.count -= 8;
+      let byte = (self.buffer >> self.count) as u8;
+      self.out.push(byte);
+      if byte == 0xff {
+        self.out.push(0);
+      }
+    }
+  }
+
+  fn write_symbol(&mut self, codes: &[(u16, u8)], symbol: u8) {
+    let (code, length) = codes[symbol as uint];
+    self.write_bits(code as u32, length as uint);
+  }
+
+  // The low 'size' bits of 'value', or of 'value' - 1 if it is negative.
+  fn write_value(&mut self, value: i32, size: u8) {
+    let bits = if value < 0 { value - 1 } else { value };
+    self.write_bits(bits as u32, size as uint);
+  }
+
+  // Pads the last byte with ones.
+  fn flush(&mut self) {
+    if self.count > 0 {
+      let padding = 8 - self.count;
+      self.write_bits(0xff, padding);
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::{JpegEncoder, category, huffman_codes, LUMA_DC_BITS, LUMA_DC_SYMBOLS};
+
+  #[test]
+  fn codes_follow_the_table_lengths() {
+    let codes = huffman_codes(&LUMA_DC_BITS, &LUMA_DC_SYMBOLS);
+    assert_eq!(*codes.get(0), (0b00, 2));
+    assert_eq!(*codes.get(1), (0b010, 3));
+    assert_eq!(*codes.get(5), (0b110, 3));
+    assert_eq!(*codes.get(6), (0b1110, 4));
+    assert_eq!(*codes.get(11), (0b111111110, 9));
+    assert_eq!(category(0), 0);
+    assert_eq!(category(-1), 1);
+    assert_eq!(category(255), 8);
+  }
+
+  #[test]
+  fn writes_a_complete_file() {
+    // 10x9 pixels, so the blocks on the right and bottom are padded.
+    let image = Vec::from_fn(10 * 9 * 3, |i| (i * 7) as u8);
+    let mut out = Vec::new();
+    JpegEncoder::new(80).encode(image.as_slice(), 10, 9, &mut out).unwrap();
+    let jpeg = out.as_slice();
+    assert_eq!(jpeg.slice_to(2), [0xffu8, 0xd8].as_slice());
+    assert_eq!(jpeg.slice_from(jpeg.len() - 2), [0xffu8, 0xd9].as_slice());
+    // The frame header gives the size.
+    let sof = range(0, jpeg.len() - 1).find(|&i| jpeg[i] == 0xff && jpeg[i + 1] == 0xc0).unwrap();
+    assert_eq!(jpeg.slice(sof + 5, sof + 9), [0u8, 9, 0, 10].as_slice());
+  }
+
+  #[test]
+  fn rejects_images_too_big_to_describe() {
+    let mut out = Vec::new();
+    assert!(JpegEncoder::new(80).encode(&[], 1, 65536, &mut out).is_err());
+    assert!(out.is_empty());
+  }
+}
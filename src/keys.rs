@@ -0,0 +1,166 @@
+use std::sync::atomic::{AtomicBool, AtomicUint, INIT_ATOMIC_BOOL, INIT_ATOMIC_UINT, SeqCst};
+
+
+/// A keyboard command, from a window or the terminal. Commands are handled
+/// by the main loop between frames.
+#[deriving(PartialEq,Eq,Show)]
+pub enum Key {
+  /// Space: pause or carry on.
+  Pause,
+  /// n: replace the machine with a new one.
+  NewMachine,
+  /// s: save the table and a PNG snapshot.
+  Save,
+  /// +: twice the steps per frame.
+  Faster,
+  /// -: half the steps per frame.
+  Slower,
+  /// Left arrow or h: pan the viewport left.
+  PanLeft,
+  /// Right arrow or l: pan the viewport right.
+  PanRight,
+  /// Up arrow or k: pan the viewport up.
+  PanUp,
+  /// Down arrow or j: pan the viewport down.
+  PanDown,
+  /// z: zoom the viewport in.
+  ZoomIn,
+  /// x: zoom the viewport out.
+  ZoomOut,
+}
+
+static KEYS: [Key, ..11] = [Pause, NewMachine, Save, Faster, Slower, PanLeft, PanRight, PanUp, PanDown, ZoomIn,
+                            ZoomOut];
+
+// A bit for each key pressed since the last 'take'. Pressing a key again
+// before then doesn't count twice.
+static PRESSED: AtomicUint = INIT_ATOMIC_UINT;
+// Set while the terminal is switched for 'read_terminal'.
+static SWITCHED: AtomicBool = INIT_ATOMIC_BOOL;
+// The terminal's settings from before 'read_terminal' switched it, as a
+// 'struct termios', which is smaller than this on every platform. Words keep
+// it aligned.
+static mut SAVED: [u64, ..32] = [0, ..32];
+
+
+/// The command for a typed character, if any.
+pub fn from_char(c: u8) -> Option<Key> {
+  match c {
+    b' ' => Some(Pause),
+    b'n' => Some(NewMachine),
+    b's' => Some(Save),
+    b'+' | b'=' => Some(Faster),
+    b'-' => Some(Slower),
+    b'h' => Some(PanLeft),
+    b'l' => Some(PanRight),
+    b'k' => Some(PanUp),
+    b'j' => Some(PanDown),
+    b'z' => Some(ZoomIn),
+    b'x' => Some(ZoomOut),
+    _ => None,
+  }
+}
+
+
+/// Records a key press for the main loop.
+pub fn press(key: Key) {
+  PRESSED.fetch_or(1 << key as uint, SeqCst);
+}
+
+
+/// The keys pressed since the last call.
+pub fn take() -> Vec<Key> {
+  let pressed = PRESSED.swap(0, SeqCst);
+  KEYS.iter().filter(|&&key| pressed & (1 << key as uint) != 0).map(|&key| key).collect()
+}
+
+
+/// Reads keys typed in the terminal on a background task. The terminal is
+/// switched to unbuffered input without echo until the returned guard is
+/// dropped or 'restore_terminal' is called; Ctrl-C still stops the run.
+#[cfg(unix)]
+pub fn read_terminal() -> TerminalGuard {
+  use std::io::{Command, InheritFd};
+  use std::io::stdio::stdin_raw;
+  use libc::c_int;
+  extern {
+    fn tcgetattr(fd: c_int, termios: *mut u64) -> c_int;
+  }
+
+  // The settings are saved first, so they can be put back even from a
+  // signal handler, where running stty again isn't safe.
+  if unsafe { tcgetattr(0, SAVED.as_mut_ptr()) } != 0 {
+    return TerminalGuard;
+  }
+  if Command::new("stty").arg("-icanon").arg("-echo").stdin(InheritFd(0)).status().is_err() {
+    return TerminalGuard;
+  }
+  SWITCHED.store(true, SeqCst);
+  spawn(proc() {
+    let mut input = stdin_raw();
+    loop {
+      match input.read_byte() {
+        Ok(c) => match from_char(c) {
+          Some(key) => press(key),
+          None => {},
+        },
+        Err(_) => break,
+      }
+    }
+  });
+  TerminalGuard
+}
+
+#[cfg(not(unix))]
+pub fn read_terminal() -> TerminalGuard {
+  TerminalGuard
+}
+
+
+/// Puts the terminal back the way 'read_terminal' found it. Safe to call
+/// from a signal handler.
+#[cfg(unix)]
+pub fn restore_terminal() {
+  use libc::c_int;
+  // The same on Linux, the BSDs and OS X.
+  static TCSANOW: c_int = 0;
+  extern {
+    fn tcsetattr(fd: c_int, action: c_int, termios: *const u64) -> c_int;
+  }
+  if SWITCHED.swap(false, SeqCst) {
+    unsafe { tcsetattr(0, TCSANOW, SAVED.as_ptr()); }
+  }
+}
+
+#[cfg(not(unix))]
+pub fn restore_terminal() {
+}
+
+
+/// Restores the terminal when dropped, so it is also put back when the
+/// main task unwinds from a panic.
+pub struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+  fn drop(&mut self) {
+    restore_terminal();
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::{from_char, press, take, Pause, Faster, Slower};
+
+  #[test]
+  fn keys_are_taken_once() {
+    assert_eq!(from_char(b' '), Some(Pause));
+    assert_eq!(from_char(b'='), Some(Faster));
+    assert_eq!(from_char(b'q'), None);
+    press(Slower);
+    press(Pause);
+    press(Pause);
+    assert_eq!(take(), vec!(Pause, Slower));
+    assert_eq!(take(), vec!());
+  }
+}
@@ -0,0 +1,114 @@
+use std::io::{File, IoResult, stderr};
+use std::io::fs::rename;
+use time::{get_time, now};
+use toml;
+
+use turing::Machine;
+use turing::rng::SessionRng;
+use playlist::{Playlist, Shown};
+
+
+/// Unattended operation for installations. Rotates through a curated
+/// playlist with a quota of random machines in between,
+/// goes dark during quiet hours and touches a heartbeat file for watchdogs.
+pub struct Kiosk {
+  playlist: Option<Playlist>,
+  // Random machines shown between playlist entries.
+  random_quota: uint,
+  randoms_left: uint,
+  // Quiet from hour 'start' until hour 'end' (local time, may wrap midnight).
+  quiet_hours: Option<(int, int)>,
+  heartbeat_file: Option<Path>,
+  heartbeat_interval: i64,
+  last_heartbeat: i64,
+}
+
+impl Kiosk {
+  pub fn from_config(config: &toml::Value) -> Result<Kiosk, String> {
+    let int = |name: &str, default: i64| {
+      config.lookup(name).and_then(|value| value.as_integer()).unwrap_or(default)
+    };
+    let playlist = match config.lookup("kiosk.playlist").and_then(|path| path.as_str()) {
+      Some(path) => match Playlist::load(&Path::new(path)) {
+        Ok(playlist) => Some(playlist),
+        Err(why) => return Err(format!("Unable to load kiosk playlist: {}", why)),
+      },
+      None => None,
+    };
+    let quiet_hours = match (config.lookup("kiosk.quiet_start").and_then(|v| v.as_integer()),
+                             config.lookup("kiosk.quiet_end").and_then(|v| v.as_integer())) {
+      (Some(start), Some(end)) => Some((start as int, end as int)),
+      _ => None,
+    };
+    let random_quota = int("kiosk.random_quota", 2) as uint;
+    Ok(Kiosk {
+      playlist: playlist,
+      random_quota: random_quota,
+      randoms_left: random_quota,
+      quiet_hours: quiet_hours,
+      heartbeat_file: config.lookup("kiosk.heartbeat_file").and_then(|v| v.as_str()).map(|p| Path::new(p)),
+      heartbeat_interval: int("kiosk.heartbeat_interval", 10),
+      last_heartbeat: 0,
+    })
+  }
+
+  /// Picks what to show after a reset. 'machine' already has a new random
+  /// table; once the random quota is used up the next playlist entry is
+  /// loaded over it instead.
+  pub fn next_machine(&mut self, machine: &mut Machine, rng: &mut SessionRng) -> Option<Shown> {
+    let playlist = match self.playlist {
+      Some(ref mut playlist) => playlist,
+      None => return None,
+    };
+    if self.randoms_left > 0 {
+      self.randoms_left -= 1;
+      return None;
+    }
+    self.randoms_left = self.random_quota;
+    playlist.next(machine, rng)
+  }
+
+  /// True during the configured quiet hours.
+  pub fn is_quiet(&self) -> bool {
+    match self.quiet_hours {
+      Some((start, end)) => {
+        let hour = now().tm_hour as int;
+        if start <= end {
+          start <= hour && hour < end
+        } else {
+          hour >= start || hour < end
+        }
+      },
+      None => false,
+    }
+  }
+
+  /// Rewrites the heartbeat file if the heartbeat interval has passed.
+  pub fn heartbeat(&mut self, frames: u64) {
+    let time = get_time().sec;
+    if time - self.last_heartbeat < self.heartbeat_interval {
+      return;
+    }
+    self.last_heartbeat = time;
+    match self.heartbeat_file {
+      Some(ref path) => match write_heartbeat(path, time, frames) {
+        Ok(()) => {},
+        Err(why) => {
+          let _ = writeln!(stderr(), "Unable to write heartbeat {}: {}", path.display(), why.desc);
+        },
+      },
+      None => {},
+    }
+  }
+}
+
+
+// Written to a temporary file and renamed so watchdogs never see half a file.
+fn write_heartbeat(path: &Path, time: i64, frames: u64) -> IoResult<()> {
+  let temp = path.with_extension("tmp");
+  {
+    let mut file = try!(File::create(&temp));
+    try!(writeln!(file, "{} {}", time, frames));
+  }
+  rename(&temp, path)
+}
@@ -0,0 +1,1769 @@
+//! Random finite 2D turing machines that draw pictures.
+//!
+//! A machine moves a head over a torus of cells, one step at a time, reading
+//! a symbol, writing one back and turning according to a random transition
+//! table. Each symbol is drawn in a color, so the tape is a picture that
+//! changes as the machine runs.
+//!
+//! ```no_run
+//! extern crate turing;
+//!
+//! use turing::{Machine, BLACK, WHITE};
+//! use turing::builder::TuringMachineBuilder;
+//!
+//! fn main() {
+//!   let mut machine = TuringMachineBuilder::new().size(64, 48).states(4).symbols(2).seed(42).build().unwrap();
+//!   machine.set_palette(&vec!(BLACK, WHITE));
+//!   for _ in range(0u, 100000) {
+//!     machine.step();
+//!   }
+//!   // A 64x48 bgr24 frame.
+//!   machine.render(&mut std::io::stdout()).unwrap();
+//! }
+//! ```
+
+extern crate rand;
+extern crate serialize;
+extern crate toml;
+#[cfg(test)]
+extern crate quickcheck;
+
+use std::hash::hash;
+use std::io::{File, IoResult};
+use std::num::Float;
+use std::rand::Rng;
+use serialize::{Encodable, Decodable, Encoder, Decoder};
+use serialize::json::{Json, ToJson};
+
+use error::{MachineError, BadTable, BadState};
+use neighbors::{NeighborTable, OFF_TAPE};
+use palette::Lut;
+use rng::SessionRng;
+use table::{Table, Transition, HALT};
+use sparse::{SparseTape, View};
+use tape::{Tape, DenseTape};
+
+pub mod builder;
+pub mod eca;
+pub mod error;
+pub mod hybrid;
+pub mod interleave;
+pub mod montage;
+pub mod neighbors;
+pub mod palette;
+pub mod preset;
+pub mod rng;
+pub mod session;
+pub mod spacetime;
+pub mod sparse;
+pub mod table;
+pub mod tape;
+pub mod trace;
+pub mod turmite;
+pub mod two_tape;
+
+/// A move of the head, one cell on the torus, or none. The cardinal
+/// directions come first so tables saved before the others existed still
+/// load.
+#[deriving(PartialEq,Eq,PartialOrd,Ord,Show)]
+pub enum Direction {
+  NORTH,
+  EAST,
+  SOUTH,
+  WEST,
+  NORTHEAST,
+  SOUTHEAST,
+  SOUTHWEST,
+  NORTHWEST,
+  STAY,
+}
+
+impl Direction {
+  /// The direction whose 'as uint' value is 'index'.
+  pub fn from_index(index: uint) -> Option<Direction> {
+    match index {
+      0 => Some(NORTH),
+      1 => Some(EAST),
+      2 => Some(SOUTH),
+      3 => Some(WEST),
+      4 => Some(NORTHEAST),
+      5 => Some(SOUTHEAST),
+      6 => Some(SOUTHWEST),
+      7 => Some(NORTHWEST),
+      8 => Some(STAY),
+      _ => None,
+    }
+  }
+
+  /// The (vertical, horizontal) moves a diagonal move is made of.
+  pub fn diagonal_parts(&self) -> Option<(Direction, Direction)> {
+    match *self {
+      NORTHEAST => Some((NORTH, EAST)),
+      SOUTHEAST => Some((SOUTH, EAST)),
+      SOUTHWEST => Some((SOUTH, WEST)),
+      NORTHWEST => Some((NORTH, WEST)),
+      _ => None,
+    }
+  }
+
+  /// A short name, for tables shown in a terminal.
+  pub fn abbreviation(&self) -> &'static str {
+    match *self {
+      NORTH => "N",
+      EAST => "E",
+      SOUTH => "S",
+      WEST => "W",
+      NORTHEAST => "NE",
+      SOUTHEAST => "SE",
+      SOUTHWEST => "SW",
+      NORTHWEST => "NW",
+      STAY => "-",
+    }
+  }
+}
+
+// Directions are saved as their index, as in tables.
+impl<E, S: Encoder<E>> Encodable<S, E> for Direction {
+  fn encode(&self, s: &mut S) -> Result<(), E> {
+    s.emit_uint(*self as uint)
+  }
+}
+
+impl<E, D: Decoder<E>> Decodable<D, E> for Direction {
+  fn decode(d: &mut D) -> Result<Direction, E> {
+    let index = try!(d.read_uint());
+    match Direction::from_index(index) {
+      Some(direction) => Ok(direction),
+      None => Err(d.error(format!("Invalid direction: {}", index).as_slice())),
+    }
+  }
+}
+
+
+/// The directions random tables draw moves from ('turing.directions').
+#[deriving(PartialEq,Eq,Show)]
+pub enum DirectionSet {
+  /// North, east, south and west.
+  Cardinal,
+  /// The cardinal and diagonal directions.
+  EightWay,
+  /// All eight directions and STAY.
+  AllDirections,
+  /// The six directions of a hex tape: east, west and the diagonals.
+  Hexagonal,
+  /// East and west only, for tapes one cell high.
+  Horizontal,
+}
+
+// The moves to the six cells around a hex cell, clockwise from east.
+static HEX_DIRECTIONS: [Direction, ..6] = [EAST, SOUTHEAST, SOUTHWEST, WEST, NORTHWEST, NORTHEAST];
+
+impl DirectionSet {
+  /// The number of directions in the set. Except for 'Hexagonal' and
+  /// 'Horizontal' they are the first ones of 'Direction'.
+  pub fn count(&self) -> uint {
+    match *self {
+      Cardinal => 4,
+      EightWay => 8,
+      AllDirections => 9,
+      Hexagonal => 6,
+      Horizontal => 2,
+    }
+  }
+
+  /// The direction at 'index' in the set, below 'count'.
+  pub fn get(&self, index: uint) -> Direction {
+    match *self {
+      Hexagonal => HEX_DIRECTIONS[index],
+      Horizontal => [EAST, WEST][index],
+      _ => Direction::from_index(index).unwrap(),
+    }
+  }
+
+  /// The direction following 'direction' in the set, wrapping around. The
+  /// first direction for one not in the set.
+  pub fn after(&self, direction: Direction) -> Direction {
+    match range(0, self.count()).find(|&index| self.get(index) == direction) {
+      Some(index) => self.get((index + 1) % self.count()),
+      None => self.get(0),
+    }
+  }
+
+  /// A direction from the set drawn uniformly from 'rng'.
+  pub fn random(&self, rng: &mut SessionRng) -> Direction {
+    // The same draw as a derived Rand, so cardinal tables match the ones
+    // from before the other directions.
+    self.get(rng.gen::<uint>() % self.count())
+  }
+}
+
+
+/// What a head does when it moves off an edge of the tape.
+#[deriving(PartialEq,Eq,Show)]
+pub enum Boundary {
+  /// It comes back at the opposite edge: the tape is a torus.
+  Wrap,
+  /// It bounces back, moving one cell away from the edge instead.
+  Reflect,
+  /// It stays on the edge cell.
+  Clamp,
+  /// The machine halts (see 'Machine::halted').
+  Halt,
+}
+
+
+/// How the cells of a tape touch: which cell a move leads to, and where a
+/// cell is drawn.
+#[deriving(PartialEq,Eq,Show)]
+pub enum Topology {
+  /// Square cells in rows and columns.
+  Square,
+  /// Hexagonal cells in offset rows: odd rows sit half a cell east of even
+  /// ones, so each cell touches two cells of the rows above and below. The
+  /// diagonal moves go to those; NORTH and SOUTH move straight along the
+  /// column, to one of the two.
+  Hex,
+}
+
+impl Topology {
+  /// The neighbor table of a 'width'x'height' tape whose edges behave as
+  /// 'boundary'.
+  pub fn neighbors(&self, width: uint, height: uint, boundary: Boundary) -> NeighborTable {
+    match *self {
+      Square => NeighborTable::bounded(width, height, boundary),
+      Hex => NeighborTable::hex(width, height, boundary),
+    }
+  }
+
+  /// Fills the pixel block of the cell at ('x', 'y') like 'paint_cell'. Hex
+  /// cells of odd rows are shifted half a cell east, wrapping around the
+  /// right edge of the image.
+  #[inline]
+  pub fn paint_cell(&self, image: &mut Vec<u8>, row_bytes: uint, x: uint, y: uint,
+                    cell_width: uint, cell_height: uint, color: Color) {
+    let shift = cell_width / 2;
+    match *self {
+      Hex if y % 2 == 1 && shift > 0 => {
+        let [r, g, b] = color;
+        let row_pixels = row_bytes / 3;
+        for dy in range(0, cell_height) {
+          let row = (y * cell_height + dy) * row_bytes;
+          for dx in range(0, cell_width) {
+            let p = row + (x * cell_width + shift + dx) % row_pixels * 3;
+            *image.get_mut(p+2) = r;
+            *image.get_mut(p+1) = g;
+            *image.get_mut(p+0) = b;
+          }
+        }
+      },
+      _ => paint_cell(image, row_bytes, x, y, cell_width, cell_height, color),
+    }
+  }
+}
+
+
+/// What happens when heads of a multi-head machine meet on a cell.
+#[deriving(PartialEq,Eq,Show)]
+pub enum HeadCollision {
+  /// Heads step one after another, each reading what the heads before it
+  /// wrote.
+  Sequential,
+  /// A head on the cell of a head that already stepped this tick waits for
+  /// the next tick, so each cell is written at most once per tick.
+  Wait,
+}
+
+
+/// What the image shows of a machine's tape.
+#[deriving(PartialEq,Eq,Show)]
+pub enum RenderMode {
+  /// Each cell in the color of its symbol.
+  Symbols,
+  /// Each cell colored by how often it has been written, on a log scale
+  /// from black (never) through red and yellow to white (the most). Shows
+  /// where the head spends its time: highways, nests and the like. 'step'
+  /// still reports symbol changes, so resets don't depend on the mode.
+  Heatmap,
+  /// Cells written in the last frame in the color of their symbol, fading
+  /// to the blank symbol's color over the given number of frames (see
+  /// 'Machine::end_frame'), so the heads leave trails.
+  Trails(u8),
+  /// Each cell in the hue of its symbol, darker the higher the state that
+  /// wrote it, which shows what each state of the table draws.
+  States,
+}
+
+
+/// An extra rule input derived from the head's coordinates. The table has a
+/// separate block of transitions for each value of the feature, so machines
+/// behave differently in different regions of the tape.
+#[deriving(PartialEq,Eq,Show)]
+pub enum PositionFeature {
+  /// No extra input; the classic (state, symbol) table.
+  NoFeature,
+  /// Parity of x+y (checkerboard).
+  Parity,
+  /// Which quadrant of the tape the head is in.
+  Quadrant,
+}
+
+impl PositionFeature {
+  /// The number of distinct values this feature can take.
+  pub fn count(&self) -> uint {
+    match *self {
+      NoFeature => 1,
+      Parity => 2,
+      Quadrant => 4,
+    }
+  }
+
+  /// The value of the feature at 'position' on a 'width'x'height' tape.
+  pub fn value(&self, position: uint, width: uint, height: uint) -> uint {
+    let x = position % width;
+    let y = position / width;
+    match *self {
+      NoFeature => 0,
+      Parity => (x + y) % 2,
+      Quadrant => (if x < width / 2 { 0 } else { 1 }) + (if y < height / 2 { 0 } else { 2 }),
+    }
+  }
+}
+
+
+// Colors defined as arrays of [R,G,B].
+pub type Color = [u8, .. 3];
+pub static BLACK: Color = [0,0,0];
+pub static WHITE: Color = [255,255,255];
+pub static LIGHT_GRAY: Color = [170,170,170];
+pub static GRAY: Color = [85,85,85];
+pub static RED: Color = [255,0,0];
+pub static GREEN: Color = [0,255,0];
+pub static BLUE: Color = [0,0,255];
+pub static CYAN: Color = [0,255,255];
+pub static MAGENTA: Color = [255,0,255];
+pub static YELLOW: Color = [255,255,0];
+
+
+/// Common interface for the machine variants driven by the main loop.
+pub trait Machine {
+  /// Advances the machine one step. Returns true if this step changed a pixel.
+  fn step(&mut self) -> bool;
+
+  /// Sets the colors used for each symbol and rebuilds the image with them.
+  fn set_palette(&mut self, palette: &Vec<Color>);
+
+  /// The current state as an image (bgr24). The image is kept up to date by
+  /// 'step', so this is just the buffer.
+  fn image<'a>(&'a self) -> &'a [u8];
+
+  /// The (width, height) of the image in pixels.
+  fn image_size(&self) -> (uint, uint);
+
+  /// The (width, height) of the tape in cells.
+  fn tape_size(&self) -> (uint, uint);
+
+  /// The number of symbols.
+  fn symbols(&self) -> u8;
+
+  /// Sets every cell 'i' of the tape(s) to 'symbol(i)'.
+  fn fill_tape(&mut self, symbol: |uint| -> u8);
+
+  /// Starts over with a new random table and a blank tape.
+  fn reset(&mut self, rng: &mut SessionRng);
+
+  /// Starts over with a new random table, keeping the current tape as the
+  /// starting point for the next machine.
+  fn chain(&mut self, rng: &mut SessionRng);
+
+  /// Redraws 'count' entries of the transition table picked at random,
+  /// leaving the tape and heads alone. Does nothing for machines that can't.
+  fn mutate(&mut self, _rng: &mut SessionRng, _count: uint) {}
+
+  /// Draws the whole image from the tape(s) again, as a palette change does,
+  /// for timing the ways of drawing (see 'turing bench --compare'). With
+  /// 'lut', one-pixel cells take the single pass through the palette's
+  /// lookup table; without, every cell is painted on its own. Does nothing
+  /// for machines whose image isn't a picture of the tape, such as
+  /// space-time diagrams. For benchmarks only, so hidden from the docs.
+  #[doc(hidden)]
+  fn redraw(&mut self, _lut: bool) {}
+
+  /// The transition table as an array of arrays of integers, which is valid
+  /// as both TOML and JSON.
+  fn table_text(&self) -> String;
+
+  /// The transition table as JSON, the same array as 'table_text'.
+  fn table_json(&self) -> Json;
+
+  /// The head positions, state, table and tape as the body of a TOML section.
+  fn save_state(&self) -> String;
+
+  /// Restores what 'save_state' saved. Fails with 'BadState' or 'BadTable'
+  /// for saved states that don't fit the machine.
+  fn load_state(&mut self, saved: &toml::Value) -> Result<(), MachineError>;
+
+  /// Replaces the transition table with one written by 'table_text',
+  /// leaving the tape alone. Fails with 'BadTable' for tables that don't
+  /// fit the machine.
+  fn load_table(&mut self, table: &toml::Value) -> Result<(), MachineError>;
+
+  /// Describes in words the transition the next step will take.
+  fn explain_step(&self) -> String;
+
+  /// The tape as text with the head(s) marked. For small tapes, or the part
+  /// of a sparse tape in view.
+  fn tape_text(&self) -> String;
+
+  /// How many different symbols are on the tape(s).
+  fn symbols_used(&self) -> uint;
+
+  /// Saves the transition table to 'path' as a 'table' key, so a machine
+  /// worth keeping can be run again (see 'session::load_table').
+  fn save_table(&self, path: &Path) -> IoResult<()> {
+    let mut file = try!(File::create(path));
+    writeln!(file, "table = {}", self.table_text())
+  }
+
+  /// Writes the image to 'out' as one raw bgr24 frame.
+  fn render(&self, out: &mut Writer) -> IoResult<()> {
+    out.write(self.image())
+  }
+
+  /// True once the machine has halted, e.g. by moving off a tape with a
+  /// 'Halt' boundary. The main loop then starts a new machine.
+  fn halted(&self) -> bool {
+    false
+  }
+
+  /// The pixel rectangles (x, y, width, height) of the cells under the
+  /// heads, for marking them in frames. Empty for machines that don't say.
+  fn head_cells(&self) -> Vec<(uint, uint, uint, uint)> {
+    Vec::new()
+  }
+
+  /// Called after each frame is written, for images that change from frame
+  /// to frame as well as with each step.
+  fn end_frame(&mut self) {}
+
+  /// A hash of the heads' positions and states and the cells around them,
+  /// for spotting machines caught in short cycles. None for machines that
+  /// can't tell.
+  fn local_hash(&self) -> Option<u64> {
+    None
+  }
+}
+
+
+/// How many different symbols are on 'tapes' together.
+pub fn distinct_symbols(tapes: &[&[u8]]) -> uint {
+  let mut seen = [false, ..256];
+  for tape in tapes.iter() {
+    for &symbol in tape.iter() {
+      seen[symbol as uint] = true;
+    }
+  }
+  seen.iter().filter(|&&seen| seen).count()
+}
+
+
+/// What 'save_state' saves of a single tape machine: the first head, the
+/// table's entries in storage order, the tape (see 'session::encode_tape')
+/// and the other heads, as (position, state). Numbers are signed because
+/// the TOML decoder wraps integers that don't fit, so a state of 256 would
+/// load as 0.
+#[deriving(Encodable, Decodable)]
+pub struct SavedMachine {
+  pub position: i64,
+  pub state: i64,
+  pub table: Vec<SavedTransition>,
+  pub tape: String,
+  pub heads: Option<Vec<(i64, i64)>>,
+}
+
+
+/// A table entry as saved: (next_state, write_symbol, direction).
+pub type SavedTransition = (i64, i64, Direction);
+
+
+/// Moves 'position' one cell in 'direction' on a 'width'x'height' torus.
+/// Diagonal moves are a vertical move followed by a horizontal one.
+pub fn move_position(position: uint, direction: Direction, width: uint, height: uint) -> uint {
+  let mut x: uint = position % width;
+  let mut y: uint = position / width;
+  match direction {
+    NORTH => {
+      y = if y == 0 { height-1 } else { y-1 };
+    },
+    EAST => {
+      x += 1;
+      if x >= width { x = 0; }
+    },
+    SOUTH => {
+      y += 1;
+      if y >= height { y = 0; }
+    },
+    WEST => {
+      x = if x == 0 { width-1 } else { x-1 };
+    },
+    STAY => {},
+    _ => {
+      let (vertical, horizontal) = direction.diagonal_parts().unwrap();
+      return move_position(move_position(position, vertical, width, height), horizontal, width, height);
+    },
+  }
+  y*width + x
+}
+
+
+/// The level of heat, from 0 to 255, of a cell written 'visits' times when
+/// 'top' writes is the most: the share of the log of 'top'.
+#[inline]
+fn heat_level(visits: u32, top: u64) -> u8 {
+  if visits == 0 {
+    return 0;
+  }
+  ((visits as f32 + 1.0).ln() / (top as f32 + 1.0).ln() * 255.0) as u8
+}
+
+
+/// Fills the pixel block for the cell at ('x', 'y') (in cells) of a bgr24
+/// image with rows of 'row_bytes' bytes.
+pub fn paint_cell(image: &mut Vec<u8>, row_bytes: uint, x: uint, y: uint,
+              cell_width: uint, cell_height: uint, color: Color) {
+  let [r, g, b] = color;
+  let x = x * cell_width;
+  let y = y * cell_height;
+  for dy in range(0, cell_height) {
+    let row = (y + dy) * row_bytes;
+    for dx in range(0, cell_width) {
+      let p = row + (x + dx) * 3;
+      *image.get_mut(p+2) = r;
+      *image.get_mut(p+1) = g;
+      *image.get_mut(p+0) = b;
+    }
+  }
+}
+
+
+// The tape of a 'TuringMachine'. The default dense tape is held by value so
+// the reads, writes and moves of 'step' are direct calls that inline; other
+// tapes go through the 'Tape' trait object.
+#[deriving(Show)]
+enum MachineTape {
+  DenseCells(DenseTape),
+  OtherCells(Box<Tape>),
+}
+
+impl Tape for MachineTape {
+  fn len(&self) -> uint {
+    match *self {
+      DenseCells(ref tape) => tape.len(),
+      OtherCells(ref tape) => tape.len(),
+    }
+  }
+
+  #[inline]
+  fn read(&self, position: uint) -> u8 {
+    match *self {
+      DenseCells(ref tape) => tape.read(position),
+      OtherCells(ref tape) => tape.read(position),
+    }
+  }
+
+  #[inline]
+  fn write(&mut self, position: uint, symbol: u8) {
+    match *self {
+      DenseCells(ref mut tape) => tape.write(position, symbol),
+      OtherCells(ref mut tape) => tape.write(position, symbol),
+    }
+  }
+
+  #[inline]
+  fn neighbor(&self, position: uint, direction: Direction) -> uint {
+    match *self {
+      DenseCells(ref tape) => tape.neighbor(position, direction),
+      OtherCells(ref tape) => tape.neighbor(position, direction),
+    }
+  }
+
+  fn set_edges(&mut self, topology: Topology, boundary: Boundary) {
+    match *self {
+      DenseCells(ref mut tape) => tape.set_edges(topology, boundary),
+      OtherCells(ref mut tape) => tape.set_edges(topology, boundary),
+    }
+  }
+
+  fn clear(&mut self) {
+    match *self {
+      DenseCells(ref mut tape) => tape.clear(),
+      OtherCells(ref mut tape) => tape.clear(),
+    }
+  }
+
+  fn cells(&self) -> Vec<u8> {
+    match *self {
+      DenseCells(ref tape) => tape.cells(),
+      OtherCells(ref tape) => tape.cells(),
+    }
+  }
+
+  fn copy_cells(&self, cells: &mut [u8]) {
+    match *self {
+      DenseCells(ref tape) => tape.copy_cells(cells),
+      OtherCells(ref tape) => tape.copy_cells(cells),
+    }
+  }
+
+  fn symbols_used(&self) -> uint {
+    match *self {
+      DenseCells(ref tape) => tape.symbols_used(),
+      OtherCells(ref tape) => tape.symbols_used(),
+    }
+  }
+
+  fn load(&mut self, cells: &[u8]) {
+    match *self {
+      DenseCells(ref mut tape) => tape.load(cells),
+      OtherCells(ref mut tape) => tape.load(cells),
+    }
+  }
+}
+
+
+/// A finite 2D turing machine definition.
+/// - The 'tape' has a size of 'width'*'height'.
+/// - There is a current 'position' within the tape.
+/// - There are 'states' possible states for the machine.
+/// - Optionally more heads share the tape, each with its own position and
+///   state. All heads step on each 'step'.
+/// - There are 'symbols' possible symbols at each position.
+/// - The table defines transitions. It is a 2D table. Given the current state
+///   and the current symbol it gives the next state, the symbol to write, and
+///   the direction to move. With a position feature there is one such table
+///   per feature value.
+#[deriving(Show)]
+pub struct TuringMachine {
+  width: uint,
+  height: uint,
+  states: u8,
+  symbols: u8,
+  position: uint,
+  state: u8,
+  // (position, state) of the heads after the first. The first head is
+  // 'position' and 'state', so single head machines are unchanged.
+  extra_heads: Vec<(uint, u8)>,
+  head_collision: HeadCollision,
+  position_feature: PositionFeature,
+  // The directions random tables move in.
+  directions: DirectionSet,
+  // transition [feature, read_symbol, curr_state] -> [next_state, write_symbol, move_direction]
+  table: Table,
+  // Moves heads as 'topology' and 'boundary' say.
+  tape: MachineTape,
+  topology: Topology,
+  boundary: Boundary,
+  // Whether random tables have entries going to HALT.
+  halting: bool,
+  // Set when a head moves off a tape with a 'Halt' boundary or takes a
+  // transition to HALT.
+  halted: bool,
+
+  // Size in pixels of a single tape cell in the output image. Allows
+  // non-square cells for displays such as LED panels or terminal characters.
+  cell_width: uint,
+  cell_height: uint,
+
+  // Colors for each symbol.
+  palette: Lut,
+  render_mode: RenderMode,
+  // With 'Heatmap', the writes to each cell since the machine started, the
+  // count drawn white (a power of two at least the largest) and the colors
+  // for each level of heat.
+  visits: Vec<u32>,
+  heat_top: u64,
+  heat: Lut,
+  // With 'Trails', the frames since each cell was written, up to the
+  // frames a trail lasts.
+  ages: Vec<u8>,
+  // With 'States', the state of the head that last wrote each cell, and
+  // the color of each symbol written in each state.
+  writers: Vec<u8>,
+  state_colors: Vec<Color>,
+  // Memory for writing raw image into. Optimization. Each step patches the
+  // pixels of the cell it writes, so the image always matches the tape.
+  image: Vec<u8>,
+  // With a sparse tape, the part of the world the image shows. Otherwise
+  // the image shows the whole tape.
+  view: Option<View>,
+}
+
+impl TuringMachine {
+  /// Creates a machine with a random table moving in 'directions', a blank
+  /// tape and the head in the top left cell. Cells are one pixel and every
+  /// symbol is drawn black until 'set_palette' is called. See
+  /// 'builder::TuringMachineBuilder' for setting other options.
+  pub fn new(width: uint, height: uint, states: u8, symbols: u8, position_feature: PositionFeature,
+             directions: DirectionSet, rng: &mut SessionRng) -> Box<TuringMachine> {
+    box TuringMachine {
+      width: width,
+      height: height,
+      states: states,
+      symbols: symbols,
+      position: 0,
+      state: 0,
+      extra_heads: Vec::new(),
+      head_collision: Sequential,
+      position_feature: position_feature,
+      directions: directions,
+      table: Table::random(rng, states, symbols, position_feature.count(), directions),
+      tape: DenseCells(DenseTape::new(width, height)),
+      topology: Square,
+      boundary: Wrap,
+      halting: false,
+      halted: false,
+      cell_width: 1,
+      cell_height: 1,
+      palette: Lut::new(&Vec::new()),
+      render_mode: Symbols,
+      visits: Vec::new(),
+      heat_top: 1,
+      heat: Lut::new(&Vec::new()),
+      ages: Vec::new(),
+      writers: Vec::new(),
+      state_colors: Vec::new(),
+      image: Vec::from_elem(width * height * 3, 0u8),
+      view: None,
+    }
+  }
+
+  /// Sets what the image shows. Cells are drawn by symbol by default; a
+  /// heatmap counts writes and trails start from now on.
+  pub fn set_render_mode(&mut self, mode: RenderMode) {
+    self.render_mode = mode;
+    self.visits = Vec::new();
+    self.ages = Vec::new();
+    self.writers = Vec::new();
+    self.heat = Lut::new(&palette::heat(256));
+    self.clear_render_state();
+    self.rebuild_image();
+  }
+
+  // The color of each symbol in each state for 'States', from the palette:
+  // state 0 draws the palette's colors and higher states darker shades of
+  // them, down to 35% brightness.
+  fn shade_states(&mut self) {
+    let states = self.states as uint;
+    let mut colors = Vec::with_capacity(256 * states);
+    for symbol in range(0u, 256) {
+      let color = self.palette.get(symbol as u8);
+      for state in range(0, states) {
+        let brightness = 1.0 - 0.65 * state as f32 / ::std::cmp::max(states - 1, 1) as f32;
+        colors.push([(color[0] as f32 * brightness) as u8, (color[1] as f32 * brightness) as u8,
+                     (color[2] as f32 * brightness) as u8]);
+      }
+    }
+    self.state_colors = colors;
+  }
+
+  /// Sets what heads do at the edges of the tape. Tapes wrap by default.
+  pub fn set_boundary(&mut self, boundary: Boundary) {
+    self.boundary = boundary;
+    self.tape.set_edges(self.topology, boundary);
+  }
+
+  /// Sets whether random tables have transitions to HALT, and draws a new
+  /// table from 'rng' to match.
+  pub fn set_halting(&mut self, halting: bool, rng: &mut SessionRng) {
+    self.halting = halting;
+    self.chain(rng);
+  }
+
+  /// Sets how the cells of the tape touch. Tapes are square by default.
+  /// Random tables keep moving in the machine's 'DirectionSet', so hex
+  /// machines should be created with 'Hexagonal'.
+  pub fn set_topology(&mut self, topology: Topology) {
+    self.topology = topology;
+    self.tape.set_edges(topology, self.boundary);
+    self.rebuild_image();
+  }
+
+  /// Stores the cells in 'tape' from now on, keeping the symbols, topology
+  /// and boundary. It must have a cell for every cell of the machine. Fails
+  /// on a sparse tape, whose world is too big to copy cell by cell.
+  pub fn set_tape(&mut self, tape: Box<Tape>) -> Result<(), MachineError> {
+    if self.view.is_some() {
+      return Err(BadState("A sparse tape can't be replaced".to_string()));
+    }
+    let cells = self.tape.cells();
+    self.tape = OtherCells(tape);
+    self.tape.set_edges(self.topology, self.boundary);
+    self.tape.load(cells.as_slice());
+    Ok(())
+  }
+
+  /// Starts over on a blank sparse 'world_width'x'world_height' tape, with
+  /// the first head in the middle. The image keeps its size and shows the
+  /// part of the world around the middle, or with 'fit' everything written
+  /// so far (see 'sparse::View'). For square tapes drawn by symbol.
+  pub fn set_sparse_tape(&mut self, world_width: uint, world_height: uint, fit: bool) {
+    let (width, height) = self.view_size();
+    self.view = Some(View::new(width, height, (world_width, world_height), fit));
+    self.width = world_width;
+    self.height = world_height;
+    self.tape = OtherCells(box SparseTape::new(world_width, world_height) as Box<Tape>);
+    self.tape.set_edges(self.topology, self.boundary);
+    self.position = world_height / 2 * world_width + world_width / 2;
+    self.state = 0;
+    let (heads, collision) = (self.heads(), self.head_collision);
+    self.set_heads(heads, collision);
+    self.clear_render_state();
+    self.rebuild_image();
+  }
+
+  /// Writes 'symbol' to the cell at 'position' and repaints it, for rules
+  /// that change the tape besides the heads. Dense tapes only.
+  pub fn write_cell(&mut self, position: uint, symbol: u8) {
+    self.tape.write(position, symbol);
+    self.repaint(position);
+  }
+
+  // The size of the image in cells.
+  fn view_size(&self) -> (uint, uint) {
+    match self.view {
+      Some(ref view) => view.size(),
+      None => (self.width, self.height),
+    }
+  }
+
+  /// The number of states.
+  pub fn states(&self) -> u8 {
+    self.states
+  }
+
+  /// The head's position as an index into the tape, y*width + x.
+  pub fn position(&self) -> uint {
+    self.position
+  }
+
+  /// The current state.
+  pub fn state(&self) -> u8 {
+    self.state
+  }
+
+  /// The number of heads.
+  pub fn heads(&self) -> uint {
+    1 + self.extra_heads.len()
+  }
+
+  /// The position of every head, the first head's first.
+  pub fn head_positions(&self) -> Vec<uint> {
+    let mut positions = vec!(self.position);
+    positions.extend(self.extra_heads.iter().map(|&(position, _)| position));
+    positions
+  }
+
+  /// Uses 'heads' heads, spread along the diagonal of the image from the
+  /// first head's cell, all in state 0 except the first.
+  pub fn set_heads(&mut self, heads: uint, collision: HeadCollision) {
+    let (width, height) = (self.width, self.height);
+    let (view_width, view_height) = self.view_size();
+    let (x, y) = (self.position % width, self.position / width);
+    let extra_heads = range(1, heads).map(|i| {
+      let (dx, dy) = (i * view_width / heads, i * view_height / heads);
+      (((y + dy) % height) * width + (x + dx) % width, 0u8)
+    }).collect();
+    self.extra_heads = extra_heads;
+    self.head_collision = collision;
+  }
+
+  /// The symbol in each cell, row by row.
+  pub fn tape(&self) -> Vec<u8> {
+    self.tape.cells()
+  }
+
+  pub fn position_feature(&self) -> PositionFeature {
+    self.position_feature
+  }
+
+  pub fn directions(&self) -> DirectionSet {
+    self.directions
+  }
+
+  pub fn table<'a>(&'a self) -> &'a Table {
+    &self.table
+  }
+
+  /// The transition table, for editing. Entries must stay within the
+  /// machine's states and symbols.
+  pub fn table_mut<'a>(&'a mut self) -> &'a mut Table {
+    &mut self.table
+  }
+
+  /// Sets the pixel size of each tape cell in the output image. The output
+  /// image is then (width*cell_width)x(height*cell_height) pixels.
+  pub fn set_cell_size(&mut self, cell_width: uint, cell_height: uint) {
+    self.cell_width = cell_width;
+    self.cell_height = cell_height;
+    // Free the old image first so both are never allocated at once.
+    self.image = Vec::new();
+    let (width, height) = self.view_size();
+    self.image = Vec::from_elem(width * cell_width * height * cell_height * 3, 0u8);
+    self.rebuild_image();
+  }
+
+  // Converts the whole tape into the image (bgr24 since that's what vlc seems to expect).
+  fn rebuild_image(&mut self) {
+    self.draw_image(true);
+  }
+
+  // 'rebuild_image', using the palette's lookup table for one-pixel cells
+  // only with 'lut'.
+  fn draw_image(&mut self, lut: bool) {
+    // Direct to stdout. Slow.
+    /*
+    for &val in self.tape.iter() {
+      let color = palette.get(val as uint);
+      try!(out.write_u8(color[0]));
+      try!(out.write_u8(color[1]));
+      try!(out.write_u8(color[2]));
+    }
+    */
+
+    // Alternative seems quicker, but still not fast enough:
+    /*
+    let len = (self.width as uint) * (self.height as uint) * 3;
+    let mut image = Vec::with_capacity(len);
+    for &val in self.tape.iter() {
+      let color = palette.get(val as uint);
+      image.push(color[0]);
+      image.push(color[1]);
+      image.push(color[2]);
+    }
+    try!(out.write(image.as_slice()));
+    */
+
+    // Upfront allocation. Faster, but still not fast enough at higher resolutions.
+    // Requires adding 'image: Vec<u8>' on the struct.
+    // Each cell is expanded to a cell_width x cell_height block of pixels.
+    // Only needed on resets and palette changes; 'step' patches single cells.
+    let (view_width, _) = self.view_size();
+    let row_bytes = view_width * self.cell_width * 3;
+    match self.view {
+      Some(ref view) => {
+        let (width, height) = view.size();
+        for row in range(0, height) {
+          for column in range(0, width) {
+            let (x, y) = view.cell(column, row);
+            let color = self.cell_color(y * self.width + x);
+            self.topology.paint_cell(&mut self.image, row_bytes, column, row, self.cell_width, self.cell_height,
+                                     color);
+          }
+        }
+        return;
+      },
+      None => {},
+    }
+    // With one pixel per cell, the usual case at high resolutions, a single
+    // pass over the tape through the palette's lookup table does it. The
+    // cells are taken once rather than read through the tape cell by cell.
+    if lut && self.cell_width == 1 && self.cell_height == 1 && self.render_mode == Symbols {
+      let cells = self.tape.cells();
+      for (&symbol, pixel) in cells.iter().zip(self.image.as_mut_slice().chunks_mut(3)) {
+        let [r, g, b] = self.palette.get(symbol);
+        // Safe: the image has 3 bytes per cell, so every chunk is a whole pixel.
+        unsafe {
+          *pixel.unsafe_mut(2) = r;
+          *pixel.unsafe_mut(1) = g;
+          *pixel.unsafe_mut(0) = b;
+        }
+      }
+      return;
+    }
+    for i in range(0, self.width * self.height) {
+      let color = self.cell_color(i);
+      self.topology.paint_cell(&mut self.image, row_bytes, i % self.width, i / self.width,
+                               self.cell_width, self.cell_height, color);
+    }
+  }
+
+  // The color the cell at 'position' is drawn in.
+  #[inline]
+  fn cell_color(&self, position: uint) -> Color {
+    match self.render_mode {
+      Symbols => self.palette.get(self.tape.read(position)),
+      Heatmap => self.heat.get(heat_level(*self.visits.get(position), self.heat_top)),
+      Trails(frames) => {
+        let age = *self.ages.get(position) as uint;
+        let (fresh, faded) = (self.palette.get(self.tape.read(position)), self.palette.get(0));
+        let mut color = [0u8, 0, 0];
+        for channel in range(0u, 3) {
+          let mixed = fresh[channel] as uint * (frames as uint - age) + faded[channel] as uint * age;
+          color[channel] = (mixed / frames as uint) as u8;
+        }
+        color
+      },
+      States => {
+        let symbol = self.tape.read(position) as uint;
+        *self.state_colors.get(symbol * self.states as uint + *self.writers.get(position) as uint)
+      },
+    }
+  }
+
+  // Repaints the cell at 'position' in its current color.
+  #[inline]
+  fn repaint(&mut self, position: uint) {
+    let color = self.cell_color(position);
+    self.paint(position, color);
+  }
+
+  // Fills the pixels showing the cell at 'position' with 'color'.
+  #[inline]
+  fn paint(&mut self, position: uint, color: Color) {
+    let (x, y) = (position % self.width, position / self.width);
+    match self.view {
+      None => self.topology.paint_cell(&mut self.image, self.width * self.cell_width * 3, x, y,
+                                       self.cell_width, self.cell_height, color),
+      Some(ref view) => {
+        let ((left, right), (top, bottom)) = (view.columns(x), view.rows(y));
+        let (view_width, _) = view.size();
+        let row_bytes = view_width * self.cell_width * 3;
+        for row in range(top, bottom) {
+          for column in range(left, right) {
+            self.topology.paint_cell(&mut self.image, row_bytes, column, row, self.cell_width, self.cell_height,
+                                     color);
+          }
+        }
+      },
+    }
+  }
+
+  // Counts a write to the cell at 'position' for the heatmap and redraws it.
+  // Once a count passes the top of the scale, the scale doubles and every
+  // cell is redrawn.
+  fn visit(&mut self, position: uint) {
+    let visits = {
+      let visits = self.visits.get_mut(position);
+      if *visits < ::std::u32::MAX {
+        *visits += 1;
+      }
+      *visits as u64
+    };
+    if visits > self.heat_top {
+      while self.heat_top < visits {
+        self.heat_top *= 2;
+      }
+      self.rebuild_image();
+      return;
+    }
+    self.repaint(position);
+  }
+
+  // Starts the heatmap, trails or writing states over, for a new machine.
+  fn clear_render_state(&mut self) {
+    match self.render_mode {
+      Symbols => {},
+      Heatmap => {
+        self.visits = Vec::from_elem(self.width * self.height, 0u32);
+        self.heat_top = 1;
+      },
+      Trails(frames) => self.ages = Vec::from_elem(self.width * self.height, frames),
+      States => {
+        self.writers = Vec::from_elem(self.width * self.height, 0u8);
+        self.shade_states();
+      },
+    }
+  }
+
+  // The (next_state, write_symbol, move_direction) transition for the current
+  // state and the symbol under the head.
+  fn transition(&self) -> (u8, u8, Direction) {
+    self.transition_at(self.position, self.state)
+  }
+
+  // The transition of a head at 'position' in 'state'.
+  fn transition_at(&self, position: uint, state: u8) -> (u8, u8, Direction) {
+    let curr_symbol = self.tape.read(position);
+    let feature = self.position_feature.value(position, self.width, self.height);
+    self.table.get(feature, state, curr_symbol)
+  }
+
+  // Steps a head at 'position' in 'state'. Returns its new position and
+  // state, and whether it changed a pixel. A head that halts keeps its state.
+  #[inline]
+  fn step_head(&mut self, position: uint, state: u8) -> (uint, u8, bool) {
+    let curr_symbol = self.tape.read(position);
+    let (next_state, write_symbol, move_direction) = self.transition_at(position, state);
+    self.tape.write(position, write_symbol);
+
+    // Return whether this changes the picture or not.
+    let changed = write_symbol != curr_symbol;
+    match self.render_mode {
+      Symbols => {
+        // A fitted view may have to grow to take in the cell.
+        let grew = match self.view {
+          Some(ref mut view) => view.take_in(position % self.width, position / self.width),
+          None => false,
+        };
+        if grew {
+          self.rebuild_image();
+        } else if changed {
+          let color = self.palette.get(write_symbol);
+          self.paint(position, color);
+        }
+      },
+      Heatmap => self.visit(position),
+      Trails(_) => {
+        *self.ages.get_mut(position) = 0;
+        self.repaint(position);
+      },
+      States => if changed || *self.writers.get(position) != state {
+        *self.writers.get_mut(position) = state;
+        self.repaint(position);
+      },
+    }
+    let next_state = if next_state == HALT {
+      self.halted = true;
+      state
+    } else {
+      next_state
+    };
+    match self.tape.neighbor(position, move_direction) {
+      OFF_TAPE => {
+        self.halted = true;
+        (position, next_state, changed)
+      },
+      next => (next, next_state, changed),
+    }
+  }
+
+  // Checks a table written by 'table_text' fits this machine.
+  fn parse_table(&self, table: &toml::Value) -> Result<Table, MachineError> {
+    let entries: Vec<SavedTransition> = try!(toml::decode(table.clone())
+                                             .ok_or(BadTable(format!("Invalid table: {}", table))));
+    self.table_from_entries(entries)
+  }
+
+  // A table of this machine's shape from decoded entries.
+  fn table_from_entries(&self, entries: Vec<SavedTransition>) -> Result<Table, MachineError> {
+    if entries.len() != self.table.len() {
+      return Err(BadTable(format!("Expected {} table entries, found {}", self.table.len(), entries.len())));
+    }
+    let mut transitions: Vec<Transition> = Vec::with_capacity(entries.len());
+    for &(next_state, write_symbol, direction) in entries.iter() {
+      if next_state < 0 || next_state > 255 || write_symbol < 0 || write_symbol > 255 {
+        return Err(BadTable(format!("Invalid table entry: [{}, {}, {}]", next_state, write_symbol,
+                                    direction as uint)));
+      }
+      transitions.push((next_state as u8, write_symbol as u8, direction));
+    }
+    Table::from_entries(self.states, self.symbols, self.table.features(), transitions)
+  }
+}
+
+impl Machine for TuringMachine {
+  // Return true if this step changed a pixel.
+  fn step(&mut self) -> bool {
+    let first = self.position;
+    let (position, state, mut ret) = self.step_head(self.position, self.state);
+    self.position = position;
+    self.state = state;
+    if self.extra_heads.is_empty() {
+      return ret;
+    }
+
+    // Cells of the heads that stepped this tick, for 'Wait'.
+    let mut stepped = vec!(first);
+    for i in range(0, self.extra_heads.len()) {
+      let (position, state) = *self.extra_heads.get(i);
+      if self.head_collision == Wait {
+        if stepped.contains(&position) {
+          continue;
+        }
+        stepped.push(position);
+      }
+      let (position, state, changed) = self.step_head(position, state);
+      *self.extra_heads.get_mut(i) = (position, state);
+      ret = ret || changed;
+    }
+    ret
+  }
+
+  fn set_palette(&mut self, palette: &Vec<Color>) {
+    self.palette = Lut::new(&palette::complete(palette, self.symbols));
+    if self.render_mode == States {
+      self.shade_states();
+    }
+    self.rebuild_image();
+  }
+
+  fn redraw(&mut self, lut: bool) {
+    self.draw_image(lut);
+  }
+
+  fn image<'a>(&'a self) -> &'a [u8] {
+    self.image.as_slice()
+  }
+
+  fn image_size(&self) -> (uint, uint) {
+    let (width, height) = self.view_size();
+    (width * self.cell_width, height * self.cell_height)
+  }
+
+  fn tape_size(&self) -> (uint, uint) {
+    (self.width, self.height)
+  }
+
+  fn symbols(&self) -> u8 {
+    self.symbols
+  }
+
+  // A sparse tape is blanked and only the cells in view are filled.
+  fn fill_tape(&mut self, symbol: |uint| -> u8) {
+    match self.view {
+      Some(ref mut view) => {
+        self.tape.clear();
+        view.reset();
+        let (width, height) = view.size();
+        for row in range(0, height) {
+          for column in range(0, width) {
+            let (x, y) = view.cell(column, row);
+            let position = y * self.width + x;
+            self.tape.write(position, symbol(position));
+          }
+        }
+      },
+      None => for i in range(0, self.width * self.height) {
+        self.tape.write(i, symbol(i));
+      },
+    }
+    self.rebuild_image();
+  }
+
+  fn reset(&mut self, rng: &mut SessionRng) {
+    self.chain(rng);
+    self.tape.clear();
+    match self.view {
+      Some(ref mut view) => view.reset(),
+      None => {},
+    }
+    self.clear_render_state();
+    self.rebuild_image();
+  }
+
+  fn chain(&mut self, rng: &mut SessionRng) {
+    let features = self.position_feature.count();
+    self.table = if self.halting {
+      Table::random_halting(rng, self.states, self.symbols, features, self.directions)
+    } else {
+      Table::random(rng, self.states, self.symbols, features, self.directions)
+    };
+    self.halted = false;
+    if self.render_mode == Heatmap {
+      self.clear_render_state();
+      self.rebuild_image();
+    }
+  }
+
+
+  fn mutate(&mut self, rng: &mut SessionRng, count: uint) {
+    for _ in range(0, count) {
+      self.table.mutate(rng, self.directions, self.halting);
+    }
+  }
+
+  fn table_text(&self) -> String {
+    self.table.text()
+  }
+
+  fn table_json(&self) -> Json {
+    self.table.to_json()
+  }
+
+  fn save_state(&self) -> String {
+    let heads = if self.extra_heads.is_empty() {
+      None
+    } else {
+      Some(self.extra_heads.iter().map(|&(position, state)| (position as i64, state as i64)).collect())
+    };
+    toml::encode_str(&SavedMachine {
+      position: self.position as i64,
+      state: self.state as i64,
+      table: self.table.entries().iter().map(|&(next_state, write_symbol, direction)| {
+        (next_state as i64, write_symbol as i64, direction)
+      }).collect(),
+      tape: session::encode_tape(self.tape.cells().as_slice()),
+      heads: heads,
+    })
+  }
+
+  fn load_state(&mut self, saved: &toml::Value) -> Result<(), MachineError> {
+    let saved: SavedMachine = try!(toml::decode(saved.clone()).ok_or(BadState("Invalid saved machine".to_string())));
+    let (len, states) = (self.width * self.height, self.states as i64);
+    let in_range = |position: i64, state: i64| {
+      position >= 0 && (position as u64) < len as u64 && state >= 0 && state < states
+    };
+    if !in_range(saved.position, saved.state) {
+      return Err(BadState("Saved position or state out of range".to_string()));
+    }
+    let table = try!(self.table_from_entries(saved.table));
+    let tape = try!(session::decode_tape(saved.tape.as_slice(), len, self.symbols));
+    let extra_heads = saved.heads.unwrap_or(Vec::new());
+    if extra_heads.len() != self.extra_heads.len() {
+      return Err(BadState(format!("Saved {} heads, the machine has {}", extra_heads.len() + 1, self.heads())));
+    }
+    if extra_heads.iter().any(|&(position, state)| !in_range(position, state)) {
+      return Err(BadState("Saved head position or state out of range".to_string()));
+    }
+
+    self.position = saved.position as uint;
+    self.state = saved.state as u8;
+    self.extra_heads = extra_heads.iter().map(|&(position, state)| (position as uint, state as u8)).collect();
+    self.table = table;
+    self.tape.load(tape.as_slice());
+    self.halted = false;
+    self.clear_render_state();
+    self.rebuild_image();
+    Ok(())
+  }
+
+  fn load_table(&mut self, table: &toml::Value) -> Result<(), MachineError> {
+    self.table = try!(self.parse_table(table));
+    Ok(())
+  }
+
+  fn explain_step(&self) -> String {
+    let mut heads = vec!((self.position, self.state));
+    heads.push_all(self.extra_heads.as_slice());
+    let steps: Vec<String> = heads.iter().map(|&(position, state)| {
+      let (next_state, write_symbol, move_direction) = self.transition_at(position, state);
+      let next = if next_state == HALT { "halt".to_string() } else { format!("goto state {}", next_state) };
+      format!("state {} reads symbol {} at ({}, {}) → write {}, move {}, {}",
+              state, self.tape.read(position), position % self.width, position / self.width,
+              write_symbol, move_direction, next)
+    }).collect();
+    steps.connect("; ")
+  }
+
+  // A sparse tape shows only the world cells in view, one per image cell,
+  // with the heads in view marked.
+  fn tape_text(&self) -> String {
+    let view = match self.view {
+      Some(ref view) => view,
+      None => return trace::grid_text(self.tape.cells().as_slice(), self.width, self.head_positions().as_slice()),
+    };
+    let (width, height) = view.size();
+    let mut cells = Vec::with_capacity(width * height);
+    for row in range(0, height) {
+      for column in range(0, width) {
+        let (x, y) = view.cell(column, row);
+        cells.push(self.tape.read(y * self.width + x));
+      }
+    }
+    let heads: Vec<uint> = self.head_positions().iter().filter_map(|&position| {
+      let ((left, right), (top, bottom)) = (view.columns(position % self.width), view.rows(position / self.width));
+      if left == right || top == bottom { None } else { Some(top * width + left) }
+    }).collect();
+    trace::grid_text(cells.as_slice(), width, heads.as_slice())
+  }
+
+  fn symbols_used(&self) -> uint {
+    self.tape.symbols_used()
+  }
+
+  fn halted(&self) -> bool {
+    self.halted
+  }
+
+  // Hex cells of odd rows are drawn half a cell east.
+  fn head_cells(&self) -> Vec<(uint, uint, uint, uint)> {
+    self.head_positions().iter().filter_map(|&position| {
+      let (x, y) = (position % self.width, position / self.width);
+      match self.view {
+        // Heads out of view aren't marked.
+        Some(ref view) => {
+          let ((left, right), (top, bottom)) = (view.columns(x), view.rows(y));
+          if left == right || top == bottom {
+            return None;
+          }
+          Some((left * self.cell_width, top * self.cell_height, (right - left) * self.cell_width,
+                (bottom - top) * self.cell_height))
+        },
+        None => {
+          let shift = if self.topology == Hex && y % 2 == 1 { self.cell_width / 2 } else { 0 };
+          Some((x * self.cell_width + shift, y * self.cell_height, self.cell_width, self.cell_height))
+        },
+      }
+    }).collect()
+  }
+
+  // Trails fade one frame.
+  fn end_frame(&mut self) {
+    let frames = match self.render_mode {
+      Trails(frames) => frames,
+      _ => return,
+    };
+    for position in range(0, self.ages.len()) {
+      if *self.ages.get(position) < frames {
+        *self.ages.get_mut(position) += 1;
+        self.repaint(position);
+      }
+    }
+  }
+
+  fn local_hash(&self) -> Option<u64> {
+    let cells = &self.tape as &Tape;
+    let first = hash(&(self.position, self.state, tape::window(cells, self.position)));
+    Some(self.extra_heads.iter().fold(first, |local, &(position, state)| {
+      hash(&(local, position, state, tape::window(cells, position)))
+    }))
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::io::MemWriter;
+
+  use super::{TuringMachine, Machine, Direction, NoFeature, Cardinal, Hexagonal, NORTH, EAST, SOUTH, WEST, NORTHWEST,
+              NORTHEAST, STAY};
+  use super::{Sequential, Wait, Halt, Square, Hex, Heatmap, Trails, States, BLACK, WHITE};
+  use error::{BadTable, BadState};
+  use rng::SessionRng;
+  use session;
+  use table::{Table, HALT};
+
+  // A machine on a 'width'x'height' tape whose every entry is 'transition'.
+  fn uniform(width: uint, height: uint, states: u8, symbols: u8,
+             transition: (u8, u8, Direction)) -> Box<TuringMachine> {
+    let mut machine = TuringMachine::new(width, height, states, symbols, NoFeature, Cardinal, &mut SessionRng::new(0));
+    let entries = Vec::from_elem(states as uint * symbols as uint, transition);
+    machine.table = Table::from_entries(states, symbols, 1, entries).unwrap();
+    machine
+  }
+
+  #[test]
+  fn always_east_machine_walks_the_first_row() {
+    let mut machine = uniform(4, 3, 1, 2, (0, 1, EAST));
+    for step in range(1u, 10) {
+      machine.step();
+      assert_eq!(machine.position, step % 4);
+      assert_eq!(machine.state, 0);
+    }
+    assert_eq!(machine.tape(), vec!(1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0));
+  }
+
+  #[test]
+  fn always_south_machine_wraps_down_a_column() {
+    let mut machine = uniform(3, 4, 1, 2, (0, 1, SOUTH));
+    let positions: Vec<uint> = range(0u, 6).map(|_| { machine.step(); machine.position }).collect();
+    assert_eq!(positions, vec!(3, 6, 9, 0, 3, 6));
+  }
+
+  #[test]
+  fn diagonal_machine_wraps_at_the_corner() {
+    let mut machine = uniform(3, 3, 1, 2, (0, 1, NORTHWEST));
+    let positions: Vec<uint> = range(0u, 3).map(|_| { machine.step(); machine.position }).collect();
+    assert_eq!(positions, vec!(8, 4, 0));
+  }
+
+  #[test]
+  fn stay_machine_keeps_its_cell() {
+    let mut machine = uniform(3, 3, 1, 2, (0, 1, STAY));
+    assert_eq!((machine.step(), machine.step()), (true, false));
+    assert_eq!(machine.position, 0);
+  }
+
+  #[test]
+  fn directions_follow_each_other_within_the_set() {
+    assert_eq!(Cardinal.after(EAST), SOUTH);
+    assert_eq!(Cardinal.after(WEST), NORTH);
+    assert_eq!(Cardinal.after(STAY), NORTH);
+    assert_eq!(Hexagonal.after(NORTHEAST), EAST);
+    assert_eq!(Hexagonal.after(NORTH), EAST);
+  }
+
+  #[test]
+  fn heads_spread_along_the_diagonal_and_all_step() {
+    let mut machine = uniform(4, 4, 1, 2, (0, 1, EAST));
+    machine.set_heads(2, Sequential);
+    assert_eq!(machine.extra_heads, vec!((10, 0)));
+    machine.step();
+    assert_eq!(machine.position, 1);
+    assert_eq!(machine.extra_heads, vec!((11, 0)));
+    assert_eq!(machine.tape().iter().filter(|&&symbol| symbol == 1).count(), 2);
+  }
+
+  #[test]
+  fn waiting_heads_skip_shared_cells() {
+    for &(collision, expected) in [(Sequential, 1u), (Wait, 0)].iter() {
+      let mut machine = uniform(4, 1, 1, 2, (0, 1, EAST));
+      machine.set_heads(2, collision);
+      *machine.extra_heads.get_mut(0) = (0, 0);
+      machine.step();
+      assert_eq!(machine.extra_heads, vec!((expected, 0)));
+    }
+  }
+
+  #[test]
+  fn saved_heads_load_back() {
+    let mut saved = uniform(8, 8, 1, 2, (0, 1, SOUTH));
+    saved.set_heads(3, Wait);
+    for _ in range(0u, 20) {
+      saved.step();
+    }
+    let text = saved.save_state();
+    let mut loaded = uniform(8, 8, 1, 2, (0, 1, SOUTH));
+    assert!(loaded.load_state(&from_str(text.as_slice()).unwrap()).is_err());
+    loaded.set_heads(3, Wait);
+    loaded.load_state(&from_str(text.as_slice()).unwrap()).unwrap();
+    assert_eq!(loaded.save_state(), text);
+  }
+
+  #[test]
+  fn halt_transitions_write_move_and_halt() {
+    let mut machine = uniform(3, 2, 1, 2, (HALT, 1, EAST));
+    machine.step();
+    assert!(machine.halted());
+    assert_eq!((machine.position, machine.state, *machine.tape().get(0)), (1, 0, 1));
+    assert!(machine.explain_step().as_slice().ends_with("halt"));
+  }
+
+  #[test]
+  fn machines_halt_off_the_edge() {
+    let mut machine = uniform(3, 2, 1, 2, (0, 1, EAST));
+    machine.set_boundary(Halt);
+    machine.step();
+    machine.step();
+    assert!(!machine.halted());
+    machine.step();
+    assert!(machine.halted());
+    assert_eq!(machine.position, 2);
+    machine.chain(&mut SessionRng::new(1));
+    assert!(!machine.halted());
+  }
+
+  #[test]
+  fn step_reports_changes() {
+    // Writes 1 everywhere it goes: the first lap changes cells, later laps don't.
+    let mut machine = uniform(4, 1, 1, 2, (0, 1, EAST));
+    let changes: Vec<bool> = range(0u, 8).map(|_| machine.step()).collect();
+    assert_eq!(changes, vec!(true, true, true, true, false, false, false, false));
+  }
+
+  #[test]
+  fn changed_steps_match_tape_differences() {
+    // Over many seeded steps the number of changing steps is at least the
+    // number of cells that differ from the blank tape.
+    let mut machine = TuringMachine::new(16, 16, 3, 4, NoFeature, Cardinal, &mut SessionRng::new(7));
+    let mut changed = 0u;
+    for _ in range(0u, 5000) {
+      if machine.step() {
+        changed += 1;
+      }
+    }
+    let differing = machine.tape().iter().filter(|&&symbol| symbol != 0).count();
+    assert!(changed >= differing);
+    assert!(machine.position < 16 * 16);
+    assert!(machine.state < 3);
+  }
+
+  #[test]
+  fn seeded_machines_are_deterministic() {
+    let run = |seed: u64| {
+      let mut machine = TuringMachine::new(16, 12, 4, 5, NoFeature, Cardinal, &mut SessionRng::new(seed));
+      for _ in range(0u, 10000) {
+        machine.step();
+      }
+      (machine.tape(), machine.position, machine.state)
+    };
+    assert_eq!(run(3), run(3));
+  }
+
+  #[test]
+  fn renders_exact_bytes() {
+    // 2x1 tape, cells 2 pixels wide and 1 high, so a 4x1 bgr24 image.
+    let mut machine = uniform(2, 1, 1, 3, (0, 2, EAST));
+    machine.set_cell_size(2, 1);
+    machine.set_palette(&vec!([0, 0, 0], [1, 2, 3], [10, 20, 30]));
+    assert_eq!(machine.image(), [0u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0].as_slice());
+    machine.step();
+    assert_eq!(machine.image(), [30u8, 20, 10, 30, 20, 10, 0, 0, 0, 0, 0, 0].as_slice());
+    machine.fill_tape(|i| i as u8);
+    assert_eq!(machine.image(), [0u8, 0, 0, 0, 0, 0, 3, 2, 1, 3, 2, 1].as_slice());
+    assert_eq!(machine.image_size(), (4, 1));
+  }
+
+  #[test]
+  fn hex_rows_are_offset_half_a_cell() {
+    // 2x2 tape, 2x1 pixel cells: the odd row is shifted one pixel and its
+    // last cell wraps around to the first pixel.
+    let mut machine = uniform(2, 2, 1, 2, (0, 1, EAST));
+    machine.set_cell_size(2, 1);
+    machine.set_topology(Hex);
+    machine.set_palette(&vec!([0, 0, 0], [1, 2, 3]));
+    machine.fill_tape(|i| if i == 3 { 1 } else { 0 });
+    assert_eq!(machine.image().slice_from(12), [3u8, 2, 1, 0, 0, 0, 0, 0, 0, 3, 2, 1].as_slice());
+  }
+
+  #[test]
+  fn patched_images_match_a_full_conversion() {
+    // 'step' only repaints the cells it changes; the result must be what
+    // converting the whole tape again gives, for every topology.
+    for &topology in [Square, Hex].iter() {
+      let mut machine = TuringMachine::new(12, 8, 3, 4, NoFeature, Cardinal, &mut SessionRng::new(5));
+      machine.set_cell_size(3, 2);
+      machine.set_topology(topology);
+      let palette = vec!([0, 0, 0], [1, 2, 3], [10, 20, 30], [40, 50, 60]);
+      machine.set_palette(&palette);
+      for _ in range(0u, 3000) {
+        machine.step();
+      }
+      let patched = machine.image().to_vec();
+      machine.set_palette(&palette);
+      assert!(patched.as_slice() == machine.image());
+    }
+  }
+
+  #[test]
+  fn heatmaps_color_cells_by_writes() {
+    let mut machine = uniform(4, 1, 1, 2, (0, 1, EAST));
+    machine.set_render_mode(Heatmap);
+    let changes: Vec<bool> = range(0u, 6).map(|_| machine.step()).collect();
+    // Steps still report symbol changes.
+    assert_eq!(changes, vec!(true, true, true, true, false, false));
+    // The first two cells were written most, the others half as often.
+    let pixel = |i: uint| machine.image().slice(i * 3, i * 3 + 3).to_vec();
+    assert_eq!(pixel(0), vec!(255, 255, 255));
+    assert_eq!(pixel(1), vec!(255, 255, 255));
+    assert_eq!(pixel(2), pixel(3));
+    assert!(pixel(2) != vec!(0, 0, 0) && pixel(2) != pixel(0));
+  }
+
+  #[test]
+  fn trails_fade_to_the_blank_color() {
+    let mut machine = uniform(4, 1, 1, 2, (0, 1, EAST));
+    machine.set_palette(&vec!([0, 0, 0], [200, 100, 40]));
+    machine.set_render_mode(Trails(4));
+    // Nothing has been written yet.
+    assert!(machine.image().iter().all(|&byte| byte == 0));
+    machine.step();
+    machine.step();
+    let pixel = |machine: &TuringMachine, i: uint| machine.image().slice(i * 3, i * 3 + 3).to_vec();
+    assert_eq!(pixel(&*machine, 0), vec!(40, 100, 200));
+    machine.end_frame();
+    machine.step();
+    // A quarter of the way to black, while the cell just written is fresh.
+    assert_eq!(pixel(&*machine, 0), vec!(30, 75, 150));
+    assert_eq!(pixel(&*machine, 2), vec!(40, 100, 200));
+    for _ in range(0u, 4) {
+      machine.end_frame();
+    }
+    assert!(machine.image().iter().all(|&byte| byte == 0));
+  }
+
+  #[test]
+  fn later_states_draw_darker() {
+    // State 0 writes 1 and goes to state 1, which writes 1 and goes back.
+    let mut machine = uniform(4, 1, 2, 2, (0, 1, EAST));
+    let entries = vec!((1, 1, EAST), (0, 1, EAST), (1, 1, EAST), (0, 1, EAST));
+    machine.table = Table::from_entries(2, 2, 1, entries).unwrap();
+    machine.set_palette(&vec!([0, 0, 0], [200, 100, 40]));
+    machine.set_render_mode(States);
+    machine.step();
+    machine.step();
+    assert_eq!(machine.image().slice(0, 6), [40u8, 100, 200, 14, 35, 70].as_slice());
+  }
+
+  #[test]
+  fn render_writes_the_image() {
+    let mut machine = uniform(3, 2, 1, 2, (0, 1, EAST));
+    machine.set_palette(&vec!([0, 0, 0], [1, 2, 3]));
+    machine.step();
+    let mut out = MemWriter::new();
+    machine.render(&mut out).unwrap();
+    assert_eq!(out.get_ref(), machine.image());
+  }
+
+  #[test]
+  fn distinct_symbols_counts_across_tapes() {
+    assert_eq!(super::distinct_symbols(&[[0u8, 0, 1].as_slice(), [1u8, 5].as_slice()]), 3);
+    let machine = TuringMachine::new(4, 4, 2, 3, NoFeature, Cardinal, &mut SessionRng::new(0));
+    assert_eq!(machine.symbols_used(), 1);
+  }
+
+  #[test]
+  fn saved_tables_load_back() {
+    let path = ::std::os::tmpdir().join(format!("turing-table-{}.toml", ::std::os::getpid()));
+    let saved = TuringMachine::new(8, 8, 3, 4, NoFeature, Cardinal, &mut SessionRng::new(5));
+    saved.save_table(&path).unwrap();
+    let mut loaded = TuringMachine::new(8, 8, 3, 4, NoFeature, Cardinal, &mut SessionRng::new(6));
+    let table = session::load_table(&path);
+    let _ = ::std::io::fs::unlink(&path);
+    loaded.load_table(&table.unwrap()).unwrap();
+    assert_eq!(loaded.table_text(), saved.table_text());
+  }
+
+  #[test]
+  fn fitted_sparse_tapes_keep_everything_in_the_picture() {
+    let mut machine = uniform(8, 6, 1, 2, (0, 1, EAST));
+    machine.set_sparse_tape(1000, 1000, true);
+    machine.set_palette(&vec!(BLACK, WHITE));
+    assert_eq!((machine.position(), machine.image_size()), (500 * 1000 + 500, (8, 6)));
+    for _ in range(0u, 20) {
+      machine.step();
+    }
+    // The view doubled three times to take in the row of 20 cells, so every
+    // eighth cell is shown.
+    assert_eq!(machine.image().len(), 8 * 6 * 3);
+    assert_eq!(machine.image().chunks(3).filter(|pixel| pixel[0] == 255).count(), 3);
+    machine.reset(&mut SessionRng::new(1));
+    assert!(machine.image().iter().all(|&byte| byte == 0));
+  }
+
+  #[test]
+  fn sparse_tapes_show_the_cells_in_view_as_text() {
+    let mut machine = uniform(3, 2, 1, 2, (0, 1, EAST));
+    machine.set_sparse_tape(1000, 1000, false);
+    assert_eq!(machine.tape_text(), " 0  0  0 \n 0  0 [0]\n".to_string());
+    machine.step();
+    // The head has left the view.
+    assert_eq!(machine.tape_text(), " 0  0  0 \n 0  0  1 \n".to_string());
+    assert!(machine.set_tape(box ::tape::DenseTape::new(1000, 1000) as Box<::tape::Tape>).is_err());
+  }
+
+  #[test]
+  fn hand_written_states_load() {
+    let mut machine = TuringMachine::new(2, 1, 1, 2, NoFeature, Cardinal, &mut SessionRng::new(0));
+    let saved = "position = 1\nstate = 0\ntable = [[0, 1, 1], [0, 0, 3]]\ntape = \"0100\"\n";
+    machine.load_state(&from_str(saved).unwrap()).unwrap();
+    assert_eq!(machine.table_text(), "[[0, 1, 1], [0, 0, 3]]".to_string());
+    assert_eq!(machine.table_json().to_string(), "[[0,1,1],[0,0,3]]".to_string());
+    let bad_direction = saved.replace("[0, 0, 3]", "[0, 0, 9]");
+    assert!(machine.load_state(&from_str(bad_direction.as_slice()).unwrap()).is_err());
+  }
+
+  #[test]
+  fn load_errors_have_kinds() {
+    let mut machine = TuringMachine::new(2, 1, 1, 2, NoFeature, Cardinal, &mut SessionRng::new(0));
+    let off_tape = "position = 2\nstate = 0\ntable = [[0, 1, 1], [0, 0, 3]]\ntape = \"0100\"\n";
+    assert!(match machine.load_state(&from_str(off_tape).unwrap()) { Err(BadState(_)) => true, _ => false });
+    let short: ::toml::Value = from_str("table = [[0, 1, 1]]").unwrap();
+    assert!(match machine.load_table(short.lookup("table").unwrap()) { Err(BadTable(_)) => true, _ => false });
+  }
+
+  #[test]
+  fn short_palettes_are_completed() {
+    let mut machine = uniform(4, 4, 1, 6, (0, 5, EAST));
+    machine.set_palette(&vec!([0, 0, 0], [1, 2, 3]));
+    for _ in range(0u, 8) {
+      machine.step();
+    }
+    assert!(machine.image().iter().any(|&byte| byte != 0));
+  }
+
+  #[test]
+  fn non_ascii_tapes_are_errors() {
+    assert!(session::decode_tape("0\u00e90", 2, 2).is_err());
+    assert_eq!(session::decode_tape("0100", 2, 2), Ok(vec!(1, 0)));
+  }
+}
@@ -0,0 +1,93 @@
+use turing::{Machine, Color};
+
+
+/// How a head is marked.
+#[deriving(PartialEq,Eq,Show)]
+pub enum Shape {
+  /// A filled square over the head's cell, at least 'size' pixels across.
+  Block,
+  /// Lines through the middle of the head's cell, reaching 'size' pixels
+  /// from it each way.
+  Crosshair,
+}
+
+
+/// Marks where the heads are in frames, so they can be followed on large
+/// tapes. The machine's image is left alone: frames are copied and marked.
+pub struct HeadMarker {
+  shape: Shape,
+  color: Color,
+  size: uint,
+  frame: Vec<u8>,
+}
+
+impl HeadMarker {
+  pub fn new(shape: Shape, color: Color, size: uint) -> HeadMarker {
+    HeadMarker { shape: shape, color: color, size: size, frame: Vec::new() }
+  }
+
+  /// The machine's image with its heads marked.
+  pub fn mark<'a>(&'a mut self, machine: &Machine) -> &'a [u8] {
+    let (width, height) = machine.image_size();
+    self.frame.clear();
+    self.frame.push_all(machine.image());
+    for &(x, y, cell_width, cell_height) in machine.head_cells().iter() {
+      let (center_x, center_y) = (x + cell_width / 2, y + cell_height / 2);
+      match self.shape {
+        Block => {
+          let (half_width, half_height) = (::std::cmp::max(cell_width, self.size) / 2,
+                                           ::std::cmp::max(cell_height, self.size) / 2);
+          for py in range(center_y.saturating_sub(half_height), center_y + half_height + 1) {
+            for px in range(center_x.saturating_sub(half_width), center_x + half_width + 1) {
+              self.paint(px, py, width, height);
+            }
+          }
+        },
+        Crosshair => {
+          for px in range(center_x.saturating_sub(self.size), center_x + self.size + 1) {
+            self.paint(px, center_y, width, height);
+          }
+          for py in range(center_y.saturating_sub(self.size), center_y + self.size + 1) {
+            self.paint(center_x, py, width, height);
+          }
+        },
+      }
+    }
+    self.frame.as_slice()
+  }
+
+  // Paints the pixel at ('x', 'y') if it is in the frame.
+  fn paint(&mut self, x: uint, y: uint, width: uint, height: uint) {
+    if x >= width || y >= height {
+      return;
+    }
+    let p = (y * width + x) * 3;
+    let [r, g, b] = self.color;
+    *self.frame.get_mut(p) = b;
+    *self.frame.get_mut(p + 1) = g;
+    *self.frame.get_mut(p + 2) = r;
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use turing::{TuringMachine, Machine, NoFeature, Cardinal, BLACK};
+  use turing::rng::SessionRng;
+
+  use super::{HeadMarker, Block, Crosshair};
+
+  #[test]
+  fn marks_copies_of_the_image() {
+    let mut machine = TuringMachine::new(5, 5, 2, 2, NoFeature, Cardinal, &mut SessionRng::new(0));
+    machine.set_palette(&vec!(BLACK, BLACK));
+    // The head starts in the top left corner, so marks are cut off there.
+    let blocks: Vec<u8> = HeadMarker::new(Block, [255, 0, 0], 3).mark(&*machine).to_vec();
+    let marked: Vec<uint> = range(0u, 25).filter(|&i| *blocks.get(i * 3 + 2) == 255).collect();
+    assert_eq!(marked, vec!(0, 1, 5, 6));
+    let crosshair: Vec<u8> = HeadMarker::new(Crosshair, [255, 0, 0], 2).mark(&*machine).to_vec();
+    let marked: Vec<uint> = range(0u, 25).filter(|&i| *crosshair.get(i * 3 + 2) == 255).collect();
+    assert_eq!(marked, vec!(0, 1, 2, 5, 10));
+    assert!(machine.image().iter().all(|&byte| byte == 0));
+  }
+}
@@ -0,0 +1,169 @@
+use toml;
+
+use turing::error::{Error, invalid};
+use config;
+
+
+/// The memory a run with 'config' needs, in bytes, estimated from the
+/// biggest buffers: tapes, neighbor tables, the image, whole-frame output
+/// buffers and saved states for replays. Montage tiles each count as a
+/// machine.
+pub fn estimate(config: &toml::Value) -> u64 {
+  let int = |name: &str, default: i64| {
+    config.lookup(name).and_then(|v| v.as_integer()).unwrap_or(default) as u64
+  };
+  let cells = int("turing.width", 1) * int("turing.height", 1);
+  let cell_pixels = int("turing.cell_width", 1) * int("turing.cell_height", 1);
+  let two_tapes = int("turing.tapes", 1) == 2;
+  let interleaved = !two_tapes && config.lookup("turing.interleave").and_then(|v| v.as_str())
+    .map_or(false, |pattern| pattern != "none");
+  // Four u32 neighbors per cell, or eight on a hex tape.
+  let hex = config.lookup("turing.topology").and_then(|v| v.as_str()) == Some("hex");
+  let neighbors = cells * 4 * if hex { 8 } else { 4 };
+
+  let image = if two_tapes {
+    let columns = match config.lookup("turing.two_tape_display").and_then(|v| v.as_str()) {
+      Some("combined") => 1,
+      _ => 2,
+    };
+    cells * cell_pixels * 3 * columns
+  } else {
+    cells * cell_pixels * 3
+  };
+  let tiles = int("turing.montage_columns", 1) * int("turing.montage_rows", 1);
+  let machine = if two_tapes {
+    2 * cells + neighbors + image
+  } else if interleaved {
+    // Two machines and the combined image.
+    2 * (cells + neighbors + image) + image
+  } else if tiles > 1 {
+    // The tiles and the montage.
+    tiles * (cells + neighbors + image) + tiles * image
+  } else {
+    cells + neighbors + image
+  };
+  let image = image * tiles;
+  // A viewport copies its window of the image, and the outputs get that.
+  let (machine, image) = match (config.lookup("turing.viewport_width"), config.lookup("turing.viewport_height")) {
+    (Some(_), Some(_)) => {
+      let view = int("turing.viewport_width", 1) * int("turing.viewport_height", 1) * 3;
+      (machine + view, view)
+    },
+    _ => (machine, image),
+  };
+
+  // Raw outputs are upscaled a band of rows at a time; the others get whole
+  // upscaled frames.
+  let mut outputs = 0;
+  let default_scale = int("turing.scale", 1);
+  for block in config.lookup("output").and_then(|v| v.as_slice()).unwrap_or(&[]).iter() {
+    let scale = block.lookup("scale").and_then(|v| v.as_integer()).map(|scale| scale as u64)
+      .unwrap_or(default_scale);
+    let frame = image * scale * scale;
+    outputs += match block.lookup("format").and_then(|v| v.as_str()).unwrap_or("raw") {
+      "raw" => 0,
+      // The planes of a converted frame.
+      "y4m" => frame + if scale > 1 { frame } else { 0 },
+      // Summed in u32s, written as an average.
+      "long-exposure" => frame * 4 + frame + if scale > 1 { frame } else { 0 },
+      // A palette index per pixel for each frame, and the compressed frames.
+      "gif" => {
+        let max_frames = block.lookup("max_frames").and_then(|v| v.as_integer()).unwrap_or(100) as u64;
+        2 * frame / 3 * max_frames + if scale > 1 { frame } else { 0 }
+      },
+      // The encoder copies the frame into RGB scanlines and a zlib stream.
+      "png" => 2 * frame + if scale > 1 { frame } else { 0 },
+      _ => if scale > 1 { frame } else { 0 },
+    };
+  }
+
+  // Saved states hold the tape as two hex digits per cell, at the start of
+  // the current window and of the best one.
+  let replay = match config.lookup("turing.replay").and_then(|v| v.as_bool()) {
+    Some(true) => 2 * 2 * cells * tiles * if two_tapes || interleaved { 2 } else { 1 },
+    _ => 0,
+  };
+  machine + outputs + replay
+}
+
+
+/// Applies 'turing.memory_limit_mb' to 'config'. When the estimated memory
+/// exceeds the limit, the run is either refused or, with
+/// 'memory_policy = "downscale"', the cell size is halved until it fits.
+/// Returns the config to run with.
+pub fn apply_limit(config: toml::Value) -> Result<toml::Value, Error> {
+  let limit_mb = config.lookup("turing.memory_limit_mb").and_then(|v| v.as_integer()).unwrap_or(0) as u64;
+  if limit_mb == 0 {
+    return Ok(config);
+  }
+  let limit = limit_mb * 1024 * 1024;
+  let downscale = config.lookup("turing.memory_policy").and_then(|v| v.as_str()) == Some("downscale");
+
+  let mut config = config;
+  loop {
+    let needed = estimate(&config);
+    if needed <= limit {
+      return Ok(config);
+    }
+    let (cell_width, cell_height) = {
+      let int = |name: &str| config.lookup(name).and_then(|v| v.as_integer()).unwrap_or(1);
+      (int("turing.cell_width"), int("turing.cell_height"))
+    };
+    if !downscale || (cell_width == 1 && cell_height == 1) {
+      return Err(invalid(format!("The run needs about {} MB, more than memory_limit_mb ({} MB){}",
+                                 needed / (1024 * 1024) + 1, limit_mb,
+                                 if downscale { " even with 1x1 cells" } else { "" })));
+    }
+    // Shrink the longer side first to keep cells close to square.
+    if cell_width >= cell_height {
+      config::set(&mut config, "turing.cell_width", toml::Integer((cell_width + 1) / 2));
+    } else {
+      config::set(&mut config, "turing.cell_height", toml::Integer((cell_height + 1) / 2));
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use toml;
+
+  use super::{estimate, apply_limit};
+
+  fn config(extra: &str) -> toml::Value {
+    from_str(format!("[turing]\nstates = 4\nsymbols = 6\npicture_steps = 10\nreset_steps = 100\n\
+                      width = 1024\nheight = 1024\n{}", extra).as_slice()).unwrap()
+  }
+
+  #[test]
+  fn estimate_counts_tape_neighbors_and_image() {
+    assert_eq!(estimate(&config("")), 1024 * 1024 * (1 + 16 + 3));
+    assert_eq!(estimate(&config("cell_width = 2\ncell_height = 2")), 1024 * 1024 * (1 + 16 + 12));
+  }
+
+  #[test]
+  fn no_limit_by_default() {
+    assert!(apply_limit(config("cell_width = 64\ncell_height = 64")).is_ok());
+  }
+
+  #[test]
+  fn refuses_runs_over_the_limit() {
+    assert!(apply_limit(config("memory_limit_mb = 19")).is_err());
+    assert!(apply_limit(config("memory_limit_mb = 20")).is_ok());
+  }
+
+  #[test]
+  fn downscales_cells_to_fit() {
+    let limited = apply_limit(config("memory_limit_mb = 40\nmemory_policy = \"downscale\"\n\
+                                      cell_width = 4\ncell_height = 4")).unwrap();
+    let int = |name: &str| limited.lookup(name).and_then(|v| v.as_integer()).unwrap();
+    // 4x4 cells need 65 MB, 2x4 41 MB and 2x2 29 MB.
+    assert_eq!((int("turing.cell_width"), int("turing.cell_height")), (2, 2));
+    assert!(estimate(&limited) <= 40 * 1024 * 1024);
+  }
+
+  #[test]
+  fn downscale_gives_up_at_one_pixel_cells() {
+    assert!(apply_limit(config("memory_limit_mb = 10\nmemory_policy = \"downscale\"")).is_err());
+  }
+}
@@ -0,0 +1,99 @@
+use std::comm::RecvDisconnected;
+use std::io::{IoResult, Listener, Acceptor, TcpListener, TcpStream};
+use std::sync::Arc;
+
+use jpeg::JpegEncoder;
+use output::ImageSink;
+
+
+// Separates the JPEG parts of the stream.
+static BOUNDARY: &'static str = "turingframe";
+
+
+/// Serves frames as a multipart MJPEG stream over HTTP, which browsers show
+/// as a moving image: every client that connects to the port gets a
+/// 'multipart/x-mixed-replace' response with each frame as a JPEG part.
+/// Frames are only encoded while someone is watching, and a client that
+/// can't keep up misses frames rather than holding up the others.
+pub struct MjpegSink {
+  encoder: JpegEncoder,
+  // Clients accepted since the last frame.
+  new_clients: Receiver<TcpStream>,
+  // A sender to the task writing to each client. Each holds at most one
+  // frame waiting to be written.
+  clients: Vec<SyncSender<Arc<Vec<u8>>>>,
+  jpeg: Vec<u8>,
+}
+
+impl MjpegSink {
+  /// Starts accepting clients on 'port' in the background.
+  pub fn listen(port: u16, quality: uint) -> IoResult<MjpegSink> {
+    let listener = try!(TcpListener::bind("0.0.0.0", port));
+    let mut acceptor = try!(listener.listen());
+    let (client_sender, new_clients) = channel::<TcpStream>();
+    spawn(proc() {
+      for client in acceptor.incoming() {
+        match client {
+          Ok(client) => {
+            if client_sender.send_opt(client).is_err() {
+              break;
+            }
+          },
+          Err(_) => {},
+        }
+      }
+    });
+    Ok(MjpegSink { encoder: JpegEncoder::new(quality), new_clients: new_clients, clients: Vec::new(),
+                   jpeg: Vec::new() })
+  }
+}
+
+impl ImageSink for MjpegSink {
+  fn write_frame(&mut self, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    loop {
+      match self.new_clients.try_recv() {
+        Ok(client) => {
+          let (sender, frames) = sync_channel(1);
+          spawn(proc() {
+            let _ = stream_to(client, frames);
+          });
+          self.clients.push(sender);
+        },
+        Err(_) => break,
+      }
+    }
+    if self.clients.is_empty() {
+      return Ok(());
+    }
+
+    try!(self.encoder.encode(image, width, height, &mut self.jpeg));
+    let frame = Arc::new(self.jpeg.clone());
+    // Drop clients that have gone away. A full sender means the client is
+    // still writing the last frame, so it skips this one.
+    self.clients.retain(|client| {
+      match client.try_send(frame.clone()) {
+        Err(RecvDisconnected(_)) => false,
+        _ => true,
+      }
+    });
+    Ok(())
+  }
+}
+
+
+// Writes the response headers to 'client', then each frame from 'frames' as
+// a part, until the client goes away.
+fn stream_to(mut client: TcpStream, frames: Receiver<Arc<Vec<u8>>>) -> IoResult<()> {
+  try!(write!(client, "HTTP/1.1 200 OK\r\n\
+                       Content-Type: multipart/x-mixed-replace; boundary={}\r\n\
+                       Cache-Control: no-cache\r\n\
+                       Connection: close\r\n\r\n", BOUNDARY));
+  for frame in frames.iter() {
+    try!(write!(client, "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+                BOUNDARY, frame.len()));
+    try!(client.write(frame.as_slice()));
+    try!(client.write_str("\r\n"));
+    try!(client.flush());
+  }
+  Ok(())
+}
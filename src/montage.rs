@@ -0,0 +1,282 @@
+use std::hash::hash;
+use serialize::json;
+use serialize::json::Json;
+use toml;
+
+use super::{Machine, TuringMachine, Color, distinct_symbols};
+use error::{MachineError, BadTable, BadState};
+use rng::SessionRng;
+
+
+/// Several single tape machines with their own random tables, run together
+/// and drawn as tiles of a grid in one frame, so many rules can be screened
+/// at once. Tiles are filled row by row, 'columns' to a row.
+pub struct Montage {
+  tiles: Vec<Box<TuringMachine>>,
+  columns: uint,
+  // Composed from the tiles' images. Kept up to date by 'step'.
+  image: Vec<u8>,
+}
+
+impl Montage {
+  /// Tiles 'tiles', which must all have the same size, 'columns' to a row.
+  /// The last row is padded with black if the tiles don't fill it.
+  pub fn new(tiles: Vec<Box<TuringMachine>>, columns: uint) -> Box<Montage> {
+    let (width, height) = tiles.get(0).image_size();
+    let rows = (tiles.len() + columns - 1) / columns;
+    let mut montage = box Montage {
+      tiles: tiles,
+      columns: columns,
+      image: Vec::from_elem(width * columns * height * rows * 3, 0u8),
+    };
+    montage.rebuild_image();
+    montage
+  }
+
+  // Copies the pixels of the cell at 'position' of tile 'tile'.
+  fn copy_cell(&mut self, tile: uint, position: uint) {
+    let machine = &**self.tiles.get(tile);
+    let (cell_width, cell_height) = (machine.cell_width, machine.cell_height);
+    let tile_row_bytes = machine.width * cell_width * 3;
+    let row_bytes = tile_row_bytes * self.columns;
+    // The tile's top left pixel, as a byte offset and a row.
+    let tile_x = tile % self.columns * tile_row_bytes;
+    let tile_y = tile / self.columns * machine.height * cell_height;
+    let x = position % machine.width * cell_width * 3;
+    let y = position / machine.width * cell_height;
+    for dy in range(0, cell_height) {
+      let from = (y + dy) * tile_row_bytes + x;
+      let to = (tile_y + y + dy) * row_bytes + tile_x + x;
+      for p in range(0, cell_width * 3) {
+        *self.image.get_mut(to + p) = *machine.image.get(from + p);
+      }
+    }
+  }
+
+  fn rebuild_image(&mut self) {
+    for tile in range(0, self.tiles.len()) {
+      let (width, height) = self.tiles.get(tile).tape_size();
+      for position in range(0, width * height) {
+        self.copy_cell(tile, position);
+      }
+    }
+  }
+}
+
+impl Machine for Montage {
+  fn step(&mut self) -> bool {
+    let mut changed = false;
+    for tile in range(0, self.tiles.len()) {
+      if self.tiles.get(tile).heads() == 1 {
+        let position = self.tiles.get(tile).position;
+        if self.tiles.get_mut(tile).step() {
+          self.copy_cell(tile, position);
+          changed = true;
+        }
+      } else {
+        let positions = self.tiles.get(tile).head_positions();
+        if self.tiles.get_mut(tile).step() {
+          for &position in positions.iter() {
+            self.copy_cell(tile, position);
+          }
+          changed = true;
+        }
+      }
+    }
+    changed
+  }
+
+  fn set_palette(&mut self, palette: &Vec<Color>) {
+    for tile in self.tiles.iter_mut() {
+      tile.set_palette(palette);
+    }
+    self.rebuild_image();
+  }
+
+  fn redraw(&mut self, lut: bool) {
+    for tile in self.tiles.iter_mut() {
+      tile.redraw(lut);
+    }
+    self.rebuild_image();
+  }
+
+  fn image<'a>(&'a self) -> &'a [u8] {
+    self.image.as_slice()
+  }
+
+  fn image_size(&self) -> (uint, uint) {
+    let (width, height) = self.tiles.get(0).image_size();
+    let rows = (self.tiles.len() + self.columns - 1) / self.columns;
+    (width * self.columns, height * rows)
+  }
+
+  /// The size of each tile's tape.
+  fn tape_size(&self) -> (uint, uint) {
+    self.tiles.get(0).tape_size()
+  }
+
+  fn symbols(&self) -> u8 {
+    self.tiles.get(0).symbols
+  }
+
+  fn fill_tape(&mut self, symbol: |uint| -> u8) {
+    let (width, height) = self.tape_size();
+    let tape: Vec<u8> = range(0, width * height).map(|i| symbol(i)).collect();
+    for tile in self.tiles.iter_mut() {
+      tile.fill_tape(|i| *tape.get(i));
+    }
+    self.rebuild_image();
+  }
+
+  fn reset(&mut self, rng: &mut SessionRng) {
+    for tile in self.tiles.iter_mut() {
+      tile.reset(rng);
+    }
+    self.rebuild_image();
+  }
+
+  fn chain(&mut self, rng: &mut SessionRng) {
+    for tile in self.tiles.iter_mut() {
+      tile.chain(rng);
+    }
+  }
+
+  fn mutate(&mut self, rng: &mut SessionRng, count: uint) {
+    for tile in self.tiles.iter_mut() {
+      tile.mutate(rng, count);
+    }
+  }
+
+  fn table_text(&self) -> String {
+    let tables: Vec<String> = self.tiles.iter().map(|tile| tile.table_text()).collect();
+    format!("[{}]", tables.connect(", "))
+  }
+
+  fn table_json(&self) -> Json {
+    json::List(self.tiles.iter().map(|tile| tile.table_json()).collect())
+  }
+
+  // Each tile gets a subsection of the caller's section.
+  fn save_state(&self) -> String {
+    let mut saved = String::new();
+    for (i, tile) in self.tiles.iter().enumerate() {
+      saved.push_str(format!("\n[machine.tile{}]\n{}", i, tile.save_state()).as_slice());
+    }
+    saved
+  }
+
+  fn load_state(&mut self, saved: &toml::Value) -> Result<(), MachineError> {
+    for (i, tile) in self.tiles.iter_mut().enumerate() {
+      match saved.lookup(format!("tile{}", i).as_slice()) {
+        Some(tile_saved) => try!(tile.load_state(tile_saved)),
+        None => return Err(BadState(format!("Missing saved tile {}", i))),
+      }
+    }
+    self.rebuild_image();
+    Ok(())
+  }
+
+  fn load_table(&mut self, table: &toml::Value) -> Result<(), MachineError> {
+    match table.as_slice() {
+      Some(tables) if tables.len() == self.tiles.len() => {
+        for (tile, table) in self.tiles.iter_mut().zip(tables.iter()) {
+          try!(tile.load_table(table));
+        }
+        Ok(())
+      },
+      _ => Err(BadTable(format!("Expected the tables of {} tiles", self.tiles.len()))),
+    }
+  }
+
+  fn explain_step(&self) -> String {
+    let steps: Vec<String> = self.tiles.iter().enumerate().map(|(i, tile)| {
+      format!("tile {}: {}", i, tile.explain_step())
+    }).collect();
+    steps.connect("; ")
+  }
+
+  fn tape_text(&self) -> String {
+    let mut text = String::new();
+    for (i, tile) in self.tiles.iter().enumerate() {
+      text.push_str(format!("Tile {}:\n{}", i, tile.tape_text()).as_slice());
+    }
+    text
+  }
+
+  // One halted tile resets the whole montage, like the run's other resets.
+  fn halted(&self) -> bool {
+    self.tiles.iter().any(|tile| tile.halted())
+  }
+
+  fn symbols_used(&self) -> uint {
+    let tapes: Vec<Vec<u8>> = self.tiles.iter().map(|tile| tile.tape()).collect();
+    let tapes: Vec<&[u8]> = tapes.iter().map(|tape| tape.as_slice()).collect();
+    distinct_symbols(tapes.as_slice())
+  }
+
+  fn head_cells(&self) -> Vec<(uint, uint, uint, uint)> {
+    let mut cells = Vec::new();
+    for (tile, machine) in self.tiles.iter().enumerate() {
+      let (width, height) = machine.image_size();
+      let (left, top) = (tile % self.columns * width, tile / self.columns * height);
+      cells.extend(machine.head_cells().into_iter().map(|(x, y, w, h)| (left + x, top + y, w, h)));
+    }
+    cells
+  }
+
+  // Repeats once every tile's cycle has come round together.
+  fn local_hash(&self) -> Option<u64> {
+    let hashes: Vec<Option<u64>> = self.tiles.iter().map(|tile| tile.local_hash()).collect();
+    Some(hash(&hashes))
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::Montage;
+  use super::super::{TuringMachine, Machine, NoFeature, Cardinal, BLACK, WHITE};
+  use rng::SessionRng;
+
+  fn montage(count: uint, columns: uint) -> Box<Montage> {
+    let mut rng = SessionRng::new(3);
+    let tiles = range(0, count).map(|_| {
+      let mut tile = TuringMachine::new(6, 4, 3, 2, NoFeature, Cardinal, &mut rng);
+      tile.set_cell_size(2, 1);
+      tile
+    }).collect();
+    let mut montage = Montage::new(tiles, columns);
+    montage.set_palette(&vec!(BLACK, WHITE));
+    montage
+  }
+
+  // The pixels of tile 'tile' cut out of the montage's image.
+  fn tile_pixels(montage: &Montage, tile: uint) -> Vec<u8> {
+    let (width, height) = montage.tiles.get(tile).image_size();
+    let row_bytes = width * 3 * montage.columns;
+    let (x, y) = (tile % montage.columns * width * 3, tile / montage.columns * height);
+    let mut pixels = Vec::new();
+    for row in range(y, y + height) {
+      pixels.push_all(montage.image.slice(row * row_bytes + x, row * row_bytes + x + width * 3));
+    }
+    pixels
+  }
+
+  #[test]
+  fn tiles_fill_a_grid() {
+    let montage = montage(5, 2);
+    assert_eq!(montage.image_size(), (24, 12));
+    assert_eq!(montage.image.len(), 24 * 12 * 3);
+  }
+
+  #[test]
+  fn tiles_show_their_machines() {
+    let mut montage = montage(4, 2);
+    for _ in range(0u, 300) {
+      montage.step();
+    }
+    for tile in range(0u, 4) {
+      assert_eq!(tile_pixels(&*montage, tile).as_slice(), montage.tiles.get(tile).image());
+    }
+  }
+}
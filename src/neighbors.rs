@@ -0,0 +1,327 @@
+use super::{Direction, Boundary, Wrap, Reflect, Clamp, Halt, NORTH, EAST, SOUTH, WEST};
+use super::{NORTHEAST, SOUTHEAST, SOUTHWEST, NORTHWEST, STAY, move_position};
+
+
+/// The neighbor of a move off a tape with a 'Halt' boundary.
+pub static OFF_TAPE: uint = ::std::u32::MAX as uint;
+
+
+/// Precomputed index of the neighboring cell for every (cell, direction) pair.
+/// Building this once at construction keeps 'step' down to a table lookup no
+/// matter how complicated the topology's movement rules are. Directions
+/// beyond the table's are diagonals, looked up as their two parts, or STAY.
+#[deriving(Show)]
+pub struct NeighborTable {
+  directions: uint,
+  // neighbor of [position, direction]
+  table: Vec<u32>,
+}
+
+impl NeighborTable {
+  /// Builds a table for 'cells' cells and 'directions' directions where
+  /// 'neighbor(position, direction)' gives the cell moved to.
+  pub fn new(cells: uint, directions: uint, neighbor: |uint, uint| -> uint) -> NeighborTable {
+    let mut table = Vec::with_capacity(cells * directions);
+    for position in range(0, cells) {
+      for direction in range(0, directions) {
+        table.push(neighbor(position, direction) as u32);
+      }
+    }
+    NeighborTable { directions: directions, table: table }
+  }
+
+  /// The table for a 'width'x'height' torus, where moving off one edge wraps
+  /// around to the opposite one.
+  pub fn torus(width: uint, height: uint) -> NeighborTable {
+    NeighborTable::bounded(width, height, Wrap)
+  }
+
+  /// The table for a 'width'x'height' tape whose edges behave as 'boundary'.
+  /// With 'Halt', moves off the tape lead to OFF_TAPE.
+  pub fn bounded(width: uint, height: uint, boundary: Boundary) -> NeighborTable {
+    NeighborTable::new(width * height, CARDINALS.len(), |position, direction| {
+      bounded_move(position, CARDINALS[direction], width, height, boundary)
+    })
+  }
+
+  /// The table for a 'width'x'height' hex tape in offset rows (see
+  /// 'Topology::Hex') whose edges behave as 'boundary'. Wrapping only
+  /// lines the rows up when 'height' is even.
+  pub fn hex(width: uint, height: uint, boundary: Boundary) -> NeighborTable {
+    // Every direction but STAY, since diagonals aren't made of two moves.
+    NeighborTable::new(width * height, 8, |position, index| {
+      match (hex_move(position, Direction::from_index(index).unwrap(), width, height), boundary) {
+        ((next, false), _) | ((next, true), Wrap) => next,
+        (_, Reflect) => {
+          // Opposite directions are two apart in both halves of 'Direction'.
+          let opposite = index / 4 * 4 + (index + 2) % 4;
+          let (next, _) = hex_move(position, Direction::from_index(opposite).unwrap(), width, height);
+          next
+        },
+        (_, Clamp) => position,
+        (_, Halt) => OFF_TAPE,
+      }
+    })
+  }
+
+  /// The symbols of 'tape' at 'position' and its north, east, south and
+  /// west neighbors. Neighbors off the tape read as 255, which is never a
+  /// symbol.
+  pub fn window(&self, tape: &[u8], position: uint) -> [u8, ..5] {
+    let cell = |direction: Direction| match self.get(position, direction) {
+      OFF_TAPE => 255,
+      neighbor => tape[neighbor],
+    };
+    [tape[position], cell(NORTH), cell(EAST), cell(SOUTH), cell(WEST)]
+  }
+
+  /// The cell reached by moving from 'position' in 'direction'.
+  #[inline]
+  pub fn get(&self, position: uint, direction: Direction) -> uint {
+    let index = direction as uint;
+    if index < self.directions {
+      return *self.table.get(position * self.directions + index) as uint;
+    }
+    match direction.diagonal_parts() {
+      Some((vertical, horizontal)) => match self.get(position, vertical) {
+        OFF_TAPE => OFF_TAPE,
+        position => self.get(position, horizontal),
+      },
+      None => position,
+    }
+  }
+}
+
+
+static CARDINALS: [Direction, ..4] = [NORTH, EAST, SOUTH, WEST];
+
+
+/// The cell reached by moving from 'position' in the cardinal 'direction' on
+/// a 'width'x'height' square tape whose edges behave as 'boundary', or
+/// OFF_TAPE. For tapes too big for a table.
+pub fn bounded_move(position: uint, direction: Direction, width: uint, height: uint, boundary: Boundary) -> uint {
+  let (x, y) = (position % width, position / width);
+  let off = match direction {
+    NORTH => y == 0,
+    EAST => x == width - 1,
+    SOUTH => y == height - 1,
+    _ => x == 0,
+  };
+  match boundary {
+    _ if !off => move_position(position, direction, width, height),
+    Wrap => move_position(position, direction, width, height),
+    // The opposite direction, two cardinal directions on.
+    Reflect => move_position(position, CARDINALS[(direction as uint + 2) % 4], width, height),
+    Clamp => position,
+    Halt => OFF_TAPE,
+  }
+}
+
+
+// The cell of a hex tape reached by moving from 'position' in 'direction',
+// wrapping around the edges, and whether the move left the tape.
+fn hex_move(position: uint, direction: Direction, width: uint, height: uint) -> (uint, bool) {
+  let (x, y) = ((position % width) as int, (position / width) as int);
+  // Odd rows sit half a cell east, so their diagonal neighbors are a column
+  // further east.
+  let shift = y % 2;
+  let (dx, dy) = match direction {
+    NORTH => (0, -1),
+    EAST => (1, 0),
+    SOUTH => (0, 1),
+    WEST => (-1, 0),
+    NORTHEAST => (shift, -1),
+    SOUTHEAST => (shift, 1),
+    SOUTHWEST => (shift - 1, 1),
+    NORTHWEST => (shift - 1, -1),
+    STAY => (0, 0),
+  };
+  let (width, height) = (width as int, height as int);
+  let (x, y) = (x + dx, y + dy);
+  let off = x < 0 || x >= width || y < 0 || y >= height;
+  ((((y + height) % height) * width + (x + width) % width) as uint, off)
+}
+
+
+#[cfg(test)]
+mod test {
+  use quickcheck::{quickcheck, TestResult};
+
+  use super::{NeighborTable, OFF_TAPE};
+  use super::super::{Direction, NORTH, EAST, SOUTH, WEST, NORTHEAST, SOUTHEAST, SOUTHWEST, NORTHWEST, STAY};
+  use super::super::{Wrap, Reflect, Clamp, Halt, move_position};
+
+  static WIDTH: uint = 5;
+  static HEIGHT: uint = 3;
+
+  fn at(x: uint, y: uint) -> uint {
+    y * WIDTH + x
+  }
+
+  #[test]
+  fn moves_inside_the_tape() {
+    assert_eq!(move_position(at(2, 1), NORTH, WIDTH, HEIGHT), at(2, 0));
+    assert_eq!(move_position(at(2, 1), EAST, WIDTH, HEIGHT), at(3, 1));
+    assert_eq!(move_position(at(2, 1), SOUTH, WIDTH, HEIGHT), at(2, 2));
+    assert_eq!(move_position(at(2, 1), WEST, WIDTH, HEIGHT), at(1, 1));
+  }
+
+  #[test]
+  fn wraps_at_every_edge() {
+    for x in range(0, WIDTH) {
+      assert_eq!(move_position(at(x, 0), NORTH, WIDTH, HEIGHT), at(x, HEIGHT - 1));
+      assert_eq!(move_position(at(x, HEIGHT - 1), SOUTH, WIDTH, HEIGHT), at(x, 0));
+    }
+    for y in range(0, HEIGHT) {
+      assert_eq!(move_position(at(0, y), WEST, WIDTH, HEIGHT), at(WIDTH - 1, y));
+      assert_eq!(move_position(at(WIDTH - 1, y), EAST, WIDTH, HEIGHT), at(0, y));
+    }
+  }
+
+  #[test]
+  fn wraps_at_the_corners() {
+    assert_eq!(move_position(at(0, 0), NORTH, WIDTH, HEIGHT), at(0, HEIGHT - 1));
+    assert_eq!(move_position(at(0, 0), WEST, WIDTH, HEIGHT), at(WIDTH - 1, 0));
+    assert_eq!(move_position(at(WIDTH - 1, HEIGHT - 1), SOUTH, WIDTH, HEIGHT), at(WIDTH - 1, 0));
+    assert_eq!(move_position(at(WIDTH - 1, HEIGHT - 1), EAST, WIDTH, HEIGHT), at(0, HEIGHT - 1));
+  }
+
+  #[test]
+  fn diagonals_wrap_at_the_corners() {
+    assert_eq!(move_position(at(2, 1), NORTHEAST, WIDTH, HEIGHT), at(3, 0));
+    assert_eq!(move_position(at(WIDTH - 1, 0), NORTHEAST, WIDTH, HEIGHT), at(0, HEIGHT - 1));
+    assert_eq!(move_position(at(0, HEIGHT - 1), SOUTHWEST, WIDTH, HEIGHT), at(WIDTH - 1, 0));
+    assert_eq!(move_position(at(2, 1), STAY, WIDTH, HEIGHT), at(2, 1));
+  }
+
+  #[test]
+  fn edges_reflect_clamp_or_halt() {
+    let reflect = NeighborTable::bounded(WIDTH, HEIGHT, Reflect);
+    assert_eq!(reflect.get(at(2, 0), NORTH), at(2, 1));
+    assert_eq!(reflect.get(at(WIDTH - 1, 1), EAST), at(WIDTH - 2, 1));
+    assert_eq!(reflect.get(at(0, 0), NORTHWEST), at(1, 1));
+    let clamp = NeighborTable::bounded(WIDTH, HEIGHT, Clamp);
+    assert_eq!(clamp.get(at(0, 1), WEST), at(0, 1));
+    assert_eq!(clamp.get(at(0, HEIGHT - 1), SOUTHEAST), at(1, HEIGHT - 1));
+    let halt = NeighborTable::bounded(WIDTH, HEIGHT, Halt);
+    assert_eq!(halt.get(at(2, HEIGHT - 1), SOUTH), OFF_TAPE);
+    assert_eq!(halt.get(at(2, 0), NORTHEAST), OFF_TAPE);
+    assert_eq!(halt.get(at(2, 1), NORTHEAST), at(3, 0));
+  }
+
+  #[test]
+  fn hex_cells_have_six_mutual_neighbors() {
+    // Wrapping lines up the offset rows on an even number of rows.
+    let (width, height) = (WIDTH, 4u);
+    let table = NeighborTable::hex(width, height, Wrap);
+    let moves = [(EAST, WEST), (SOUTHEAST, NORTHWEST), (SOUTHWEST, NORTHEAST)];
+    for position in range(0, width * height) {
+      let mut neighbors = Vec::new();
+      for &(there, back) in moves.iter() {
+        assert_eq!(table.get(table.get(position, there), back), position);
+        neighbors.push(table.get(position, there));
+        neighbors.push(table.get(position, back));
+      }
+      neighbors.sort();
+      neighbors.dedup();
+      assert_eq!(neighbors.len(), 6);
+    }
+    // Odd rows sit half a cell east.
+    assert_eq!(table.get(at(2, 0), SOUTHEAST), at(2, 1));
+    assert_eq!(table.get(at(2, 1), SOUTHEAST), at(3, 2));
+    assert_eq!(table.get(at(2, 1), NORTH), at(2, 0));
+    assert_eq!(table.get(at(2, 1), STAY), at(2, 1));
+    assert_eq!(NeighborTable::hex(width, height, Halt).get(at(0, 2), NORTHWEST), OFF_TAPE);
+  }
+
+  #[test]
+  fn table_matches_move_position() {
+    let table = NeighborTable::torus(WIDTH, HEIGHT);
+    for position in range(0, WIDTH * HEIGHT) {
+      for index in range(0u, 9) {
+        let direction = Direction::from_index(index).unwrap();
+        assert_eq!(table.get(position, direction), move_position(position, direction, WIDTH, HEIGHT));
+      }
+    }
+  }
+
+  #[test]
+  fn single_cell_tape_stays_put() {
+    for index in range(0u, 9) {
+      assert_eq!(move_position(0, Direction::from_index(index).unwrap(), 1, 1), 0);
+    }
+  }
+
+  // Tapes of 1 to 256 cells on each side.
+  fn size(width: u8, height: u8) -> (uint, uint) {
+    (width as uint + 1, height as uint + 1)
+  }
+
+  #[test]
+  fn position_stays_on_the_tape() {
+    fn prop(width: u8, height: u8, start: uint, moves: Vec<u8>) -> bool {
+      let (width, height) = size(width, height);
+      let mut position = start % (width * height);
+      for &index in moves.iter() {
+        position = move_position(position, Direction::from_index(index as uint % 9).unwrap(), width, height);
+        if position >= width * height {
+          return false;
+        }
+      }
+      true
+    }
+    quickcheck(prop as fn(u8, u8, uint, Vec<u8>) -> bool);
+  }
+
+  #[test]
+  fn opposite_moves_cancel() {
+    fn prop(width: u8, height: u8, start: uint) -> bool {
+      let (width, height) = size(width, height);
+      let position = start % (width * height);
+      let there_and_back = |there, back| {
+        move_position(move_position(position, there, width, height), back, width, height) == position
+      };
+      there_and_back(NORTH, SOUTH) && there_and_back(SOUTH, NORTH)
+        && there_and_back(EAST, WEST) && there_and_back(WEST, EAST)
+    }
+    quickcheck(prop as fn(u8, u8, uint) -> bool);
+  }
+
+  #[test]
+  fn edge_wrap_is_an_involution() {
+    // Crossing an edge and crossing it back lands on the starting edge cell.
+    fn prop(width: u8, height: u8, along: uint) -> TestResult {
+      let (width, height) = size(width, height);
+      if width < 2 || height < 2 {
+        return TestResult::discard();
+      }
+      let (x, y) = (along % width, along % height);
+      let cases = [(y * width, WEST, EAST), (y * width + width - 1, EAST, WEST),
+                   (x, NORTH, SOUTH), ((height - 1) * width + x, SOUTH, NORTH)];
+      TestResult::from_bool(cases.iter().all(|&(position, out, back)| {
+        let wrapped = move_position(position, out, width, height);
+        let (wx, wy) = (wrapped % width, wrapped / width);
+        let crossed = match out {
+          WEST => wx == width - 1,
+          EAST => wx == 0,
+          NORTH => wy == height - 1,
+          _ => wy == 0,
+        };
+        crossed && move_position(wrapped, back, width, height) == position
+      }))
+    }
+    quickcheck(prop as fn(u8, u8, uint) -> TestResult);
+  }
+
+  #[test]
+  fn table_agrees_with_move_position() {
+    fn prop(width: u8, height: u8, start: uint, direction: u8) -> bool {
+      let (width, height) = (width as uint % 32 + 1, height as uint % 32 + 1);
+      let position = start % (width * height);
+      let direction = Direction::from_index(direction as uint % 9).unwrap();
+      NeighborTable::torus(width, height).get(position, direction)
+        == move_position(position, direction, width, height)
+    }
+    quickcheck(prop as fn(u8, u8, uint, u8) -> bool);
+  }
+}
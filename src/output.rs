@@ -0,0 +1,961 @@
+use std::collections::HashMap;
+use std::io::{File, IoResult, IoError, OtherIoError, Command, Process, Ignored, InheritFd};
+use std::io::fs::readdir;
+use toml;
+
+use turing::Color;
+
+use gif::write_gif;
+use image::write_ppm;
+use mjpeg::MjpegSink;
+use platform;
+use png::write_png;
+use preview::TerminalPreview;
+use window;
+
+
+/// Something that consumes rendered frames.
+pub trait ImageSink {
+  /// Writes one frame, a 'width'x'height' bgr24 image.
+  fn write_frame(&mut self, image: &[u8], width: uint, height: uint) -> IoResult<()>;
+
+  /// Whether the sink takes frames a band of rows at a time with
+  /// 'write_rows', which saves keeping a whole upscaled frame.
+  fn streams(&self) -> bool {
+    false
+  }
+
+  /// Writes the next rows of the current frame; 'last' is set for the band
+  /// that ends it. Only called if 'streams' returns true.
+  fn write_rows(&mut self, _rows: &[u8], _last: bool) -> IoResult<()> {
+    Ok(())
+  }
+
+  /// Completes the output at the end of the run, e.g. writes what is
+  /// buffered. No frames are written after this.
+  fn finish(&mut self) -> IoResult<()> {
+    Ok(())
+  }
+
+  /// Checks for keys pressed in the output, e.g. a window, and passes them
+  /// to 'keys::press'. Called between frames, and while paused.
+  fn poll_input(&mut self) {}
+
+  /// Whether the sink needs frames to stay open, like a player reading a
+  /// stream. Only these get the black frames of quiet hours.
+  fn keep_alive(&self) -> bool {
+    false
+  }
+}
+
+
+/// The layout of a raw frame's pixels ('turing.pixel_format'), for the
+/// consumers that don't take bgr24.
+#[deriving(PartialEq,Eq,Show)]
+pub enum PixelFormat {
+  Bgr24,
+  Rgb24,
+  /// Red, green, blue and an opaque alpha byte.
+  Rgba,
+  /// One byte of luma (BT.601, full range) a pixel.
+  Gray8,
+}
+
+impl PixelFormat {
+  pub fn from_name(name: &str) -> Option<PixelFormat> {
+    match name {
+      "bgr24" => Some(Bgr24),
+      "rgb24" => Some(Rgb24),
+      "rgba" => Some(Rgba),
+      "gray8" => Some(Gray8),
+      _ => None,
+    }
+  }
+
+  /// Repacks the pixels of a bgr24 image (or whole rows of one) into 'out'.
+  pub fn convert(&self, image: &[u8], out: &mut Vec<u8>) {
+    out.clear();
+    for pixel in image.chunks(3) {
+      let (b, g, r) = (pixel[0], pixel[1], pixel[2]);
+      match *self {
+        Bgr24 => out.push_all(pixel),
+        Rgb24 => out.push_all(&[r, g, b]),
+        Rgba => out.push_all(&[r, g, b, 255]),
+        Gray8 => out.push(((r as uint * 77 + g as uint * 150 + b as uint * 29 + 128) >> 8) as u8),
+      }
+    }
+  }
+}
+
+
+/// Writes frames as raw bytes, e.g. to stdout for vlc or ffmpeg: bgr24 or
+/// another pixel format.
+pub struct RawSink {
+  out: Box<Writer>,
+  format: PixelFormat,
+  // Frames repacked for formats other than bgr24.
+  converted: Vec<u8>,
+}
+
+impl RawSink {
+  pub fn new(out: Box<Writer>) -> RawSink {
+    RawSink::with_format(out, Bgr24)
+  }
+
+  pub fn with_format(out: Box<Writer>, format: PixelFormat) -> RawSink {
+    RawSink { out: out, format: format, converted: Vec::new() }
+  }
+
+  fn write_pixels(&mut self, pixels: &[u8]) -> IoResult<()> {
+    if self.format == Bgr24 {
+      return self.out.write(pixels);
+    }
+    self.format.convert(pixels, &mut self.converted);
+    self.out.write(self.converted.as_slice())
+  }
+}
+
+impl ImageSink for RawSink {
+  fn write_frame(&mut self, image: &[u8], _width: uint, _height: uint) -> IoResult<()> {
+    try!(self.write_pixels(image));
+    self.out.flush()
+  }
+
+  fn streams(&self) -> bool {
+    true
+  }
+
+  fn write_rows(&mut self, rows: &[u8], last: bool) -> IoResult<()> {
+    try!(self.write_pixels(rows));
+    if last { self.out.flush() } else { Ok(()) }
+  }
+
+  fn finish(&mut self) -> IoResult<()> {
+    self.out.flush()
+  }
+
+  fn keep_alive(&self) -> bool {
+    true
+  }
+}
+
+
+/// Writes frames as a YUV4MPEG2 stream, which players read without being
+/// told the frame size or pixel format. Chroma is full resolution with
+/// 'full_chroma' and halved both ways (4:2:0) otherwise.
+pub struct Y4mSink {
+  out: Box<Writer>,
+  fps: uint,
+  full_chroma: bool,
+  // Size given in the stream header, once it is written.
+  size: Option<(uint, uint)>,
+  planes: Vec<u8>,
+}
+
+impl Y4mSink {
+  pub fn new(out: Box<Writer>, fps: uint, full_chroma: bool) -> Y4mSink {
+    Y4mSink { out: out, fps: fps, full_chroma: full_chroma, size: None, planes: Vec::new() }
+  }
+}
+
+impl ImageSink for Y4mSink {
+  fn write_frame(&mut self, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    match self.size {
+      None => {
+        try!(write!(self.out, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 {}\n", width, height, self.fps,
+                    if self.full_chroma { "C444" } else { "C420jpeg" }));
+        self.size = Some((width, height));
+      },
+      // A stream has one frame size.
+      Some(size) if size != (width, height) => return Ok(()),
+      Some(_) => {},
+    }
+    yuv_planes(image, width, height, self.full_chroma, &mut self.planes);
+    try!(self.out.write(b"FRAME\n"));
+    try!(self.out.write(self.planes.as_slice()));
+    self.out.flush()
+  }
+
+  fn finish(&mut self) -> IoResult<()> {
+    self.out.flush()
+  }
+
+  fn keep_alive(&self) -> bool {
+    true
+  }
+}
+
+
+/// Pipes frames into an ffmpeg process that encodes them to a video file,
+/// e.g. an .mp4 or .mkv (ffmpeg goes by the extension). ffmpeg is started
+/// with the first frame, once its size is known, and given 'args' as output
+/// options. Like a y4m stream, a video has one frame size; frames of
+/// another size are left out.
+pub struct FfmpegSink {
+  path: Path,
+  fps: uint,
+  args: Vec<String>,
+  process: Option<Process>,
+  size: (uint, uint),
+}
+
+impl FfmpegSink {
+  pub fn new(path: Path, fps: uint, args: Vec<String>) -> FfmpegSink {
+    FfmpegSink { path: path, fps: fps, args: args, process: None, size: (0, 0) }
+  }
+
+  // ffmpeg's arguments for 'width'x'height' frames, up to the output path.
+  fn arguments(&self, width: uint, height: uint) -> Vec<String> {
+    let mut arguments: Vec<String> = ["-loglevel", "error", "-y", "-f", "rawvideo", "-pix_fmt", "bgr24"].iter()
+      .map(|arg| arg.to_string()).collect();
+    arguments.push_all(&["-s".to_string(), format!("{}x{}", width, height), "-r".to_string(), self.fps.to_string(),
+                         "-i".to_string(), "-".to_string()]);
+    arguments.push_all(self.args.as_slice());
+    arguments
+  }
+
+  fn start(&mut self, width: uint, height: uint) -> IoResult<()> {
+    let mut command = Command::new("ffmpeg");
+    command.args(self.arguments(width, height).as_slice())
+           .arg(&self.path)
+           .stdout(Ignored)
+           .stderr(InheritFd(2));
+    self.process = Some(try!(command.spawn()));
+    self.size = (width, height);
+    Ok(())
+  }
+}
+
+impl ImageSink for FfmpegSink {
+  fn write_frame(&mut self, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    if self.process.is_none() {
+      try!(self.start(width, height));
+    } else if self.size != (width, height) {
+      return Ok(());
+    }
+    match self.process.as_mut().unwrap().stdin {
+      Some(ref mut stdin) => stdin.write(image),
+      None => Ok(()),
+    }
+  }
+
+  // Closes ffmpeg's input and waits for it to write the end of the file.
+  fn finish(&mut self) -> IoResult<()> {
+    let mut process = match self.process.take() {
+      Some(process) => process,
+      None => return Ok(()),
+    };
+    drop(process.stdin.take());
+    let status = try!(process.wait());
+    if status.success() {
+      Ok(())
+    } else {
+      Err(IoError { kind: OtherIoError, desc: "ffmpeg failed", detail: Some(status.to_string()) })
+    }
+  }
+}
+
+
+/// Converts a bgr24 image to planar Y'CbCr (BT.601, limited range) in 'out',
+/// with the chroma planes at full or half resolution.
+fn yuv_planes(image: &[u8], width: uint, height: uint, full_chroma: bool, out: &mut Vec<u8>) {
+  let rgb = |x: uint, y: uint| {
+    let p = (y * width + x) * 3;
+    (image[p + 2] as int, image[p + 1] as int, image[p] as int)
+  };
+  out.clear();
+  for y in range(0, height) {
+    for x in range(0, width) {
+      let (r, g, b) = rgb(x, y);
+      out.push((((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8);
+    }
+  }
+  let (step, chroma_width, chroma_height) = if full_chroma {
+    (1, width, height)
+  } else {
+    (2, (width + 1) / 2, (height + 1) / 2)
+  };
+  // The average color of each block of 'step'x'step' pixels.
+  let mut averages = Vec::with_capacity(chroma_width * chroma_height);
+  for cy in range(0, chroma_height) {
+    for cx in range(0, chroma_width) {
+      let (mut r, mut g, mut b, mut n) = (0, 0, 0, 0);
+      for y in range(cy * step, ::std::cmp::min(cy * step + step, height)) {
+        for x in range(cx * step, ::std::cmp::min(cx * step + step, width)) {
+          let (pr, pg, pb) = rgb(x, y);
+          r += pr;
+          g += pg;
+          b += pb;
+          n += 1;
+        }
+      }
+      averages.push((r / n, g / n, b / n));
+    }
+  }
+  for &(r, g, b) in averages.iter() {
+    out.push((((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8);
+  }
+  for &(r, g, b) in averages.iter() {
+    out.push((((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8);
+  }
+}
+
+
+/// Writes every frame it gets as a numbered PPM image in a directory.
+pub struct PpmSink {
+  dir: Path,
+  count: u64,
+}
+
+impl PpmSink {
+  pub fn new(dir: Path) -> PpmSink {
+    let count = next_number(&dir, "frame_", ".ppm");
+    PpmSink { dir: dir, count: count }
+  }
+}
+
+impl ImageSink for PpmSink {
+  fn write_frame(&mut self, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    let path = self.dir.join(format!("frame_{:06u}.ppm", self.count));
+    self.count += 1;
+    write_ppm(&path, image, width, height)
+  }
+}
+
+
+/// Writes every frame it gets as a numbered PNG image in a directory.
+/// Numbers go on from the highest already there.
+pub struct PngSink {
+  dir: Path,
+  count: u64,
+}
+
+impl PngSink {
+  pub fn new(dir: Path) -> PngSink {
+    let count = next_number(&dir, "frame_", ".png");
+    PngSink { dir: dir, count: count }
+  }
+}
+
+impl ImageSink for PngSink {
+  fn write_frame(&mut self, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    let path = self.dir.join(format!("frame_{:06u}.png", self.count));
+    self.count += 1;
+    write_png(&path, image, width, height, &[])
+  }
+}
+
+
+// The number after the highest of the 'prefix'NNNNNN'extension' images in
+// 'dir', so a restarted recording doesn't write over the last one.
+fn next_number(dir: &Path, prefix: &str, extension: &str) -> u64 {
+  let names = readdir(dir).unwrap_or(Vec::new());
+  names.iter().filter_map(|path| path.filename_str()).filter_map(|name| {
+    if name.len() > prefix.len() + extension.len() && name.starts_with(prefix) && name.ends_with(extension) {
+      from_str::<u64>(name.slice(prefix.len(), name.len() - extension.len()))
+    } else {
+      None
+    }
+  }).max().map_or(0, |last| last + 1)
+}
+
+
+/// Averages 'frames' consecutive frames into one long exposure image, written
+/// as a numbered PPM image in a directory.
+pub struct LongExposureSink {
+  dir: Path,
+  frames: u64,
+  collected: u64,
+  sum: Vec<u32>,
+  count: u64,
+  // Size of the frames being averaged.
+  size: (uint, uint),
+}
+
+impl LongExposureSink {
+  pub fn new(dir: Path, frames: u64) -> LongExposureSink {
+    let count = next_number(&dir, "exposure_", ".ppm");
+    LongExposureSink { dir: dir, frames: frames, collected: 0, sum: Vec::new(), count: count, size: (0, 0) }
+  }
+
+  // Writes the average of the frames collected so far and starts over.
+  fn write_exposure(&mut self) -> IoResult<()> {
+    let average: Vec<u8> = self.sum.iter().map(|&sum| (sum as u64 / self.collected) as u8).collect();
+    let path = self.dir.join(format!("exposure_{:06u}.ppm", self.count));
+    self.count += 1;
+    self.collected = 0;
+    for sum in self.sum.iter_mut() {
+      *sum = 0;
+    }
+    let (width, height) = self.size;
+    write_ppm(&path, average.as_slice(), width, height)
+  }
+}
+
+impl ImageSink for LongExposureSink {
+  fn write_frame(&mut self, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    if self.sum.len() != image.len() {
+      self.sum = Vec::from_elem(image.len(), 0u32);
+      self.collected = 0;
+    }
+    self.size = (width, height);
+    for (sum, &value) in self.sum.iter_mut().zip(image.iter()) {
+      *sum += value as u32;
+    }
+    self.collected += 1;
+    if self.collected < self.frames {
+      return Ok(());
+    }
+    self.write_exposure()
+  }
+
+  // A shorter exposure of the last frames rather than none.
+  fn finish(&mut self) -> IoResult<()> {
+    if self.collected == 0 {
+      return Ok(());
+    }
+    self.write_exposure()
+  }
+}
+
+
+/// Collects up to 'max_frames' frames and writes them to a file as an
+/// animated GIF, when it has them all or when the run ends. Frames are
+/// mapped to a palette of the first 256 colors seen; later colors get the
+/// nearest of those.
+pub struct GifSink {
+  path: Path,
+  // Hundredths of a second each frame is shown for.
+  delay: u16,
+  max_frames: uint,
+  size: (uint, uint),
+  frames: Vec<Vec<u8>>,
+  palette: Vec<Color>,
+  indices: HashMap<(u8, u8, u8), u8>,
+  written: bool,
+}
+
+impl GifSink {
+  pub fn new(path: Path, delay_ms: u64, max_frames: uint) -> GifSink {
+    GifSink {
+      path: path,
+      delay: ::std::cmp::min((delay_ms + 5) / 10, 0xffff) as u16,
+      max_frames: max_frames,
+      size: (0, 0),
+      frames: Vec::new(),
+      palette: Vec::new(),
+      indices: HashMap::new(),
+      written: false,
+    }
+  }
+
+  // The palette index for 'color', adding it to the palette if there is room.
+  fn index(&mut self, color: Color) -> u8 {
+    let key = (color[0], color[1], color[2]);
+    match self.indices.find(&key) {
+      Some(&index) => return index,
+      None => {},
+    }
+    let index = if self.palette.len() < 256 {
+      self.palette.push(color);
+      (self.palette.len() - 1) as u8
+    } else {
+      let distance = |other: &Color| {
+        range(0u, 3).map(|i| {
+          let d = color[i] as int - other[i] as int;
+          d * d
+        }).fold(0, |sum, d| sum + d)
+      };
+      let mut nearest = 0u;
+      for (i, other) in self.palette.iter().enumerate() {
+        if distance(other) < distance(self.palette.get(nearest)) {
+          nearest = i;
+        }
+      }
+      nearest as u8
+    };
+    self.indices.insert(key, index);
+    index
+  }
+
+  fn write_gif(&mut self) -> IoResult<()> {
+    self.written = true;
+    let (width, height) = self.size;
+    let mut file = try!(File::create(&self.path));
+    write_gif(&mut file, width, height, self.palette.as_slice(), self.frames.as_slice(), self.delay)
+  }
+}
+
+impl ImageSink for GifSink {
+  fn write_frame(&mut self, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    if self.written {
+      return Ok(());
+    }
+    self.size = (width, height);
+    let mut frame = Vec::with_capacity(width * height);
+    for pixel in image.chunks(3) {
+      let index = self.index([pixel[2], pixel[1], pixel[0]]);
+      frame.push(index);
+    }
+    self.frames.push(frame);
+    if self.frames.len() < self.max_frames {
+      return Ok(());
+    }
+    self.write_gif()
+  }
+
+  // A shorter clip rather than none.
+  fn finish(&mut self) -> IoResult<()> {
+    if self.written || self.frames.is_empty() {
+      return Ok(());
+    }
+    self.write_gif()
+  }
+}
+
+
+// Source rows upscaled at a time for sinks that stream.
+static BAND_ROWS: uint = 16;
+
+
+/// A sink along with how its frames are prepared: every 'divisor'th frame is
+/// sent to it, upscaled by 'scale'.
+pub struct Output {
+  sink: Box<ImageSink>,
+  scale: uint,
+  divisor: u64,
+  // Upscaled frame, or band of rows for sinks that stream. Only used when
+  // scale > 1.
+  buffer: Vec<u8>,
+  // The '[[output]]' block the sink was opened from, to open it again.
+  block: Option<toml::Value>,
+}
+
+impl Output {
+  pub fn new(sink: Box<ImageSink>, scale: uint, divisor: u64) -> Output {
+    Output { sink: sink, scale: scale, divisor: divisor, buffer: Vec::new(), block: None }
+  }
+
+  // Writes a frame to the sink, upscaled.
+  fn write(&mut self, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    if self.scale == 1 {
+      try!(self.sink.write_frame(image, width, height));
+    } else if self.sink.streams() {
+      let row_bytes = width * 3;
+      for (band, rows) in image.chunks(BAND_ROWS * row_bytes).enumerate() {
+        upscale(rows, width, rows.len() / row_bytes, self.scale, &mut self.buffer);
+        let last = (band + 1) * BAND_ROWS >= height;
+        try!(self.sink.write_rows(self.buffer.as_slice(), last));
+      }
+    } else {
+      upscale(image, width, height, self.scale, &mut self.buffer);
+      try!(self.sink.write_frame(self.buffer.as_slice(), width * self.scale, height * self.scale));
+    }
+    Ok(())
+  }
+}
+
+
+/// Feeds each rendered frame to any number of outputs.
+pub struct Outputs {
+  outputs: Vec<Output>,
+  // The output that failed to write a frame, which gets no more until it is
+  // reopened.
+  failed: Option<uint>,
+}
+
+impl Outputs {
+  pub fn new(outputs: Vec<Output>) -> Outputs {
+    Outputs { outputs: outputs, failed: None }
+  }
+
+  /// Outputs from the '[[output]]' blocks of the config. An output with
+  /// 'path = "-"' (the default for raw output) writes to 'stdout'.
+  ///
+  ///     [[output]]
+  ///     format = "raw"            # raw, y4m, ffmpeg, terminal, window, mjpeg, ppm, png, gif or long-exposure
+  ///     path = "-"                # file (or \\.\pipe\name) for raw, y4m, terminal, ffmpeg and gif, directory otherwise
+  ///     scale = 1                 # integer upscaling, 'turing.scale' by default except for terminal
+  ///     divisor = 1               # only every divisor'th frame
+  ///     exposure_frames = 100     # frames per long exposure image
+  ///     title = "turing"          # title of a window
+  ///     fps = 25                  # frame rate in a y4m header or ffmpeg video
+  ///     args = ["-crf", "18"]     # ffmpeg output options
+  ///     chroma = "420"            # y4m chroma resolution, 420 or 444
+  ///     delay_ms = 100            # time each frame of a gif is shown
+  ///     max_frames = 100          # frames in a gif
+  ///     port = 8080               # port an mjpeg stream is served on
+  ///     quality = 80              # jpeg quality of an mjpeg stream, 1 to 100
+  ///     pixel_format = "bgr24"    # raw pixels: bgr24, rgb24, rgba or gray8, 'turing.pixel_format' by default
+  pub fn from_config(config: &toml::Value, stdout: Box<Writer>) -> Result<Outputs, String> {
+    let blocks = match config.lookup("output").and_then(|outputs| outputs.as_slice()) {
+      Some(blocks) => blocks,
+      None => return Err("Expected [[output]] blocks".to_string()),
+    };
+    let mut stdout = Some(stdout);
+    let mut outputs = Vec::new();
+    for block in blocks.iter() {
+      let int = |name: &str, default: i64| {
+        block.lookup(name).and_then(|value| value.as_integer()).unwrap_or(default)
+      };
+      let sink = try!(open_sink(block, config, &mut stdout));
+      let scale = int("scale", default_scale(block, config));
+      let divisor = int("divisor", 1);
+      if scale < 1 || divisor < 1 {
+        return Err("Output scale and divisor must be at least 1".to_string());
+      }
+      let mut output = Output::new(sink, scale as uint, divisor as u64);
+      output.block = Some(block.clone());
+      outputs.push(output);
+    }
+    Ok(Outputs::new(outputs))
+  }
+
+  /// Adds an output after those already opened.
+  pub fn push(&mut self, output: Output) {
+    self.outputs.push(output);
+  }
+
+  /// Sends frame number 'frame' to every output due for it. An output that
+  /// fails gets no more frames until 'reopen_failed'; the others still get
+  /// this one, and the first error is returned.
+  pub fn write(&mut self, frame: u64, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    self.write_to(frame, image, width, height, false)
+  }
+
+  /// Like 'write', but only to the outputs that need frames to stay open
+  /// (see 'ImageSink::keep_alive').
+  pub fn keep_alive(&mut self, frame: u64, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    self.write_to(frame, image, width, height, true)
+  }
+
+  fn write_to(&mut self, frame: u64, image: &[u8], width: uint, height: uint, keep_alive: bool)
+      -> IoResult<()> {
+    let mut written = Ok(());
+    for (index, output) in self.outputs.iter_mut().enumerate() {
+      if frame % output.divisor != 0 || self.failed == Some(index) || (keep_alive && !output.sink.keep_alive()) {
+        continue;
+      }
+      match output.write(image, width, height) {
+        Ok(()) => {},
+        Err(why) => {
+          if written.is_ok() {
+            self.failed = Some(index);
+            written = Err(why);
+          }
+        },
+      }
+    }
+    written
+  }
+
+  /// Closes the output that failed to write a frame and opens it again from
+  /// its '[[output]]' block, leaving the others as they are. The old sink
+  /// is finished and dropped first: a named pipe takes one writer at a time
+  /// and dropping a window shuts SDL down. Outputs that can't be reopened
+  /// without writing over what they wrote (see 'can_reopen'), or that
+  /// aren't from a block, are left closed with an error.
+  pub fn reopen_failed(&mut self, config: &toml::Value) -> IoResult<()> {
+    let index = match self.failed.take() {
+      Some(index) => index,
+      None => return Ok(()),
+    };
+    let Output { mut sink, scale, divisor, block, .. } = self.outputs.remove(index).unwrap();
+    let _ = sink.finish();
+    drop(sink);
+    let block = match block {
+      Some(block) => block,
+      None => return Err(not_reopened("the output isn't from an [[output]] block")),
+    };
+    if !can_reopen(&block) {
+      return Err(not_reopened("only named pipes, fifos and windows are reopened"));
+    }
+    let sink = try!(open_sink(&block, config, &mut None).map_err(|why| IoError {
+      kind: OtherIoError,
+      desc: "unable to reopen the output",
+      detail: Some(why),
+    }));
+    let mut output = Output::new(sink, scale, divisor);
+    output.block = Some(block);
+    self.outputs.insert(index, output);
+    Ok(())
+  }
+
+  /// Checks every output for keys pressed.
+  pub fn poll_input(&mut self) {
+    for output in self.outputs.iter_mut() {
+      output.sink.poll_input();
+    }
+  }
+
+  /// Finishes every output, even after one fails, and returns the first
+  /// error. Called once, when the run ends.
+  pub fn finish(&mut self) -> IoResult<()> {
+    let mut finished = Ok(());
+    for output in self.outputs.iter_mut() {
+      let result = output.sink.finish();
+      if finished.is_ok() {
+        finished = result;
+      }
+    }
+    finished
+  }
+}
+
+
+// The scale of an '[[output]]' block without one: 'turing.scale', except
+// for a terminal preview, which fits frames to the terminal itself.
+fn default_scale(block: &toml::Value, config: &toml::Value) -> i64 {
+  if block.lookup("format").and_then(|value| value.as_str()) == Some("terminal") {
+    return 1;
+  }
+  config.lookup("turing.scale").and_then(|value| value.as_integer()).unwrap_or(1)
+}
+
+
+// The output options of an ffmpeg '[[output]]' block: its 'args', or by
+// default ones most players can show.
+fn ffmpeg_args(block: &toml::Value) -> Result<Vec<String>, String> {
+  match block.lookup("args") {
+    Some(args) => match args.as_slice() {
+      Some(args) if args.iter().all(|arg| arg.as_str().is_some()) => {
+        Ok(args.iter().map(|arg| arg.as_str().unwrap().to_string()).collect())
+      },
+      _ => Err("ffmpeg args must be a list of strings".to_string()),
+    },
+    // Most players only take 4:2:0 video, which needs an even size.
+    None => Ok(vec!("-pix_fmt".to_string(), "yuv420p".to_string(),
+                    "-vf".to_string(), "pad=ceil(iw/2)*2:ceil(ih/2)*2".to_string())),
+  }
+}
+
+
+// Opens the sink of one '[[output]]' block.
+fn open_sink(block: &toml::Value, config: &toml::Value, stdout: &mut Option<Box<Writer>>)
+    -> Result<Box<ImageSink>, String> {
+  let int = |name: &str, default: i64| {
+    block.lookup(name).and_then(|value| value.as_integer()).unwrap_or(default)
+  };
+  let format = block.lookup("format").and_then(|value| value.as_str()).unwrap_or("raw");
+  let path = block.lookup("path").and_then(|value| value.as_str()).unwrap_or("-");
+  let sink = match format {
+    "raw" => {
+      let name = block.lookup("pixel_format").or(config.lookup("turing.pixel_format"))
+        .and_then(|value| value.as_str()).unwrap_or("bgr24");
+      let format = try!(PixelFormat::from_name(name).ok_or(format!("Unknown pixel_format: {}", name)));
+      box RawSink::with_format(try!(open_stream(path, stdout)), format) as Box<ImageSink>
+    },
+    "y4m" => {
+      let fps = int("fps", 25);
+      if fps < 1 {
+        return Err("A y4m output needs an fps of at least 1".to_string());
+      }
+      let full_chroma = match block.lookup("chroma").and_then(|value| value.as_str()).unwrap_or("420") {
+        "420" => false,
+        "444" => true,
+        other => return Err(format!("Unknown y4m chroma: {}", other)),
+      };
+      box Y4mSink::new(try!(open_stream(path, stdout)), fps as uint, full_chroma) as Box<ImageSink>
+    },
+    "ffmpeg" => {
+      let fps = int("fps", 25);
+      if fps < 1 || path == "-" {
+        return Err("An ffmpeg output needs a file path and an fps of at least 1".to_string());
+      }
+      box FfmpegSink::new(Path::new(path), fps as uint, try!(ffmpeg_args(block))) as Box<ImageSink>
+    },
+    "terminal" => match TerminalPreview::new(try!(open_stream(path, stdout))) {
+      Ok(preview) => box preview as Box<ImageSink>,
+      Err(why) => return Err(format!("Unable to write to {}: {}", path, why.desc)),
+    },
+    "window" => try!(window::open(block.lookup("title").and_then(|value| value.as_str()).unwrap_or("turing"))),
+    "mjpeg" => {
+      let (port, quality) = (int("port", 8080), int("quality", 80));
+      if port < 1 || port > 65535 || quality < 1 || quality > 100 {
+        return Err("An mjpeg output needs a port from 1 to 65535 and a quality from 1 to 100".to_string());
+      }
+      match MjpegSink::listen(port as u16, quality as uint) {
+        Ok(sink) => box sink as Box<ImageSink>,
+        Err(why) => return Err(format!("Unable to listen on port {}: {}", port, why.desc)),
+      }
+    },
+    "ppm" => box PpmSink::new(Path::new(path)) as Box<ImageSink>,
+    "png" => box PngSink::new(Path::new(path)) as Box<ImageSink>,
+    "gif" => {
+      let (delay_ms, max_frames) = (int("delay_ms", 100), int("max_frames", 100));
+      if delay_ms < 0 || max_frames < 1 {
+        return Err("A gif needs a delay_ms of at least 0 and max_frames of at least 1".to_string());
+      }
+      box GifSink::new(Path::new(path), delay_ms as u64, max_frames as uint) as Box<ImageSink>
+    },
+    "long-exposure" => {
+      box LongExposureSink::new(Path::new(path), int("exposure_frames", 100) as u64) as Box<ImageSink>
+    },
+    other => return Err(format!("Unknown output format: {}", other)),
+  };
+  Ok(sink)
+}
+
+
+// Whether the output of 'block' can be opened again after it failed without
+// losing what it wrote: a named pipe or fifo gets a new reader and a window
+// is shown again. Files and directories would be written over.
+fn can_reopen(block: &toml::Value) -> bool {
+  let path = block.lookup("path").and_then(|value| value.as_str()).unwrap_or("-");
+  match block.lookup("format").and_then(|value| value.as_str()).unwrap_or("raw") {
+    "raw" | "y4m" | "terminal" => platform::is_named_pipe(path) || platform::is_fifo(path),
+    "window" => true,
+    _ => false,
+  }
+}
+
+
+/// Whether every stream output of the '[[output]]' blocks writes to a named
+/// pipe or fifo, which a new reader can open after the last one went away.
+/// Without the blocks frames go to stdout, which can't get a new reader.
+pub fn reopenable(config: &toml::Value) -> bool {
+  let blocks = match config.lookup("output").and_then(|outputs| outputs.as_slice()) {
+    Some(blocks) => blocks,
+    None => return false,
+  };
+  blocks.iter().all(|block| {
+    match block.lookup("format").and_then(|value| value.as_str()).unwrap_or("raw") {
+      "raw" | "y4m" | "terminal" => can_reopen(block),
+      _ => true,
+    }
+  })
+}
+
+
+// The error for an output left closed, for 'reason'.
+fn not_reopened(reason: &'static str) -> IoError {
+  IoError { kind: OtherIoError, desc: reason, detail: None }
+}
+
+
+/// Opens the file a stream output writes to: stdout for "-" (which only
+/// one output can have), a named pipe on Windows or a file.
+fn open_stream(path: &str, stdout: &mut Option<Box<Writer>>) -> Result<Box<Writer>, String> {
+  if path == "-" {
+    stdout.take().ok_or("Only one output can write to stdout".to_string())
+  } else if platform::is_named_pipe(path) {
+    platform::named_pipe(path).map_err(|why| format!("Unable to open {}: {}", path, why))
+  } else {
+    match File::create(&Path::new(path)) {
+      Ok(file) => Ok(box file as Box<Writer>),
+      Err(why) => Err(format!("Unable to create {}: {}", path, why.desc)),
+    }
+  }
+}
+
+
+/// Nearest neighbor upscaling of a bgr24 image by an integer factor into 'out'.
+fn upscale(image: &[u8], width: uint, height: uint, scale: uint, out: &mut Vec<u8>) {
+  out.clear();
+  for y in range(0, height) {
+    let row = image.slice(y * width * 3, (y + 1) * width * 3);
+    for _ in range(0, scale) {
+      for pixel in row.chunks(3) {
+        for _ in range(0, scale) {
+          out.push_all(pixel);
+        }
+      }
+    }
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::io::{File, IoResult, IoError, BrokenPipe, MemWriter, TempDir};
+  use toml;
+
+  use super::{yuv_planes, Rgb24, Rgba, Gray8, ImageSink, RawSink, FfmpegSink, Output, Outputs, next_number,
+              default_scale, ffmpeg_args};
+
+  // A sink whose reader has gone away.
+  struct Closed;
+
+  impl ImageSink for Closed {
+    fn write_frame(&mut self, _image: &[u8], _width: uint, _height: uint) -> IoResult<()> {
+      Err(IoError { kind: BrokenPipe, desc: "closed", detail: None })
+    }
+  }
+
+  #[test]
+  fn numbers_go_on_after_the_last_image() {
+    let dir = TempDir::new("turing").unwrap();
+    assert_eq!(next_number(dir.path(), "frame_", ".png"), 0);
+    for name in ["frame_000003.png", "frame_000011.png", "frame_000020.ppm", "notes.png"].iter() {
+      File::create(&dir.path().join(*name)).unwrap();
+    }
+    assert_eq!(next_number(dir.path(), "frame_", ".png"), 12);
+    assert_eq!(next_number(dir.path(), "frame_", ".ppm"), 21);
+  }
+
+  #[test]
+  fn converts_to_yuv_planes() {
+    // 2x1 bgr24: white and blue.
+    let image = [255u8, 255, 255, 255, 0, 0];
+    let mut planes = Vec::new();
+    yuv_planes(&image, 2, 1, true, &mut planes);
+    assert_eq!(planes, vec!(235, 41, 128, 240, 128, 110));
+    // One chroma sample for both, from their average color.
+    yuv_planes(&image, 2, 1, false, &mut planes);
+    assert_eq!(planes, vec!(235, 41, 184, 119));
+  }
+
+  #[test]
+  fn repacks_pixel_formats() {
+    // 2x1 bgr24: red and white.
+    let image = [0u8, 0, 255, 255, 255, 255];
+    let mut out = Vec::new();
+    Rgb24.convert(&image, &mut out);
+    assert_eq!(out, vec!(255, 0, 0, 255, 255, 255));
+    Rgba.convert(&image, &mut out);
+    assert_eq!(out, vec!(255, 0, 0, 255, 255, 255, 255, 255));
+    Gray8.convert(&image, &mut out);
+    assert_eq!(out, vec!(77, 255));
+  }
+
+  #[test]
+  fn terminal_outputs_ignore_the_default_scale() {
+    let config: toml::Value = from_str("[turing]\nscale = 4").unwrap();
+    let raw: toml::Value = from_str("format = \"raw\"").unwrap();
+    let terminal: toml::Value = from_str("format = \"terminal\"").unwrap();
+    assert_eq!((default_scale(&raw, &config), default_scale(&terminal, &config)), (4, 1));
+  }
+
+  #[test]
+  fn reads_ffmpeg_args() {
+    let block: toml::Value = from_str("args = [\"-crf\", \"18\"]").unwrap();
+    assert_eq!(ffmpeg_args(&block), Ok(vec!("-crf".to_string(), "18".to_string())));
+    let block: toml::Value = from_str("args = [\"-crf\", 18]").unwrap();
+    assert!(ffmpeg_args(&block).is_err());
+    let block: toml::Value = from_str("args = \"-crf 18\"").unwrap();
+    assert!(ffmpeg_args(&block).is_err());
+  }
+
+  #[test]
+  fn builds_the_default_ffmpeg_command_line() {
+    let block: toml::Value = from_str("format = \"ffmpeg\"").unwrap();
+    let sink = FfmpegSink::new(Path::new("out.mp4"), 30, ffmpeg_args(&block).unwrap());
+    let expected = ["-loglevel", "error", "-y", "-f", "rawvideo", "-pix_fmt", "bgr24", "-s", "64x48", "-r", "30",
+                    "-i", "-", "-pix_fmt", "yuv420p", "-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"];
+    assert_eq!(sink.arguments(64, 48), expected.iter().map(|arg| arg.to_string()).collect());
+  }
+
+  #[test]
+  fn failed_outputs_are_left_out_until_reopened() {
+    let raw = box RawSink::new(box MemWriter::new() as Box<Writer>) as Box<ImageSink>;
+    let mut outputs = Outputs::new(vec!(Output::new(box Closed as Box<ImageSink>, 1, 1), Output::new(raw, 1, 1)));
+    assert!(outputs.write(0, &[0u8, 0, 0], 1, 1).is_err());
+    assert_eq!(outputs.failed, Some(0));
+    assert!(outputs.write(1, &[0u8, 0, 0], 1, 1).is_ok());
+    // Not from an [[output]] block, so it stays closed.
+    let config: toml::Value = from_str("").unwrap();
+    assert!(outputs.reopen_failed(&config).is_err());
+    assert_eq!(outputs.outputs.len(), 1);
+    assert!(outputs.write(2, &[0u8, 0, 0], 1, 1).is_ok());
+  }
+}
@@ -0,0 +1,260 @@
+use std::fmt;
+use toml;
+
+use super::Color;
+
+
+/// A palette as a table with a color for every possible symbol, so finding
+/// the color of a cell is a single load without a bounds check. Symbols
+/// past the end of the palette are black.
+pub struct Lut {
+  colors: [Color, ..256],
+}
+
+impl Lut {
+  pub fn new(palette: &Vec<Color>) -> Lut {
+    let mut colors = [[0u8, 0, 0], ..256];
+    for (entry, &color) in colors.iter_mut().zip(palette.iter()) {
+      *entry = color;
+    }
+    Lut { colors: colors }
+  }
+
+  /// The color of 'symbol'.
+  #[inline]
+  pub fn get(&self, symbol: u8) -> Color {
+    // Safe: the table has an entry for each of the 256 values of a u8.
+    unsafe { *self.colors.as_slice().unsafe_get(symbol as uint) }
+  }
+}
+
+impl fmt::Show for Lut {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Lut")
+  }
+}
+
+
+/// 'count' bright colors with hues spread around the color wheel. Hues step
+/// by the golden ratio, so however many are asked for, neighbouring symbols
+/// get clearly different colors and no two colors share a hue.
+pub fn hue_spaced(count: uint) -> Vec<Color> {
+  static GOLDEN_RATIO_CONJUGATE: f64 = 0.618033988749895;
+  range(0, count).map(|i| {
+    // Start between red and yellow so the first colors aren't copies of the
+    // curated primaries.
+    let hue = (0.1 + i as f64 * GOLDEN_RATIO_CONJUGATE) % 1.0;
+    hsv(hue, 0.75, 0.95)
+  }).collect()
+}
+
+
+/// Black for the blank symbol followed by 'count' - 1 colors with hues
+/// evenly spaced around the color wheel, for 'turing.palette = "generated"'.
+pub fn evenly_spaced(count: uint) -> Vec<Color> {
+  let mut palette = vec!([0, 0, 0]);
+  let hues = if count > 1 { count - 1 } else { 1 };
+  for i in range(0, hues) {
+    palette.push(hsv(i as f64 / hues as f64, 0.8, 1.0));
+  }
+  palette.truncate(count);
+  palette
+}
+
+
+/// 'palette' with at least 'symbols' colors, the missing ones generated with
+/// 'hue_spaced'. Machines use this so a short palette (e.g. from a playlist)
+/// can't leave symbols without a color.
+pub fn complete(palette: &Vec<Color>, symbols: u8) -> Vec<Color> {
+  let mut complete = palette.clone();
+  if complete.len() < symbols as uint {
+    let extra = hue_spaced(symbols as uint - complete.len());
+    complete.push_all(extra.as_slice());
+  }
+  complete
+}
+
+
+/// 'palette' stretched to 'symbols' colors by repeating all but its first
+/// (blank) color, for 'turing.palette = "cycled"'. Each repeat is darker
+/// than the one before, down to about a third of the brightness, so symbols
+/// that share a hue can still be told apart. Symbols past the darkest repeat
+/// get 'hue_spaced' colors as with 'complete', as do all those of palettes
+/// with nothing to repeat.
+pub fn cycled(palette: &Vec<Color>, symbols: u8) -> Vec<Color> {
+  let mut cycled = palette.clone();
+  if palette.len() >= 2 {
+    let repeated = palette.slice_from(1);
+    let mut i = 0u;
+    loop {
+      let scale = 0.7f64.powi((i / repeated.len() + 1) as i32);
+      if cycled.len() >= symbols as uint || scale < 1.0 / 3.0 {
+        break;
+      }
+      let color = repeated[i % repeated.len()];
+      cycled.push([(color[0] as f64 * scale).round() as u8, (color[1] as f64 * scale).round() as u8,
+                   (color[2] as f64 * scale).round() as u8]);
+      i += 1;
+    }
+  }
+  complete(&cycled, symbols)
+}
+
+
+/// 'count' colors from black through red and yellow to white, for showing
+/// amounts from none to the most.
+pub fn heat(count: uint) -> Vec<Color> {
+  static STOPS: [Color, ..4] = [[0, 0, 0], [255, 0, 0], [255, 255, 0], [255, 255, 255]];
+  range(0, count).map(|i| {
+    let position = if count > 1 { i as f64 / (count - 1) as f64 * 3.0 } else { 0.0 };
+    let stop = ::std::cmp::min(position as uint, 2);
+    let f = position - stop as f64;
+    let mut color = [0u8, 0, 0];
+    for channel in range(0u, 3) {
+      let (from, to) = (STOPS[stop][channel] as f64, STOPS[stop + 1][channel] as f64);
+      color[channel] = (from + (to - from) * f).round() as u8;
+    }
+    color
+  }).collect()
+}
+
+
+/// Parses a list of colors, each a "#rrggbb" hex string or an [r, g, b]
+/// array of integers from 0 to 255.
+pub fn parse(value: &toml::Value) -> Result<Vec<Color>, String> {
+  let colors = match value.as_slice() {
+    Some(colors) => colors,
+    None => return Err("Expected a list of colors".to_string()),
+  };
+  let mut palette = Vec::with_capacity(colors.len());
+  for color in colors.iter() {
+    match parse_color(color) {
+      Some(color) => palette.push(color),
+      None => return Err(format!("Invalid color: {}", color)),
+    }
+  }
+  Ok(palette)
+}
+
+
+/// Parses one color of a list 'parse' takes.
+pub fn parse_color(value: &toml::Value) -> Option<Color> {
+  match value.as_str() {
+    Some(hex) => {
+      let hex = if hex.starts_with("#") { hex.slice_from(1) } else { hex };
+      if hex.len() != 6 || !hex.chars().all(|c| c.is_digit_radix(16)) {
+        return None;
+      }
+      let channel = |i: uint| ::std::num::from_str_radix::<u8>(hex.slice(i * 2, i * 2 + 2), 16);
+      match (channel(0), channel(1), channel(2)) {
+        (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+        _ => None,
+      }
+    },
+    None => {
+      let channels: Vec<i64> = value.as_slice().unwrap_or(&[]).iter()
+        .filter_map(|channel| channel.as_integer()).collect();
+      if channels.len() != 3 || channels.iter().any(|&c| c < 0 || c > 255) {
+        return None;
+      }
+      Some([*channels.get(0) as u8, *channels.get(1) as u8, *channels.get(2) as u8])
+    },
+  }
+}
+
+
+/// Converts hue, saturation and value (all 0 to 1) to a color.
+pub fn hsv(hue: f64, saturation: f64, value: f64) -> Color {
+  let sector = hue * 6.0;
+  let i = sector.floor();
+  let f = sector - i;
+  let p = value * (1.0 - saturation);
+  let q = value * (1.0 - saturation * f);
+  let t = value * (1.0 - saturation * (1.0 - f));
+  let (r, g, b) = match i as int % 6 {
+    0 => (value, t, p),
+    1 => (q, value, p),
+    2 => (p, value, t),
+    3 => (p, q, value),
+    4 => (t, p, value),
+    _ => (value, p, q),
+  };
+  let byte = |channel: f64| (channel * 255.0).round() as u8;
+  [byte(r), byte(g), byte(b)]
+}
+
+
+#[cfg(test)]
+mod test {
+  use toml;
+
+  use super::{Lut, hue_spaced, evenly_spaced, cycled, heat, hsv, parse};
+
+  #[test]
+  fn lookup_tables_cover_every_symbol() {
+    let lut = Lut::new(&vec!([1, 2, 3], [4, 5, 6]));
+    assert_eq!(lut.get(1), [4, 5, 6]);
+    assert_eq!(lut.get(2), [0, 0, 0]);
+    assert_eq!(lut.get(255), [0, 0, 0]);
+  }
+
+  #[test]
+  fn hsv_primaries() {
+    assert_eq!(hsv(0.0, 1.0, 1.0), [255, 0, 0]);
+    assert_eq!(hsv(1.0 / 3.0, 1.0, 1.0), [0, 255, 0]);
+    assert_eq!(hsv(2.0 / 3.0, 1.0, 1.0), [0, 0, 255]);
+    assert_eq!(hsv(0.5, 0.0, 1.0), [255, 255, 255]);
+  }
+
+  #[test]
+  fn hue_spaced_colors_are_distinct() {
+    assert_eq!(hue_spaced(245).len(), 245);
+    let colors = hue_spaced(64);
+    for (i, a) in colors.iter().enumerate() {
+      for b in colors.slice_from(i + 1).iter() {
+        assert!(a != b);
+      }
+    }
+  }
+
+  #[test]
+  fn evenly_spaced_colors() {
+    assert_eq!(evenly_spaced(1), vec!([0, 0, 0]));
+    assert_eq!(evenly_spaced(4), vec!([0, 0, 0], [255, 51, 51], [51, 255, 51], [51, 51, 255]));
+    assert_eq!(evenly_spaced(255).len(), 255);
+  }
+
+  #[test]
+  fn cycled_palettes_repeat_darker() {
+    let palette = vec!([0, 0, 0], [200, 100, 0], [0, 0, 100]);
+    assert_eq!(cycled(&palette, 6), vec!([0, 0, 0], [200, 100, 0], [0, 0, 100], [140, 70, 0], [0, 0, 70],
+                                         [98, 49, 0]));
+    assert_eq!(cycled(&palette, 2), palette);
+    // Three darker rounds, then colors of their own.
+    let long = cycled(&palette, 255);
+    assert_eq!(long.slice(7, 9), [[69u8, 34, 0], [0, 0, 34]].as_slice());
+    assert_eq!(long.slice_from(9), hue_spaced(246).as_slice());
+    assert_eq!(cycled(&vec!([0, 0, 0]), 3).len(), 3);
+  }
+
+  #[test]
+  fn heat_runs_from_black_to_white() {
+    assert_eq!(heat(4), vec!([0, 0, 0], [255, 0, 0], [255, 255, 0], [255, 255, 255]));
+    assert_eq!(heat(7).get(1), &[128, 0, 0]);
+  }
+
+  #[test]
+  fn parses_hex_and_array_colors() {
+    let colors = |text: &str| {
+      let value: toml::Value = from_str(format!("colors = {}", text).as_slice()).unwrap();
+      parse(value.lookup("colors").unwrap())
+    };
+    assert_eq!(colors("[\"#000000\", \"ff8000\", \"#0040FF\"]"), Ok(vec!([0, 0, 0], [255, 128, 0], [0, 64, 255])));
+    assert_eq!(colors("[[0, 0, 0], [255, 128, 0]]"), Ok(vec!([0, 0, 0], [255, 128, 0])));
+    assert!(colors("[\"#fff\"]").is_err());
+    assert!(colors("[\"#gg0000\"]").is_err());
+    assert!(colors("[[0, 0, 256]]").is_err());
+    assert!(colors("[[0, 0]]").is_err());
+    assert!(colors("\"#000000\"").is_err());
+  }
+}
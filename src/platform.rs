@@ -0,0 +1,147 @@
+use std::io::IoResult;
+
+
+/// Makes stdout binary safe. On Windows the C runtime opens stdout in text
+/// mode, which turns every 0x0a byte of a raw frame into 0x0d 0x0a.
+#[cfg(windows)]
+pub fn binary_stdout() {
+  use libc::c_int;
+  static O_BINARY: c_int = 0x8000;
+  extern {
+    fn _setmode(fd: c_int, mode: c_int) -> c_int;
+  }
+  unsafe {
+    _setmode(1, O_BINARY);
+  }
+}
+
+#[cfg(not(windows))]
+pub fn binary_stdout() {
+}
+
+
+/// Whether 'path' names a Windows named pipe (\\.\pipe\name), the
+/// counterpart of a fifo made with mkfifo.
+pub fn is_named_pipe(path: &str) -> bool {
+  path.starts_with(r"\\.\pipe\")
+}
+
+
+/// Whether 'path' is a fifo (made with mkfifo), which a new reader can open
+/// after the last one went away.
+#[cfg(unix)]
+pub fn is_fifo(path: &str) -> bool {
+  use std::io::TypeNamedPipe;
+  use std::io::fs::stat;
+  stat(&Path::new(path)).map(|stat| stat.kind == TypeNamedPipe).unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+pub fn is_fifo(_path: &str) -> bool {
+  false
+}
+
+
+/// Creates the named pipe 'name' and waits for a reader to connect, like
+/// opening a fifo for writing blocks until it has a reader.
+#[cfg(windows)]
+pub fn named_pipe(name: &str) -> IoResult<Box<Writer>> {
+  windows::NamedPipe::create(name).map(|pipe| box pipe as Box<Writer>)
+}
+
+#[cfg(not(windows))]
+pub fn named_pipe(name: &str) -> IoResult<Box<Writer>> {
+  use std::io::{IoError, OtherIoError};
+  Err(IoError {
+    kind: OtherIoError,
+    desc: "named pipes are only supported on Windows, use mkfifo instead",
+    detail: Some(name.to_string()),
+  })
+}
+
+
+#[cfg(windows)]
+mod windows {
+  use libc::{c_void, c_int};
+  use std::io::{IoResult, IoError, BrokenPipe, OtherIoError};
+  use std::ptr;
+
+  type Handle = *mut c_void;
+
+  static PIPE_ACCESS_OUTBOUND: u32 = 0x2;
+  // Byte stream, blocking.
+  static PIPE_TYPE_BYTE: u32 = 0;
+  static BUFFER_SIZE: u32 = 1 << 20;
+  static ERROR_BROKEN_PIPE: u32 = 109;
+  static ERROR_NO_DATA: u32 = 232;
+  static ERROR_PIPE_CONNECTED: u32 = 535;
+
+  extern "system" {
+    fn CreateNamedPipeW(name: *const u16, open_mode: u32, pipe_mode: u32, max_instances: u32,
+                        out_buffer_size: u32, in_buffer_size: u32, default_timeout: u32,
+                        security_attributes: *mut c_void) -> Handle;
+    fn ConnectNamedPipe(pipe: Handle, overlapped: *mut c_void) -> c_int;
+    fn WriteFile(file: Handle, buffer: *const u8, bytes: u32, written: *mut u32,
+                 overlapped: *mut c_void) -> c_int;
+    fn FlushFileBuffers(file: Handle) -> c_int;
+    fn CloseHandle(handle: Handle) -> c_int;
+    fn GetLastError() -> u32;
+  }
+
+  fn last_error(desc: &'static str) -> IoError {
+    let code = unsafe { GetLastError() };
+    IoError {
+      // So a reader going away is handled like a closed fifo.
+      kind: if code == ERROR_BROKEN_PIPE || code == ERROR_NO_DATA { BrokenPipe } else { OtherIoError },
+      desc: desc,
+      detail: Some(format!("Windows error {}", code)),
+    }
+  }
+
+  pub struct NamedPipe {
+    handle: Handle,
+  }
+
+  impl NamedPipe {
+    pub fn create(name: &str) -> IoResult<NamedPipe> {
+      let mut wide: Vec<u16> = name.utf16_units().collect();
+      wide.push(0);
+      let handle = unsafe {
+        CreateNamedPipeW(wide.as_ptr(), PIPE_ACCESS_OUTBOUND, PIPE_TYPE_BYTE, 1, BUFFER_SIZE, 0, 0,
+                         ptr::null_mut())
+      };
+      if handle as int == -1 {
+        return Err(last_error("unable to create named pipe"));
+      }
+      let pipe = NamedPipe { handle: handle };
+      // A reader that connected between the two calls is fine too.
+      if unsafe { ConnectNamedPipe(handle, ptr::null_mut()) } == 0
+          && unsafe { GetLastError() } != ERROR_PIPE_CONNECTED {
+        return Err(last_error("unable to wait for a reader"));
+      }
+      Ok(pipe)
+    }
+  }
+
+  impl Writer for NamedPipe {
+    fn write(&mut self, mut buf: &[u8]) -> IoResult<()> {
+      while !buf.is_empty() {
+        let mut written = 0u32;
+        if unsafe { WriteFile(self.handle, buf.as_ptr(), buf.len() as u32, &mut written, ptr::null_mut()) } == 0 {
+          return Err(last_error("unable to write to named pipe"));
+        }
+        buf = buf.slice_from(written as uint);
+      }
+      Ok(())
+    }
+  }
+
+  impl Drop for NamedPipe {
+    fn drop(&mut self) {
+      unsafe {
+        FlushFileBuffers(self.handle);
+        CloseHandle(self.handle);
+      }
+    }
+  }
+}
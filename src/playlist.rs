@@ -0,0 +1,150 @@
+use std::io::{File, stderr};
+use std::rand::Rng;
+use toml;
+
+use turing::{Machine, Color, palette, session};
+use turing::rng::SessionRng;
+
+
+/// How the tape starts when a playlist entry is shown.
+#[deriving(PartialEq,Eq,Show)]
+pub enum InitPattern {
+  /// The tape saved with the machine.
+  SavedTape,
+  /// All zeros.
+  Blank,
+  /// A random symbol in every cell.
+  Noise,
+  /// All zeros except symbol 1 in the center cell.
+  CenterDot,
+}
+
+
+/// One machine in a playlist.
+pub struct Entry {
+  /// A saved session or batch machine file with a [machine] section.
+  pub machine: Path,
+  /// How long to show it, instead of 'reset_steps'.
+  pub steps: Option<u64>,
+  pub init: InitPattern,
+  pub palette: Option<Vec<Color>>,
+}
+
+
+/// What a playlist entry changes about the run besides the machine.
+pub struct Shown {
+  pub steps: Option<u64>,
+  pub palette: Option<Vec<Color>>,
+}
+
+
+/// A list of saved machines to show in order (or shuffled), each with its own
+/// duration, initial tape and palette. Read from a TOML file:
+///
+///     shuffle = false
+///
+///     [[entry]]
+///     machine = "favorites/spiral.toml"
+///     steps = 5000000              # optional, defaults to reset_steps
+///     init = "saved"               # saved, blank, noise or dot
+///     palette = [[0, 0, 0], [255, 128, 0], [0, 64, 255]]   # optional, or "#rrggbb" strings
+pub struct Playlist {
+  entries: Vec<Entry>,
+  shuffle: bool,
+  // Order of the entries for the current pass.
+  order: Vec<uint>,
+  next: uint,
+}
+
+impl Playlist {
+  pub fn load(path: &Path) -> Result<Playlist, String> {
+    let data = match File::open(path).read_to_string() {
+      Ok(data) => data,
+      Err(why) => return Err(format!("Unable to read {}: {}", path.display(), why.desc)),
+    };
+    let playlist: toml::Value = match from_str(data.as_slice()) {
+      Some(playlist) => playlist,
+      None => return Err(format!("{} is not valid TOML", path.display())),
+    };
+    // Entry paths are relative to the playlist file.
+    let dir = path.dir_path();
+
+    let mut entries = Vec::new();
+    for entry in playlist.lookup("entry").and_then(|e| e.as_slice()).unwrap_or(&[]).iter() {
+      let machine = match entry.lookup("machine").and_then(|m| m.as_str()) {
+        Some(machine) => dir.join(machine),
+        None => return Err("Playlist entry without a machine".to_string()),
+      };
+      let init = match entry.lookup("init").and_then(|i| i.as_str()).unwrap_or("saved") {
+        "saved" => SavedTape,
+        "blank" => Blank,
+        "noise" => Noise,
+        "dot" => CenterDot,
+        other => return Err(format!("Unknown init pattern: {}", other)),
+      };
+      let palette = match entry.lookup("palette") {
+        Some(colors) => Some(try!(palette::parse(colors))),
+        None => None,
+      };
+      let steps = match entry.lookup("steps").and_then(|s| s.as_integer()) {
+        Some(steps) if steps < 1 => return Err(format!("Playlist entry steps must be at least 1, not {}", steps)),
+        steps => steps.map(|steps| steps as u64),
+      };
+      entries.push(Entry {
+        machine: machine,
+        steps: steps,
+        init: init,
+        palette: palette,
+      });
+    }
+    if entries.is_empty() {
+      return Err(format!("{} has no entries", path.display()));
+    }
+
+    let order = range(0, entries.len()).collect();
+    Ok(Playlist {
+      entries: entries,
+      shuffle: playlist.lookup("shuffle").and_then(|s| s.as_bool()).unwrap_or(false),
+      order: order,
+      next: 0,
+    })
+  }
+
+  /// Loads the next entry into 'machine'. Entries that fail to load are
+  /// reported and skipped; if none load the machine is left alone.
+  pub fn next(&mut self, machine: &mut Machine, rng: &mut SessionRng) -> Option<Shown> {
+    for _ in range(0, self.entries.len()) {
+      if self.next == 0 && self.shuffle {
+        rng.shuffle(self.order.as_mut_slice());
+      }
+      let entry = self.entries.get(*self.order.get(self.next));
+      self.next = (self.next + 1) % self.entries.len();
+
+      match session::load_machine(&entry.machine, machine) {
+        Ok(()) => {
+          init_tape(machine, entry.init, rng);
+          return Some(Shown { steps: entry.steps, palette: entry.palette.clone() });
+        },
+        Err(why) => {
+          let _ = writeln!(stderr(), "Skipping playlist entry {}: {}", entry.machine.display(), why);
+        },
+      }
+    }
+    None
+  }
+}
+
+
+fn init_tape(machine: &mut Machine, init: InitPattern, rng: &mut SessionRng) {
+  let (width, height) = machine.tape_size();
+  let symbols = machine.symbols();
+  match init {
+    SavedTape => {},
+    Blank => machine.fill_tape(|_| 0),
+    Noise => machine.fill_tape(|_| rng.gen_range(0, symbols)),
+    CenterDot => {
+      let center = (height / 2) * width + width / 2;
+      machine.fill_tape(|i| if i == center && symbols > 1 { 1 } else { 0 })
+    },
+  }
+}
@@ -0,0 +1,185 @@
+use std::io::{File, IoResult, IoError, OtherIoError};
+use flate::deflate_bytes_zlib;
+
+
+static SIGNATURE: &'static [u8] = &[137, 80, 78, 71, 13, 10, 26, 10];
+
+
+/// Writes a bgr24 image as an 8 bit RGB PNG file. Each (keyword, text) pair
+/// in 'text' is stored in a tEXt chunk, or an iTXt chunk if the text has
+/// characters Latin-1 lacks. The image data is deflated.
+pub fn write_png(path: &Path, image: &[u8], width: uint, height: uint,
+                 text: &[(&str, String)]) -> IoResult<()> {
+  let mut file = try!(File::create(path));
+  try!(file.write(SIGNATURE));
+
+  let mut header = Vec::with_capacity(13);
+  push_u32(&mut header, width as u32);
+  push_u32(&mut header, height as u32);
+  // Bit depth 8, color type 2 (RGB), default compression, filter and interlacing.
+  header.push_all(&[8, 2, 0, 0, 0]);
+  try!(write_chunk(&mut file, b"IHDR", header.as_slice()));
+
+  for &(keyword, ref value) in text.iter() {
+    let mut data = Vec::with_capacity(keyword.len() + 5 + value.len());
+    data.push_all(keyword.as_bytes());
+    data.push(0);
+    if value.as_slice().chars().all(|c| (c as u32) < 0x100) {
+      // tEXt is Latin-1: each character is the byte of its code point.
+      data.extend(value.as_slice().chars().map(|c| c as u8));
+      try!(write_chunk(&mut file, b"tEXt", data.as_slice()));
+    } else {
+      // Uncompressed UTF-8 with no language tag or translated keyword.
+      data.push_all(&[0, 0, 0, 0]);
+      data.push_all(value.as_bytes());
+      try!(write_chunk(&mut file, b"iTXt", data.as_slice()));
+    }
+  }
+
+  // Every scanline starts with its filter type, 0 (none).
+  let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+  for row in image.chunks(width * 3) {
+    raw.push(0);
+    for pixel in row.chunks(3) {
+      raw.push(pixel[2]);
+      raw.push(pixel[1]);
+      raw.push(pixel[0]);
+    }
+  }
+  let data = try!(deflate_bytes_zlib(raw.as_slice()).ok_or(IoError {
+    kind: OtherIoError, desc: "Unable to compress the image", detail: None,
+  }));
+  try!(write_chunk(&mut file, b"IDAT", data.as_slice()));
+  write_chunk(&mut file, b"IEND", &[])
+}
+
+
+/// The (keyword, text) pairs of the tEXt and uncompressed iTXt chunks of the
+/// PNG file at 'path'.
+pub fn read_text(path: &Path) -> Result<Vec<(String, String)>, String> {
+  let data = match File::open(path).read_to_end() {
+    Ok(data) => data,
+    Err(why) => return Err(format!("Unable to read {}: {}", path.display(), why.desc)),
+  };
+  if !data.as_slice().starts_with(SIGNATURE) {
+    return Err(format!("{} is not a PNG file", path.display()));
+  }
+
+  let mut text = Vec::new();
+  let mut offset = SIGNATURE.len();
+  while offset + 8 <= data.len() {
+    let length = read_u32(data.slice(offset, offset + 4)) as uint;
+    let kind = data.slice(offset + 4, offset + 8);
+    let start = offset + 8;
+    if start + length + 4 > data.len() {
+      return Err(format!("{} is truncated", path.display()));
+    }
+    if kind == b"tEXt" {
+      let chunk = data.slice(start, start + length);
+      match chunk.iter().position(|&byte| byte == 0) {
+        Some(separator) => {
+          // tEXt is Latin-1; map each byte to the code point of the same value.
+          let latin1 = |bytes: &[u8]| -> String { bytes.iter().map(|&byte| byte as char).collect() };
+          text.push((latin1(chunk.slice_to(separator)), latin1(chunk.slice_from(separator + 1))));
+        },
+        None => return Err(format!("Invalid tEXt chunk in {}", path.display())),
+      }
+    }
+    if kind == b"iTXt" {
+      match itxt(data.slice(start, start + length)) {
+        Some(pair) => text.push(pair),
+        None => return Err(format!("Invalid iTXt chunk in {}", path.display())),
+      }
+    }
+    if kind == b"IEND" {
+      break;
+    }
+    offset = start + length + 4;
+  }
+  Ok(text)
+}
+
+
+// The keyword and text of an iTXt chunk, unless it is compressed or not
+// UTF-8.
+fn itxt(chunk: &[u8]) -> Option<(String, String)> {
+  let keyword = match chunk.iter().position(|&byte| byte == 0) {
+    Some(separator) => separator,
+    None => return None,
+  };
+  // The compression flag and method, then the language tag and translated
+  // keyword, each ending in a 0.
+  let rest = chunk.slice_from(keyword + 1);
+  if rest.len() < 2 || rest[0] != 0 {
+    return None;
+  }
+  let mut offset = keyword + 3;
+  for _ in range(0u, 2) {
+    match chunk.slice_from(::std::cmp::min(offset, chunk.len())).iter().position(|&byte| byte == 0) {
+      Some(end) => offset += end + 1,
+      None => return None,
+    }
+  }
+  match (::std::str::from_utf8(chunk.slice_to(keyword)), ::std::str::from_utf8(chunk.slice_from(offset))) {
+    (Some(keyword), Some(text)) => Some((keyword.to_string(), text.to_string())),
+    _ => None,
+  }
+}
+
+
+fn write_chunk(out: &mut Writer, kind: &[u8], data: &[u8]) -> IoResult<()> {
+  let mut length = Vec::with_capacity(4);
+  push_u32(&mut length, data.len() as u32);
+  try!(out.write(length.as_slice()));
+  try!(out.write(kind));
+  try!(out.write(data));
+  let mut crc = Vec::with_capacity(4);
+  push_u32(&mut crc, crc32(kind, data));
+  out.write(crc.as_slice())
+}
+
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+  out.push_all(&[(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]);
+}
+
+
+fn read_u32(bytes: &[u8]) -> u32 {
+  (bytes[0] as u32 << 24) | (bytes[1] as u32 << 16) | (bytes[2] as u32 << 8) | bytes[3] as u32
+}
+
+
+// CRC-32 of the chunk type and data, as required for every PNG chunk.
+fn crc32(kind: &[u8], data: &[u8]) -> u32 {
+  let mut table = [0u32, ..256];
+  for n in range(0u, 256) {
+    let mut c = n as u32;
+    for _ in range(0u, 8) {
+      c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+    }
+    table[n] = c;
+  }
+  let mut crc = 0xffffffffu32;
+  for &byte in kind.iter().chain(data.iter()) {
+    crc = table[((crc ^ byte as u32) & 0xff) as uint] ^ (crc >> 8);
+  }
+  crc ^ 0xffffffff
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::io::TempDir;
+
+  use super::{write_png, read_text};
+
+  #[test]
+  fn text_outside_latin1_survives() {
+    let dir = TempDir::new("turing").unwrap();
+    let path = dir.path().join("text.png");
+    let text = [("Comment", "café".to_string()), ("Title", "→ ☃".to_string())];
+    write_png(&path, &[0u8, 0, 0], 1, 1, &text).unwrap();
+    assert_eq!(read_text(&path).unwrap(), vec!(("Comment".to_string(), "café".to_string()),
+                                               ("Title".to_string(), "→ ☃".to_string())));
+  }
+}
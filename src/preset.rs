@@ -0,0 +1,140 @@
+use toml;
+
+
+/// A well known machine, selected with 'turing.preset'. Presets are known
+/// good starting points and a check that the simulator behaves like the
+/// literature says it should.
+pub struct Preset {
+  pub name: &'static str,
+  pub description: &'static str,
+  /// The 'turing.mode' the table is for: "turing" or "turmite".
+  pub mode: &'static str,
+  pub states: u8,
+  pub symbols: u8,
+  /// The table as written by 'Machine::table_text'.
+  pub table: &'static str,
+}
+
+impl Preset {
+  /// The table, for 'Machine::load_table'.
+  pub fn table(&self) -> toml::Value {
+    let parsed: toml::Value = from_str(format!("table = {}", self.table).as_slice()).unwrap();
+    parsed.lookup("table").unwrap().clone()
+  }
+}
+
+
+// Turmite turns: 0 none, 1 right, 2 u-turn, 3 left. Multi-color ants have
+// one state and write the next symbol, turning by the letter of their name
+// for the symbol read.
+pub static PRESETS: &'static [Preset] = &[
+  Preset {
+    name: "langtons-ant",
+    description: "Langton's ant: chaos for about 10000 steps, then a diagonal highway",
+    mode: "turmite",
+    states: 1,
+    symbols: 2,
+    table: "[[0, 1, 1], [0, 0, 3]]",
+  },
+  Preset {
+    name: "llrr",
+    description: "Multi-color ant LLRR: grows symmetrically",
+    mode: "turmite",
+    states: 1,
+    symbols: 4,
+    table: "[[0, 1, 3], [0, 2, 3], [0, 3, 1], [0, 0, 1]]",
+  },
+  Preset {
+    name: "lrrrrrllr",
+    description: "Multi-color ant LRRRRRLLR: fills a growing square",
+    mode: "turmite",
+    states: 1,
+    symbols: 9,
+    table: "[[0, 1, 3], [0, 2, 1], [0, 3, 1], [0, 4, 1], [0, 5, 1], [0, 6, 1], [0, 7, 3], [0, 8, 3], \
+            [0, 0, 1]]",
+  },
+  Preset {
+    name: "rrlllrlllrrr",
+    description: "Multi-color ant RRLLLRLLLRRR: grows a triangle that moves off as a highway",
+    mode: "turmite",
+    states: 1,
+    symbols: 12,
+    table: "[[0, 1, 1], [0, 2, 1], [0, 3, 3], [0, 4, 3], [0, 5, 3], [0, 6, 1], [0, 7, 3], [0, 8, 3], \
+            [0, 9, 3], [0, 10, 1], [0, 11, 1], [0, 0, 1]]",
+  },
+  Preset {
+    name: "fibonacci-spiral",
+    description: "Two state turmite that draws a square spiral growing like the Fibonacci numbers",
+    mode: "turmite",
+    states: 2,
+    symbols: 2,
+    table: "[[1, 1, 3], [1, 1, 1], [1, 1, 3], [0, 0, 0]]",
+  },
+  Preset {
+    name: "binary-counter",
+    description: "Counts in binary along the first row, least significant bit in the top left cell",
+    mode: "turing",
+    states: 2,
+    symbols: 3,
+    // Symbol 1 is a 0 bit and 2 a 1 bit. State 0 carries east; state 1
+    // returns west to the blank cell wrapped around from the left edge.
+    table: "[[1, 2, 3], [0, 0, 1], [1, 2, 3], [1, 1, 3], [0, 1, 1], [1, 2, 3]]",
+  },
+];
+
+
+/// The preset called 'name'.
+pub fn find(name: &str) -> Option<&'static Preset> {
+  PRESETS.iter().find(|preset| preset.name == name)
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::{PRESETS, find};
+  use super::super::{TuringMachine, Machine, NoFeature, Cardinal};
+  use rng::SessionRng;
+  use turmite::TurmiteMachine;
+
+  #[test]
+  fn tables_fit_their_machines() {
+    for preset in PRESETS.iter() {
+      let mut rng = SessionRng::new(0);
+      let mut machine: Box<Machine> = match preset.mode {
+        "turmite" => TurmiteMachine::new(16, 16, preset.states, preset.symbols, &mut rng) as Box<Machine>,
+        _ => TuringMachine::new(16, 16, preset.states, preset.symbols, NoFeature, Cardinal, &mut rng)
+          as Box<Machine>,
+      };
+      assert!(machine.load_table(&preset.table()).is_ok(), "{}", preset.name);
+    }
+  }
+
+  #[test]
+  fn binary_counter_counts() {
+    let preset = find("binary-counter").unwrap();
+    let mut machine = TuringMachine::new(12, 1, preset.states, preset.symbols, NoFeature, Cardinal,
+                                         &mut SessionRng::new(0));
+    machine.load_table(&preset.table()).unwrap();
+    let mut count = 0u;
+    for _ in range(0u, 3000) {
+      // Each increment starts carrying from the first cell.
+      if machine.state() == 0 && machine.position() == 0 {
+        let tape = machine.tape();
+        let bits = tape.iter().take_while(|&&symbol| symbol != 0);
+        let value = bits.enumerate().fold(0u, |value, (i, &symbol)| {
+          if symbol == 2 { value | 1 << i } else { value }
+        });
+        assert_eq!(value, count);
+        count += 1;
+      }
+      machine.step();
+    }
+    assert!(count > 500);
+  }
+
+  #[test]
+  fn unknown_presets_are_not_found() {
+    assert!(find("langtons-ant").is_some());
+    assert!(find("langtons-aunt").is_none());
+  }
+}
@@ -0,0 +1,141 @@
+use std::io::{IoResult, IoError, OtherIoError};
+use std::os::getenv;
+
+use output::ImageSink;
+
+
+/// A downsampled preview of the image drawn in the terminal with 24-bit ANSI
+/// colors. Each character is an upper half block showing two pixels, the top
+/// one in the foreground color and the bottom one in the background color.
+/// Used when stdout is a terminal instead of a video player, so running the
+/// binary bare still shows something, and by the "terminal" output.
+pub struct TerminalPreview {
+  out: Box<Writer>,
+  // First terminal row (1 based) of the preview.
+  top: uint,
+  columns: uint,
+  rows: uint,
+}
+
+impl TerminalPreview {
+  /// Creates a preview sized to the terminal, drawn to 'out'. Clears the
+  /// screen and hides the cursor. Fails if the terminal has no room for it.
+  pub fn new(mut out: Box<Writer>) -> IoResult<TerminalPreview> {
+    let (columns, rows) = terminal_size();
+    // Leave the last line free so the terminal doesn't scroll.
+    let rows = match rows.checked_sub(&1) {
+      Some(rows) if rows > 0 && columns > 0 => rows,
+      _ => return Err(too_small()),
+    };
+    try!(out.write_str("\x1b[2J\x1b[?25l"));
+    Ok(TerminalPreview::with_area(out, 1, columns, rows))
+  }
+
+  /// Creates a preview occupying 'rows' rows from row 'top' (1 based).
+  pub fn with_area(out: Box<Writer>, top: uint, columns: uint, rows: uint) -> TerminalPreview {
+    TerminalPreview { out: out, top: top, columns: columns, rows: rows }
+  }
+
+  // The escape sequences and characters drawing a 'width'x'height' bgr24
+  // image, sampling one pixel per half character.
+  fn render(&self, image: &[u8], width: uint, height: uint) -> String {
+    let pixels = self.rows * 2;
+    let color = |column: uint, pixel_row: uint| {
+      let x = (column * width + width / 2) / self.columns;
+      let y = (pixel_row * height + height / 2) / pixels;
+      let p = (y * width + x) * 3;
+      (image[p+2], image[p+1], image[p])
+    };
+    // Draw over the previous frame, positioning the cursor for each row so
+    // this also works with the terminal in raw mode. Colors are only set
+    // when they change, which keeps frames small over slow connections.
+    let mut text = String::new();
+    for row in range(0, self.rows) {
+      text.push_str(format!("\x1b[{};1H", self.top + row).as_slice());
+      let mut last = None;
+      for column in range(0, self.columns) {
+        let colors = (color(column, row * 2), color(column, row * 2 + 1));
+        if last != Some(colors) {
+          let ((tr, tg, tb), (br, bg, bb)) = colors;
+          text.push_str(format!("\x1b[38;2;{};{};{};48;2;{};{};{}m", tr, tg, tb, br, bg, bb).as_slice());
+          last = Some(colors);
+        }
+        text.push('\u2580');
+      }
+      text.push_str("\x1b[0m");
+    }
+    text
+  }
+}
+
+impl ImageSink for TerminalPreview {
+  fn write_frame(&mut self, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    let text = self.render(image, width, height);
+    try!(self.out.write_str(text.as_slice()));
+    self.out.flush()
+  }
+
+  // Leaves the terminal usable.
+  fn finish(&mut self) -> IoResult<()> {
+    try!(self.out.write_str("\x1b[0m\x1b[?25h\n"));
+    self.out.flush()
+  }
+}
+
+
+/// The terminal's (columns, rows) from $COLUMNS and $LINES, or 80x24.
+pub fn terminal_size() -> (uint, uint) {
+  let size = |name: &str, default: uint| {
+    getenv(name).and_then(|value| from_str::<uint>(value.as_slice())).unwrap_or(default)
+  };
+  (size("COLUMNS", 80), size("LINES", 24))
+}
+
+
+/// The error for a terminal with no rows or columns left for a preview.
+pub fn too_small() -> IoError {
+  let (columns, rows) = terminal_size();
+  IoError { kind: OtherIoError, desc: "Terminal too small",
+            detail: Some(format!("{} columns, {} rows", columns, rows)) }
+}
+
+
+/// Returns true if stdout is a terminal rather than a pipe or file.
+pub fn stdout_is_terminal() -> bool {
+  is_terminal(1)
+}
+
+
+/// Returns true if stdin is a terminal, so keys can be read from it.
+pub fn stdin_is_terminal() -> bool {
+  is_terminal(0)
+}
+
+
+fn is_terminal(fd: ::libc::c_int) -> bool {
+  extern {
+    fn isatty(fd: ::libc::c_int) -> ::libc::c_int;
+  }
+  unsafe { isatty(fd) != 0 }
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::io::util::NullWriter;
+
+  use super::TerminalPreview;
+
+  #[test]
+  fn draws_two_pixels_per_character() {
+    let preview = TerminalPreview::with_area(box NullWriter as Box<Writer>, 1, 2, 1);
+    // 2x2 bgr24: red and green over blue and blue.
+    let image = [0u8, 0, 255, 0, 255, 0, 255, 0, 0, 255, 0, 0];
+    assert_eq!(preview.render(&image, 2, 2).as_slice(),
+               "\x1b[1;1H\x1b[38;2;255;0;0;48;2;0;0;255m\u2580\x1b[38;2;0;255;0;48;2;0;0;255m\u2580\x1b[0m");
+    // Runs of the same colors share one escape sequence.
+    let blue = [255u8, 0, 0, 255, 0, 0, 255, 0, 0, 255, 0, 0];
+    assert_eq!(preview.render(&blue, 2, 2).as_slice(),
+               "\x1b[1;1H\x1b[38;2;0;0;255;48;2;0;0;255m\u2580\u2580\x1b[0m");
+  }
+}
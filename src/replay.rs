@@ -0,0 +1,102 @@
+use std::cmp::max;
+use std::io::IoResult;
+use toml;
+
+use turing::Machine;
+use turing::error::{MachineError, BadState};
+use runner::{Runner, FrameSink};
+
+
+/// Finds the most active interval of a machine's life for a slow motion
+/// replay when it is replaced. The life is split into windows of
+/// 'window_frames' frames; the machine's state at the start of the window
+/// with the highest rate of changed pixels is kept so the window can be
+/// stepped through again.
+pub struct Highlight {
+  window_frames: u64,
+  // The current window: the machine at its start, and its frames, steps
+  // and changed pixels so far.
+  start: Option<String>,
+  frames: u64,
+  steps: u64,
+  changes: u64,
+  // The best complete window so far: (start state, steps, changes).
+  best: Option<(String, u64, u64)>,
+}
+
+impl Highlight {
+  pub fn new(window_frames: u64) -> Highlight {
+    Highlight { window_frames: window_frames, start: None, frames: 0, steps: 0, changes: 0, best: None }
+  }
+
+  /// Starts tracking a new machine, forgetting the previous one's windows.
+  pub fn begin(&mut self, machine: &Machine) {
+    self.start = Some(machine.save_state());
+    self.frames = 0;
+    self.steps = 0;
+    self.changes = 0;
+    self.best = None;
+  }
+
+  /// Accounts for a frame of 'steps' steps that changed 'changes' pixels.
+  /// 'machine' is the state at the end of the frame.
+  pub fn frame(&mut self, machine: &Machine, steps: u64, changes: u64) {
+    self.frames += 1;
+    self.steps += steps;
+    self.changes += changes;
+    if self.frames < self.window_frames {
+      return;
+    }
+
+    // Compare changes per step without dividing.
+    let better = match self.best {
+      Some((_, best_steps, best_changes)) => self.changes * best_steps > best_changes * self.steps,
+      None => self.changes > 0,
+    };
+    match self.start.take() {
+      Some(start) if better => self.best = Some((start, self.steps, self.changes)),
+      _ => {},
+    }
+    self.start = Some(machine.save_state());
+    self.frames = 0;
+    self.steps = 0;
+    self.changes = 0;
+  }
+
+  /// The start state and length in steps of the most active window, if any
+  /// window was completed.
+  pub fn take(&mut self) -> Option<(String, u64)> {
+    self.best.take().map(|(state, steps, _)| (state, steps))
+  }
+}
+
+
+/// Restores a state saved by 'Machine::save_state' into 'machine'.
+pub fn restore(machine: &mut Machine, state: &str) -> Result<(), MachineError> {
+  let saved: toml::Value = match from_str(format!("[machine]\n{}", state).as_slice()) {
+    Some(saved) => saved,
+    None => return Err(BadState("Invalid saved state".to_string())),
+  };
+  match saved.lookup("machine") {
+    Some(saved) => machine.load_state(saved),
+    None => Err(BadState("Invalid saved state".to_string())),
+  }
+}
+
+
+/// Steps 'machine' for 'steps' steps, writing a frame through 'runner'
+/// every 'picture_steps' steps.
+pub fn play(machine: &mut Machine, steps: u64, picture_steps: u64, runner: &mut Runner,
+            outputs: &mut FrameSink) -> IoResult<()> {
+  let picture_steps = max(1, picture_steps);
+  let mut done = 0;
+  while done < steps {
+    for _ in range(0, picture_steps) {
+      machine.step();
+    }
+    done += picture_steps;
+    try!(runner.write_frame(&*machine, outputs));
+    machine.end_frame();
+  }
+  Ok(())
+}
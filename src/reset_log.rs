@@ -0,0 +1,97 @@
+use std::io::{File, IoResult, Append, Write};
+use std::io::fs::PathExtensions;
+use time::get_time;
+
+use turing::Machine;
+use turing::rng::SessionRng;
+
+
+static HEADER: &'static str =
+  "time,seed,draws,fingerprint,reason,steps,changed_pixels,symbols_used,halted,cycled";
+
+
+/// Appends a CSV row for every machine replaced, for analyzing long runs:
+/// which machines stayed active and how each ended. A new file starts with a
+/// header row.
+pub struct ResetLog {
+  file: File,
+  // Pixels the current machine has changed so far.
+  changes: u64,
+}
+
+impl ResetLog {
+  /// Opens 'path' for appending, creating it with a header if needed.
+  pub fn open(path: &Path) -> IoResult<ResetLog> {
+    let new = !path.exists() || try!(path.stat()).size == 0;
+    let mut file = try!(File::open_mode(path, Append, Write));
+    if new {
+      try!(writeln!(file, "{}", HEADER));
+      try!(file.flush());
+    }
+    Ok(ResetLog { file: file, changes: 0 })
+  }
+
+  /// Accounts for a frame of the current machine that changed 'changes'
+  /// pixels.
+  pub fn frame(&mut self, changes: u64) {
+    self.changes += changes;
+  }
+
+  /// Records 'machine', whose table was drawn from 'rng' after 'draws'
+  /// numbers and has 'fingerprint', being replaced for 'reason' after
+  /// 'steps' steps. The next frames count for the next machine.
+  pub fn replaced(&mut self, machine: &Machine, rng: &SessionRng, draws: u64, fingerprint: u64, reason: &str,
+                  steps: u64) -> IoResult<()> {
+    let changes = self.changes;
+    self.changes = 0;
+    try!(writeln!(self.file, "{}", row(get_time().sec, rng.seed(), draws, fingerprint, reason, steps, changes,
+                                       machine.symbols_used(), machine.halted(), reason == "cycle")));
+    self.file.flush()
+  }
+}
+
+
+fn row(time: i64, seed: u64, draws: u64, fingerprint: u64, reason: &str, steps: u64, changes: u64,
+       symbols_used: uint, halted: bool, cycled: bool) -> String {
+  format!("{},{},{},{:016x},{},{},{},{},{},{}", time, seed, draws, fingerprint, reason, steps, changes,
+          symbols_used, halted, cycled)
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::io::File;
+  use std::io::fs::unlink;
+
+  use turing::{TuringMachine, NoFeature, Cardinal};
+  use turing::rng::SessionRng;
+
+  use super::{ResetLog, HEADER, row};
+
+  #[test]
+  fn rows_match_the_header() {
+    let columns = HEADER.split(',').count();
+    assert_eq!(row(1, 2, 3, 4, "cycle", 5, 6, 7, false, true).as_slice().split(',').count(), columns);
+    assert_eq!(row(1, 2, 3, 255, "halted", 5, 6, 7, true, false),
+               "1,2,3,00000000000000ff,halted,5,6,7,true,false".to_string());
+  }
+
+  #[test]
+  fn appends_one_header() {
+    let path = ::std::os::tmpdir().join(format!("turing-resets-{}.csv", ::std::os::getpid()));
+    let machine = TuringMachine::new(4, 4, 2, 2, NoFeature, Cardinal, &mut SessionRng::new(0));
+    let rng = SessionRng::new(9);
+    for _ in range(0u, 2) {
+      let mut log = ResetLog::open(&path).unwrap();
+      log.frame(3);
+      log.frame(4);
+      log.replaced(&*machine, &rng, 0, 1, "reset_steps", 100).unwrap();
+    }
+    let text = File::open(&path).read_to_string().unwrap();
+    let _ = unlink(&path);
+    let lines: Vec<&str> = text.as_slice().lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], HEADER);
+    assert!(lines[1].ends_with(",0000000000000001,reset_steps,100,7,1,false,false"));
+  }
+}
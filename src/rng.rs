@@ -0,0 +1,216 @@
+use std::rand::{Rng, SeedableRng, XorShiftRng, StdRng, ChaChaRng};
+use toml;
+
+use error::{Error, invalid};
+
+
+/// The generator algorithms a session can use, chosen with 'turing.rng'.
+#[deriving(PartialEq,Eq,Show)]
+pub enum Algorithm {
+  /// XorShift: small and fast, but not cryptographically strong. The default.
+  Small,
+  /// The standard library's default generator (ISAAC).
+  Std,
+  /// ChaCha with 20 rounds.
+  ChaCha,
+}
+
+impl Algorithm {
+  /// The configured algorithm.
+  pub fn from_config(config: &toml::Value) -> Result<Algorithm, Error> {
+    let name = config.lookup("turing.rng").and_then(|value| value.as_str()).unwrap_or("small");
+    Algorithm::from_name(name).ok_or(invalid(format!("Unknown rng: {}", name)))
+  }
+
+  pub fn from_name(name: &str) -> Option<Algorithm> {
+    match name {
+      "small" => Some(Small),
+      "std" => Some(Std),
+      "chacha" => Some(ChaCha),
+      _ => None,
+    }
+  }
+
+  /// The name used in configs and session files.
+  pub fn name(&self) -> &'static str {
+    match *self {
+      Small => "small",
+      Std => "std",
+      ChaCha => "chacha",
+    }
+  }
+}
+
+
+// Draws between reseeds of the generator (see 'SessionRng').
+static BLOCK_DRAWS: u64 = 1 << 16;
+
+
+enum Generator {
+  SmallGenerator(XorShiftRng),
+  StdGenerator(StdRng),
+  ChaChaGenerator(ChaChaRng),
+}
+
+
+/// The random number generator behind all machine randomness (tables, tapes,
+/// playlist order). It is a seeded generator of the chosen algorithm that
+/// counts the numbers it has produced, so its state can be saved as just
+/// (algorithm, seed, draws) and restored by reseeding and skipping ahead.
+/// The generator is reseeded from the seed and the block number every
+/// BLOCK_DRAWS draws, so restoring skips at most that many however long the
+/// run has been.
+pub struct SessionRng {
+  algorithm: Algorithm,
+  seed: u64,
+  draws: u64,
+  rng: Generator,
+}
+
+impl SessionRng {
+  /// A 'Small' generator, the one checksums and golden frames are recorded with.
+  pub fn new(seed: u64) -> SessionRng {
+    SessionRng::with_algorithm(Small, seed)
+  }
+
+  pub fn with_algorithm(algorithm: Algorithm, seed: u64) -> SessionRng {
+    SessionRng { algorithm: algorithm, seed: seed, draws: 0, rng: generator(algorithm, seed) }
+  }
+
+  /// A generator with a random seed. Seeds are below 2^63 so they can be
+  /// written back as 'turing.seed'.
+  pub fn from_entropy(algorithm: Algorithm) -> SessionRng {
+    SessionRng::with_algorithm(algorithm, ::std::rand::task_rng().gen::<u64>() >> 1)
+  }
+
+  /// The configured generator: seeded with 'turing.seed' so a run can be
+  /// reproduced exactly, or with a random seed when it isn't set.
+  pub fn from_config(config: &toml::Value) -> Result<SessionRng, Error> {
+    let algorithm = try!(Algorithm::from_config(config));
+    Ok(match config.lookup("turing.seed").and_then(|value| value.as_integer()) {
+      Some(seed) => SessionRng::with_algorithm(algorithm, seed as u64),
+      None => SessionRng::from_entropy(algorithm),
+    })
+  }
+
+  /// Recreates the state of a generator that had produced 'draws' numbers.
+  pub fn restore(algorithm: Algorithm, seed: u64, draws: u64) -> SessionRng {
+    let mut rng = SessionRng::with_algorithm(algorithm, seed);
+    // The next draw reseeds for the block 'draws' is in; only the draws
+    // within the block are made again.
+    rng.draws = draws - draws % BLOCK_DRAWS;
+    for _ in range(0, draws % BLOCK_DRAWS) {
+      rng.next_u32();
+    }
+    rng
+  }
+
+  pub fn algorithm(&self) -> Algorithm {
+    self.algorithm
+  }
+
+  pub fn seed(&self) -> u64 {
+    self.seed
+  }
+
+  /// The number of 32 bit values produced so far.
+  pub fn draws(&self) -> u64 {
+    self.draws
+  }
+}
+
+impl Rng for SessionRng {
+  // Only 'next_u32' is forwarded, so every value drawn is counted the same
+  // way whatever the generator.
+  fn next_u32(&mut self) -> u32 {
+    if self.draws > 0 && self.draws % BLOCK_DRAWS == 0 {
+      self.rng = generator(self.algorithm, block_seed(self.seed, self.draws / BLOCK_DRAWS));
+    }
+    self.draws += 1;
+    match self.rng {
+      SmallGenerator(ref mut rng) => rng.next_u32(),
+      StdGenerator(ref mut rng) => rng.next_u32(),
+      ChaChaGenerator(ref mut rng) => rng.next_u32(),
+    }
+  }
+}
+
+
+// A generator of 'algorithm' seeded with 'seed'.
+fn generator(algorithm: Algorithm, seed: u64) -> Generator {
+  let seed32 = expand_seed(seed);
+  match algorithm {
+    Small => SmallGenerator(SeedableRng::from_seed(seed32)),
+    Std => StdGenerator(SeedableRng::from_seed(&[seed as uint, (seed >> 32) as uint])),
+    ChaCha => ChaChaGenerator(SeedableRng::from_seed(seed32.as_slice())),
+  }
+}
+
+
+// The seed of the generator for draws from 'block' * BLOCK_DRAWS on. The
+// first block uses the session seed itself, so short runs draw what they
+// always did.
+fn block_seed(seed: u64, block: u64) -> u64 {
+  if block == 0 {
+    return seed;
+  }
+  // SplitMix64's finalizer, so neighbouring blocks get unrelated seeds.
+  let mut mixed = seed ^ block * 0x9e3779b97f4a7c15;
+  mixed = (mixed ^ (mixed >> 30)) * 0xbf58476d1ce4e5b9;
+  mixed = (mixed ^ (mixed >> 27)) * 0x94d049bb133111eb;
+  mixed ^ (mixed >> 31)
+}
+
+
+// XorShiftRng needs a 128 bit seed that isn't all zeros.
+fn expand_seed(seed: u64) -> [u32, ..4] {
+  let low = seed as u32;
+  let high = (seed >> 32) as u32;
+  [low, high, low ^ 0x9e3779b9, high ^ 0x7f4a7c15]
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::rand::Rng;
+  use toml;
+
+  use super::{SessionRng, Small, ChaCha, BLOCK_DRAWS};
+
+  fn config(extra: &str) -> toml::Value {
+    from_str(format!("[turing]\n{}", extra).as_slice()).unwrap()
+  }
+
+  #[test]
+  fn configured_seed_repeats_runs() {
+    let draws = |config: &toml::Value| {
+      let mut rng = SessionRng::from_config(config).unwrap();
+      range(0u, 8).map(|_| rng.next_u32()).collect::<Vec<u32>>()
+    };
+    let seeded = config("seed = 42");
+    assert_eq!(SessionRng::from_config(&seeded).unwrap().seed(), 42);
+    assert_eq!(draws(&seeded), draws(&seeded));
+    assert_eq!(draws(&config("seed = 42\nrng = \"chacha\"")), draws(&config("seed = 42\nrng = \"chacha\"")));
+  }
+
+  #[test]
+  fn random_seeds_can_be_configured() {
+    for _ in range(0u, 100) {
+      assert!(SessionRng::from_config(&config("")).unwrap().seed() <= ::std::i64::MAX as u64);
+    }
+  }
+
+  #[test]
+  fn restored_generators_continue_the_run() {
+    for &algorithm in [Small, ChaCha].iter() {
+      let mut run = SessionRng::with_algorithm(algorithm, 9);
+      let drawn: Vec<u32> = range(0, 2 * BLOCK_DRAWS + 10).map(|_| run.next_u32()).collect();
+      for &draws in [0, 100, BLOCK_DRAWS, BLOCK_DRAWS + 1, 2 * BLOCK_DRAWS + 5].iter() {
+        let mut restored = SessionRng::restore(algorithm, 9, draws);
+        let next: Vec<u32> = range(0u, 5).map(|_| restored.next_u32()).collect();
+        assert_eq!(next.as_slice(), drawn.slice(draws as uint, draws as uint + 5));
+        assert_eq!(restored.draws(), draws + 5);
+      }
+    }
+  }
+}
@@ -0,0 +1,590 @@
+use std::cmp::{max, min};
+use std::io::IoResult;
+use time::precise_time_ns;
+use toml;
+
+use turing::Machine;
+use turing::session::LoopState;
+use adjust::ColorAdjust;
+use blend::FrameBlend;
+use cycle::CycleDetector;
+use marker::HeadMarker;
+use output::Outputs;
+use stagnation::Stagnation;
+use viewport::Viewport;
+
+
+/// Why a machine is being replaced, as reported in events and the event log.
+pub type Reason = &'static str;
+
+
+/// The run loop's counters.
+pub struct Counts {
+  /// Steps of the current machine.
+  pub step: u64,
+  /// Steps since the start of the run, across machines.
+  pub total_steps: u64,
+  /// Steps and steps that changed a cell since the last frame.
+  pub frame_steps: u64,
+  pub frame_changes: u64,
+  /// Frames written so far.
+  pub frames: u64,
+  /// Steps between frames.
+  pub picture_steps: u64,
+  /// Steps before the machine is replaced, with the step limit policy.
+  pub reset_steps: u64,
+}
+
+
+/// Decides when a machine has been shown long enough. A policy watches
+/// steps, frames or the counters, and gives the reason to replace the
+/// machine once it should go.
+pub trait ResetPolicy {
+  /// The reason the policy gives, which also names it.
+  fn name(&self) -> Reason;
+
+  /// Accounts for a step of 'machine', which changed a cell if 'changed'.
+  /// Returns true if the machine should be replaced.
+  fn on_step(&mut self, _machine: &Machine, _changed: bool) -> bool {
+    false
+  }
+
+  /// Accounts for a frame during which 'changes' steps changed a cell.
+  /// Returns true if the machine should be replaced.
+  fn on_frame(&mut self, _changes: u64) -> bool {
+    false
+  }
+
+  /// Whether the machine should be replaced given the counters. Checked
+  /// after every step and again after a frame, which may have changed them.
+  fn due(&self, _counts: &Counts) -> bool {
+    false
+  }
+
+  /// Starts over for a new machine.
+  fn restart(&mut self);
+
+  /// The policy's progress with the current machine, for saving sessions.
+  fn progress(&self) -> Option<u64> {
+    None
+  }
+
+  fn set_progress(&mut self, _progress: u64) {}
+}
+
+
+/// Something that takes the frames of a run.
+pub trait FrameSink {
+  /// Writes frame number 'frame', a 'width'x'height' bgr24 image.
+  fn write_frame(&mut self, frame: u64, image: &[u8], width: uint, height: uint) -> IoResult<()>;
+
+  /// Completes the frames at the end of the run.
+  fn finish(&mut self) -> IoResult<()>;
+
+  /// Checks for keys pressed in the outputs (see 'ImageSink::poll_input').
+  fn poll_input(&mut self) {}
+
+  /// Takes the count of frames that 'write_frame' accepted but that failed
+  /// to be written afterwards. Sinks that write before returning have none.
+  fn dropped_frames(&mut self) -> u64 { 0 }
+
+  /// Opens the output that failed to write a frame again from 'config'
+  /// (see 'Outputs::reopen_failed').
+  fn reopen_failed(&mut self, config: &toml::Value) -> IoResult<()>;
+
+  /// Writes frame number 'frame' only to the outputs that need frames to
+  /// stay open (see 'Outputs::keep_alive').
+  fn keep_alive(&mut self, frame: u64, image: &[u8], width: uint, height: uint) -> IoResult<()>;
+}
+
+impl FrameSink for Outputs {
+  fn write_frame(&mut self, frame: u64, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    self.write(frame, image, width, height)
+  }
+
+  fn finish(&mut self) -> IoResult<()> {
+    self.finish()
+  }
+
+  fn poll_input(&mut self) {
+    self.poll_input()
+  }
+
+  fn reopen_failed(&mut self, config: &toml::Value) -> IoResult<()> {
+    self.reopen_failed(config)
+  }
+
+  fn keep_alive(&mut self, frame: u64, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+    self.keep_alive(frame, image, width, height)
+  }
+}
+
+
+/// Steps a machine in batches between frames and keeps the counters, asking
+/// its reset policies when the machine should be replaced. Reasons from
+/// steps and the counters win over reasons from frames; a halted machine is
+/// always replaced.
+pub struct Runner {
+  pub counts: Counts,
+  policies: Vec<Box<ResetPolicy + 'static>>,
+  // Steps per call to 'run', or 0 to run until something happens.
+  batch_steps: u64,
+  // The first reason given by a policy's steps and by its frames.
+  step_reason: Option<Reason>,
+  frame_reason: Option<Reason>,
+  // Steps between mutations of the table, or 0 for none.
+  mutate_steps: u64,
+  marker: Option<HeadMarker>,
+  viewport: Option<Viewport>,
+  blend: Option<FrameBlend>,
+  adjust: Option<ColorAdjust>,
+  // How long the last frame took to render and to write.
+  render_ns: u64,
+  write_ns: u64,
+}
+
+impl Runner {
+  pub fn new(picture_steps: u64, reset_steps: u64, batch_steps: u64) -> Runner {
+    Runner {
+      counts: Counts { step: 0, total_steps: 0, frame_steps: 0, frame_changes: 0, frames: 0,
+                       picture_steps: picture_steps, reset_steps: reset_steps },
+      policies: Vec::new(),
+      batch_steps: batch_steps,
+      step_reason: None,
+      frame_reason: None,
+      mutate_steps: 0,
+      marker: None,
+      viewport: None,
+      blend: None,
+      adjust: None,
+      render_ns: 0,
+      write_ns: 0,
+    }
+  }
+
+  /// Stops 'run' every 'steps' steps of a machine, for mutating its table.
+  pub fn set_mutate_steps(&mut self, steps: u64) {
+    self.mutate_steps = steps;
+  }
+
+  /// Whether the machine's table is due a mutation.
+  pub fn mutation_due(&self) -> bool {
+    self.mutate_steps > 0 && self.counts.step > 0 && self.counts.step % self.mutate_steps == 0
+  }
+
+  /// Marks the heads in the frames written from now on.
+  pub fn set_marker(&mut self, marker: HeadMarker) {
+    self.marker = Some(marker);
+  }
+
+  /// Shows only a window of the machine's image in the frames written from
+  /// now on.
+  pub fn set_viewport(&mut self, viewport: Viewport) {
+    self.viewport = Some(viewport);
+  }
+
+  /// Blends each frame written from now on with the one before it, after
+  /// marking the heads and taking the viewport's window.
+  pub fn set_blend(&mut self, blend: FrameBlend) {
+    self.blend = Some(blend);
+  }
+
+  /// Adjusts the colors of the frames written from now on, after marking
+  /// the heads, taking the viewport's window and blending.
+  pub fn set_adjust(&mut self, adjust: ColorAdjust) {
+    self.adjust = Some(adjust);
+  }
+
+  /// The viewport, to pan and zoom it.
+  pub fn viewport(&mut self) -> Option<&mut Viewport> {
+    self.viewport.as_mut()
+  }
+
+  /// The size of the frames written for 'machine'.
+  pub fn frame_size(&self, machine: &Machine) -> (uint, uint) {
+    match self.viewport {
+      Some(ref viewport) => viewport.size(),
+      None => machine.image_size(),
+    }
+  }
+
+  /// Adds a policy. Policies added first win when several give a reason at
+  /// the same time.
+  pub fn add_policy(&mut self, policy: Box<ResetPolicy + 'static>) {
+    self.policies.push(policy);
+  }
+
+  /// Steps 'machine' until a frame is due, a policy wants the machine
+  /// replaced, a batch of steps has run or the table is due a mutation.
+  /// Returns the steps run.
+  pub fn run(&mut self, machine: &mut Machine) -> u64 {
+    let mut steps = 0;
+    loop {
+      let changed = machine.step();
+      steps += 1;
+      self.counts.step += 1;
+      self.counts.total_steps += 1;
+      self.counts.frame_steps += 1;
+      if changed {
+        self.counts.frame_changes += 1;
+      }
+      // Every policy sees every step, even once one has given a reason.
+      let mut reason = self.step_reason;
+      for policy in self.policies.iter_mut() {
+        if policy.on_step(&*machine, changed) && reason.is_none() {
+          reason = Some(policy.name());
+        }
+      }
+      self.step_reason = reason;
+      if reason.is_some() || self.frame_due(&*machine) || self.due().is_some() || steps == self.batch_steps
+          || self.mutation_due() {
+        return steps;
+      }
+    }
+  }
+
+  /// Whether the next frame is due. A halted machine gets a last frame
+  /// whatever the step count.
+  pub fn frame_due(&self, machine: &Machine) -> bool {
+    self.counts.frame_steps >= self.counts.picture_steps || machine.halted()
+  }
+
+  /// Writes the current frame of 'machine' to 'sink'. The frame is counted
+  /// even if writing it fails, as the run may go on with reopened outputs.
+  pub fn write_frame(&mut self, machine: &Machine, sink: &mut FrameSink) -> IoResult<()> {
+    let start = precise_time_ns();
+    let (width, height) = machine.image_size();
+    let image = match self.marker {
+      Some(ref mut marker) => marker.mark(machine),
+      None => machine.image(),
+    };
+    let (image, width, height) = match self.viewport {
+      Some(ref mut viewport) => {
+        if viewport.following() {
+          match machine.head_cells().iter().next() {
+            Some(&(x, y, cell_width, cell_height)) => {
+              viewport.track(x + cell_width / 2, y + cell_height / 2, width, height);
+            },
+            None => {},
+          }
+        }
+        let (view_width, view_height) = viewport.size();
+        (viewport.view(image, width, height), view_width, view_height)
+      },
+      None => (image, width, height),
+    };
+    let image = match self.blend {
+      Some(ref mut blend) => blend.apply(image),
+      None => image,
+    };
+    let image = match self.adjust {
+      Some(ref mut adjust) => adjust.apply(image),
+      None => image,
+    };
+    let rendered = precise_time_ns();
+    let written = sink.write_frame(self.counts.frames, image, width, height);
+    self.render_ns = rendered - start;
+    self.write_ns = precise_time_ns() - rendered;
+    self.counts.frames += 1;
+    written
+  }
+
+  /// Writes a black frame to the outputs of 'sink' that need frames to stay
+  /// open, e.g. while the run is paused for quiet hours. It is numbered and
+  /// counted like the others.
+  pub fn keep_alive(&mut self, machine: &Machine, sink: &mut FrameSink) -> IoResult<()> {
+    let (width, height) = self.frame_size(machine);
+    let black = Vec::from_elem(width * height * 3, 0u8);
+    let written = sink.keep_alive(self.counts.frames, black.as_slice(), width, height);
+    self.counts.frames += 1;
+    written
+  }
+
+  /// How long the last 'write_frame' took to render the frame (marking the
+  /// heads, the viewport, blending and color adjustment) and to write it.
+  pub fn frame_times(&self) -> (u64, u64) {
+    (self.render_ns, self.write_ns)
+  }
+
+  /// Ends the frame: the policies see its changes and the frame counters
+  /// start over.
+  pub fn end_frame(&mut self) {
+    let mut reason = self.frame_reason;
+    for policy in self.policies.iter_mut() {
+      if policy.on_frame(self.counts.frame_changes) && reason.is_none() {
+        reason = Some(policy.name());
+      }
+    }
+    self.frame_reason = reason;
+    self.counts.frame_steps = 0;
+    self.counts.frame_changes = 0;
+  }
+
+  /// Why 'machine' should be replaced now, if it should, with 'requested'
+  /// asked for from outside the run, e.g. by a control request. Ranks,
+  /// highest first: halting, the policies' steps and counters, 'requested'
+  /// and the policies' frames.
+  pub fn reason(&mut self, machine: &Machine, requested: Option<Reason>) -> Option<Reason> {
+    let frame_reason = self.frame_reason.take();
+    if machine.halted() {
+      return Some("halted");
+    }
+    self.step_reason.or(self.due()).or(requested).or(frame_reason)
+  }
+
+  // The first policy due given the counters.
+  fn due(&self) -> Option<Reason> {
+    self.policies.iter().find(|policy| policy.due(&self.counts)).map(|policy| policy.name())
+  }
+
+  /// Starts over for a new machine.
+  pub fn new_machine(&mut self) {
+    self.counts.step = 0;
+    self.counts.frame_steps = 0;
+    self.counts.frame_changes = 0;
+    self.step_reason = None;
+    self.frame_reason = None;
+    for policy in self.policies.iter_mut() {
+      policy.restart();
+    }
+  }
+
+  /// The progress of the policy named 'name', for saving sessions.
+  pub fn progress(&self, name: Reason) -> Option<u64> {
+    self.policies.iter().find(|policy| policy.name() == name).and_then(|policy| policy.progress())
+  }
+
+  pub fn set_progress(&mut self, name: Reason, progress: u64) {
+    for policy in self.policies.iter_mut() {
+      if policy.name() == name {
+        policy.set_progress(progress);
+      }
+    }
+  }
+
+  /// The counters and the policies' progress, for saving sessions.
+  pub fn loop_state(&self) -> LoopState {
+    LoopState {
+      step: self.counts.step,
+      still_frames: self.progress("stagnant").unwrap_or(0),
+      frame_steps: self.counts.frame_steps,
+      frame_changes: self.counts.frame_changes,
+      picture_steps: self.counts.picture_steps,
+      reset_steps: self.counts.reset_steps,
+      frames: self.counts.frames,
+      total_steps: self.counts.total_steps,
+      energy: self.progress("energy"),
+    }
+  }
+
+  /// Continues from a saved session's 'state'.
+  pub fn load_state(&mut self, state: &LoopState) {
+    self.counts = Counts { step: state.step, total_steps: state.total_steps, frame_steps: state.frame_steps,
+                           frame_changes: state.frame_changes, frames: state.frames,
+                           picture_steps: state.picture_steps, reset_steps: state.reset_steps };
+    self.set_progress("stagnant", state.still_frames);
+    match state.energy {
+      Some(energy) => self.set_progress("energy", energy),
+      None => {},
+    }
+  }
+}
+
+
+/// The "steps" reset policy: replaces the machine after 'reset_steps'
+/// steps. Machines shown for fewer steps than a frame (from a playlist
+/// entry, a control request or adaptive frame lengths) still get one frame.
+pub struct StepLimit;
+
+impl ResetPolicy for StepLimit {
+  fn name(&self) -> Reason {
+    "reset_steps"
+  }
+
+  fn due(&self, counts: &Counts) -> bool {
+    counts.step >= max(counts.reset_steps, counts.picture_steps)
+  }
+
+  fn restart(&mut self) {}
+}
+
+
+/// Energy budget for the "energy" reset policy. Every step drains 'drain' and
+/// every changed pixel recharges 'recharge', up to 'capacity'. A machine is
+/// replaced once it runs out, so active machines stay on screen longer.
+pub struct Energy {
+  level: u64,
+  capacity: u64,
+  drain: u64,
+  recharge: u64,
+}
+
+impl Energy {
+  pub fn new(capacity: u64, drain: u64, recharge: u64) -> Energy {
+    Energy { level: capacity, capacity: capacity, drain: drain, recharge: recharge }
+  }
+}
+
+impl ResetPolicy for Energy {
+  fn name(&self) -> Reason {
+    "energy"
+  }
+
+  fn on_step(&mut self, _machine: &Machine, changed: bool) -> bool {
+    self.level -= min(self.level, self.drain);
+    if changed {
+      self.level = min(self.capacity, self.level + self.recharge);
+    }
+    self.level == 0
+  }
+
+  fn restart(&mut self) {
+    self.level = self.capacity;
+  }
+
+  fn progress(&self) -> Option<u64> {
+    Some(self.level)
+  }
+
+  fn set_progress(&mut self, level: u64) {
+    self.level = level;
+  }
+}
+
+
+impl ResetPolicy for Stagnation {
+  fn name(&self) -> Reason {
+    "stagnant"
+  }
+
+  fn on_frame(&mut self, changes: u64) -> bool {
+    self.frame(changes)
+  }
+
+  fn restart(&mut self) {
+    self.reset();
+  }
+
+  fn progress(&self) -> Option<u64> {
+    Some(self.still_frames())
+  }
+
+  fn set_progress(&mut self, still_frames: u64) {
+    self.set_still_frames(still_frames);
+  }
+}
+
+
+impl ResetPolicy for CycleDetector {
+  fn name(&self) -> Reason {
+    "cycle"
+  }
+
+  fn on_step(&mut self, machine: &Machine, _changed: bool) -> bool {
+    machine.local_hash().map_or(false, |hash| self.step(hash))
+  }
+
+  fn restart(&mut self) {
+    self.reset();
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use turing::{TuringMachine, Machine, NoFeature, Cardinal};
+  use turing::rng::SessionRng;
+  use stagnation::Stagnation;
+
+  use super::{Runner, StepLimit, Energy};
+
+  fn machine() -> Box<TuringMachine> {
+    TuringMachine::new(16, 16, 3, 2, NoFeature, Cardinal, &mut SessionRng::new(0))
+  }
+
+  #[test]
+  fn runs_until_the_frame_or_the_batch() {
+    let mut machine = machine();
+    let mut runner = Runner::new(100, 1000, 0);
+    assert_eq!(runner.run(&mut *machine), 100);
+    assert!(runner.frame_due(&*machine));
+    runner.end_frame();
+    let mut batched = Runner::new(100, 1000, 30);
+    assert_eq!(batched.run(&mut *machine), 30);
+    assert!(!batched.frame_due(&*machine));
+    assert_eq!(batched.counts.frame_steps, 30);
+    let mut mutating = Runner::new(100, 1000, 0);
+    mutating.set_mutate_steps(40);
+    assert_eq!(mutating.run(&mut *machine), 40);
+    assert!(mutating.mutation_due());
+    assert_eq!(mutating.run(&mut *machine), 40);
+    assert_eq!(mutating.run(&mut *machine), 20);
+    assert!(!mutating.mutation_due());
+  }
+
+  #[test]
+  fn the_step_limit_waits_for_a_frame() {
+    let mut machine = machine();
+    let mut runner = Runner::new(100, 250, 0);
+    runner.add_policy(box StepLimit);
+    runner.run(&mut *machine);
+    runner.end_frame();
+    assert_eq!(runner.reason(&*machine, None), None);
+    runner.run(&mut *machine);
+    runner.end_frame();
+    runner.run(&mut *machine);
+    assert_eq!(runner.counts.step, 250);
+    assert_eq!(runner.reason(&*machine, None), Some("reset_steps"));
+    // Fewer reset steps than picture steps still shows a frame.
+    let mut short = Runner::new(100, 10, 0);
+    short.add_policy(box StepLimit);
+    assert_eq!(short.run(&mut *machine), 100);
+  }
+
+  #[test]
+  fn step_reasons_win_over_frame_reasons() {
+    let mut machine = machine();
+    let mut runner = Runner::new(10, 1000, 0);
+    // The energy runs out after 5 steps, before the first frame.
+    runner.add_policy(box Energy::new(5, 1, 0));
+    assert_eq!(runner.run(&mut *machine), 5);
+    assert!(!runner.frame_due(&*machine));
+    assert_eq!(runner.reason(&*machine, Some("control")), Some("energy"));
+    runner.new_machine();
+    assert_eq!(runner.counts.step, 0);
+    assert_eq!(runner.progress("energy"), Some(5));
+  }
+
+  #[test]
+  fn saved_states_round_trip() {
+    let mut machine = machine();
+    let mut runner = Runner::new(10, 1000, 0);
+    runner.add_policy(box Energy::new(1000, 1, 0));
+    runner.run(&mut *machine);
+    runner.run(&mut *machine);
+    let state = runner.loop_state();
+    assert_eq!((state.step, state.energy), (20, Some(980)));
+    let mut resumed = Runner::new(1, 1, 0);
+    resumed.add_policy(box Energy::new(1000, 1, 0));
+    resumed.load_state(&state);
+    let again = resumed.loop_state();
+    assert_eq!((again.step, again.picture_steps, again.energy), (20, 10, Some(980)));
+  }
+
+  #[test]
+  fn requests_win_over_frame_reasons() {
+    let mut machine = machine();
+    let mut runner = Runner::new(10, 1000, 0);
+    runner.add_policy(box Stagnation::new(1));
+    runner.run(&mut *machine);
+    // Pretend nothing changed during the frame.
+    runner.counts.frame_changes = 0;
+    runner.end_frame();
+    assert_eq!(runner.reason(&*machine, Some("control")), Some("control"));
+    runner.counts.frame_changes = 0;
+    runner.end_frame();
+    assert_eq!(runner.reason(&*machine, None), Some("stagnant"));
+    // Frame reasons only last until they are asked for.
+    assert_eq!(runner.reason(&*machine, None), None);
+  }
+}
@@ -0,0 +1,203 @@
+use std::rand::distributions::{Range, IndependentSample};
+use toml;
+
+use turing::{Machine, Direction, DirectionSet};
+use turing::error::{MachineError, BadState, BadTable};
+use turing::rng::SessionRng;
+use turing::session;
+use turing::table::{Table, Transition};
+use interest;
+use replay;
+
+
+/// Breeds transition tables for interesting machines instead of drawing
+/// each one at random. A population of tables is scored like
+/// 'turing.min_interest' scores machines; each generation adds children of
+/// pairs of good tables (crossover, then a mutation) and keeps the best.
+/// Each table shown is taken out of the population, so every machine shown
+/// is a different one.
+pub struct Search {
+  // Scores tables: a machine of the live kind, and its blank starting state.
+  scratch: Box<Machine>,
+  start: String,
+  // Warm-up steps per score.
+  steps: u64,
+  size: uint,
+  states: u8,
+  symbols: u8,
+  features: uint,
+  directions: DirectionSet,
+  halting: bool,
+  // The tables and their scores, best first.
+  population: Vec<(Table, f64)>,
+}
+
+impl Search {
+  /// A search of 'size' tables like those of 'scratch', a machine only used
+  /// for scoring them over 'steps' steps.
+  pub fn new(scratch: Box<Machine>, steps: u64, size: uint, states: u8, symbols: u8, features: uint,
+             directions: DirectionSet, halting: bool) -> Search {
+    let start = scratch.save_state();
+    Search { scratch: scratch, start: start, steps: steps, size: size, states: states, symbols: symbols,
+             features: features, directions: directions, halting: halting, population: Vec::new() }
+  }
+
+  /// Breeds a generation, first filling the population with random tables.
+  pub fn generation(&mut self, rng: &mut SessionRng) {
+    while self.population.len() < self.size {
+      let table = if self.halting {
+        Table::random_halting(rng, self.states, self.symbols, self.features, self.directions)
+      } else {
+        Table::random(rng, self.states, self.symbols, self.features, self.directions)
+      };
+      self.add(table);
+    }
+    for _ in range(0, self.size) {
+      let (first, second) = (self.pick(rng), self.pick(rng));
+      let child = {
+        let (&(ref a, _), &(ref b, _)) = (self.population.get(first), self.population.get(second));
+        a.crossover(b, rng)
+      };
+      let mut child = match child {
+        Some(child) => child,
+        None => continue,
+      };
+      child.mutate(rng, self.directions, self.halting);
+      self.add(child);
+    }
+    self.population.truncate(self.size);
+  }
+
+  /// Takes the best table out of the population, as the value
+  /// 'Machine::load_table' takes, and its score. The next generation fills
+  /// its place with a random table.
+  pub fn take_best(&mut self) -> Option<(toml::Value, f64)> {
+    if self.population.is_empty() {
+      return None;
+    }
+    self.population.remove(0).and_then(|(table, score)| table_value(&table).map(|table| (table, score)))
+  }
+
+  /// The population as a '[search]' section of a saved session, read back
+  /// by 'load_state'.
+  pub fn save_state(&self) -> String {
+    let tables: Vec<String> = self.population.iter().map(|&(ref table, _)| table.text()).collect();
+    let scores: Vec<String> = self.population.iter().map(|&(_, score)| format!("{:.6}", score)).collect();
+    format!("[search]\ntables = [{}]\nscores = [{}]\n", tables.connect(", "), scores.connect(", "))
+  }
+
+  /// Restores a population saved by 'save_state', replacing this one. The
+  /// tables must have the shape of this search's.
+  pub fn load_state(&mut self, saved: &toml::Value) -> Result<(), MachineError> {
+    let tables = try!(saved.lookup("tables").and_then(|tables| tables.as_slice())
+                      .ok_or(BadState("Missing search.tables".to_string())));
+    let scores: Vec<f64> = saved.lookup("scores").and_then(|scores| scores.as_slice())
+      .map(|scores| scores.iter().filter_map(|score| score.as_float()).collect())
+      .unwrap_or(Vec::new());
+    if scores.len() != tables.len() {
+      return Err(BadState(format!("Expected {} search scores, found {}", tables.len(), scores.len())));
+    }
+    let len = self.features * self.states as uint * self.symbols as uint;
+    let mut population = Vec::with_capacity(tables.len());
+    for (table, &score) in tables.iter().zip(scores.iter()) {
+      let rows = try!(session::table_rows(table, len, 3));
+      let mut entries: Vec<Transition> = Vec::with_capacity(len);
+      for row in rows.iter() {
+        match Direction::from_index(*row.get(2) as uint) {
+          Some(direction) => entries.push((*row.get(0) as u8, *row.get(1) as u8, direction)),
+          None => return Err(BadTable(format!("Invalid table entry: {}", row))),
+        }
+      }
+      population.push((try!(Table::from_entries(self.states, self.symbols, self.features, entries)), score));
+    }
+    population.truncate(self.size);
+    self.population = population;
+    Ok(())
+  }
+
+  // Scores 'table' and puts it in its place in the population.
+  fn add(&mut self, table: Table) {
+    let score = self.score(&table);
+    let place = self.population.iter().position(|&(_, other)| other < score).unwrap_or(self.population.len());
+    self.population.insert(place, (table, score));
+  }
+
+  // Runs 'table' on the blank scratch machine.
+  fn score(&mut self, table: &Table) -> f64 {
+    let _ = replay::restore(&mut *self.scratch, self.start.as_slice());
+    match table_value(table) {
+      Some(ref value) if self.scratch.load_table(value).is_ok() => interest::score(&mut *self.scratch, self.steps),
+      _ => 0.0,
+    }
+  }
+
+  // A parent, by a tournament of two: the better of two tables picked
+  // uniformly.
+  fn pick(&self, rng: &mut SessionRng) -> uint {
+    let range = Range::new(0, self.population.len());
+    let (a, b) = (range.ind_sample(rng), range.ind_sample(rng));
+    // The population is sorted, best first.
+    ::std::cmp::min(a, b)
+  }
+}
+
+
+// 'table' as the TOML value 'Machine::load_table' takes.
+fn table_value(table: &Table) -> Option<toml::Value> {
+  let parsed: Option<toml::Value> = from_str(format!("table = {}", table.text()).as_slice());
+  parsed.and_then(|parsed| parsed.lookup("table").map(|table| table.clone()))
+}
+
+
+#[cfg(test)]
+mod test {
+  use turing::{TuringMachine, Machine, NoFeature, Cardinal, BLACK, WHITE};
+  use turing::rng::SessionRng;
+
+  use super::Search;
+
+  fn new_search() -> Search {
+    let mut scratch = TuringMachine::new(16, 16, 3, 2, NoFeature, Cardinal, &mut SessionRng::new(0));
+    scratch.set_palette(&vec!(BLACK, WHITE));
+    Search::new(scratch as Box<Machine>, 200, 6, 3, 2, 1, Cardinal, false)
+  }
+
+  #[test]
+  fn generations_never_get_worse() {
+    let mut search = new_search();
+    let mut rng = SessionRng::new(1);
+    search.generation(&mut rng);
+    assert_eq!(search.population.len(), 6);
+    let &(_, first) = search.population.get(0);
+    for _ in range(0u, 3) {
+      search.generation(&mut rng);
+    }
+    let &(_, best) = search.population.get(0);
+    assert!(best >= first);
+  }
+
+  #[test]
+  fn shown_tables_leave_the_population() {
+    let mut search = new_search();
+    let mut rng = SessionRng::new(1);
+    search.generation(&mut rng);
+    let (table, _) = search.take_best().unwrap();
+    assert!(scratch_accepts(&table));
+    assert_eq!(search.population.len(), 5);
+  }
+
+  #[test]
+  fn saved_populations_load_back() {
+    let mut search = new_search();
+    search.generation(&mut SessionRng::new(1));
+    let saved: ::toml::Value = from_str(search.save_state().as_slice()).unwrap();
+    let mut restored = new_search();
+    restored.load_state(saved.lookup("search").unwrap()).unwrap();
+    assert_eq!(restored.save_state(), search.save_state());
+  }
+
+  fn scratch_accepts(table: &::toml::Value) -> bool {
+    let mut machine = TuringMachine::new(4, 4, 3, 2, NoFeature, Cardinal, &mut SessionRng::new(2));
+    machine.load_table(table).is_ok()
+  }
+}
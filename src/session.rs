@@ -0,0 +1,197 @@
+use std::io::{File, IoResult};
+use std::io::fs::rename;
+use toml;
+
+use super::Machine;
+use error::{MachineError, BadTable, BadState, BadFile};
+use rng::{SessionRng, Algorithm, Small};
+
+
+/// The main loop's counters, saved along with the machine so a restored
+/// session continues exactly where it left off.
+pub struct LoopState {
+  pub step: u64,
+  /// Frames in a row without changes (see Stagnation).
+  pub still_frames: u64,
+  pub frame_steps: u64,
+  pub frame_changes: u64,
+  pub picture_steps: u64,
+  pub reset_steps: u64,
+  pub frames: u64,
+  pub total_steps: u64,
+  pub energy: Option<u64>,
+}
+
+
+/// Saves the machine, the generator and the loop counters to 'path', followed
+/// by 'sections', further TOML sections such as a search's population (see
+/// 'read_section'). The file is written next to 'path' first and then renamed
+/// over it, so a crash while saving leaves the previous session intact.
+pub fn save(path: &Path, machine: &Machine, rng: &SessionRng, state: &LoopState, sections: &str)
+    -> IoResult<()> {
+  let temp = path.with_extension("tmp");
+  {
+    let mut file = try!(File::create(&temp));
+    try!(writeln!(file, "[session]"));
+    try!(writeln!(file, "rng = \"{}\"", rng.algorithm().name()));
+    try!(writeln!(file, "seed = {}", rng.seed() as i64));
+    try!(writeln!(file, "draws = {}", rng.draws()));
+    try!(writeln!(file, "step = {}", state.step));
+    try!(writeln!(file, "still_frames = {}", state.still_frames));
+    try!(writeln!(file, "frame_steps = {}", state.frame_steps));
+    try!(writeln!(file, "frame_changes = {}", state.frame_changes));
+    try!(writeln!(file, "picture_steps = {}", state.picture_steps));
+    try!(writeln!(file, "reset_steps = {}", state.reset_steps));
+    try!(writeln!(file, "frames = {}", state.frames));
+    try!(writeln!(file, "total_steps = {}", state.total_steps));
+    match state.energy {
+      Some(energy) => try!(writeln!(file, "energy = {}", energy)),
+      None => {},
+    }
+    try!(writeln!(file, "\n[machine]"));
+    try!(file.write_str(machine.save_state().as_slice()));
+    if !sections.is_empty() {
+      try!(writeln!(file, ""));
+      try!(file.write_str(sections));
+    }
+  }
+  rename(&temp, path)
+}
+
+
+/// Restores a session saved with 'save' into 'machine', which must have the
+/// same kind and dimensions as the saved one.
+pub fn load(path: &Path, machine: &mut Machine) -> Result<(SessionRng, LoopState), MachineError> {
+  let session = try!(read(path));
+  let int = |name: &str| -> Result<i64, MachineError> {
+    session.lookup(format!("session.{}", name).as_slice())
+      .and_then(|value| value.as_integer())
+      .ok_or(BadState(format!("Missing session.{}", name)))
+  };
+
+  // Sessions from before the generator was configurable used 'Small'.
+  let algorithm = match session.lookup("session.rng").and_then(|value| value.as_str()) {
+    Some(name) => try!(Algorithm::from_name(name).ok_or(BadState(format!("Unknown rng: {}", name)))),
+    None => Small,
+  };
+  let rng = SessionRng::restore(algorithm, try!(int("seed")) as u64, try!(int("draws")) as u64);
+  let state = LoopState {
+    step: try!(int("step")) as u64,
+    still_frames: int("still_frames").unwrap_or(0) as u64,
+    frame_steps: try!(int("frame_steps")) as u64,
+    frame_changes: try!(int("frame_changes")) as u64,
+    picture_steps: try!(int("picture_steps")) as u64,
+    reset_steps: try!(int("reset_steps")) as u64,
+    frames: try!(int("frames")) as u64,
+    total_steps: int("total_steps").unwrap_or(0) as u64,
+    energy: int("energy").ok().map(|energy| energy as u64),
+  };
+  try!(load_section(&session, machine));
+  Ok((rng, state))
+}
+
+
+/// Restores only the machine from a saved session, leaving the rest of the
+/// run alone. Used to show saved machines again.
+pub fn load_machine(path: &Path, machine: &mut Machine) -> Result<(), MachineError> {
+  let session = try!(read(path));
+  load_section(&session, machine)
+}
+
+
+/// The section 'name' of a saved session, if it has one.
+pub fn read_section(path: &Path, name: &str) -> Result<Option<toml::Value>, MachineError> {
+  let session = try!(read(path));
+  Ok(session.lookup(name).map(|section| section.clone()))
+}
+
+
+/// Reads a table written by 'Machine::save_table', for 'Machine::load_table'.
+pub fn load_table(path: &Path) -> Result<toml::Value, MachineError> {
+  let file = try!(read(path));
+  match file.lookup("table") {
+    Some(table) => Ok(table.clone()),
+    None => Err(BadTable(format!("Missing table in {}", path.display()))),
+  }
+}
+
+
+fn read(path: &Path) -> Result<toml::Value, MachineError> {
+  let data = match File::open(path).read_to_string() {
+    Ok(data) => data,
+    Err(why) => return Err(BadFile(format!("Unable to read {}: {}", path.display(), why.desc))),
+  };
+  match from_str(data.as_slice()) {
+    Some(session) => Ok(session),
+    None => Err(BadFile(format!("{} is not valid TOML", path.display()))),
+  }
+}
+
+
+fn load_section(session: &toml::Value, machine: &mut Machine) -> Result<(), MachineError> {
+  match session.lookup("machine") {
+    Some(saved) => machine.load_state(saved),
+    None => Err(BadState("Missing [machine] section".to_string())),
+  }
+}
+
+
+/// Encodes tape symbols as a string of two hex digits per cell.
+pub fn encode_tape(tape: &[u8]) -> String {
+  let mut text = String::with_capacity(tape.len() * 2);
+  for &symbol in tape.iter() {
+    text.push_str(format!("{:02x}", symbol).as_slice());
+  }
+  text
+}
+
+
+/// Decodes a tape written by 'encode_tape', checking it has 'len' cells with
+/// symbols below 'symbols'.
+pub fn decode_tape(text: &str, len: uint, symbols: u8) -> Result<Vec<u8>, MachineError> {
+  if text.len() != len * 2 {
+    return Err(BadState(format!("Expected a tape of {} cells, found {}", len, text.len() / 2)));
+  }
+  // Slicing by bytes below needs every character to be one byte.
+  if text.bytes().any(|byte| byte >= 0x80) {
+    return Err(BadState("Invalid characters in tape".to_string()));
+  }
+  let mut tape = Vec::with_capacity(len);
+  for i in range(0, len) {
+    match ::std::num::from_str_radix::<u8>(text.slice(i * 2, i * 2 + 2), 16) {
+      Some(symbol) if symbol < symbols => tape.push(symbol),
+      _ => return Err(BadState(format!("Invalid tape cell {}", i))),
+    }
+  }
+  Ok(tape)
+}
+
+
+/// Looks up the integer 'name' in a saved section.
+pub fn int(saved: &toml::Value, name: &str) -> Result<i64, MachineError> {
+  saved.lookup(name).and_then(|value| value.as_integer()).ok_or(BadState(format!("Missing {}", name)))
+}
+
+
+/// Reads a saved table as 'len' rows of 'columns' integers each.
+pub fn table_rows(table: &toml::Value, len: uint, columns: uint) -> Result<Vec<Vec<i64>>, MachineError> {
+  let rows = match table.as_slice() {
+    Some(rows) => rows,
+    None => return Err(BadTable("Expected the table to be an array".to_string())),
+  };
+  if rows.len() != len {
+    return Err(BadTable(format!("Expected {} table entries, found {}", len, rows.len())));
+  }
+  let mut table = Vec::with_capacity(len);
+  for row in rows.iter() {
+    let values: Vec<i64> = match row.as_slice() {
+      Some(values) => values.iter().filter_map(|value| value.as_integer()).collect(),
+      None => Vec::new(),
+    };
+    if values.len() != columns || values.iter().any(|&value| value < 0 || value > 255) {
+      return Err(BadTable(format!("Invalid table entry: {}", row)));
+    }
+    table.push(values);
+  }
+  Ok(table)
+}
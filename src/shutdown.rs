@@ -0,0 +1,56 @@
+use std::sync::atomic::{AtomicBool, INIT_ATOMIC_BOOL, SeqCst};
+
+use keys;
+
+
+static REQUESTED: AtomicBool = INIT_ATOMIC_BOOL;
+static RELOAD: AtomicBool = INIT_ATOMIC_BOOL;
+
+
+/// Catches SIGTERM and SIGINT so the main loop can stop cleanly at the end of
+/// the current frame (see 'requested'). A second signal exits right away, in
+/// case the loop is stuck, e.g. writing to a reader that went away, after
+/// putting the terminal back (see 'keys::restore_terminal'). SIGHUP
+/// asks for the configuration to be read again (see 'reload_requested').
+#[cfg(unix)]
+pub fn install() {
+  use libc::c_int;
+  // The same on Linux, the BSDs and OS X.
+  static SIGHUP: c_int = 1;
+  static SIGINT: c_int = 2;
+  static SIGTERM: c_int = 15;
+  extern {
+    fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> uint;
+    fn _exit(status: c_int) -> !;
+  }
+  extern "C" fn handle(signum: c_int) {
+    if REQUESTED.swap(true, SeqCst) {
+      keys::restore_terminal();
+      unsafe { _exit(128 + signum); }
+    }
+  }
+  extern "C" fn reload(_signum: c_int) {
+    RELOAD.store(true, SeqCst);
+  }
+  unsafe {
+    signal(SIGINT, handle);
+    signal(SIGTERM, handle);
+    signal(SIGHUP, reload);
+  }
+}
+
+#[cfg(not(unix))]
+pub fn install() {
+}
+
+
+/// Whether a shutdown signal has arrived.
+pub fn requested() -> bool {
+  REQUESTED.load(SeqCst)
+}
+
+
+/// Whether SIGHUP has arrived since the last call.
+pub fn reload_requested() -> bool {
+  RELOAD.swap(false, SeqCst)
+}
@@ -0,0 +1,94 @@
+use std::io::IoResult;
+use toml;
+
+use turing::Machine;
+use turing::error::{Error, MachineError};
+use turing::rng::{SessionRng, Algorithm};
+use png::{write_png, read_text};
+
+
+/// The machine behind a snapshot, as read back from the PNG's tEXt chunks.
+pub struct Snapshot {
+  /// The configuration of the run that took the snapshot.
+  pub config: toml::Value,
+  /// The generator state the machine's table was drawn from.
+  pub seed: u64,
+  pub draws: u64,
+  /// Steps the machine had run when the snapshot was taken.
+  pub step: u64,
+  /// The transition table, as written by 'Machine::table_text'.
+  pub table: toml::Value,
+}
+
+
+/// Writes the current frame of 'machine' as a PNG file that also describes
+/// how to reproduce it: the configuration, the generator seed and the number
+/// of values drawn before the machine's table, the step count and the table.
+pub fn save(path: &Path, machine: &Machine, config: &toml::Value, rng: &SessionRng, draws: u64,
+            step: u64) -> IoResult<()> {
+  let (width, height) = machine.image_size();
+  write_png(path, machine.image(), width, height, &[
+    ("Software", "turing".to_string()),
+    ("turing:config", config.to_string()),
+    ("turing:seed", rng.seed().to_string()),
+    ("turing:draws", draws.to_string()),
+    ("turing:step", step.to_string()),
+    ("turing:table", machine.table_text()),
+  ])
+}
+
+
+/// Reads the machine description from a PNG written by 'save'.
+pub fn load(path: &Path) -> Result<Snapshot, String> {
+  let text = try!(read_text(path));
+  let field = |name: &str| -> Result<String, String> {
+    match text.iter().find(|&&(ref keyword, _)| keyword.as_slice() == name) {
+      Some(&(_, ref value)) => Ok(value.clone()),
+      None => Err(format!("{} has no {} metadata", path.display(), name)),
+    }
+  };
+  let number = |name: &str| -> Result<u64, String> {
+    let value = try!(field(name));
+    from_str(value.as_slice()).ok_or(format!("Invalid {}: {}", name, value))
+  };
+  let config: toml::Value = match from_str(try!(field("turing:config")).as_slice()) {
+    Some(config) => config,
+    None => return Err("Invalid turing:config".to_string()),
+  };
+  // The table is an array, which TOML only allows as a value.
+  let table = match from_str::<toml::Value>(format!("table = {}", try!(field("turing:table"))).as_slice()) {
+    Some(table) => match table.lookup("table") {
+      Some(table) => table.clone(),
+      None => return Err("Invalid turing:table".to_string()),
+    },
+    None => return Err("Invalid turing:table".to_string()),
+  };
+  Ok(Snapshot {
+    config: config,
+    seed: try!(number("turing:seed")),
+    draws: try!(number("turing:draws")),
+    step: try!(number("turing:step")),
+    table: table,
+  })
+}
+
+
+impl Snapshot {
+  /// The generator as it was just before the machine's table was drawn, so
+  /// the machines after it are the same as in the original run too.
+  pub fn rng(&self) -> Result<SessionRng, Error> {
+    Ok(SessionRng::restore(try!(Algorithm::from_config(&self.config)), self.seed, self.draws))
+  }
+
+  /// Sets up 'machine', freshly built from 'config', as it was when the
+  /// snapshot was taken: the saved table run for the saved number of steps.
+  /// Machines that started from a chained or playlist tape come out with the
+  /// right rules but a different picture.
+  pub fn restore(&self, machine: &mut Machine) -> Result<(), MachineError> {
+    try!(machine.load_table(&self.table));
+    for _ in range(0, self.step) {
+      machine.step();
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,274 @@
+use serialize::json::Json;
+use toml;
+
+use super::{Machine, TuringMachine, Color};
+use error::MachineError;
+use rng::SessionRng;
+
+
+/// A picture of the last 'rows' rows of a one dimensional world, oldest at
+/// the top: the frame buffer of the spacetime diagrams. Each row is the
+/// image of the world at one time, all of them the same number of bytes.
+/// Rows keep the colors they were drawn in.
+///
+/// The rows are a ring, so scrolling writes the new row over the oldest
+/// instead of moving the others. Every row is kept twice, 'rows' apart, so
+/// the picture is always the contiguous run of 'rows' rows from the oldest.
+pub struct Scroll {
+  rows: uint,
+  row_bytes: uint,
+  // Two copies of the ring, one after the other.
+  image: Vec<u8>,
+  // The ring index of the oldest row, at the top of the picture.
+  head: uint,
+}
+
+impl Scroll {
+  /// 'rows' copies of 'row', as if it had been there all along.
+  pub fn new(row: &[u8], rows: uint) -> Scroll {
+    let mut scroll = Scroll { rows: rows, row_bytes: row.len(), image: Vec::from_elem(row.len() * rows * 2, 0u8),
+                              head: 0 };
+    scroll.fill(row);
+    scroll
+  }
+
+  /// The number of rows.
+  pub fn rows(&self) -> uint {
+    self.rows
+  }
+
+  /// The rows, top to bottom.
+  pub fn image<'a>(&'a self) -> &'a [u8] {
+    self.image.slice(self.head * self.row_bytes, (self.head + self.rows) * self.row_bytes)
+  }
+
+  /// Draws 'row' in every row.
+  pub fn fill(&mut self, row: &[u8]) {
+    let row_bytes = self.row_bytes;
+    for (i, byte) in self.image.iter_mut().enumerate() {
+      *byte = row[i % row_bytes];
+    }
+    self.head = 0;
+  }
+
+  /// Scrolls every row up one, the oldest off the top, and draws 'row' at
+  /// the bottom.
+  pub fn push(&mut self, row: &[u8]) {
+    // The oldest row's place is the bottom's once the head moves on.
+    self.head = (self.head + 1) % self.rows;
+    self.set_bottom(row);
+  }
+
+  /// Draws 'row' over the bottom row.
+  pub fn set_bottom(&mut self, row: &[u8]) {
+    self.set_bottom_bytes(row, 0, self.row_bytes);
+  }
+
+  /// Draws bytes 'from' to 'to' of 'row' over the same bytes of the bottom
+  /// row.
+  pub fn set_bottom_bytes(&mut self, row: &[u8], from: uint, to: uint) {
+    let bottom = (self.head + self.rows - 1) % self.rows;
+    for &copy in [bottom, bottom + self.rows].iter() {
+      let start = copy * self.row_bytes;
+      let bytes = self.image.as_mut_slice().slice_mut(start + from, start + to);
+      for (byte, &value) in bytes.iter_mut().zip(row.slice(from, to).iter()) {
+        *byte = value;
+      }
+    }
+  }
+}
+
+
+/// A classic one dimensional machine drawn as a spacetime diagram: the tape
+/// is a single row shown at the bottom of the frame, and each frame scrolls
+/// the rows before it up one, so time runs down the picture. The machine is
+/// an ordinary single tape machine on a 'width'x1 tape, which should move
+/// east and west only (see 'DirectionSet::Horizontal').
+pub struct SpacetimeMachine {
+  machine: Box<TuringMachine>,
+  // The machine's image at the bottom, earlier frames' above it.
+  scroll: Scroll,
+}
+
+impl SpacetimeMachine {
+  /// Shows 'machine', which must have a tape one cell high, and the last
+  /// 'rows' - 1 frames before it.
+  pub fn new(machine: Box<TuringMachine>, rows: uint) -> Box<SpacetimeMachine> {
+    let scroll = Scroll::new(machine.image.as_slice(), rows);
+    box SpacetimeMachine { machine: machine, scroll: scroll }
+  }
+
+  // Copies the pixels of the cell at 'position' into the bottom row.
+  fn copy_cell(&mut self, position: uint) {
+    let machine = &*self.machine;
+    // Heads that left a halting tape have no cell.
+    if position >= machine.width {
+      return;
+    }
+    let cell_bytes = machine.cell_width * 3;
+    let line_bytes = machine.width * cell_bytes;
+    for dy in range(0, machine.cell_height) {
+      let from = dy * line_bytes + position * cell_bytes;
+      self.scroll.set_bottom_bytes(machine.image.as_slice(), from, from + cell_bytes);
+    }
+  }
+}
+
+impl Machine for SpacetimeMachine {
+  fn step(&mut self) -> bool {
+    // The cells left and the cells reached are the ones redrawn.
+    let mut positions = self.machine.head_positions();
+    if !self.machine.step() {
+      return false;
+    }
+    positions.push_all(self.machine.head_positions().as_slice());
+    for &position in positions.iter() {
+      self.copy_cell(position);
+    }
+    true
+  }
+
+  // The rows before can't be redrawn in the new colors, so the diagram
+  // starts again from the current row.
+  fn set_palette(&mut self, palette: &Vec<Color>) {
+    self.machine.set_palette(palette);
+    self.scroll.fill(self.machine.image.as_slice());
+  }
+
+  fn image<'a>(&'a self) -> &'a [u8] {
+    self.scroll.image()
+  }
+
+  fn image_size(&self) -> (uint, uint) {
+    let (width, height) = self.machine.image_size();
+    (width, height * self.scroll.rows())
+  }
+
+  fn tape_size(&self) -> (uint, uint) {
+    self.machine.tape_size()
+  }
+
+  fn symbols(&self) -> u8 {
+    self.machine.symbols()
+  }
+
+  fn fill_tape(&mut self, symbol: |uint| -> u8) {
+    self.machine.fill_tape(symbol);
+    self.scroll.fill(self.machine.image.as_slice());
+  }
+
+  fn reset(&mut self, rng: &mut SessionRng) {
+    self.machine.reset(rng);
+    self.scroll.fill(self.machine.image.as_slice());
+  }
+
+  // The diagram carries on with the next machine.
+  fn chain(&mut self, rng: &mut SessionRng) {
+    self.machine.chain(rng);
+    self.scroll.set_bottom(self.machine.image.as_slice());
+  }
+
+  fn mutate(&mut self, rng: &mut SessionRng, count: uint) {
+    self.machine.mutate(rng, count);
+  }
+
+  fn table_text(&self) -> String {
+    self.machine.table_text()
+  }
+
+  fn table_json(&self) -> Json {
+    self.machine.table_json()
+  }
+
+  // Only the machine is saved; the rows before it are redrawn from its row.
+  fn save_state(&self) -> String {
+    self.machine.save_state()
+  }
+
+  fn load_state(&mut self, saved: &toml::Value) -> Result<(), MachineError> {
+    try!(self.machine.load_state(saved));
+    self.scroll.fill(self.machine.image.as_slice());
+    Ok(())
+  }
+
+  fn load_table(&mut self, table: &toml::Value) -> Result<(), MachineError> {
+    self.machine.load_table(table)
+  }
+
+  fn explain_step(&self) -> String {
+    self.machine.explain_step()
+  }
+
+  fn tape_text(&self) -> String {
+    self.machine.tape_text()
+  }
+
+  fn symbols_used(&self) -> uint {
+    self.machine.symbols_used()
+  }
+
+  fn halted(&self) -> bool {
+    self.machine.halted()
+  }
+
+  // The heads are on the bottom row.
+  fn head_cells(&self) -> Vec<(uint, uint, uint, uint)> {
+    let (_, height) = self.machine.image_size();
+    self.machine.head_cells().iter().map(|&(x, y, width, cell_height)| {
+      (x, y + height * (self.scroll.rows() - 1), width, cell_height)
+    }).collect()
+  }
+
+  // Scrolls every row up one, the oldest off the top, leaving the current
+  // row at the bottom to carry on from.
+  fn end_frame(&mut self) {
+    self.machine.end_frame();
+    self.scroll.push(self.machine.image.as_slice());
+  }
+
+  fn local_hash(&self) -> Option<u64> {
+    self.machine.local_hash()
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::super::{Machine, Horizontal, EAST, BLACK, WHITE};
+  use builder::TuringMachineBuilder;
+  use table::Table;
+
+  use super::{SpacetimeMachine, Scroll};
+
+  #[test]
+  fn scrolling_wraps_around_the_ring() {
+    let mut scroll = Scroll::new(&[0u8], 3);
+    for row in range(1u8, 6) {
+      scroll.push(&[row]);
+    }
+    assert_eq!(scroll.image(), [3u8, 4, 5].as_slice());
+    scroll.set_bottom(&[9]);
+    assert_eq!(scroll.image(), [3u8, 4, 9].as_slice());
+    scroll.fill(&[7]);
+    assert_eq!(scroll.image(), [7u8, 7, 7].as_slice());
+  }
+
+  #[test]
+  fn frames_scroll_up_into_a_staircase() {
+    let table = Table::from_entries(1, 2, 1, vec!((0, 1, EAST), (0, 1, EAST))).unwrap();
+    let mut machine = TuringMachineBuilder::new().size(4, 1).states(1).symbols(2).directions(Horizontal).table(table)
+      .build().unwrap();
+    machine.set_palette(&vec!(BLACK, WHITE));
+    let mut spacetime = SpacetimeMachine::new(machine, 3);
+    assert_eq!(spacetime.image_size(), (4, 3));
+    for _ in range(0u, 2) {
+      spacetime.step();
+      spacetime.end_frame();
+    }
+    let white: Vec<bool> = spacetime.image().chunks(3).map(|pixel| pixel[0] == 255).collect();
+    assert_eq!(white, vec!(true, false, false, false,
+                           true, true, false, false,
+                           true, true, false, false));
+    assert_eq!(spacetime.head_cells(), vec!((2, 2, 1, 1)));
+  }
+}
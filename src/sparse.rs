@@ -0,0 +1,260 @@
+use std::cmp::min;
+use std::collections::HashMap;
+
+use super::{Direction, Boundary, Wrap, Topology, STAY, distinct_symbols};
+use neighbors::{bounded_move, OFF_TAPE};
+use tape::Tape;
+
+
+// The side of a chunk in cells.
+static CHUNK: uint = 64;
+
+/// The largest side of a sparse world. Bigger worlds would have cells whose
+/// position is OFF_TAPE.
+pub static MAX_WORLD: uint = 65535;
+
+
+/// A tape that only stores the chunks of cells that have been written, so a
+/// machine can roam a world far bigger than the frame. Moves are worked out
+/// as they happen instead of looked up. Square cells only.
+#[deriving(Show)]
+pub struct SparseTape {
+  width: uint,
+  height: uint,
+  // CHUNK x CHUNK cells each, row by row, by chunk index. Cells of missing
+  // chunks are 0.
+  chunks: HashMap<uint, Vec<u8>>,
+  boundary: Boundary,
+}
+
+impl SparseTape {
+  /// A blank 'width'x'height' torus, at most MAX_WORLD cells a side.
+  pub fn new(width: uint, height: uint) -> SparseTape {
+    SparseTape { width: width, height: height, chunks: HashMap::new(), boundary: Wrap }
+  }
+
+  /// The number of chunks stored.
+  pub fn chunks(&self) -> uint {
+    self.chunks.len()
+  }
+
+  // The index of the chunk holding 'position' and the cell's offset in it.
+  #[inline]
+  fn locate(&self, position: uint) -> (uint, uint) {
+    let (x, y) = (position % self.width, position / self.width);
+    let across = (self.width + CHUNK - 1) / CHUNK;
+    ((y / CHUNK) * across + x / CHUNK, (y % CHUNK) * CHUNK + x % CHUNK)
+  }
+}
+
+impl Tape for SparseTape {
+  fn len(&self) -> uint {
+    self.width * self.height
+  }
+
+  #[inline]
+  fn read(&self, position: uint) -> u8 {
+    let (chunk, offset) = self.locate(position);
+    match self.chunks.find(&chunk) {
+      Some(cells) => *cells.get(offset),
+      None => 0,
+    }
+  }
+
+  #[inline]
+  fn write(&mut self, position: uint, symbol: u8) {
+    let (chunk, offset) = self.locate(position);
+    match self.chunks.find_mut(&chunk) {
+      Some(cells) => {
+        *cells.get_mut(offset) = symbol;
+        return;
+      },
+      None => {},
+    }
+    // Blank cells need no chunk.
+    if symbol != 0 {
+      let mut cells = Vec::from_elem(CHUNK * CHUNK, 0u8);
+      *cells.get_mut(offset) = symbol;
+      self.chunks.insert(chunk, cells);
+    }
+  }
+
+  fn neighbor(&self, position: uint, direction: Direction) -> uint {
+    if direction == STAY {
+      return position;
+    }
+    match direction.diagonal_parts() {
+      Some((vertical, horizontal)) => match bounded_move(position, vertical, self.width, self.height, self.boundary) {
+        OFF_TAPE => OFF_TAPE,
+        position => bounded_move(position, horizontal, self.width, self.height, self.boundary),
+      },
+      None => bounded_move(position, direction, self.width, self.height, self.boundary),
+    }
+  }
+
+  // Topologies other than square are refused by the config.
+  fn set_edges(&mut self, _topology: Topology, boundary: Boundary) {
+    self.boundary = boundary;
+  }
+
+  fn clear(&mut self) {
+    self.chunks.clear();
+  }
+
+  // Only the stored chunks need looking at, and blank cells if any chunk is
+  // missing.
+  fn symbols_used(&self) -> uint {
+    let blank = [0u8];
+    let mut cells: Vec<&[u8]> = self.chunks.values().map(|cells| cells.as_slice()).collect();
+    if self.chunks.len() * CHUNK * CHUNK < self.len() {
+      cells.push(blank.as_slice());
+    }
+    distinct_symbols(cells.as_slice())
+  }
+}
+
+
+/// The part of a world drawn into a 'width'x'height' cell image: a box of
+/// world cells sampled into the image's cells. The box starts as big as the
+/// image in the middle of the world. A fixed view keeps it; a fitted view
+/// doubles it as often as needed to take in every cell written, so the
+/// whole of what the machine drew stays in the picture.
+#[deriving(Show)]
+pub struct View {
+  width: uint,
+  height: uint,
+  world: (uint, uint),
+  fit: bool,
+  // (x, y, width, height) of the box in world cells.
+  shown: (uint, uint, uint, uint),
+}
+
+impl View {
+  /// A view of a 'world' (width, height) world, which must be at least as
+  /// big as the image.
+  pub fn new(width: uint, height: uint, world: (uint, uint), fit: bool) -> View {
+    let mut view = View { width: width, height: height, world: world, fit: fit, shown: (0, 0, 0, 0) };
+    view.reset();
+    view
+  }
+
+  /// The size of the image in cells.
+  pub fn size(&self) -> (uint, uint) {
+    (self.width, self.height)
+  }
+
+  /// Goes back to the starting box.
+  pub fn reset(&mut self) {
+    let (world_width, world_height) = self.world;
+    self.shown = ((world_width - self.width) / 2, (world_height - self.height) / 2, self.width, self.height);
+  }
+
+  /// Takes in the world cell ('x', 'y') just written. Returns true if the box
+  /// grew, so the whole image needs drawing again.
+  pub fn take_in(&mut self, x: uint, y: uint) -> bool {
+    if !self.fit {
+      return false;
+    }
+    let mut grew = false;
+    loop {
+      let (left, top, width, height) = self.shown;
+      if (x >= left && x < left + width && y >= top && y < top + height) || self.covers_world() {
+        return grew;
+      }
+      let (world_width, world_height) = self.world;
+      let (left, width) = grow(left, width, world_width);
+      let (top, height) = grow(top, height, world_height);
+      self.shown = (left, top, width, height);
+      grew = true;
+    }
+  }
+
+  /// The image columns showing world column 'x', as a range.
+  pub fn columns(&self, x: uint) -> (uint, uint) {
+    let (left, _, width, _) = self.shown;
+    shown_at(x, left, width, self.width)
+  }
+
+  /// The image rows showing world row 'y', as a range.
+  pub fn rows(&self, y: uint) -> (uint, uint) {
+    let (_, top, _, height) = self.shown;
+    shown_at(y, top, height, self.height)
+  }
+
+  /// The world cell shown in image cell ('column', 'row').
+  pub fn cell(&self, column: uint, row: uint) -> (uint, uint) {
+    let (left, top, width, height) = self.shown;
+    (left + column * width / self.width, top + row * height / self.height)
+  }
+
+  fn covers_world(&self) -> bool {
+    let (_, _, width, height) = self.shown;
+    (width, height) == self.world
+  }
+}
+
+
+// The span 'start', 'size' of a box doubled about its middle, kept within
+// 'world'.
+fn grow(start: uint, size: uint, world: uint) -> (uint, uint) {
+  let grown = min(size * 2, world);
+  let middle = start + size / 2;
+  let start = if middle < grown / 2 { 0 } else { middle - grown / 2 };
+  (min(start, world - grown), grown)
+}
+
+
+// The image cells sampling world coordinate 'v' of a box 'start', 'size'
+// shown in 'view' image cells. Image cell i shows start + i*size/view.
+fn shown_at(v: uint, start: uint, size: uint, view: uint) -> (uint, uint) {
+  if v < start || v >= start + size {
+    return (0, 0);
+  }
+  let offset = v - start;
+  ((offset * view + size - 1) / size, ((offset + 1) * view + size - 1) / size)
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::super::{Halt, Square, EAST, NORTHWEST};
+  use neighbors::OFF_TAPE;
+  use tape::Tape;
+
+  use super::{SparseTape, View, MAX_WORLD};
+
+  #[test]
+  fn only_written_chunks_are_stored() {
+    let mut tape = SparseTape::new(MAX_WORLD, MAX_WORLD);
+    let far = MAX_WORLD * MAX_WORLD - 1;
+    assert_eq!(tape.read(far), 0);
+    tape.write(far, 3);
+    tape.write(5, 0);
+    assert_eq!((tape.read(far), tape.chunks()), (3, 1));
+    assert_eq!(tape.symbols_used(), 2);
+    assert_eq!(tape.neighbor(far, EAST), far + 1 - MAX_WORLD);
+    assert_eq!(tape.neighbor(0, NORTHWEST), far);
+    tape.set_edges(Square, Halt);
+    assert_eq!(tape.neighbor(far, EAST), OFF_TAPE);
+    tape.clear();
+    assert_eq!((tape.read(far), tape.chunks()), (0, 0));
+  }
+
+  #[test]
+  fn fitted_views_grow_to_take_in_writes() {
+    let mut fixed = View::new(4, 2, (100, 100), false);
+    assert_eq!((fixed.columns(48), fixed.columns(52)), ((0, 1), (0, 0)));
+    assert!(!fixed.take_in(0, 0));
+    let mut fit = View::new(4, 2, (100, 100), true);
+    assert!(!fit.take_in(49, 49));
+    assert!(fit.take_in(53, 50));
+    assert_eq!(fit.shown, (46, 48, 8, 4));
+    // Every other cell is shown.
+    assert_eq!((fit.columns(46), fit.columns(47)), ((0, 1), (1, 1)));
+    assert_eq!(fit.cell(3, 1), (52, 50));
+    assert!(fit.take_in(0, 99));
+    assert_eq!(fit.shown, (0, 0, 100, 100));
+    fit.reset();
+    assert_eq!(fit.shown, (48, 49, 4, 2));
+  }
+}
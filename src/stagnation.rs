@@ -0,0 +1,97 @@
+/// Detects machines that have stopped changing the picture: after 'window'
+/// frames in a row without a single changed pixel the machine is considered
+/// dead and can be replaced early.
+pub struct Stagnation {
+  window: u64,
+  // Frames in a row without changes so far.
+  still_frames: u64,
+}
+
+impl Stagnation {
+  /// A detector for 'window' still frames in a row. A window of 0 never
+  /// reports stagnation.
+  pub fn new(window: u64) -> Stagnation {
+    Stagnation { window: window, still_frames: 0 }
+  }
+
+  /// Accounts for a frame during which 'changes' pixels changed. Returns
+  /// true if the machine is stagnant.
+  pub fn frame(&mut self, changes: u64) -> bool {
+    if changes > 0 {
+      self.still_frames = 0;
+    } else {
+      self.still_frames += 1;
+    }
+    self.window > 0 && self.still_frames >= self.window
+  }
+
+  /// Starts over for a new machine.
+  pub fn reset(&mut self) {
+    self.still_frames = 0;
+  }
+
+  /// The number of still frames in a row so far, for saving sessions.
+  pub fn still_frames(&self) -> u64 {
+    self.still_frames
+  }
+
+  pub fn set_still_frames(&mut self, still_frames: u64) {
+    self.still_frames = still_frames;
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::Stagnation;
+
+  #[test]
+  fn changing_machine_is_not_stagnant() {
+    let mut stagnation = Stagnation::new(1);
+    for _ in range(0u, 100) {
+      assert!(!stagnation.frame(5));
+    }
+  }
+
+  #[test]
+  fn detected_after_window_still_frames() {
+    let mut stagnation = Stagnation::new(3);
+    assert!(!stagnation.frame(10));
+    assert!(!stagnation.frame(0));
+    assert!(!stagnation.frame(0));
+    assert!(stagnation.frame(0));
+  }
+
+  #[test]
+  fn detected_after_the_first_frame() {
+    // The old detector only ever noticed a machine that never changed at all.
+    let mut stagnation = Stagnation::new(1);
+    assert!(!stagnation.frame(1));
+    assert!(stagnation.frame(0));
+  }
+
+  #[test]
+  fn a_change_restarts_the_window() {
+    let mut stagnation = Stagnation::new(2);
+    assert!(!stagnation.frame(0));
+    assert!(!stagnation.frame(1));
+    assert!(!stagnation.frame(0));
+    assert!(stagnation.frame(0));
+  }
+
+  #[test]
+  fn reset_forgets_still_frames() {
+    let mut stagnation = Stagnation::new(2);
+    assert!(!stagnation.frame(0));
+    stagnation.reset();
+    assert!(!stagnation.frame(0));
+  }
+
+  #[test]
+  fn zero_window_disables() {
+    let mut stagnation = Stagnation::new(0);
+    for _ in range(0u, 10) {
+      assert!(!stagnation.frame(0));
+    }
+  }
+}
@@ -0,0 +1,115 @@
+use std::io::stderr;
+use time::precise_time_ns;
+
+
+/// Throughput and frame timing statistics, periodically reported to stderr.
+/// Counters cover the time since the previous report, except 'total_steps'.
+pub struct Stats {
+  interval_ns: u64,
+  last_report: u64,
+  total_steps: u64,
+  steps: u64,
+  frames: u64,
+  render_ns: u64,
+  write_ns: u64,
+  dropped: u64,
+}
+
+impl Stats {
+  /// Creates stats that report every 'interval' seconds.
+  pub fn new(interval: u64) -> Stats {
+    Stats {
+      interval_ns: interval * 1_000_000_000,
+      last_report: precise_time_ns(),
+      total_steps: 0,
+      steps: 0,
+      frames: 0,
+      render_ns: 0,
+      write_ns: 0,
+      dropped: 0,
+    }
+  }
+
+  /// Counts 'steps' machine steps.
+  pub fn steps(&mut self, steps: u64) {
+    self.total_steps += steps;
+    self.steps += steps;
+  }
+
+  /// Continues the total of a resumed session.
+  pub fn set_total_steps(&mut self, total_steps: u64) {
+    self.total_steps = total_steps;
+  }
+
+  /// Counts a frame that took 'render_ns' to render and 'write_ns' to write.
+  pub fn frame(&mut self, render_ns: u64, write_ns: u64) {
+    self.frames += 1;
+    self.render_ns += render_ns;
+    self.write_ns += write_ns;
+  }
+
+  /// Counts 'frames' frames that were rendered but never made it to the
+  /// output.
+  pub fn dropped_frames(&mut self, frames: u64) {
+    self.dropped += frames;
+  }
+
+  /// Prints a report line if the interval has passed since the last one.
+  pub fn maybe_report(&mut self) {
+    let now = precise_time_ns();
+    let elapsed = now - self.last_report;
+    if elapsed < self.interval_ns {
+      return;
+    }
+
+    let _ = writeln!(stderr(), "{}", self.report(elapsed));
+    self.last_report = now;
+    self.steps = 0;
+    self.frames = 0;
+    self.render_ns = 0;
+    self.write_ns = 0;
+    self.dropped = 0;
+  }
+
+  // The report line for counters gathered over 'elapsed_ns'.
+  fn report(&self, elapsed_ns: u64) -> String {
+    let secs = elapsed_ns as f64 / 1e9;
+    let frames = if self.frames == 0 { 1 } else { self.frames } as f64;
+    format!("steps/sec: {:.0}  frames/sec: {:.1}  render ms/frame: {:.2}  write ms/frame: {:.2}  \
+             dropped frames: {}  total steps: {}",
+            self.steps as f64 / secs,
+            self.frames as f64 / secs,
+            self.render_ns as f64 / frames / 1e6,
+            self.write_ns as f64 / frames / 1e6,
+            self.dropped,
+            self.total_steps)
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::Stats;
+
+  #[test]
+  fn report_averages_over_the_interval() {
+    let mut stats = Stats::new(1);
+    stats.set_total_steps(1000);
+    stats.steps(4000);
+    stats.frame(1_000_000, 3_000_000);
+    stats.frame(2_000_000, 5_000_000);
+    stats.dropped_frames(1);
+    assert_eq!(stats.report(2_000_000_000).as_slice(),
+               "steps/sec: 2000  frames/sec: 1.0  render ms/frame: 1.50  write ms/frame: 4.00  \
+                dropped frames: 1  total steps: 5000");
+  }
+
+  #[test]
+  fn report_without_frames() {
+    let mut stats = Stats::new(1);
+    stats.steps(500);
+    assert_eq!(stats.report(1_000_000_000).as_slice(),
+               "steps/sec: 500  frames/sec: 0.0  render ms/frame: 0.00  write ms/frame: 0.00  \
+                dropped frames: 0  total steps: 500");
+  }
+}
@@ -0,0 +1,320 @@
+use std::rand::Rng;
+use std::rand::distributions::{Range, IndependentSample};
+use serialize::json;
+use serialize::json::{Json, ToJson};
+
+use super::{Direction, DirectionSet};
+use error::{MachineError, BadTable};
+use rng::SessionRng;
+
+
+/// A (next_state, write_symbol, move_direction) transition.
+pub type Transition = (u8, u8, Direction);
+
+
+/// The next state of transitions that halt the machine after writing and
+/// moving. Machines have at most 255 states, so it is never a real state.
+pub static HALT: u8 = 255;
+
+
+/// The transition table of a single tape machine.
+///
+/// Entries are stored as [feature][read_symbol][state]: all states for
+/// symbol 0 of feature 0 first, then all states for symbol 1, and so on. This
+/// is the order of the entries in saved machines and 'table_text', so it
+/// must not change.
+#[deriving(Show,Clone)]
+pub struct Table {
+  states: u8,
+  symbols: u8,
+  features: uint,
+  entries: Vec<Transition>,
+}
+
+impl Table {
+  /// A table with every entry drawn uniformly from 'rng', moving in
+  /// 'directions'.
+  pub fn random(rng: &mut SessionRng, states: u8, symbols: u8, features: uint,
+                directions: DirectionSet) -> Table {
+    let state_range = Range::new(0, states);
+    let symbol_range = Range::new(0, symbols);
+    let entries = Vec::from_fn(features * states as uint * symbols as uint, |_| {
+      (state_range.ind_sample(rng), symbol_range.ind_sample(rng), directions.random(rng))
+    });
+    Table { states: states, symbols: symbols, features: features, entries: entries }
+  }
+
+  /// Like 'random', but each entry's next state may also be HALT, as likely
+  /// as any other state.
+  pub fn random_halting(rng: &mut SessionRng, states: u8, symbols: u8, features: uint,
+                        directions: DirectionSet) -> Table {
+    let state_range = Range::new(0u, states as uint + 1);
+    let symbol_range = Range::new(0, symbols);
+    let entries = Vec::from_fn(features * states as uint * symbols as uint, |_| {
+      let next_state = match state_range.ind_sample(rng) {
+        next_state if next_state == states as uint => HALT,
+        next_state => next_state as u8,
+      };
+      (next_state, symbol_range.ind_sample(rng), directions.random(rng))
+    });
+    Table { states: states, symbols: symbols, features: features, entries: entries }
+  }
+
+  /// A table from entries in storage order. Fails with 'BadTable' unless
+  /// there is exactly one valid entry for every (feature, symbol, state).
+  pub fn from_entries(states: u8, symbols: u8, features: uint, entries: Vec<Transition>)
+      -> Result<Table, MachineError> {
+    let len = features * states as uint * symbols as uint;
+    if entries.len() != len {
+      return Err(BadTable(format!("Expected {} table entries, found {}", len, entries.len())));
+    }
+    for &(next_state, write_symbol, direction) in entries.iter() {
+      if (next_state >= states && next_state != HALT) || write_symbol >= symbols {
+        return Err(BadTable(format!("Invalid table entry: [{}, {}, {}]", next_state, write_symbol,
+                                    direction as uint)));
+      }
+    }
+    Ok(Table { states: states, symbols: symbols, features: features, entries: entries })
+  }
+
+  /// The position of the entry for ('feature', 'state', 'symbol') in storage
+  /// order.
+  #[inline]
+  pub fn index(&self, feature: uint, state: u8, symbol: u8) -> uint {
+    (feature * self.symbols as uint + symbol as uint) * self.states as uint + state as uint
+  }
+
+  /// The transition taken in 'state' reading 'symbol' with position feature
+  /// value 'feature'.
+  #[inline]
+  pub fn get(&self, feature: uint, state: u8, symbol: u8) -> Transition {
+    *self.entries.get(self.index(feature, state, symbol))
+  }
+
+  pub fn set(&mut self, feature: uint, state: u8, symbol: u8, transition: Transition) {
+    let index = self.index(feature, state, symbol);
+    *self.entries.get_mut(index) = transition;
+  }
+
+  /// All entries in storage order.
+  pub fn entries<'a>(&'a self) -> &'a [Transition] {
+    self.entries.as_slice()
+  }
+
+  pub fn len(&self) -> uint {
+    self.entries.len()
+  }
+
+  pub fn features(&self) -> uint {
+    self.features
+  }
+
+  /// The entries in storage order as an array of [next_state, write_symbol,
+  /// direction] arrays, which is valid as both TOML and JSON.
+  pub fn text(&self) -> String {
+    let entries: Vec<String> = self.entries.iter().map(|&(next_state, write_symbol, direction)| {
+      format!("[{}, {}, {}]", next_state, write_symbol, direction as uint)
+    }).collect();
+    format!("[{}]", entries.connect(", "))
+  }
+
+  /// Redraws one entry picked uniformly from 'rng', the way 'random' (or
+  /// 'random_halting' with 'halting') draws entries.
+  pub fn mutate(&mut self, rng: &mut SessionRng, directions: DirectionSet, halting: bool) {
+    let index = Range::new(0, self.entries.len()).ind_sample(rng);
+    let next_states = self.states as uint + if halting { 1 } else { 0 };
+    let next_state = match Range::new(0u, next_states).ind_sample(rng) {
+      next_state if next_state == self.states as uint => HALT,
+      next_state => next_state as u8,
+    };
+    let entry = (next_state, Range::new(0, self.symbols).ind_sample(rng), directions.random(rng));
+    *self.entries.get_mut(index) = entry;
+  }
+
+  /// A child of this table and 'other' taking each entry from one or the
+  /// other, as 'rng' picks. None unless both have the same states, symbols
+  /// and features.
+  pub fn crossover(&self, other: &Table, rng: &mut SessionRng) -> Option<Table> {
+    if (self.states, self.symbols, self.features) != (other.states, other.symbols, other.features) {
+      return None;
+    }
+    let entries = self.entries.iter().zip(other.entries.iter()).map(|(&mine, &theirs)| {
+      if rng.gen::<bool>() { mine } else { theirs }
+    }).collect();
+    Some(Table { states: self.states, symbols: self.symbols, features: self.features, entries: entries })
+  }
+}
+
+// A table is shown as its entries, like 'text'; reading it back needs the
+// machine's states, symbols and features (see 'from_entries').
+impl ToJson for Table {
+  fn to_json(&self) -> Json {
+    json::List(self.entries.iter().map(|&(next_state, write_symbol, direction)| {
+      json::List(vec!(json::U64(next_state as u64), json::U64(write_symbol as u64), json::U64(direction as u64)))
+    }).collect())
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use quickcheck::{quickcheck, TestResult};
+
+  use super::{Table, HALT};
+  use super::super::{Direction, TuringMachine, Machine, NoFeature, Parity, Cardinal, AllDirections};
+  use rng::SessionRng;
+
+  // A table whose entry for every (feature, state, symbol) is different:
+  // it names its own state and symbol.
+  fn labelled(states: u8, symbols: u8, features: uint) -> Table {
+    let mut entries = Vec::new();
+    for feature in range(0, features) {
+      for symbol in range(0, symbols) {
+        for state in range(0, states) {
+          entries.push((state, symbol, Direction::from_index(feature % 4).unwrap()));
+        }
+      }
+    }
+    Table::from_entries(states, symbols, features, entries).unwrap()
+  }
+
+  #[test]
+  fn halting_tables_go_to_halt() {
+    let table = Table::random_halting(&mut SessionRng::new(2), 3, 4, 1, Cardinal);
+    assert!(table.entries().iter().any(|&(next_state, _, _)| next_state == HALT));
+    assert!(table.entries().iter().all(|&(next_state, _, _)| next_state < 3 || next_state == HALT));
+    assert!(Table::from_entries(3, 4, 1, table.entries().to_vec()).is_ok());
+  }
+
+  #[test]
+  fn mutants_and_children_stay_valid() {
+    let mut rng = SessionRng::new(3);
+    let parent = Table::random(&mut rng, 3, 4, 1, Cardinal);
+    let mut mutant = parent.clone();
+    for _ in range(0u, 20) {
+      mutant.mutate(&mut rng, Cardinal, false);
+    }
+    assert!(Table::from_entries(3, 4, 1, mutant.entries().to_vec()).is_ok());
+    assert!(mutant.entries() != parent.entries());
+    let child = parent.crossover(&mutant, &mut rng).unwrap();
+    assert!(child.entries().iter().enumerate().all(|(i, entry)| {
+      *entry == parent.entries()[i] || *entry == mutant.entries()[i]
+    }));
+    assert!(parent.crossover(&Table::random(&mut rng, 2, 4, 1, Cardinal), &mut rng).is_none());
+  }
+
+  #[test]
+  fn indexes_are_distinct_and_in_range() {
+    let table = labelled(5, 7, 4);
+    let mut seen = Vec::from_elem(table.len(), false);
+    for feature in range(0u, 4) {
+      for state in range(0u8, 5) {
+        for symbol in range(0u8, 7) {
+          let index = table.index(feature, state, symbol);
+          assert!(index < table.len());
+          assert!(!seen[index]);
+          seen[index] = true;
+        }
+      }
+    }
+    assert!(seen.iter().all(|&seen| seen));
+  }
+
+  #[test]
+  fn get_returns_the_entry_for_every_pair() {
+    let table = labelled(5, 7, 4);
+    for feature in range(0u, 4) {
+      for state in range(0u8, 5) {
+        for symbol in range(0u8, 7) {
+          let (next_state, write_symbol, direction) = table.get(feature, state, symbol);
+          assert_eq!((next_state, write_symbol), (state, symbol));
+          assert_eq!(direction as uint, feature % 4);
+        }
+      }
+    }
+  }
+
+  #[test]
+  fn from_entries_rejects_bad_tables() {
+    assert!(Table::from_entries(2, 2, 1, vec!((0, 0, Direction::from_index(0).unwrap()))).is_err());
+    let too_big_state = Vec::from_elem(4, (2u8, 0u8, Direction::from_index(0).unwrap()));
+    assert!(Table::from_entries(2, 2, 1, too_big_state).is_err());
+    let too_big_symbol = Vec::from_elem(4, (0u8, 2u8, Direction::from_index(0).unwrap()));
+    assert!(Table::from_entries(2, 2, 1, too_big_symbol).is_err());
+  }
+
+  // Every (state, symbol) pair, set up on the machine, makes 'step' take
+  // that pair's entry: write it, move as it says and enter its next state.
+  #[test]
+  fn step_uses_the_entry_for_every_pair() {
+    let (states, symbols) = (3u8, 4u8);
+    let mut machine = TuringMachine::new(8, 8, states, symbols, NoFeature, Cardinal, &mut SessionRng::new(1));
+    let mut entries = Vec::new();
+    for symbol in range(0, symbols) {
+      for state in range(0, states) {
+        entries.push(((state + 1) % states, (symbol + 1) % symbols, Direction::from_index(1).unwrap()));
+      }
+    }
+    machine.table = Table::from_entries(states, symbols, 1, entries).unwrap();
+
+    for state in range(0, states) {
+      for symbol in range(0, symbols) {
+        machine.position = 9;
+        machine.state = state;
+        machine.tape.write(9, symbol);
+        machine.step();
+        assert_eq!(machine.tape.read(9), (symbol + 1) % symbols);
+        assert_eq!(machine.state, (state + 1) % states);
+        // EAST
+        assert_eq!(machine.position, 10);
+      }
+    }
+  }
+
+  #[test]
+  fn step_uses_the_feature_block() {
+    let mut machine = TuringMachine::new(8, 8, 1, 2, Parity, Cardinal, &mut SessionRng::new(1));
+    machine.table = labelled(1, 2, 2);
+    // (1, 0) has odd parity, so feature 1: write symbol 0, move EAST.
+    machine.position = 1;
+    machine.step();
+    assert_eq!(machine.position, 2);
+  }
+
+  #[test]
+  fn random_tables_index_in_range() {
+    fn prop(states: u8, symbols: u8, features: u8, seed: u64) -> TestResult {
+      if states == 0 || symbols == 0 {
+        return TestResult::discard();
+      }
+      let features = features as uint % 4 + 1;
+      let table = Table::random(&mut SessionRng::new(seed), states, symbols, features, AllDirections);
+      if table.len() != features * states as uint * symbols as uint {
+        return TestResult::failed();
+      }
+      // The corners of the index space are enough, since the index is
+      // increasing in every argument.
+      let last = table.index(features - 1, states - 1, symbols - 1);
+      TestResult::from_bool(table.index(0, 0, 0) == 0 && last == table.len() - 1
+        && table.entries().iter().all(|&(next_state, write_symbol, _)| {
+          next_state < states && write_symbol < symbols
+        }))
+    }
+    quickcheck(prop as fn(u8, u8, u8, u64) -> TestResult);
+  }
+
+  #[test]
+  fn random_machines_stay_valid() {
+    fn prop(width: u8, height: u8, states: u8, symbols: u8, seed: u64) -> bool {
+      let (width, height) = (width as uint % 16 + 1, height as uint % 16 + 1);
+      let (states, symbols) = (states % 8 + 1, symbols % 8 + 1);
+      let mut machine = TuringMachine::new(width, height, states, symbols, Parity, Cardinal, &mut SessionRng::new(seed));
+      range(0u, 500).all(|_| {
+        machine.step();
+        machine.position < width * height && machine.state < states
+          && machine.tape().iter().all(|&symbol| symbol < symbols)
+      })
+    }
+    quickcheck(prop as fn(u8, u8, u8, u8, u64) -> bool);
+  }
+}
@@ -0,0 +1,149 @@
+use std::fmt::Show;
+use std::slice::bytes::copy_memory;
+
+use super::{Direction, Boundary, Topology, NORTH, EAST, SOUTH, WEST, distinct_symbols};
+use neighbors::{NeighborTable, OFF_TAPE};
+
+
+/// The cells of a single tape machine and how heads move between them.
+/// Positions are indexes y*width + x whatever the storage, so 'step' and the
+/// renderer work the same on any tape.
+pub trait Tape: Show {
+  /// The number of cells.
+  fn len(&self) -> uint;
+
+  /// The symbol in the cell at 'position'.
+  fn read(&self, position: uint) -> u8;
+
+  fn write(&mut self, position: uint, symbol: u8);
+
+  /// The cell reached by moving from 'position' in 'direction', or OFF_TAPE
+  /// for moves off a tape with a 'Halt' boundary.
+  fn neighbor(&self, position: uint, direction: Direction) -> uint;
+
+  /// Sets how the cells touch and what heads do at the edges.
+  fn set_edges(&mut self, topology: Topology, boundary: Boundary);
+
+  /// Sets every cell to symbol 0.
+  fn clear(&mut self);
+
+  /// The symbol in each cell, row by row.
+  fn cells(&self) -> Vec<u8> {
+    range(0, self.len()).map(|position| self.read(position)).collect()
+  }
+
+  /// Copies the symbol in each cell, row by row, into 'cells', which has a
+  /// place for every cell.
+  fn copy_cells(&self, cells: &mut [u8]) {
+    for (position, cell) in cells.iter_mut().enumerate() {
+      *cell = self.read(position);
+    }
+  }
+
+  /// How many different symbols are on the tape.
+  fn symbols_used(&self) -> uint {
+    distinct_symbols(&[self.cells().as_slice()])
+  }
+
+  /// Replaces every cell with 'cells', row by row.
+  fn load(&mut self, cells: &[u8]) {
+    for (position, &symbol) in cells.iter().enumerate() {
+      self.write(position, symbol);
+    }
+  }
+}
+
+
+/// The symbols of the cell at 'position' and its four cardinal neighbors,
+/// 255 off the tape. See 'NeighborTable::window'.
+pub fn window(tape: &Tape, position: uint) -> [u8, ..5] {
+  let cell = |direction: Direction| match tape.neighbor(position, direction) {
+    OFF_TAPE => 255,
+    neighbor => tape.read(neighbor),
+  };
+  [tape.read(position), cell(NORTH), cell(EAST), cell(SOUTH), cell(WEST)]
+}
+
+
+/// A tape storing every cell, with moves looked up in a 'NeighborTable'.
+/// Fast, and the default.
+#[deriving(Show)]
+pub struct DenseTape {
+  width: uint,
+  height: uint,
+  cells: Vec<u8>,
+  neighbors: NeighborTable,
+}
+
+impl DenseTape {
+  /// A blank square 'width'x'height' torus.
+  pub fn new(width: uint, height: uint) -> DenseTape {
+    DenseTape { width: width, height: height, cells: Vec::from_elem(width * height, 0u8),
+                neighbors: NeighborTable::torus(width, height) }
+  }
+}
+
+impl Tape for DenseTape {
+  fn len(&self) -> uint {
+    self.cells.len()
+  }
+
+  #[inline]
+  fn read(&self, position: uint) -> u8 {
+    *self.cells.get(position)
+  }
+
+  #[inline]
+  fn write(&mut self, position: uint, symbol: u8) {
+    *self.cells.get_mut(position) = symbol;
+  }
+
+  #[inline]
+  fn neighbor(&self, position: uint, direction: Direction) -> uint {
+    self.neighbors.get(position, direction)
+  }
+
+  fn set_edges(&mut self, topology: Topology, boundary: Boundary) {
+    self.neighbors = topology.neighbors(self.width, self.height, boundary);
+  }
+
+  fn clear(&mut self) {
+    for cell in self.cells.iter_mut() {
+      *cell = 0;
+    }
+  }
+
+  fn cells(&self) -> Vec<u8> {
+    self.cells.clone()
+  }
+
+  fn copy_cells(&self, cells: &mut [u8]) {
+    copy_memory(cells, self.cells.as_slice());
+  }
+
+  fn load(&mut self, cells: &[u8]) {
+    self.cells = cells.to_vec();
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::super::{Square, Reflect, EAST, WEST};
+  use super::{Tape, DenseTape, window};
+
+  #[test]
+  fn dense_tapes_read_back_and_move() {
+    let mut tape = DenseTape::new(3, 2);
+    tape.write(4, 7);
+    assert_eq!(tape.read(4), 7);
+    assert_eq!(tape.cells(), vec!(0, 0, 0, 0, 7, 0));
+    assert_eq!(tape.neighbor(2, EAST), 0);
+    assert_eq!(window(&tape as &Tape, 1), [0, 7, 0, 7, 0]);
+    tape.set_edges(Square, Reflect);
+    assert_eq!(tape.neighbor(2, EAST), 1);
+    assert_eq!(tape.neighbor(3, WEST), 4);
+    tape.clear();
+    assert_eq!(tape.cells(), Vec::from_elem(6, 0u8));
+  }
+}
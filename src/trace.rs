@@ -0,0 +1,43 @@
+use std::io::IoResult;
+
+use super::Machine;
+
+
+/// Runs 'machine' for 'steps' steps, printing each transition in words and
+/// the tape after it. Meant for teaching with small grids.
+pub fn run(machine: &mut Machine, steps: uint, out: &mut Writer) -> IoResult<()> {
+  try!(writeln!(out, "Initial tape:\n{}", machine.tape_text()));
+  for step in range(1, steps + 1) {
+    try!(writeln!(out, "Step {}: {}", step, machine.explain_step()));
+    machine.step();
+    try!(writeln!(out, "{}", machine.tape_text()));
+  }
+  Ok(())
+}
+
+
+/// Draws a tape as a grid of symbols, one character per symbol, with the
+/// cells under a head in brackets.
+pub fn grid_text(tape: &[u8], width: uint, heads: &[uint]) -> String {
+  let mut text = String::new();
+  for (i, &symbol) in tape.iter().enumerate() {
+    let head = heads.contains(&i);
+    text.push(if head { '[' } else { ' ' });
+    text.push(symbol_char(symbol));
+    text.push(if head { ']' } else { ' ' });
+    if i % width == width - 1 {
+      text.push('\n');
+    }
+  }
+  text
+}
+
+
+// Symbols are shown as 0-9, then a-z, then '?' for anything larger.
+fn symbol_char(symbol: u8) -> char {
+  match symbol {
+    0...9 => (b'0' + symbol) as char,
+    10...35 => (b'a' + symbol - 10) as char,
+    _ => '?',
+  }
+}
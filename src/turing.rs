@@ -1,180 +1,202 @@
-extern crate rand; 
+extern crate flate;
+extern crate libc;
+extern crate serialize;
+extern crate time;
 extern crate toml;
+extern crate turing;
+#[cfg(feature = "window")]
+extern crate sdl2;
 
-use std::rand::Rng;
-use std::rand::distributions::{Range, IndependentSample};
-use std::iter::count;
-
-#[deriving(PartialEq,Eq,PartialOrd,Ord,Show,Rand)]
-enum Direction {
-  NORTH,
-  EAST,
-  SOUTH,
-  WEST,
-
-  // TODO: consider other directions:
-  //STAY,
-  //NORTHEAST,
-  //NORTHWEST,
-  //SOUTHEAST,
-  //SOUTHWEST,
-}
-
-
-// Colors defined as arrays of [R,G,B].
-type Color = [u8, .. 3];
-static BLACK: Color = [0,0,0];
-static WHITE: Color = [255,255,255];
-static LIGHT_GRAY: Color = [170,170,170];
-static GRAY: Color = [85,85,85];
-static RED: Color = [255,0,0];
-static GREEN: Color = [0,255,0];
-static BLUE: Color = [0,0,255];
-static CYAN: Color = [0,255,255];
-static MAGENTA: Color = [255,0,255];
-static YELLOW: Color = [255,255,0];
-
-
-/// A finite 2D turing machine definition.
-/// - The 'tape' has a size of 'width'*'height'.
-/// - There is a current 'position' within the tape.
-/// - There are 'states' possible states for the machine.
-/// - There are 'symbols' possible symbols at each position.
-/// - The table defines transitions. It is a 2D table. Given the current state
-///   and the current symbol it gives the next state, the symbol to write, and
-///   the direction to move.
-#[deriving(Show)]
-struct TuringMachine {
-  width: uint,
-  height: uint,
-  states: u8,
-  symbols: u8,
-  position: uint,
-  state: u8,
-  // transition [curr_state, read_symbol] -> [next_state, write_symbol, move_direction]
-  table: Vec<(u8, u8, Direction)>,
-  tape: Vec<u8>,
-
-  // Memory for writing raw image into. Optimization.
-  image: Vec<u8>,
-}
-
-impl TuringMachine {
-  pub fn new(width: uint, height: uint, states: u8, symbols: u8) -> Box<TuringMachine> {
-    box TuringMachine {
-      width: width,
-      height: height,
-      states: states,
-      symbols: symbols,
-      position: 0,
-      state: 0,
-      table: TuringMachine::random_table(states, symbols),
-      tape: Vec::from_elem(width * height, 0u8),
-      image: Vec::from_elem(width * height * 3, 0u8),
-    }
-  }
+use std::io::USER_RWX;
+use std::io::fs::{PathExtensions, mkdir_recursive};
+use std::io::timer::sleep;
+use std::time::Duration;
+use serialize::json;
 
-  fn random_table(states: u8, symbols: u8) -> Vec<(u8, u8, Direction)> {
-    let mut rng = std::rand::task_rng();
-    let state_range = Range::new(0, states);
-    let symbol_range = Range::new(0, symbols);
-    Vec::from_fn((states*symbols) as uint, |_| {
-      (state_range.ind_sample(&mut rng), symbol_range.ind_sample(&mut rng), rng.gen::<Direction>())
-    })
-  }
+use turing::{Machine, Color, PositionFeature, NoFeature, Parity, Quadrant};
+use turing::{DirectionSet, Cardinal, EightWay, AllDirections, Horizontal, HeadCollision, Sequential, Wait};
+use turing::{Boundary, Wrap, Reflect, Clamp, Halt, Hexagonal, Square, Hex, RenderMode, Symbols, Heatmap,
+              Trails, States};
+use turing::{BLACK, WHITE, LIGHT_GRAY, GRAY, RED, GREEN, BLUE, CYAN, MAGENTA, YELLOW};
+use turing::builder::TuringMachineBuilder;
+use turing::eca::EcaMachine;
+use turing::hybrid::{HybridMachine, LifeRule};
+use turing::error::{Error, Usage, invalid};
+use turing::interleave::{Interleaved, Stripes, Checkerboard};
+use turing::montage::Montage;
+use turing::rng::{SessionRng, Algorithm};
+use turing::session::LoopState;
+use turing::spacetime::SpacetimeMachine;
+use turing::sparse::MAX_WORLD;
+use turing::turmite::TurmiteMachine;
+use turing::two_tape::{TwoTapeMachine, SideBySide, Combined};
+use turing::{palette, preset, session, trace};
+use adjust::ColorAdjust;
+use background::BackgroundOutputs;
+use blend::FrameBlend;
+use control::{Control, Pause, Resume, Reset, SetPictureSteps, SetResetSteps, Snapshot, SaveTable, GetTable,
+              Status, StatusReply, TableReply};
+use cycle::CycleDetector;
+use event_log::EventLog;
+use hall_of_fame::HallOfFame;
+use events::EventStream;
+use interest::Interest;
+use kiosk::Kiosk;
+use marker::{HeadMarker, Block, Crosshair};
+use output::{Outputs, Output, RawSink, PixelFormat, PngSink, ImageSink, reopenable};
+use playlist::{Playlist, Shown};
+use preview::TerminalPreview;
+use replay::Highlight;
+use reset_log::ResetLog;
+use runner::{Runner, FrameSink, StepLimit, Energy};
+use search::Search;
+use stagnation::Stagnation;
+use stats::Stats;
+use viewport::Viewport;
 
-  // Return true if this step changed a pixel.
-  fn step(&mut self) -> bool {
-    let curr_symbol = *self.tape.get(self.position);
-    let (next_state, write_symbol, move_direction) =
-      *self.table.get((self.states*curr_symbol + self.state) as uint);
-    *self.tape.get_mut(self.position) = write_symbol;
+mod adjust;
+mod background;
+mod batch;
+mod bench;
+mod blend;
+mod breed;
+mod config;
+mod control;
+mod cycle;
+mod editor;
+mod event_log;
+mod events;
+#[cfg(test)]
+mod fuzz;
+mod gif;
+mod grpc;
+mod hall_of_fame;
+mod image;
+mod interest;
+mod jpeg;
+mod keys;
+mod kiosk;
+mod marker;
+mod memory;
+mod mjpeg;
+mod output;
+mod platform;
+mod playlist;
+mod png;
+mod preview;
+mod replay;
+mod reset_log;
+mod runner;
+mod search;
+mod shutdown;
+mod snapshot;
+mod stagnation;
+mod stats;
+mod verify;
+mod viewport;
+mod window;
 
-    // Return whether this changes the picture or not.
-    let ret = write_symbol != curr_symbol;
 
-    self.state = next_state;
-    let mut x: uint = self.position % self.width;
-    let mut y: uint = self.position / self.width;
-    match move_direction {
-      NORTH => {
-        y = if y == 0 { self.height-1 } else { y-1 };
-      },
-      EAST => {
-        x += 1;
-        if x >= self.width { x = 0; }
-      },
-      SOUTH => {
-        y += 1;
-        if y >= self.height { y = 0; }
-      },
-      WEST => {
-        x = if x == 0 { self.width-1 } else { x-1 };
-      },
-    }
-    self.position = y*self.width + x;
+/// Adjusts the number of steps between frames so that roughly 'target' pixels
+/// change from one frame to the next, within ['min', 'max'].
+struct AdaptiveSteps {
+  min: u64,
+  max: u64,
+  target: u64,
+}
 
-    return ret;
+impl AdaptiveSteps {
+  // The step count for the next frame given the last frame's steps and changes.
+  fn next(&self, steps: u64, changes: u64) -> u64 {
+    // Saturate rather than overflow for huge step counts.
+    let wanted = if changes == 0 {
+      self.max
+    } else {
+      steps.checked_mul(&self.target).map_or(self.max, |product| product / changes)
+    };
+    // Only move halfway towards the wanted value to avoid oscillating.
+    let next = steps.checked_add(&wanted).map_or(steps / 2 + wanted / 2, |sum| sum / 2);
+    std::cmp::max(self.min, std::cmp::min(self.max, next))
   }
+}
 
-  /// Writes the current state as an image (bgr24 since that's what vlc seems to expect).
-  fn write_image<W: Writer>(&mut self, palette: &Vec<Color>, out: &mut Box<W>) -> std::io::IoResult<()> {
-    // Direct to stdout. Slow.
-    /*
-    for &val in self.tape.iter() {
-      let color = palette.get(val as uint);
-      try!(out.write_u8(color[0]));
-      try!(out.write_u8(color[1]));
-      try!(out.write_u8(color[2]));
-    }
-    */
-
-    // Alternative seems quicker, but still not fast enough:
-    /*
-    let len = (self.width as uint) * (self.height as uint) * 3;
-    let mut image = Vec::with_capacity(len);
-    for &val in self.tape.iter() {
-      let color = palette.get(val as uint);
-      image.push(color[0]);
-      image.push(color[1]);
-      image.push(color[2]);
-    }
-    try!(out.write(image.as_slice()));
-    */
-
-    // Upfront allocation. Faster, but still not fast enough at higher resolutions.
-    // Requires adding 'image: Vec<u8>' on the struct.
-    for (&val, x) in self.tape.iter().zip(count(0u,3)) {
-      let [r, g, b] = *palette.get(val as uint);
-      *self.image.get_mut(x+2) = r;
-      *self.image.get_mut(x+1) = g;
-      *self.image.get_mut(x+0) = b;
-    }
-    try!(out.write(self.image.as_slice()))
 
-    try!(out.flush());
-    Ok(())
+/// Paces frames to a target rate of 'interval' nanoseconds per frame. A
+/// frame that is ready early waits for its time; one that is late is shown
+/// at once, and the frame after it is due an interval later rather than
+/// rushed to catch up. With 'adapt', the steps between frames are instead
+/// adjusted so computing a frame takes about an interval, within ['min',
+/// 'max'].
+struct FramePacing {
+  interval: u64,
+  adapt: bool,
+  min: u64,
+  max: u64,
+  // When the next frame is due, and when the steps for it started.
+  due: u64,
+  started: u64,
+}
+
+impl FramePacing {
+  fn new(fps: u64, adapt: bool, min: u64, max: u64, now: u64) -> FramePacing {
+    let interval = 1000000000 / fps;
+    FramePacing { interval: interval, adapt: adapt, min: min, max: max, due: now + interval, started: now }
+  }
+
+  // Accounts for a frame ready at 'now' after 'steps' steps. Returns the
+  // step count for the next frame and how long to wait before showing this
+  // one, in nanoseconds.
+  fn frame(&mut self, steps: u64, now: u64) -> (u64, u64) {
+    // Sleeps can end a little early.
+    let busy = if now > self.started { now - self.started } else { 0 };
+    let next = if self.adapt {
+      let wanted = if busy == 0 {
+        self.max
+      } else {
+        steps.checked_mul(&self.interval).map_or(self.max, |product| product / busy)
+      };
+      // Halfway, like 'AdaptiveSteps', to ride out noisy timings.
+      let next = steps.checked_add(&wanted).map_or(steps / 2 + wanted / 2, |sum| sum / 2);
+      std::cmp::max(self.min, std::cmp::min(self.max, next))
+    } else {
+      steps
+    };
+    let shown = std::cmp::max(now, self.due);
+    self.started = shown;
+    self.due = shown + self.interval;
+    (next, shown - now)
   }
 }
 
 
-fn load_config() -> toml::Value {
-  let path = Path::new("turing.toml");
-  let mut file = std::io::File::open(&path);
-  let data = match file.read_to_str() {
-    Err(why) => fail!("Unable to read config file: {}", why.desc),
-    Ok(str) => str,
-  };
-  from_str(data.as_slice()).unwrap()
+/// Lowers the scheduling priority of the process by 'increment' (like nice(1))
+/// so a long running instance doesn't compete with foreground work.
+#[cfg(unix)]
+fn lower_priority(increment: i64) {
+  extern {
+    fn nice(inc: libc::c_int) -> libc::c_int;
+  }
+  unsafe {
+    nice(increment as libc::c_int);
+  }
+}
+
+#[cfg(not(unix))]
+fn lower_priority(_increment: i64) {
 }
 
 
-// These colors correspond to the symbols. Having more symbols than colors will
-// result in an error.
+// These colors correspond to the symbols: generated ones with 'turing.palette
+// = "generated"', otherwise the [palette] section's colors or the curated
+// ones. Symbols beyond those get generated colors, or repeated ones with
+// 'turing.palette = "cycled"'.
 fn load_palette(config: &toml::Value) -> Vec<Color> {
-  // TODO: Consider randomized colors, palette from config, or some way of
-  // ensuring enough colors.
-  let palette = vec!( 
+  let symbols = get(config, "turing.symbols") as u8;
+  let fill = get_str_or(config, "turing.palette", "curated");
+  if fill.as_slice() == "generated" {
+    return palette::evenly_spaced(symbols as uint);
+  }
+  let curated = vec!(
     BLACK,
     RED,
     GREEN,
@@ -186,60 +208,1393 @@ fn load_palette(config: &toml::Value) -> Vec<Color> {
     LIGHT_GRAY,
     GRAY,
   );
+  // Checked by config::check.
+  let palette = config.lookup("palette.colors").and_then(|colors| palette::parse(colors).ok())
+    .unwrap_or(curated);
 
-  if palette.len() < get(config, "turing.symbols") as uint {
-    fail!("Too many symbols for possible colors. Fix the code.");
+  if fill.as_slice() == "cycled" {
+    palette::cycled(&palette, symbols)
+  } else {
+    palette::complete(&palette, symbols)
   }
-
-  palette
 }
 
 
+// The config getters read values 'config::check' has already checked, so a
+// missing or mistyped value can only come from an unchecked config. They
+// fall back to 0 or the default then rather than panic.
 fn get(config: &toml::Value, name: &str) -> i64 {
-  config.lookup(name).unwrap().as_integer().unwrap()
+  get_or(config, name, 0)
+}
+
+
+// Like 'get', but for optional keys.
+fn get_or(config: &toml::Value, name: &str, default: i64) -> i64 {
+  config.lookup(name).and_then(|value| value.as_integer()).unwrap_or(default)
+}
+
+
+// Optional number keys, which may be written without a decimal point.
+fn get_float_or(config: &toml::Value, name: &str, default: f64) -> f64 {
+  config.lookup(name).and_then(|value| config::number(value)).unwrap_or(default)
+}
+
+
+// Optional boolean keys.
+fn get_bool_or(config: &toml::Value, name: &str, default: bool) -> bool {
+  config.lookup(name).and_then(|value| value.as_bool()).unwrap_or(default)
+}
+
+
+// Optional string keys.
+fn get_str_or(config: &toml::Value, name: &str, default: &str) -> String {
+  config.lookup(name).and_then(|value| value.as_str()).unwrap_or(default).to_string()
+}
+
+
+// The feature of the head's position that transitions also depend on. The
+// value was checked by 'config::check'.
+fn load_position_feature(config: &toml::Value) -> PositionFeature {
+  match get_str_or(config, "turing.position_feature", "none").as_slice() {
+    "parity" => Parity,
+    "quadrant" => Quadrant,
+    _ => NoFeature,
+  }
+}
+
+
+// The directions random tables move in. The value was checked by
+// 'config::check'. Hex tapes always move in the six hex directions, and
+// spacetime machines east and west.
+fn load_directions(config: &toml::Value) -> DirectionSet {
+  if get_str_or(config, "turing.topology", "square").as_slice() == "hex" {
+    return Hexagonal;
+  }
+  if get_str_or(config, "turing.mode", "turing").as_slice() == "spacetime" {
+    return Horizontal;
+  }
+  match get_str_or(config, "turing.directions", "cardinal").as_slice() {
+    "eightway" => EightWay,
+    "all" => AllDirections,
+    _ => Cardinal,
+  }
+}
+
+
+// What heads do when they meet. The value was checked by 'config::check'.
+fn load_head_collision(config: &toml::Value) -> HeadCollision {
+  match get_str_or(config, "turing.head_collision", "sequential").as_slice() {
+    "wait" => Wait,
+    _ => Sequential,
+  }
+}
+
+
+// What heads do at the edges of the tape. The value was checked by
+// 'config::check'.
+fn load_boundary(config: &toml::Value) -> Boundary {
+  match get_str_or(config, "turing.boundary", "wrap").as_slice() {
+    "reflect" => Reflect,
+    "clamp" => Clamp,
+    "halt" => Halt,
+    _ => Wrap,
+  }
+}
+
+
+// What the image shows. The value was checked by 'config::check'.
+fn load_render_mode(config: &toml::Value) -> RenderMode {
+  match get_str_or(config, "turing.render", "symbols").as_slice() {
+    "heatmap" => Heatmap,
+    "trails" => Trails(get_or(config, "turing.trail_frames", 25) as u8),
+    "states" => States,
+    _ => Symbols,
+  }
+}
+
+
+// Creates the configured kind of machine with a random table from 'rng'.
+fn build_machine(config: &toml::Value, rng: &mut SessionRng) -> Result<Box<Machine>, Error> {
+  try!(config::check(config));
+  let states: u8 = get(config, "turing.states") as u8;
+  let symbols: u8 = get(config, "turing.symbols") as u8;
+  let width: uint = get(config, "turing.width") as uint;
+  let height: uint = get(config, "turing.height") as uint;
+  let cell_width: uint = get_or(config, "turing.cell_width", 1) as uint;
+  let cell_height: uint = get_or(config, "turing.cell_height", 1) as uint;
+  let directions = load_directions(config);
+  let boundary = load_boundary(config);
+  // Only halting machines draw their table again, so seeded runs without it
+  // are unchanged.
+  let halting = get_bool_or(config, "turing.halting", false);
+  if get_str_or(config, "turing.mode", "turing").as_slice() == "turmite" {
+    let mut machine = TurmiteMachine::new(width, height, states, symbols, rng);
+    machine.set_cell_size(cell_width, cell_height);
+    machine.set_boundary(boundary);
+    return Ok(machine as Box<Machine>);
+  }
+  if get_str_or(config, "turing.mode", "turing").as_slice() == "eca" {
+    let mut machine = EcaMachine::new(width, height, rng);
+    machine.set_cell_size(cell_width, cell_height);
+    match config.lookup("turing.eca_rule").and_then(|value| value.as_integer()) {
+      Some(rule) => machine.set_rule(rule as u8),
+      None => {},
+    }
+    return Ok(machine as Box<Machine>);
+  }
+  match get_or(config, "turing.tapes", 1) {
+    1 => {
+      let position_feature = load_position_feature(config);
+      let heads = get_or(config, "turing.heads", 1) as uint;
+      let head_collision = load_head_collision(config);
+      // A spacetime machine's tape is one row, its earlier frames the rest of
+      // the picture.
+      let spacetime = get_str_or(config, "turing.mode", "turing").as_slice() == "spacetime";
+      let mut builder = TuringMachineBuilder::new();
+      let topology = if get_str_or(config, "turing.topology", "square").as_slice() == "hex" { Hex } else { Square };
+      builder.size(width, if spacetime { 1 } else { height }).states(states).symbols(symbols)
+        .position_feature(position_feature).directions(directions).cell_size(cell_width, cell_height)
+        .heads(heads, head_collision).boundary(boundary).halting(halting).topology(topology)
+        .render_mode(load_render_mode(config));
+      if get_str_or(config, "turing.tape", "dense").as_slice() == "sparse" {
+        let world = get_or(config, "turing.world_size", MAX_WORLD as i64) as uint;
+        builder.sparse_tape(world, world, get_str_or(config, "turing.sparse_view", "fixed").as_slice() == "fit");
+      }
+      let machine = try!(builder.build_with(rng).map_err(|why| invalid(why.to_string())));
+      if spacetime {
+        return Ok(SpacetimeMachine::new(machine, height) as Box<Machine>);
+      }
+      if get_str_or(config, "turing.mode", "turing").as_slice() == "hybrid" {
+        // The rule was checked by 'config::check'.
+        let rule = LifeRule::parse(get_str_or(config, "turing.ca_rule", "B3/S23").as_slice()).unwrap();
+        let ca_steps = get_or(config, "turing.ca_steps", 100) as uint;
+        return Ok(HybridMachine::new(machine, rule, ca_steps) as Box<Machine>);
+      }
+      let columns = get_or(config, "turing.montage_columns", 1) as uint;
+      let tiles = columns * get_or(config, "turing.montage_rows", 1) as uint;
+      if tiles > 1 {
+        let mut machines = vec!(machine);
+        for _ in range(1, tiles) {
+          machines.push(try!(builder.build_with(rng).map_err(|why| invalid(why.to_string()))));
+        }
+        return Ok(Montage::new(machines, columns) as Box<Machine>);
+      }
+      let pattern = match get_str_or(config, "turing.interleave", "none").as_slice() {
+        "none" => return Ok(machine as Box<Machine>),
+        "stripes" => Stripes,
+        "checkerboard" => Checkerboard,
+        other => return Err(invalid(format!("Unknown interleave pattern: {}", other))),
+      };
+      let other = try!(builder.build_with(rng).map_err(|why| invalid(why.to_string())));
+      let size = get_or(config, "turing.interleave_size", 32) as uint;
+      Ok(Interleaved::new(machine, other, pattern, size) as Box<Machine>)
+    },
+    2 => {
+      let display = match get_str_or(config, "turing.two_tape_display", "side-by-side").as_slice() {
+        "side-by-side" => SideBySide,
+        "combined" => Combined,
+        other => return Err(invalid(format!("Unknown two_tape_display: {}", other))),
+      };
+      let mut machine = TwoTapeMachine::new(width, height, states, symbols, display, cell_width, cell_height,
+                                            directions, rng);
+      machine.set_boundary(boundary);
+      Ok(machine as Box<Machine>)
+    },
+    n => Err(invalid(format!("Unsupported number of tapes: {}", n))),
+  }
+}
+
+
+// Opens the configured outputs, written by a background task of their own
+// with more than one render thread.
+fn open_outputs(config: &toml::Value, stdio_control: bool, grpc: Option<Box<ImageSink + Send>>)
+    -> Result<Box<FrameSink>, Error> {
+  if get_or(config, "turing.render_threads", 1) > 1 {
+    let config = config.clone();
+    let outputs = try!(BackgroundOutputs::start(proc() open_direct_outputs(&config, stdio_control, grpc)));
+    Ok(box outputs as Box<FrameSink>)
+  } else {
+    Ok(box try!(open_direct_outputs(config, stdio_control, grpc)) as Box<FrameSink>)
+  }
+}
+
+
+// The sink for the frames of gRPC 'Frames' calls, if 'control' serves gRPC.
+fn grpc_frames(control: &Option<Control>) -> Option<Box<ImageSink + Send>> {
+  control.as_ref().and_then(|control| control.frame_sink())
+}
+
+
+// Opens the configured outputs. Without [[output]] blocks frames go to stdout
+// (or 'frame_fd' with stdio control), or with nothing reading the video
+// stream, to a preview in the terminal. With 'record_dir', every
+// 'record_every'th frame is also written there as a numbered PNG image, and
+// every frame goes to 'grpc' if gRPC is served. Frames are upscaled by
+// 'scale', except for the preview, which fits itself to the terminal, and
+// those for gRPC.
+fn open_direct_outputs(config: &toml::Value, stdio_control: bool, grpc: Option<Box<ImageSink + Send>>)
+    -> Result<Outputs, Error> {
+  let scale = get_or(config, "turing.scale", 1) as uint;
+  let out: Box<Writer> = if stdio_control {
+    let fd = get_or(config, "turing.frame_fd", 3);
+    match std::io::pipe::PipeStream::open(fd as libc::c_int) {
+      Ok(pipe) => box pipe as Box<Writer>,
+      Err(why) => return Err(Error::io(format!("Unable to open frame_fd {}", fd), why)),
+    }
+  } else {
+    box std::io::stdout() as Box<Writer>
+  };
+
+  let mut outputs = if config.lookup("output").is_some() {
+    try!(Outputs::from_config(config, out).map_err(|why| invalid(format!("Invalid output configuration: {}", why))))
+  } else if !stdio_control && preview::stdout_is_terminal()
+      && get_bool_or(config, "turing.terminal_preview", true) {
+    match TerminalPreview::new(out) {
+      Ok(preview) => Outputs::new(vec!(Output::new(box preview as Box<ImageSink>, 1, 1))),
+      Err(why) => return Err(Error::io("Error writing to stdout".to_string(), why)),
+    }
+  } else {
+    // Checked by config::check.
+    let format = PixelFormat::from_name(get_str_or(config, "turing.pixel_format", "bgr24").as_slice()).unwrap();
+    Outputs::new(vec!(Output::new(box RawSink::with_format(out, format) as Box<ImageSink>, scale, 1)))
+  };
+
+  match config.lookup("turing.record_dir") {
+    Some(dir) => {
+      let dir = Path::new(dir.as_str().unwrap());
+      match mkdir_recursive(&dir, USER_RWX) {
+        Ok(()) => {},
+        Err(why) => return Err(Error::io(format!("Unable to create {}", dir.display()), why)),
+      }
+      let every = get_or(config, "turing.record_every", 1);
+      outputs.push(Output::new(box PngSink::new(dir) as Box<ImageSink>, scale, every as u64));
+    },
+    None => {},
+  }
+  match grpc {
+    Some(sink) => outputs.push(Output::new(sink, 1, 1)),
+    None => {},
+  }
+  Ok(outputs)
+}
+
+
+// Applies the duration and palette of a playlist entry (or the defaults
+// when 'shown' is None). Returns the number of steps to show the machine for.
+fn show_entry(machine: &mut Machine, shown: Option<Shown>, palette: &Vec<Color>,
+              default_count: u64, custom_palette: &mut bool) -> u64 {
+  let (steps, entry_palette) = match shown {
+    Some(Shown { steps, palette }) => (steps, palette),
+    None => (None, None),
+  };
+  match entry_palette {
+    Some(ref entry_palette) => {
+      machine.set_palette(entry_palette);
+      *custom_palette = true;
+    },
+    None if *custom_palette => {
+      machine.set_palette(palette);
+      *custom_palette = false;
+    },
+    None => {},
+  }
+  steps.unwrap_or(default_count)
 }
 
 
 fn main() {
-  let config = load_config();
-  let states: u8 = get(&config, "turing.states") as u8;
-  let symbols: u8 = get(&config, "turing.symbols") as u8;
+  match run() {
+    Ok(()) => {},
+    Err(error) => {
+      keys::restore_terminal();
+      let _ = writeln!(std::io::stderr(), "turing: {}", error);
+      // Exit right away rather than waiting for background tasks such as
+      // the control reader.
+      unsafe { libc::exit(error.exit_code() as libc::c_int); }
+    },
+  }
+}
+
+
+fn run() -> Result<(), Error> {
+  let args = std::os::args();
+  platform::binary_stdout();
+  // '[run] --from-image snapshot.png' continues the machine described by a
+  // PNG snapshot's metadata, with the configuration it was taken with.
+  let from_image = match args.iter().position(|arg| arg.as_slice() == "--from-image") {
+    Some(index) => match args.get_opt(index + 1) {
+      Some(path) => match snapshot::load(&Path::new(path.as_slice())) {
+        Ok(snapshot) => {
+          try!(config::check(&snapshot.config));
+          Some(snapshot)
+        },
+        Err(why) => return Err(invalid(format!("Unable to load snapshot: {}", why))),
+      },
+      None => return Err(Error::new(Usage, "--from-image needs a file".to_string())),
+    },
+    None => None,
+  };
+  // The configuration file, read again on SIGHUP. Runs continued from a
+  // snapshot use the snapshot's configuration instead.
+  let config_path = match from_image {
+    Some(_) => None,
+    None => Some(try!(config::path(args.as_slice()))),
+  };
+  let config = match from_image {
+    Some(ref snapshot) => snapshot.config.clone(),
+    None => try!(config::load(config_path.as_ref().unwrap(), args.as_slice())),
+  };
+  let config = try!(memory::apply_limit(config));
   let width: uint = get(&config, "turing.width") as uint;
   let height: uint = get(&config, "turing.height") as uint;
-  let mut machine = TuringMachine::new(width, height, states, symbols);
-  let len = machine.width * machine.height;
-  let mut out = box std::io::stdout();
+  let mut rng = match from_image {
+    Some(ref snapshot) => try!(snapshot.rng()),
+    None => try!(SessionRng::from_config(&config)),
+  };
+  // Values drawn from 'rng' before the current machine's table.
+  let mut machine_draws = rng.draws();
+  let mut machine = try!(build_machine(&config, &mut rng));
+  // A preset's or saved table to run instead of random ones.
+  let fixed_table = match (config.lookup("turing.preset"), config.lookup("turing.table_file")) {
+    (Some(name), _) => Some(preset::find(name.as_str().unwrap()).unwrap().table()),
+    (None, Some(path)) => {
+      let path = Path::new(path.as_str().unwrap());
+      Some(try!(session::load_table(&path).map_err(|why| invalid(format!("Unable to load table: {}", why)))))
+    },
+    (None, None) => None,
+  };
+  match fixed_table {
+    Some(ref table) => try!(machine.load_table(table).map_err(|why| invalid(format!("Unable to load table: {}", why)))),
+    None => {},
+  }
+  match from_image {
+    Some(ref snapshot) => match snapshot.restore(&mut *machine) {
+      Ok(()) => {},
+      Err(why) => return Err(invalid(format!("Unable to restore the snapshot's machine: {}", why))),
+    },
+    None => {},
+  }
+  // 'render-batch [--machines N] [--steps N] [--out DIR]' farms patterns
+  // without any live output.
+  if args.len() > 1 && args.get(1).as_slice() == "render-batch" {
+    let flag = |name: &str| {
+      args.iter().position(|arg| arg.as_slice() == name).and_then(|index| args.get_opt(index + 1))
+    };
+    let machines = flag("--machines").and_then(|n| batch::parse_count(n.as_slice())).unwrap_or(50);
+    let steps = flag("--steps").and_then(|n| batch::parse_count(n.as_slice())).unwrap_or(2_000_000);
+    let out = Path::new(flag("--out").map(|dir| dir.as_slice()).unwrap_or("batch"));
+    return batch::render_batch(&config, machines as uint, steps, &out);
+  }
+
+  // 'edit [file]' opens the interactive table editor, saving to 'file'.
+  if args.len() > 1 && args.get(1).as_slice() == "edit" {
+    let save_path = Path::new(args.get_opt(2).map(|path| path.as_slice()).unwrap_or("edited.toml"));
+    let mut machine = try!(TuringMachineBuilder::new().size(width, height)
+                           .states(get(&config, "turing.states") as u8).symbols(get(&config, "turing.symbols") as u8)
+                           .position_feature(load_position_feature(&config)).directions(load_directions(&config))
+                           .build_with(&mut rng).map_err(|why| invalid(why.to_string())));
+    let palette = load_palette(&config);
+    return editor::run(&mut *machine, &palette, &mut rng, &save_path)
+      .map_err(|why| Error::io("Error writing to the terminal".to_string(), why));
+  }
+
+  // '--breed a.toml b.toml [--children N] [--mutations N] [--out DIR]' writes
+  // crossovers of two saved tables, for curating lineages of machines.
+  match args.iter().position(|arg| arg.as_slice() == "--breed") {
+    Some(index) => {
+      let (a, b) = match (args.get_opt(index + 1), args.get_opt(index + 2)) {
+        (Some(a), Some(b)) => (Path::new(a.as_slice()), Path::new(b.as_slice())),
+        _ => return Err(Error::new(Usage, "--breed needs two table files".to_string())),
+      };
+      let flag = |name: &str| {
+        args.iter().position(|arg| arg.as_slice() == name).and_then(|index| args.get_opt(index + 1))
+      };
+      let children = flag("--children").and_then(|n| batch::parse_count(n.as_slice())).unwrap_or(8);
+      let mutations = flag("--mutations").and_then(|n| batch::parse_count(n.as_slice())).unwrap_or(1);
+      let out = Path::new(flag("--out").map(|dir| dir.as_slice()).unwrap_or("offspring"));
+      let mut machine = try!(TuringMachineBuilder::new().size(width, height)
+                             .states(get(&config, "turing.states") as u8)
+                             .symbols(get(&config, "turing.symbols") as u8)
+                             .position_feature(load_position_feature(&config))
+                             .directions(load_directions(&config)).build_with(&mut rng)
+                             .map_err(|why| invalid(why.to_string())));
+      let halting = get_bool_or(&config, "turing.halting", false);
+      return breed::run(&mut *machine, &a, &b, children as uint, mutations as uint, halting, &mut rng, &out);
+    },
+    None => {},
+  }
+
+  // 'verify --seed S --steps N' prints the checksum of the frame of a seeded
+  // machine after N steps, to check refactors are bit-exact.
+  if args.len() > 1 && args.get(1).as_slice() == "verify" {
+    let flag = |name: &str| {
+      args.iter().position(|arg| arg.as_slice() == name)
+        .and_then(|index| args.get_opt(index + 1))
+        .and_then(|n| batch::parse_count(n.as_slice()))
+    };
+    let seed = flag("--seed").unwrap_or(0);
+    let steps = flag("--steps").unwrap_or(1_000_000);
+    let algorithm = try!(Algorithm::from_config(&config));
+    let mut machine = try!(build_machine(&config, &mut SessionRng::with_algorithm(algorithm, seed)));
+    machine.set_palette(&load_palette(&config));
+    println!("{:016x}", verify::frame_checksum(&mut *machine, steps));
+    return Ok(());
+  }
+
+  // 'bench --compare [--seed S] [--frames N]' renders the same seeded run
+  // with every renderer and compares their speed and output.
+  if args.len() > 2 && args.get(1).as_slice() == "bench" && args.get(2).as_slice() == "--compare" {
+    let flag = |name: &str| {
+      args.iter().position(|arg| arg.as_slice() == name)
+        .and_then(|index| args.get_opt(index + 1))
+        .and_then(|n| batch::parse_count(n.as_slice()))
+    };
+    let seed = flag("--seed").unwrap_or(0);
+    let frames = flag("--frames").unwrap_or(100) as uint;
+    let picture_steps = get(&config, "turing.picture_steps") as u64;
+    return bench::compare(&config, seed, frames, picture_steps, &mut std::io::stdout());
+  }
+
+  // '--bench [--seed S] [--steps N] [--encode raw|jpeg]' times a seeded run
+  // without any real output: steps per second, frame encoding and bytes.
+  if args.iter().any(|arg| arg.as_slice() == "--bench") {
+    let flag = |name: &str| {
+      args.iter().position(|arg| arg.as_slice() == name).and_then(|index| args.get_opt(index + 1))
+    };
+    let (encode, quality) = (flag("--encode"), flag("--quality"));
+    let (seed, steps) = (flag("--seed"), flag("--steps"));
+    let count = |n: Option<&String>| n.and_then(|n| batch::parse_count(n.as_slice()));
+    let encoding = match encode.map(|encoding| encoding.as_slice()).unwrap_or("raw") {
+      "raw" => bench::Raw,
+      "jpeg" => bench::Jpeg(count(quality).unwrap_or(80) as uint),
+      other => return Err(Error::new(Usage, format!("Unknown --encode: {} (expected raw or jpeg)", other))),
+    };
+    let picture_steps = get(&config, "turing.picture_steps") as u64;
+    return bench::throughput(&config, count(seed).unwrap_or(0), count(steps).unwrap_or(10_000_000),
+                             picture_steps, encoding, &mut std::io::stdout());
+  }
+
+  // '--trace [steps]' explains each step on stdout instead of rendering.
+  match args.iter().position(|arg| arg.as_slice() == "--trace") {
+    Some(index) => {
+      if width * height > 32 * 32 {
+        return Err(Error::new(Usage, format!("--trace is meant for small grids (at most 32x32 cells), not {}x{}",
+                                             width, height)));
+      }
+      let steps = args.get_opt(index + 1).and_then(|steps| from_str::<uint>(steps.as_slice())).unwrap_or(50);
+      machine.set_palette(&load_palette(&config));
+      return trace::run(&mut *machine, steps, &mut std::io::stdout())
+        .map_err(|why| Error::io("Error writing to stdout".to_string(), why));
+    },
+    None => {},
+  }
+
+  // With "stdio" control, stdin/stdout carry JSON-RPC messages and frames are
+  // written to file descriptor 'frame_fd' instead. 'control_port' serves the
+  // same requests over HTTP, and 'grpc_port' over gRPC along with the frames.
+  let stdio_control = match get_str_or(&config, "turing.control", "none").as_slice() {
+    "none" => false,
+    "stdio" => true,
+    other => return Err(invalid(format!("Unknown control: {}", other))),
+  };
+  let port = |name: &str| match get_or(&config, name, 0) {
+    0 => None,
+    port => Some(port as u16),
+  };
+  let (http_port, grpc_port) = (port("turing.control_port"), port("turing.grpc_port"));
+  let mut control = if stdio_control || http_port.is_some() || grpc_port.is_some() {
+    // Only local clients unless another interface is asked for: the API can
+    // write files.
+    let host = get_str_or(&config, "turing.control_host", "127.0.0.1");
+    Some(try!(Control::start(stdio_control, host.as_slice(), http_port, grpc_port)))
+  } else {
+    None
+  };
+  let mut outputs = try!(open_outputs(&config, stdio_control, grpc_frames(&control)));
+  // What to do when the reader of the output goes away: "exit" or "wait"
+  // (for a new reader of a named pipe).
+  let wait_for_reader = match get_str_or(&config, "turing.on_closed_output", "exit").as_slice() {
+    "exit" => false,
+    "wait" if reopenable(&config) => true,
+    "wait" => return Err(invalid("on_closed_output = \"wait\" needs every raw, y4m and terminal output to \
+                                  write to a named pipe or fifo".to_string())),
+    other => return Err(invalid(format!("Unknown on_closed_output: {}", other))),
+  };
+
+  let niceness = get_or(&config, "turing.nice", 0);
+  if niceness > 0 {
+    lower_priority(niceness);
+  }
+  // Sleep this long after every frame to leave the CPU idle part of the time.
+  let mut throttle_ms = get_or(&config, "turing.throttle_ms", 0);
+  let halt_hold_frames = get_or(&config, "turing.halt_hold_frames", 0);
+  // Show frames at 'fps' frames per second, by waiting or by adapting the
+  // steps per frame.
+  let mut pacing = match get_or(&config, "turing.fps", 0) {
+    0 => None,
+    fps => Some(FramePacing::new(fps as u64, get_str_or(&config, "turing.fps_mode", "sleep").as_slice() == "adapt",
+                                 get_or(&config, "turing.min_picture_steps", 1000) as u64,
+                                 get_or(&config, "turing.max_picture_steps", 1000000) as u64,
+                                 time::precise_time_ns())),
+  };
 
   // Reset the pattern after this step count
-  let count: u32 = get(&config, "turing.reset_steps") as u32;
-  // print the picture after this step count
-  let stops: u32 = get(&config, "turing.picture_steps") as u32;
+  let reset_steps: u64 = get(&config, "turing.reset_steps") as u64;
+  // 'reset_steps' for machines that aren't from a playlist.
+  let mut default_count = reset_steps;
+  // Steps the machine, printing the picture every 'picture_steps' steps.
+  // Steps run in batches of 'batch_steps' between checks for shutdown and
+  // quiet hours, or up to the next frame with 0.
+  let mut runner = Runner::new(get(&config, "turing.picture_steps") as u64, reset_steps,
+                               get_or(&config, "turing.batch_steps", 0) as u64);
+
+  // Marks the heads in frames, so they can be followed on large tapes.
+  let marker_shape = match get_str_or(&config, "turing.head_marker", "none").as_slice() {
+    "block" => Some(Block),
+    "crosshair" => Some(Crosshair),
+    _ => None,
+  };
+  match marker_shape {
+    Some(shape) => {
+      let color = config.lookup("turing.head_marker_color").and_then(|color| palette::parse_color(color));
+      let size = get_or(&config, "turing.head_marker_size", 9) as uint;
+      runner.set_marker(HeadMarker::new(shape, color.unwrap_or(MAGENTA), size));
+    },
+    None => {},
+  }
+
+  // Shows a window of the image, for tapes bigger than the frames.
+  match (config.lookup("turing.viewport_width"), config.lookup("turing.viewport_height")) {
+    (Some(_), Some(_)) => {
+      let center = match (config.lookup("turing.viewport_x"), config.lookup("turing.viewport_y")) {
+        (None, None) => None,
+        _ => {
+          let (image_width, image_height) = machine.image_size();
+          Some((get_or(&config, "turing.viewport_x", (image_width / 2) as i64) as int,
+                get_or(&config, "turing.viewport_y", (image_height / 2) as i64) as int))
+        },
+      };
+      let mut viewport = Viewport::new(get(&config, "turing.viewport_width") as uint,
+                                       get(&config, "turing.viewport_height") as uint, center,
+                                       get_or(&config, "turing.zoom", 0) as int);
+      if get_bool_or(&config, "turing.follow_head", false) {
+        viewport.follow(get_or(&config, "turing.follow_deadzone", 50) as uint,
+                        get_or(&config, "turing.follow_smoothing", 8) as uint);
+      }
+      runner.set_viewport(viewport);
+    },
+    _ => {},
+  }
+
+  // Softens the strobing of frames by blending each with the one before.
+  match config.lookup("render.blend").and_then(|value| config::number(value)) {
+    Some(alpha) if alpha > 0.0 => runner.set_blend(FrameBlend::new(alpha)),
+    _ => {},
+  }
+
+  // Adjusts the colors of frames for video, if any [render] setting asks.
+  if ["render.gamma", "render.brightness", "render.contrast"].iter().any(|&name| config.lookup(name).is_some()) {
+    runner.set_adjust(ColorAdjust::new(get_float_or(&config, "render.gamma", 1.0),
+                                       get_float_or(&config, "render.brightness", 0.0),
+                                       get_float_or(&config, "render.contrast", 1.0)));
+  }
+
+  let adaptive = if get_bool_or(&config, "turing.adaptive_picture_steps", false) {
+    Some(AdaptiveSteps {
+      min: get_or(&config, "turing.min_picture_steps", 1000) as u64,
+      max: get_or(&config, "turing.max_picture_steps", 1000000) as u64,
+      target: get_or(&config, "turing.target_changes", 2000) as u64,
+    })
+  } else {
+    None
+  };
+
+  // Replaces machines caught in short cycles.
+  match get_or(&config, "turing.cycle_period", 0) {
+    0 => {},
+    period => runner.add_policy(box CycleDetector::new(period as u64,
+                                                      get_or(&config, "turing.cycle_repeats", 8) as u64)),
+  }
+  // With the energy policy machines are reset when their energy runs out
+  // instead of after 'reset_steps'.
+  let energy = get_str_or(&config, "turing.reset_policy", "steps").as_slice() == "energy";
+  match get_str_or(&config, "turing.reset_policy", "steps").as_slice() {
+    "steps" => runner.add_policy(box StepLimit),
+    "energy" => runner.add_policy(box Energy::new(get_or(&config, "turing.energy", reset_steps as i64) as u64,
+                                                  get_or(&config, "turing.energy_drain", 1) as u64,
+                                                  get_or(&config, "turing.energy_recharge", 10) as u64)),
+    other => return Err(invalid(format!("Unknown reset_policy: {}", other))),
+  }
+  // Replaces machines that stop changing the picture.
+  runner.add_policy(box Stagnation::new(get_or(&config, "turing.stagnant_frames", 1) as u64));
+
+  // Report throughput and frame timing to stderr every this many seconds.
+  let mut stats = match get_or(&config, "turing.stats_interval", 0) {
+    0 => None,
+    secs => Some(Stats::new(secs as u64)),
+  };
+
+  // Per-frame statistics and reset events as Server-Sent Events.
+  let events = match get_or(&config, "turing.events_port", 0) {
+    0 => None,
+    port => match EventStream::listen(get_str_or(&config, "turing.control_host", "127.0.0.1").as_slice(),
+                                      port as u16) {
+      Ok(events) => Some(events),
+      Err(why) => return Err(Error::io(format!("Unable to listen on port {}", port), why)),
+    },
+  };
+
+  // Keep the tape when switching to a new machine.
+  let chain = get_bool_or(&config, "turing.chain", false);
+
+  let mut palette: Vec<Color> = load_palette(&config);
+  machine.set_palette(&palette);
+
+  let mut paused = false;
+  match from_image {
+    Some(ref snapshot) => runner.counts.step = snapshot.step,
+    None => {},
+  }
+
+  // A session file holds everything needed to continue a run with identical
+  // frames: the machine, the random number generator and the loop counters.
+  // It is loaded at startup if it exists and saved every
+  // 'session_save_frames' frames.
+  let configured_session = config.lookup("turing.session_file").map(|path| Path::new(path.as_str().unwrap()));
+  // '--checkpoint file' saves the session to 'file' without continuing from
+  // it; '--resume file' continues from 'file', which must exist, and saves
+  // to it too unless there is a checkpoint file.
+  let checkpoint = try!(path_arg(args.as_slice(), "--checkpoint"));
+  let resume = try!(path_arg(args.as_slice(), "--resume"));
+  let session_file = checkpoint.clone().or(resume.clone()).or(configured_session.clone());
+  if session_file.is_some() && get_str_or(&config, "turing.tape", "dense").as_slice() == "sparse" {
+    return Err(Error::new(Usage, "Sessions save the whole tape, which sparse tapes can't".to_string()));
+  }
+  let resume_file = match (resume, configured_session) {
+    (Some(path), _) => {
+      if !path.exists() {
+        return Err(Error::new(Usage, format!("No session to resume at {}", path.display())));
+      }
+      Some(path)
+    },
+    (None, Some(path)) if checkpoint.is_none() && path.exists() => Some(path),
+    _ => None,
+  };
+  let session_frames = get_or(&config, "turing.session_save_frames", 100) as u64;
+  let mut resumed = false;
+  match resume_file {
+    Some(ref path) => match session::load(path, &mut *machine) {
+      Ok((saved_rng, state)) => {
+        rng = saved_rng;
+        resumed = true;
+        runner.load_state(&state);
+        match stats {
+          Some(ref mut stats) => stats.set_total_steps(state.total_steps),
+          None => {},
+        }
+      },
+      Err(why) => return Err(invalid(format!("Unable to load session {}: {}", path.display(), why))),
+    },
+    None => {},
+  }
+  // Without a configured seed, report the random one so a run worth keeping
+  // can be repeated.
+  if !resumed && from_image.is_none() && config.lookup("turing.seed").is_none() {
+    let _ = writeln!(std::io::stderr(), "Seed {} (set turing.seed = {} to run this again)",
+                     rng.seed(), rng.seed());
+  }
 
-  let palette: Vec<Color> = load_palette(&config);
+  // Replaces dull random machines before they are shown.
+  let interest = match get_or(&config, "turing.min_interest", 0) {
+    0 => None,
+    min => Some(Interest::new(get_or(&config, "turing.interest_steps", 10000) as u64, min as u64,
+                              get_or(&config, "turing.interest_attempts", 100) as u64)),
+  };
+  // Breeds the tables of new random machines instead of drawing them, on a
+  // machine of its own.
+  let mut search = if get_bool_or(&config, "turing.search", false) {
+    // Tables are scored on a tape of at most 128x128 one-pixel cells, so
+    // breeding at resets doesn't hold up the video however large the picture.
+    let mut scratch_config = config.clone();
+    for &(name, most) in [("turing.width", 128), ("turing.height", 128), ("turing.cell_width", 1),
+                          ("turing.cell_height", 1)].iter() {
+      let value = std::cmp::min(get_or(&config, name, 1), most);
+      config::set(&mut scratch_config, name, toml::Integer(value));
+    }
+    let mut scratch = try!(build_machine(&scratch_config, &mut rng));
+    scratch.set_palette(&palette);
+    let features = load_position_feature(&config).count();
+    Some(Search::new(scratch, get_or(&config, "turing.interest_steps", 10000) as u64,
+                     get_or(&config, "turing.search_population", 16) as uint,
+                     get(&config, "turing.states") as u8, get(&config, "turing.symbols") as u8, features,
+                     load_directions(&config), get_bool_or(&config, "turing.halting", false)))
+  } else {
+    None
+  };
+  // A resumed session goes on breeding the population it saved.
+  match (&mut search, &resume_file) {
+    (&Some(ref mut search), &Some(ref path)) => match session::read_section(path, "search") {
+      Ok(Some(saved)) => try!(search.load_state(&saved)
+                              .map_err(|why| invalid(format!("Unable to load session {}: {}", path.display(), why)))),
+      Ok(None) => {},
+      Err(why) => return Err(invalid(format!("Unable to load session {}: {}", path.display(), why))),
+    },
+    _ => {},
+  }
+  let search_generations = get_or(&config, "turing.search_generations", 1);
+
+  // Machines age: every 'mutate_steps' steps a few entries of the table are
+  // redrawn.
+  runner.set_mutate_steps(get_or(&config, "turing.mutate_steps", 0) as u64);
+  let mutations = get_or(&config, "turing.mutations_per_event", 1) as uint;
+  let random_start = fixed_table.is_none() && config.lookup("turing.playlist").is_none();
+  if !resumed && from_image.is_none() && random_start {
+    match interest {
+      Some(ref interest) => match interest.screen(&mut *machine, &mut rng, false) {
+        Some(draws) => machine_draws = draws,
+        None => {},
+      },
+      None => {},
+    }
+  }
+
+  // A JSON-lines history of every machine created and destroyed.
+  let mut event_log = match config.lookup("turing.event_log") {
+    Some(path) => match EventLog::open(&Path::new(path.as_str().unwrap())) {
+      Ok(log) => Some(log),
+      Err(why) => return Err(Error::io("Unable to open event log".to_string(), why)),
+    },
+    None => None,
+  };
+  // A CSV row for every machine replaced.
+  let mut reset_log = match config.lookup("turing.reset_log") {
+    Some(path) => match ResetLog::open(&Path::new(path.as_str().unwrap())) {
+      Ok(log) => Some(log),
+      Err(why) => return Err(Error::io("Unable to open reset log".to_string(), why)),
+    },
+    None => None,
+  };
+  // Fingerprint of the current machine's table, for the event log.
+  let mut fingerprint = event_log::fingerprint(machine.table_text().as_slice());
+  if !resumed {
+    match event_log {
+      Some(ref mut log) => {
+        let _ = log.created(&*machine, &rng, machine_draws, 0);
+      },
+      None => {},
+    }
+  }
+
+  // A playlist of saved machines to show instead of random ones.
+  let mut playlist = match config.lookup("turing.playlist") {
+    Some(path) => match Playlist::load(&Path::new(path.as_str().unwrap())) {
+      Ok(playlist) => Some(playlist),
+      Err(why) => return Err(invalid(format!("Unable to load playlist: {}", why))),
+    },
+    None => None,
+  };
+  // Whether a playlist entry's palette is in use.
+  let mut custom_palette = false;
+  if !resumed {
+    match playlist {
+      Some(ref mut playlist) => {
+        let shown = playlist.next(&mut *machine, &mut rng);
+        runner.counts.reset_steps = show_entry(&mut *machine, shown, &palette, default_count, &mut custom_palette);
+      },
+      None => {},
+    }
+  }
+
+  // Installation mode: playlist rotation, quiet hours, heartbeat and
+  // reopening outputs that fail.
+  let mut kiosk = if get_bool_or(&config, "turing.kiosk", false) {
+    Some(try!(Kiosk::from_config(&config).map_err(|why| invalid(why))))
+  } else {
+    None
+  };
+
+  // Replays the most active interval of each machine in slow motion before
+  // it is replaced.
+  let mut highlight = if get_bool_or(&config, "turing.replay", false) {
+    Some(Highlight::new(get_or(&config, "turing.replay_window", 50) as u64))
+  } else {
+    None
+  };
+  let replay_slowdown = get_or(&config, "turing.replay_slowdown", 4) as u64;
+  match highlight {
+    Some(ref mut highlight) => highlight.begin(&*machine),
+    None => {},
+  }
 
-  let mut i = 0;
-  let mut change = false;
+  // Saves the tables and thumbnails of machines that stay busy.
+  let mut hall_of_fame = match config.lookup("turing.hall_of_fame") {
+    Some(dir) => {
+      let dir = Path::new(dir.as_str().unwrap());
+      match mkdir_recursive(&dir, USER_RWX) {
+        Ok(()) => {},
+        Err(why) => return Err(Error::io(format!("Unable to create {}", dir.display()), why)),
+      }
+      Some(HallOfFame::new(dir, get_or(&config, "turing.hall_of_fame_window", 100) as u64,
+                           get_or(&config, "turing.hall_of_fame_windows", 3) as u64,
+                           get_or(&config, "turing.hall_of_fame_rate", 10) as u64))
+    },
+    None => None,
+  };
+
+  // On SIGTERM or SIGINT, or when the output is closed, stop at the end of
+  // the current frame: finish the outputs, save the session (and the table,
+  // to 'shutdown_table') and exit with status 0. On SIGHUP, read the configuration file again.
+  shutdown::install();
+  let shutdown_table = config.lookup("turing.shutdown_table").map(|path| Path::new(path.as_str().unwrap()));
+
+  // Keyboard commands from windows and, when it is a terminal, stdin.
+  let keyboard = get_bool_or(&config, "turing.keyboard", true) && !stdio_control;
+  // Puts the terminal back if the loop panics.
+  let _terminal = if keyboard && preview::stdin_is_terminal() {
+    Some(keys::read_terminal())
+  } else {
+    None
+  };
+
+  // Set when the reader of the output went away, to stop like on SIGTERM.
+  let mut closed = false;
   loop {
-    change = machine.step() || change;
-    i += 1;
-    if i % stops == 0 {
-      if machine.write_image(&palette, &mut out).is_err() {
-        fail!("Error writing to stdout");
+    let quiet = kiosk.as_ref().map_or(false, |kiosk| kiosk.is_quiet());
+    if closed || (shutdown::requested() && (runner.counts.frame_steps == 0 || quiet)) {
+      match session_file {
+        Some(ref path) => save_session(path, &*machine, &rng, &runner.loop_state(), &search),
+        None => {},
       }
-      if !change {
-        // new machine
-        machine.table = TuringMachine::random_table(machine.states, machine.symbols);
-        machine.tape = Vec::from_elem(len, 0u8);
-        i = 0;
-      } else {
-        change = true;
+      match shutdown_table {
+        Some(ref path) => match machine.save_table(path) {
+          Ok(()) => {},
+          Err(why) => {
+            let _ = writeln!(std::io::stderr(), "Unable to save table {}: {}", path.display(), why.desc);
+          },
+        },
+        None => {},
+      }
+      match event_log {
+        Some(ref mut log) => {
+          let reason = if closed { "output closed" } else { "shutdown" };
+          let _ = log.destroyed(fingerprint, reason, runner.counts.step);
+        },
+        None => {},
+      }
+      // The closed output can't be finished, but the others are.
+      match outputs.finish() {
+        Ok(()) => {},
+        Err(ref why) if closed && why.kind == std::io::BrokenPipe => {},
+        Err(why) => return Err(Error::io("Error finishing the outputs".to_string(), why)),
+      }
+      keys::restore_terminal();
+      let _ = writeln!(std::io::stderr(), "Stopped after {} frames.", runner.counts.frames);
+      // Like a closed output, don't wait for the control reader.
+      unsafe { libc::exit(0); }
+    }
+
+    // Between frames, apply a reloaded configuration's palette and timing.
+    // Other changes, like the tape size, need a restart.
+    if runner.counts.frame_steps == 0 && shutdown::reload_requested() {
+      match config_path {
+        Some(ref path) => match config::load(path, args.as_slice()) {
+          Ok(reloaded) => {
+            palette = load_palette(&reloaded);
+            if !custom_palette {
+              machine.set_palette(&palette);
+            }
+            runner.counts.picture_steps = get(&reloaded, "turing.picture_steps") as u64;
+            default_count = get(&reloaded, "turing.reset_steps") as u64;
+            runner.counts.reset_steps = default_count;
+            throttle_ms = get_or(&reloaded, "turing.throttle_ms", 0);
+            let _ = writeln!(std::io::stderr(), "Reloaded {}.", path.display());
+          },
+          Err(why) => {
+            let _ = writeln!(std::io::stderr(), "Unable to reload {}: {}", path.display(), why.message);
+          },
+        },
+        None => {},
+      }
+    }
+
+    let mut wrote_frame = false;
+    // A reset asked for by a control request.
+    let mut requested = None;
+    match kiosk {
+      Some(ref mut kiosk) if quiet => {
+        // Instead of stepping, keep the outputs that need frames to stay
+        // open, like a player reading a pipe, alive with a black frame every
+        // second. Control requests and keys are still handled.
+        match runner.keep_alive(&*machine, &mut *outputs) {
+          Ok(()) => {},
+          Err(why) => reopen_output(&mut *outputs, &config, why),
+        }
+        kiosk.heartbeat(runner.counts.frames);
+        sleep(Duration::seconds(1));
+      },
+      _ => {
+        let steps = runner.run(&mut *machine);
+        if runner.mutation_due() {
+          machine.mutate(&mut rng, mutations);
+        }
+        match stats {
+          Some(ref mut stats) => stats.steps(steps),
+          None => {},
+        }
+      },
+    }
+    if !quiet && runner.frame_due(&*machine) {
+      match pacing {
+        Some(ref mut pacing) => {
+          let (next, wait) = pacing.frame(runner.counts.picture_steps, time::precise_time_ns());
+          runner.counts.picture_steps = next;
+          if wait > 0 {
+            sleep(Duration::nanoseconds(wait as i64));
+          }
+        },
+        None => {},
+      }
+      let written = runner.write_frame(&*machine, &mut *outputs);
+      // Frames the outputs took but failed to write later, and this one if
+      // it failed.
+      let failed = written.is_err();
+      let dropped = outputs.dropped_frames() + if failed { 1 } else { 0 };
+      match written {
+        Ok(()) => {},
+        Err(why) => match kiosk {
+          Some(_) => reopen_output(&mut *outputs, &config, why),
+          // The video player or ffmpeg went away.
+          None if why.kind == std::io::BrokenPipe => {
+            if !wait_for_reader {
+              let _ = writeln!(std::io::stderr(), "Output closed, exiting.");
+              closed = true;
+              continue;
+            }
+            // Opening a named pipe output blocks until a new reader opens it.
+            let _ = writeln!(std::io::stderr(), "Output closed. Waiting for a new reader.");
+            sleep(Duration::seconds(1));
+            match outputs.reopen_failed(&config) {
+              Ok(()) => {},
+              Err(why) => return Err(Error::io("Unable to reopen the output".to_string(), why)),
+            }
+          },
+          None => return Err(Error::io("Error writing frame".to_string(), why)),
+        },
+      }
+      match kiosk {
+        Some(ref mut kiosk) => kiosk.heartbeat(runner.counts.frames),
+        None => {},
+      }
+      match stats {
+        Some(ref mut stats) => {
+          if !failed {
+            let (render_ns, write_ns) = runner.frame_times();
+            stats.frame(render_ns, write_ns);
+          }
+          stats.dropped_frames(dropped);
+          stats.maybe_report();
+        },
+        None => {},
+      }
+      wrote_frame = true;
+      match highlight {
+        Some(ref mut highlight) => highlight.frame(&*machine, runner.counts.frame_steps,
+                                                   runner.counts.frame_changes),
+        None => {},
+      }
+      match reset_log {
+        Some(ref mut log) => log.frame(runner.counts.frame_changes),
+        None => {},
+      }
+      match hall_of_fame {
+        Some(ref mut hall_of_fame) => {
+          let _ = match hall_of_fame.frame(&*machine, runner.counts.frame_steps, runner.counts.frame_changes) {
+            Ok(Some(path)) => writeln!(std::io::stderr(), "Saved {} to the hall of fame.", path.display()),
+            Ok(None) => Ok(()),
+            Err(why) => writeln!(std::io::stderr(), "Unable to save to the hall of fame: {}", why.desc),
+          };
+        },
+        None => {},
+      }
+      match events {
+        Some(ref events) => events.send(format!(
+            "{{\"type\":\"frame\",\"frame\":{},\"step\":{},\"total_steps\":{},\"picture_steps\":{},\"changes\":{}}}",
+            runner.counts.frames, runner.counts.step, runner.counts.total_steps, runner.counts.picture_steps,
+            runner.counts.frame_changes)),
+        None => {},
+      }
+      match adaptive {
+        Some(ref adaptive) => {
+          runner.counts.picture_steps = adaptive.next(runner.counts.picture_steps, runner.counts.frame_changes);
+        },
+        None => {},
+      }
+      runner.end_frame();
+      machine.end_frame();
+      if throttle_ms > 0 {
+        sleep(Duration::milliseconds(throttle_ms));
+      }
+    }
+
+    if wrote_frame || quiet {
+      // Handle control requests between frames. While paused, wait for them.
+      match control {
+        Some(ref mut control) => loop {
+          let next = if paused { control.recv() } else { control.try_recv() };
+          let request = match next {
+            Some(request) => request,
+            None => {
+              // stdin was closed and there is no HTTP API, so nothing could
+              // ever resume us.
+              paused = false;
+              break;
+            },
+          };
+          match request.command {
+            Pause => paused = true,
+            Resume => paused = false,
+            Reset => requested = Some("control"),
+            SetPictureSteps(steps) => match config::check_steps(steps, runner.counts.reset_steps, energy) {
+              Ok(()) => runner.counts.picture_steps = steps,
+              Err(why) => {
+                control.reply_invalid(&request, why.message);
+                continue;
+              },
+            },
+            SetResetSteps(steps) => match config::check_steps(runner.counts.picture_steps, steps, energy) {
+              Ok(()) => {
+                runner.counts.reset_steps = steps;
+                default_count = steps;
+              },
+              Err(why) => {
+                control.reply_invalid(&request, why.message);
+                continue;
+              },
+            },
+            Snapshot(ref path) => {
+              let (image_width, image_height) = machine.image_size();
+              // PNG snapshots carry the machine, for '--from-image'.
+              let written = if path.extension() == Some(b"png") {
+                snapshot::save(path, &*machine, &config, &rng, machine_draws, runner.counts.step)
+              } else {
+                image::write_ppm(path, machine.image(), image_width, image_height)
+              };
+              match written {
+                Ok(()) => {},
+                Err(why) => {
+                  control.reply_error(&request, format!("Unable to write snapshot: {}", why.desc));
+                  continue;
+                },
+              }
+            },
+            SaveTable(ref path) => match machine.save_table(path) {
+              Ok(()) => {},
+              Err(why) => {
+                control.reply_error(&request, format!("Unable to save table: {}", why.desc));
+                continue;
+              },
+            },
+            GetTable => {
+              control.reply(&request, json::encode(&TableReply { symbols: machine.symbols(),
+                                                                  table: machine.table_json() }));
+              continue;
+            },
+            Status => {},
+          }
+          control.reply(&request, json::encode(&StatusReply {
+            paused: paused,
+            step: runner.counts.step,
+            picture_steps: runner.counts.picture_steps,
+            reset_steps: runner.counts.reset_steps,
+          }));
+        },
+        None => {},
+      }
+
+      // Handle keys pressed since the last frame. Paused with the space bar,
+      // wait for more.
+      let mut key_paused = false;
+      while keyboard {
+        outputs.poll_input();
+        let mut moved = false;
+        for key in keys::take().into_iter() {
+          match key {
+            keys::Pause => key_paused = !key_paused,
+            keys::NewMachine => requested = Some("keyboard"),
+            keys::Save => {
+              let name = format!("turing-{}", runner.counts.frames);
+              let (table, image) = (Path::new(format!("{}.toml", name)), Path::new(format!("{}.png", name)));
+              let saved = machine.save_table(&table).and_then(|()| {
+                snapshot::save(&image, &*machine, &config, &rng, machine_draws, runner.counts.step)
+              });
+              let _ = match saved {
+                Ok(()) => writeln!(std::io::stderr(), "Saved {} and {}.", table.display(), image.display()),
+                Err(why) => writeln!(std::io::stderr(), "Unable to save {}: {}", name, why.desc),
+              };
+            },
+            keys::Faster => {
+              let steps = runner.counts.picture_steps;
+              runner.counts.picture_steps = steps.checked_mul(&2).unwrap_or(steps);
+            },
+            keys::Slower => runner.counts.picture_steps = std::cmp::max(1, runner.counts.picture_steps / 2),
+            keys::PanLeft | keys::PanRight | keys::PanUp | keys::PanDown | keys::ZoomIn | keys::ZoomOut => {
+              match runner.viewport() {
+                Some(viewport) => {
+                  match key {
+                    keys::PanLeft => viewport.pan(-1, 0),
+                    keys::PanRight => viewport.pan(1, 0),
+                    keys::PanUp => viewport.pan(0, -1),
+                    keys::PanDown => viewport.pan(0, 1),
+                    keys::ZoomIn => viewport.zoom(1),
+                    _ => viewport.zoom(-1),
+                  }
+                  moved = true;
+                },
+                None => {},
+              }
+            },
+          }
+        }
+        if !key_paused || shutdown::requested() {
+          break;
+        }
+        // Show where the viewport went while paused. Errors come up with the
+        // next frame.
+        if moved {
+          let _ = runner.write_frame(&*machine, &mut *outputs);
+        }
+        sleep(Duration::milliseconds(50));
+      }
+    }
+    if machine.halted() {
+      // Hold the last frame, just written, before the next machine. Trails
+      // go on fading while it's held.
+      for _ in range(0, halt_hold_frames) {
+        match runner.write_frame(&*machine, &mut *outputs) {
+          Ok(()) => {},
+          Err(why) => return Err(Error::io("Error writing frame".to_string(), why)),
+        }
+        machine.end_frame();
       }
     }
-    if i >= count {
-      // new machine
-      machine.table = TuringMachine::random_table(machine.states, machine.symbols);
-      machine.tape = Vec::from_elem(len, 0u8);
-      i = 0;
+    // Why the machine is being replaced, if it is.
+    match runner.reason(&*machine, requested) {
+      Some(reason) => {
+        let i = runner.counts.step;
+        match events {
+          Some(ref events) => events.send(format!(
+              "{{\"type\":\"reset\",\"reason\":\"{}\",\"steps\":{}}}", reason, i)),
+          None => {},
+        }
+        match reset_log {
+          Some(ref mut log) => {
+            let table_hash = event_log::fingerprint(machine.table_text().as_slice());
+            let _ = log.replaced(&*machine, &rng, machine_draws, table_hash, reason, i);
+          },
+          None => {},
+        }
+
+        // No replays in quiet hours.
+        match highlight.as_mut().and_then(|highlight| highlight.take()) {
+          Some((state, steps)) if !quiet => {
+            // Chained machines continue from the final tape, not the replay's.
+            let final_state = if chain { Some(machine.save_state()) } else { None };
+            let slow_steps = std::cmp::max(1, runner.counts.picture_steps / std::cmp::max(1, replay_slowdown));
+            match replay::restore(&mut *machine, state.as_slice()) {
+              Ok(()) => match replay::play(&mut *machine, steps, slow_steps, &mut runner, &mut *outputs) {
+                Ok(()) => {},
+                Err(why) => return Err(Error::io("Error writing frame".to_string(), why)),
+              },
+              Err(why) => {
+                let _ = writeln!(std::io::stderr(), "Unable to replay: {}", why);
+              },
+            }
+            match final_state {
+              Some(ref state) => { let _ = replay::restore(&mut *machine, state.as_slice()); },
+              None => {},
+            }
+          },
+          _ => {},
+        }
+
+        // new machine
+        let mut draws = rng.draws();
+        machine_draws = draws;
+        if chain {
+          machine.chain(&mut rng);
+        } else {
+          machine.reset(&mut rng);
+        }
+        match fixed_table {
+          Some(ref table) => { let _ = machine.load_table(table); },
+          None => {},
+        }
+        let shown = match kiosk {
+          Some(ref mut kiosk) => kiosk.next_machine(&mut *machine, &mut rng),
+          None => match playlist {
+            Some(ref mut playlist) => playlist.next(&mut *machine, &mut rng),
+            None => None,
+          },
+        };
+        let random = shown.is_none() && fixed_table.is_none();
+        runner.counts.reset_steps = show_entry(&mut *machine, shown, &palette, default_count, &mut custom_palette);
+        // The search's best table not shown yet takes the random one's place.
+        match search {
+          Some(ref mut search) if random => {
+            for _ in range(0, search_generations) {
+              search.generation(&mut rng);
+            }
+            match search.take_best() {
+              Some((table, _)) => { let _ = machine.load_table(&table); },
+              None => {},
+            }
+          },
+          _ => {},
+        }
+        match interest {
+          Some(ref interest) if random && search.is_none() => match interest.screen(&mut *machine, &mut rng, chain) {
+            Some(kept_draws) => {
+              draws = kept_draws;
+              machine_draws = kept_draws;
+            },
+            None => {},
+          },
+          _ => {},
+        }
+        match highlight {
+          Some(ref mut highlight) => highlight.begin(&*machine),
+          None => {},
+        }
+        match hall_of_fame {
+          Some(ref mut hall_of_fame) => hall_of_fame.begin(),
+          None => {},
+        }
+        match event_log {
+          Some(ref mut log) => {
+            let _ = log.destroyed(fingerprint, reason, i);
+            fingerprint = log.created(&*machine, &rng, draws, runner.counts.total_steps).unwrap_or(0);
+          },
+          None => {},
+        }
+        runner.new_machine();
+      },
+      None => {},
     }
+
+    if wrote_frame && session_frames > 0 && runner.counts.frames % session_frames == 0 {
+      match session_file {
+        Some(ref path) => save_session(path, &*machine, &rng, &runner.loop_state(), &search),
+        None => {},
+      }
+    }
+  }
+}
+
+
+// Reopens the output that failed with 'why', for kiosk mode: a sink that
+// went away is given a moment and opened again, or left out if it can't be.
+fn reopen_output(outputs: &mut FrameSink, config: &toml::Value, why: std::io::IoError) {
+  let _ = writeln!(std::io::stderr(), "Error writing frame: {}. Reopening the output.", why.desc);
+  sleep(Duration::seconds(1));
+  match outputs.reopen_failed(config) {
+    Ok(()) => {},
+    Err(why) => {
+      let _ = writeln!(std::io::stderr(), "Unable to reopen the output, going on without it: {}", why);
+    },
+  }
+}
+
+
+// The path following 'flag' in 'args', if 'flag' is given.
+fn path_arg(args: &[String], flag: &str) -> Result<Option<Path>, Error> {
+  match args.iter().position(|arg| arg.as_slice() == flag) {
+    Some(index) => match args.get(index + 1) {
+      Some(path) => Ok(Some(Path::new(path.as_slice()))),
+      None => Err(Error::new(Usage, format!("{} needs a file", flag))),
+    },
+    None => Ok(None),
+  }
+}
+
+
+// Saves the session with the search's population, reporting rather than
+// failing on errors: the run can go on without it.
+fn save_session(path: &Path, machine: &Machine, rng: &SessionRng, state: &LoopState, search: &Option<Search>) {
+  let sections = search.as_ref().map_or(String::new(), |search| search.save_state());
+  match session::save(path, machine, rng, state, sections.as_slice()) {
+    Ok(()) => {},
+    Err(why) => {
+      let _ = writeln!(std::io::stderr(), "Unable to save session {}: {}", path.display(), why.desc);
+    },
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use toml;
+
+  use turing::{TuringMachine, NoFeature, Cardinal};
+  use turing::rng::SessionRng;
+  use super::{build_machine, path_arg, FramePacing};
+  use replay;
+
+  static MS: u64 = 1000000;
+
+  #[test]
+  fn pacing_waits_for_early_frames() {
+    // 10 fps: a frame every 100ms.
+    let mut pacing = FramePacing::new(10, false, 1, 1000, 0);
+    assert_eq!(pacing.frame(500, 30 * MS), (500, 70 * MS));
+    assert_eq!(pacing.frame(500, 150 * MS), (500, 50 * MS));
+  }
+
+  #[test]
+  fn late_frames_do_not_rush_the_next() {
+    let mut pacing = FramePacing::new(10, false, 1, 1000, 0);
+    assert_eq!(pacing.frame(500, 250 * MS), (500, 0));
+    // Due 100ms after the late frame, not at 200ms.
+    assert_eq!(pacing.frame(500, 300 * MS), (500, 50 * MS));
+  }
+
+  #[test]
+  fn pacing_can_adapt_the_steps() {
+    let mut pacing = FramePacing::new(10, true, 1, 1000, 0);
+    // Half the interval for 400 steps wants 800: halfway is 600.
+    let (steps, _) = pacing.frame(400, 50 * MS);
+    assert_eq!(steps, 600);
+    // 600ms from when that frame was shown wants 100: halfway is 350.
+    let (steps, _) = pacing.frame(600, 700 * MS);
+    assert_eq!(steps, 350);
+  }
+
+  #[test]
+  fn path_args_need_a_file() {
+    let args = vec!("turing".to_string(), "--resume".to_string(), "run.toml".to_string());
+    assert_eq!(path_arg(args.as_slice(), "--resume").ok().unwrap(), Some(Path::new("run.toml")));
+    assert_eq!(path_arg(args.as_slice(), "--checkpoint").ok().unwrap(), None);
+    assert!(path_arg(args.slice_to(2), "--resume").is_err());
+  }
+
+  #[test]
+  fn bad_configs_are_errors() {
+    let config: toml::Value = from_str("[turing]\nstates = 2\nsymbols = 2\nwidth = 4\nheight = 4\n\
+                                        picture_steps = 1\nreset_steps = 1\ntapes = 3\n").unwrap();
+    assert!(build_machine(&config, &mut SessionRng::new(0)).is_err());
+    let empty: toml::Value = from_str("").unwrap();
+    assert!(build_machine(&empty, &mut SessionRng::new(0)).is_err());
+  }
+
+  #[test]
+  fn out_of_range_saved_states_are_errors() {
+    let mut machine = TuringMachine::new(4, 4, 2, 2, NoFeature, Cardinal, &mut SessionRng::new(0));
+    let table = "table = [[0, 1, 1], [0, 1, 1], [0, 1, 1], [0, 1, 1]]";
+    let tape = "tape = \"00000000000000000000000000000000\"";
+    let state = |position: &str, state: &str| format!("position = {}\nstate = {}\n{}\n{}\n", position, state, tape, table);
+    assert!(replay::restore(&mut *machine, state("3", "1").as_slice()).is_ok());
+    // 256 would wrap to state 0, and -1 to a huge position.
+    assert!(replay::restore(&mut *machine, state("3", "256").as_slice()).is_err());
+    assert!(replay::restore(&mut *machine, state("-1", "0").as_slice()).is_err());
+    assert!(replay::restore(&mut *machine, "position = ").is_err());
   }
 }
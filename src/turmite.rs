@@ -0,0 +1,394 @@
+use std::hash::hash;
+use std::rand::Rng;
+use std::rand::distributions::{Range, IndependentSample};
+use serialize::json;
+use serialize::json::Json;
+use toml;
+
+use super::{Machine, Direction, Boundary, Color, BLACK, paint_cell, distinct_symbols};
+use error::{MachineError, BadTable, BadState};
+use neighbors::{NeighborTable, OFF_TAPE};
+use palette;
+use rng::SessionRng;
+use session;
+use trace;
+
+
+/// A turn relative to the heading of a turmite's head.
+#[deriving(PartialEq,Eq,Show)]
+pub enum Turn {
+  NoTurn,
+  Right,
+  UTurn,
+  Left,
+}
+
+impl Turn {
+  /// The turn whose 'as uint' value is 'index'. Each index is that many
+  /// quarter turns clockwise.
+  pub fn from_index(index: uint) -> Option<Turn> {
+    match index {
+      0 => Some(NoTurn),
+      1 => Some(Right),
+      2 => Some(UTurn),
+      3 => Some(Left),
+      _ => None,
+    }
+  }
+
+  /// The heading after turning from 'heading', one of the cardinal
+  /// directions.
+  pub fn apply(&self, heading: Direction) -> Direction {
+    Direction::from_index((heading as uint + *self as uint) % 4).unwrap()
+  }
+}
+
+
+/// A (next_state, write_symbol, turn) transition.
+pub type TurmiteTransition = (u8, u8, Turn);
+
+
+/// What 'save_state' saves of a turmite: the head, its heading, the table's
+/// entries as (next_state, write_symbol, turn) and the tape (see
+/// 'session::encode_tape'). Numbers are signed for the reason given at
+/// 'SavedMachine'.
+#[deriving(Encodable, Decodable)]
+pub struct SavedTurmite {
+  pub position: i64,
+  pub heading: Direction,
+  pub state: i64,
+  pub table: Vec<(i64, i64, i64)>,
+  pub tape: String,
+}
+
+
+/// A turmite: a 2D turing machine whose head has a heading. Transitions turn
+/// the head relative to the heading, and after turning it moves one cell
+/// forward. Langton's ant is the turmite with one state and two symbols that
+/// turns right on symbol 0 and left on symbol 1, flipping the symbol.
+///
+/// The table is stored as [read_symbol][state], like the single tape
+/// machine's without a position feature.
+#[deriving(Show)]
+pub struct TurmiteMachine {
+  width: uint,
+  height: uint,
+  states: u8,
+  symbols: u8,
+  position: uint,
+  heading: Direction,
+  state: u8,
+  table: Vec<TurmiteTransition>,
+  tape: Vec<u8>,
+  neighbors: NeighborTable,
+  halted: bool,
+
+  cell_width: uint,
+  cell_height: uint,
+  palette: Vec<Color>,
+  // Kept up to date by 'step', like the single tape machine's image.
+  image: Vec<u8>,
+}
+
+impl TurmiteMachine {
+  /// Creates a turmite with a random table, a blank tape and the head in
+  /// the middle of the tape, heading north.
+  pub fn new(width: uint, height: uint, states: u8, symbols: u8,
+             rng: &mut SessionRng) -> Box<TurmiteMachine> {
+    box TurmiteMachine {
+      width: width,
+      height: height,
+      states: states,
+      symbols: symbols,
+      position: TurmiteMachine::start(width, height),
+      heading: super::NORTH,
+      state: 0,
+      table: TurmiteMachine::random_table(rng, states, symbols),
+      tape: Vec::from_elem(width * height, 0u8),
+      neighbors: NeighborTable::torus(width, height),
+      halted: false,
+      cell_width: 1,
+      cell_height: 1,
+      palette: Vec::from_elem(symbols as uint, BLACK),
+      image: Vec::from_elem(width * height * 3, 0u8),
+    }
+  }
+
+  // The middle cell, where ants traditionally start.
+  fn start(width: uint, height: uint) -> uint {
+    height / 2 * width + width / 2
+  }
+
+  fn random_table(rng: &mut SessionRng, states: u8, symbols: u8) -> Vec<TurmiteTransition> {
+    let state_range = Range::new(0, states);
+    let symbol_range = Range::new(0, symbols);
+    Vec::from_fn(states as uint * symbols as uint, |_| {
+      (state_range.ind_sample(rng), symbol_range.ind_sample(rng),
+       Turn::from_index(rng.gen::<uint>() % 4).unwrap())
+    })
+  }
+
+  /// The head's heading, one of the cardinal directions.
+  pub fn heading(&self) -> Direction {
+    self.heading
+  }
+
+  /// Sets what the head does at the edges of the tape. A reflected head
+  /// steps back but keeps its heading.
+  pub fn set_boundary(&mut self, boundary: Boundary) {
+    self.neighbors = NeighborTable::bounded(self.width, self.height, boundary);
+  }
+
+  /// Sets the pixel size of each tape cell in the output image.
+  pub fn set_cell_size(&mut self, cell_width: uint, cell_height: uint) {
+    self.cell_width = cell_width;
+    self.cell_height = cell_height;
+    self.image = Vec::new();
+    self.image = Vec::from_elem(self.width * cell_width * self.height * cell_height * 3, 0u8);
+    self.rebuild_image();
+  }
+
+  // The transition for the current state and the symbol under the head.
+  fn transition(&self) -> TurmiteTransition {
+    let symbol = *self.tape.get(self.position);
+    *self.table.get(symbol as uint * self.states as uint + self.state as uint)
+  }
+
+  // Checks a table written by 'table_text' fits this machine.
+  fn parse_table(&self, table: &toml::Value) -> Result<Vec<TurmiteTransition>, MachineError> {
+    let entries: Vec<(i64, i64, i64)> = try!(toml::decode(table.clone())
+                                             .ok_or(BadTable(format!("Invalid table: {}", table))));
+    self.table_from_entries(entries)
+  }
+
+  // A table of this machine's shape from decoded entries.
+  fn table_from_entries(&self, entries: Vec<(i64, i64, i64)>) -> Result<Vec<TurmiteTransition>, MachineError> {
+    if entries.len() != self.table.len() {
+      return Err(BadTable(format!("Expected {} table entries, found {}", self.table.len(), entries.len())));
+    }
+    let mut parsed = Vec::with_capacity(entries.len());
+    for &(next_state, write_symbol, turn) in entries.iter() {
+      match Turn::from_index(turn as uint) {
+        Some(turn) if next_state >= 0 && next_state < self.states as i64 && write_symbol >= 0
+                      && write_symbol < self.symbols as i64 =>
+          parsed.push((next_state as u8, write_symbol as u8, turn)),
+        _ => return Err(BadTable(format!("Invalid table entry: [{}, {}, {}]", next_state, write_symbol, turn))),
+      }
+    }
+    Ok(parsed)
+  }
+
+  fn paint(&mut self, i: uint) {
+    let color = *self.palette.get(*self.tape.get(i) as uint);
+    paint_cell(&mut self.image, self.width * self.cell_width * 3, i % self.width, i / self.width,
+               self.cell_width, self.cell_height, color);
+  }
+
+  fn rebuild_image(&mut self) {
+    for i in range(0, self.width * self.height) {
+      self.paint(i);
+    }
+  }
+}
+
+impl Machine for TurmiteMachine {
+  fn step(&mut self) -> bool {
+    let read = *self.tape.get(self.position);
+    let (next_state, write_symbol, turn) = self.transition();
+    *self.tape.get_mut(self.position) = write_symbol;
+
+    let changed = write_symbol != read;
+    if changed {
+      let position = self.position;
+      self.paint(position);
+    }
+
+    self.state = next_state;
+    self.heading = turn.apply(self.heading);
+    match self.neighbors.get(self.position, self.heading) {
+      OFF_TAPE => self.halted = true,
+      next => self.position = next,
+    }
+
+    changed
+  }
+
+  fn set_palette(&mut self, palette: &Vec<Color>) {
+    self.palette = palette::complete(palette, self.symbols);
+    self.rebuild_image();
+  }
+
+  fn redraw(&mut self, _lut: bool) {
+    self.rebuild_image();
+  }
+
+  fn image<'a>(&'a self) -> &'a [u8] {
+    self.image.as_slice()
+  }
+
+  fn image_size(&self) -> (uint, uint) {
+    (self.width * self.cell_width, self.height * self.cell_height)
+  }
+
+  fn tape_size(&self) -> (uint, uint) {
+    (self.width, self.height)
+  }
+
+  fn symbols(&self) -> u8 {
+    self.symbols
+  }
+
+  fn fill_tape(&mut self, symbol: |uint| -> u8) {
+    for (i, cell) in self.tape.iter_mut().enumerate() {
+      *cell = symbol(i);
+    }
+    self.rebuild_image();
+  }
+
+  fn reset(&mut self, rng: &mut SessionRng) {
+    self.chain(rng);
+    self.tape = Vec::from_elem(self.width * self.height, 0u8);
+    self.position = TurmiteMachine::start(self.width, self.height);
+    self.heading = super::NORTH;
+    self.state = 0;
+    self.rebuild_image();
+  }
+
+  fn chain(&mut self, rng: &mut SessionRng) {
+    self.table = TurmiteMachine::random_table(rng, self.states, self.symbols);
+    self.halted = false;
+  }
+
+  fn table_text(&self) -> String {
+    let table: Vec<String> = self.table.iter().map(|&(next_state, write_symbol, turn)| {
+      format!("[{}, {}, {}]", next_state, write_symbol, turn as uint)
+    }).collect();
+    format!("[{}]", table.connect(", "))
+  }
+
+  fn table_json(&self) -> Json {
+    json::List(self.table.iter().map(|&(next_state, write_symbol, turn)| {
+      json::List(vec!(json::U64(next_state as u64), json::U64(write_symbol as u64), json::U64(turn as u64)))
+    }).collect())
+  }
+
+  fn save_state(&self) -> String {
+    toml::encode_str(&SavedTurmite {
+      position: self.position as i64,
+      heading: self.heading,
+      state: self.state as i64,
+      table: self.table.iter().map(|&(next_state, write_symbol, turn)| {
+        (next_state as i64, write_symbol as i64, turn as i64)
+      }).collect(),
+      tape: session::encode_tape(self.tape.as_slice()),
+    })
+  }
+
+  fn load_state(&mut self, saved: &toml::Value) -> Result<(), MachineError> {
+    let saved: SavedTurmite = try!(toml::decode(saved.clone()).ok_or(BadState("Invalid saved turmite".to_string())));
+    let len = self.width * self.height;
+    if saved.heading as uint >= 4 {
+      return Err(BadState(format!("Saved heading out of range: {}", saved.heading as uint)));
+    }
+    if saved.position < 0 || saved.position as u64 >= len as u64 || saved.state < 0
+        || saved.state >= self.states as i64 {
+      return Err(BadState("Saved position or state out of range".to_string()));
+    }
+    let table = try!(self.table_from_entries(saved.table));
+    let tape = try!(session::decode_tape(saved.tape.as_slice(), len, self.symbols));
+
+    self.position = saved.position as uint;
+    self.heading = saved.heading;
+    self.state = saved.state as u8;
+    self.table = table;
+    self.tape = tape;
+    self.halted = false;
+    self.rebuild_image();
+    Ok(())
+  }
+
+  fn load_table(&mut self, table: &toml::Value) -> Result<(), MachineError> {
+    self.table = try!(self.parse_table(table));
+    Ok(())
+  }
+
+  fn explain_step(&self) -> String {
+    let (next_state, write_symbol, turn) = self.transition();
+    format!("state {} reads symbol {} at ({}, {}) heading {} → write {}, turn {}, move {}, goto state {}",
+            self.state, *self.tape.get(self.position),
+            self.position % self.width, self.position / self.width, self.heading,
+            write_symbol, turn, turn.apply(self.heading), next_state)
+  }
+
+  fn tape_text(&self) -> String {
+    trace::grid_text(self.tape.as_slice(), self.width, &[self.position])
+  }
+
+  fn symbols_used(&self) -> uint {
+    distinct_symbols(&[self.tape.as_slice()])
+  }
+
+  fn halted(&self) -> bool {
+    self.halted
+  }
+
+  fn head_cells(&self) -> Vec<(uint, uint, uint, uint)> {
+    let (x, y) = (self.position % self.width, self.position / self.width);
+    vec!((x * self.cell_width, y * self.cell_height, self.cell_width, self.cell_height))
+  }
+
+  fn local_hash(&self) -> Option<u64> {
+    let window = self.neighbors.window(self.tape.as_slice(), self.position);
+    Some(hash(&(self.position, self.heading as uint, self.state, window)))
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::{TurmiteMachine, Right, Left, UTurn, NoTurn};
+  use super::super::{Machine, NORTH, EAST, SOUTH, WEST};
+  use rng::SessionRng;
+
+  fn ant(width: uint, height: uint) -> Box<TurmiteMachine> {
+    let mut machine = TurmiteMachine::new(width, height, 1, 2, &mut SessionRng::new(0));
+    machine.table = vec!((0, 1, Right), (0, 0, Left));
+    machine
+  }
+
+  #[test]
+  fn turns_are_relative_to_the_heading() {
+    assert_eq!(Right.apply(NORTH), EAST);
+    assert_eq!(Right.apply(WEST), NORTH);
+    assert_eq!(Left.apply(NORTH), WEST);
+    assert_eq!(UTurn.apply(EAST), WEST);
+    assert_eq!(NoTurn.apply(SOUTH), SOUTH);
+  }
+
+  #[test]
+  fn ant_walks_a_square_on_a_blank_tape() {
+    // Turning right on every blank cell traces a 2x2 square clockwise.
+    let mut machine = ant(5, 5);
+    let start = machine.position;
+    assert_eq!(start, 12);
+    let positions: Vec<uint> = range(0u, 4).map(|_| { machine.step(); machine.position }).collect();
+    assert_eq!(positions, vec!(13, 18, 17, 12));
+    assert_eq!(machine.heading, NORTH);
+    // Back on a flipped cell it turns left.
+    machine.step();
+    assert_eq!((machine.position, machine.heading), (11, WEST));
+    assert_eq!(*machine.tape.get(start), 0);
+  }
+
+  #[test]
+  fn saved_states_load_back() {
+    let mut saved = ant(6, 4);
+    for _ in range(0u, 50) {
+      saved.step();
+    }
+    let text = saved.save_state();
+    let mut loaded = TurmiteMachine::new(6, 4, 1, 2, &mut SessionRng::new(1));
+    loaded.load_state(&from_str(text.as_slice()).unwrap()).unwrap();
+    assert_eq!(loaded.save_state(), text);
+  }
+}
@@ -0,0 +1,411 @@
+use std::hash::hash;
+use std::rand::distributions::{Range, IndependentSample};
+use serialize::json;
+use serialize::json::Json;
+use toml;
+
+use super::{Machine, Direction, DirectionSet, Boundary, Color, BLACK, paint_cell, distinct_symbols};
+use error::{MachineError, BadTable, BadState};
+use neighbors::{NeighborTable, OFF_TAPE};
+use palette;
+use rng::SessionRng;
+use session;
+use trace;
+
+
+/// How the two tapes are combined into one output image.
+#[deriving(PartialEq,Eq,Show)]
+pub enum TwoTapeDisplay {
+  /// The tapes are drawn next to each other, so the image is twice as wide.
+  SideBySide,
+  /// Both tapes are drawn on top of each other by adding their colors.
+  Combined,
+}
+
+
+/// What 'save_state' saves of a two tape machine: both heads, the state,
+/// the table's entries as (next_state, write_a, write_b, move_a, move_b) and
+/// both tapes (see 'session::encode_tape'). Numbers are signed for the
+/// reason given at 'SavedMachine'.
+#[deriving(Encodable, Decodable)]
+pub struct SavedTwoTape {
+  pub positions: (i64, i64),
+  pub state: i64,
+  pub table: Vec<(i64, i64, i64, Direction, Direction)>,
+  pub tape_a: String,
+  pub tape_b: String,
+}
+
+
+/// A finite 2D turing machine with two tapes and a head on each.
+/// - Both tapes have a size of 'width'*'height'.
+/// - The table is indexed by the current state and the symbols under both
+///   heads. It gives the next state and, for each tape, the symbol to write
+///   and the direction to move.
+#[deriving(Show)]
+pub struct TwoTapeMachine {
+  width: uint,
+  height: uint,
+  states: u8,
+  symbols: u8,
+  positions: (uint, uint),
+  state: u8,
+  // The directions random tables move in.
+  directions: DirectionSet,
+  // transition [curr_state, read_a, read_b] -> [next_state, write_a, write_b, move_a, move_b]
+  table: Vec<(u8, u8, u8, Direction, Direction)>,
+  tape_a: Vec<u8>,
+  tape_b: Vec<u8>,
+  // Shared by both tapes since they have the same size.
+  neighbors: NeighborTable,
+  halted: bool,
+
+  display: TwoTapeDisplay,
+  cell_width: uint,
+  cell_height: uint,
+  palette: Vec<Color>,
+  // Kept up to date by 'step', like the single tape machine's image.
+  image: Vec<u8>,
+}
+
+impl TwoTapeMachine {
+  pub fn new(width: uint, height: uint, states: u8, symbols: u8, display: TwoTapeDisplay,
+             cell_width: uint, cell_height: uint, directions: DirectionSet,
+             rng: &mut SessionRng) -> Box<TwoTapeMachine> {
+    let columns = match display {
+      SideBySide => width * 2,
+      Combined => width,
+    };
+    box TwoTapeMachine {
+      width: width,
+      height: height,
+      states: states,
+      symbols: symbols,
+      positions: (0, 0),
+      state: 0,
+      directions: directions,
+      table: TwoTapeMachine::random_table(rng, states, symbols, directions),
+      tape_a: Vec::from_elem(width * height, 0u8),
+      tape_b: Vec::from_elem(width * height, 0u8),
+      neighbors: NeighborTable::torus(width, height),
+      halted: false,
+      display: display,
+      cell_width: cell_width,
+      cell_height: cell_height,
+      palette: Vec::from_elem(symbols as uint, BLACK),
+      image: Vec::from_elem(columns * cell_width * height * cell_height * 3, 0u8),
+    }
+  }
+
+  /// Sets what both heads do at the edges of their tapes.
+  pub fn set_boundary(&mut self, boundary: Boundary) {
+    self.neighbors = NeighborTable::bounded(self.width, self.height, boundary);
+  }
+
+  fn random_table(rng: &mut SessionRng, states: u8, symbols: u8,
+                  directions: DirectionSet) -> Vec<(u8, u8, u8, Direction, Direction)> {
+    let state_range = Range::new(0, states);
+    let symbol_range = Range::new(0, symbols);
+    let len = states as uint * symbols as uint * symbols as uint;
+    Vec::from_fn(len, |_| {
+      (state_range.ind_sample(rng),
+       symbol_range.ind_sample(rng), symbol_range.ind_sample(rng),
+       directions.random(rng), directions.random(rng))
+    })
+  }
+
+  // The transition for the current state and the symbols under both heads.
+  fn transition(&self) -> (u8, u8, u8, Direction, Direction) {
+    let (pos_a, pos_b) = self.positions;
+    let symbols = self.symbols as uint;
+    let index = (self.state as uint * symbols + *self.tape_a.get(pos_a) as uint) * symbols
+                + *self.tape_b.get(pos_b) as uint;
+    *self.table.get(index)
+  }
+
+  // Checks a table written by 'table_text' fits this machine.
+  fn parse_table(&self, table: &toml::Value) -> Result<Vec<(u8, u8, u8, Direction, Direction)>, MachineError> {
+    let entries: Vec<(i64, i64, i64, Direction, Direction)> =
+      try!(toml::decode(table.clone()).ok_or(BadTable(format!("Invalid table: {}", table))));
+    self.table_from_entries(entries)
+  }
+
+  // A table of this machine's shape from decoded entries.
+  fn table_from_entries(&self, entries: Vec<(i64, i64, i64, Direction, Direction)>)
+      -> Result<Vec<(u8, u8, u8, Direction, Direction)>, MachineError> {
+    if entries.len() != self.table.len() {
+      return Err(BadTable(format!("Expected {} table entries, found {}", self.table.len(), entries.len())));
+    }
+    let symbol = |symbol: i64| symbol >= 0 && symbol < self.symbols as i64;
+    let mut parsed = Vec::with_capacity(entries.len());
+    for &(next_state, write_a, write_b, move_a, move_b) in entries.iter() {
+      if next_state < 0 || next_state >= self.states as i64 || !symbol(write_a) || !symbol(write_b) {
+        return Err(BadTable(format!("Invalid table entry: [{}, {}, {}, {}, {}]", next_state, write_a, write_b,
+                                    move_a as uint, move_b as uint)));
+      }
+      parsed.push((next_state as u8, write_a as u8, write_b as u8, move_a, move_b));
+    }
+    Ok(parsed)
+  }
+
+  // Repaints the pixels for cell 'i' of both tapes.
+  fn paint(&mut self, i: uint) {
+    let (x, y) = (i % self.width, i / self.width);
+    let a = *self.palette.get(*self.tape_a.get(i) as uint);
+    let b = *self.palette.get(*self.tape_b.get(i) as uint);
+    match self.display {
+      SideBySide => {
+        let row_bytes = self.width * 2 * self.cell_width * 3;
+        paint_cell(&mut self.image, row_bytes, x, y, self.cell_width, self.cell_height, a);
+        paint_cell(&mut self.image, row_bytes, x + self.width, y, self.cell_width, self.cell_height, b);
+      },
+      Combined => {
+        let row_bytes = self.width * self.cell_width * 3;
+        let mut color = [0u8, ..3];
+        for c in range(0u, 3) {
+          color[c] = a[c].saturating_add(b[c]);
+        }
+        paint_cell(&mut self.image, row_bytes, x, y, self.cell_width, self.cell_height, color);
+      },
+    }
+  }
+
+  fn rebuild_image(&mut self) {
+    for i in range(0, self.width * self.height) {
+      self.paint(i);
+    }
+  }
+}
+
+impl Machine for TwoTapeMachine {
+  fn step(&mut self) -> bool {
+    let (pos_a, pos_b) = self.positions;
+    let read_a = *self.tape_a.get(pos_a);
+    let read_b = *self.tape_b.get(pos_b);
+    let (next_state, write_a, write_b, move_a, move_b) = self.transition();
+    *self.tape_a.get_mut(pos_a) = write_a;
+    *self.tape_b.get_mut(pos_b) = write_b;
+
+    let changed = write_a != read_a || write_b != read_b;
+    if changed {
+      self.paint(pos_a);
+      self.paint(pos_b);
+    }
+
+    self.state = next_state;
+    // A head moving off its tape halts the machine, and both heads stay put.
+    let next = (self.neighbors.get(pos_a, move_a), self.neighbors.get(pos_b, move_b));
+    match next {
+      (OFF_TAPE, _) | (_, OFF_TAPE) => self.halted = true,
+      _ => self.positions = next,
+    }
+
+    changed
+  }
+
+  fn set_palette(&mut self, palette: &Vec<Color>) {
+    self.palette = palette::complete(palette, self.symbols);
+    self.rebuild_image();
+  }
+
+  fn redraw(&mut self, _lut: bool) {
+    self.rebuild_image();
+  }
+
+  fn image<'a>(&'a self) -> &'a [u8] {
+    self.image.as_slice()
+  }
+
+  fn image_size(&self) -> (uint, uint) {
+    let columns = match self.display {
+      SideBySide => self.width * 2,
+      Combined => self.width,
+    };
+    (columns * self.cell_width, self.height * self.cell_height)
+  }
+
+  fn tape_size(&self) -> (uint, uint) {
+    (self.width, self.height)
+  }
+
+  fn symbols(&self) -> u8 {
+    self.symbols
+  }
+
+  fn fill_tape(&mut self, symbol: |uint| -> u8) {
+    for i in range(0, self.width * self.height) {
+      let value = symbol(i);
+      *self.tape_a.get_mut(i) = value;
+      *self.tape_b.get_mut(i) = value;
+    }
+    self.rebuild_image();
+  }
+
+  fn reset(&mut self, rng: &mut SessionRng) {
+    self.chain(rng);
+    self.tape_a = Vec::from_elem(self.width * self.height, 0u8);
+    self.tape_b = Vec::from_elem(self.width * self.height, 0u8);
+    self.rebuild_image();
+  }
+
+  fn chain(&mut self, rng: &mut SessionRng) {
+    self.table = TwoTapeMachine::random_table(rng, self.states, self.symbols, self.directions);
+    self.halted = false;
+  }
+
+  fn table_text(&self) -> String {
+    let table: Vec<String> = self.table.iter().map(|&(next_state, write_a, write_b, move_a, move_b)| {
+      format!("[{}, {}, {}, {}, {}]", next_state, write_a, write_b, move_a as uint, move_b as uint)
+    }).collect();
+    format!("[{}]", table.connect(", "))
+  }
+
+  fn table_json(&self) -> Json {
+    json::List(self.table.iter().map(|&(next_state, write_a, write_b, move_a, move_b)| {
+      json::List([next_state as u64, write_a as u64, write_b as u64, move_a as u64, move_b as u64].iter()
+                 .map(|&value| json::U64(value)).collect())
+    }).collect())
+  }
+
+  fn save_state(&self) -> String {
+    let (pos_a, pos_b) = self.positions;
+    toml::encode_str(&SavedTwoTape {
+      positions: (pos_a as i64, pos_b as i64),
+      state: self.state as i64,
+      table: self.table.iter().map(|&(next_state, write_a, write_b, move_a, move_b)| {
+        (next_state as i64, write_a as i64, write_b as i64, move_a, move_b)
+      }).collect(),
+      tape_a: session::encode_tape(self.tape_a.as_slice()),
+      tape_b: session::encode_tape(self.tape_b.as_slice()),
+    })
+  }
+
+  fn load_state(&mut self, saved: &toml::Value) -> Result<(), MachineError> {
+    let saved: SavedTwoTape = try!(toml::decode(saved.clone())
+                                   .ok_or(BadState("Invalid saved two tape machine".to_string())));
+    let len = self.width * self.height;
+    let (pos_a, pos_b) = saved.positions;
+    let position = |position: i64| position >= 0 && (position as u64) < len as u64;
+    if !position(pos_a) || !position(pos_b) || saved.state < 0 || saved.state >= self.states as i64 {
+      return Err(BadState("Saved positions or state out of range".to_string()));
+    }
+    let table = try!(self.table_from_entries(saved.table));
+    let tape_a = try!(session::decode_tape(saved.tape_a.as_slice(), len, self.symbols));
+    let tape_b = try!(session::decode_tape(saved.tape_b.as_slice(), len, self.symbols));
+
+    self.positions = (pos_a as uint, pos_b as uint);
+    self.state = saved.state as u8;
+    self.table = table;
+    self.tape_a = tape_a;
+    self.tape_b = tape_b;
+    self.halted = false;
+    self.rebuild_image();
+    Ok(())
+  }
+
+  fn load_table(&mut self, table: &toml::Value) -> Result<(), MachineError> {
+    self.table = try!(self.parse_table(table));
+    Ok(())
+  }
+
+  fn explain_step(&self) -> String {
+    let (pos_a, pos_b) = self.positions;
+    let (next_state, write_a, write_b, move_a, move_b) = self.transition();
+    format!("state {} reads symbols {} and {} → write {} and {}, move {} and {}, goto state {}",
+            self.state, *self.tape_a.get(pos_a), *self.tape_b.get(pos_b),
+            write_a, write_b, move_a, move_b, next_state)
+  }
+
+  fn tape_text(&self) -> String {
+    let (pos_a, pos_b) = self.positions;
+    format!("Tape A:\n{}Tape B:\n{}",
+            trace::grid_text(self.tape_a.as_slice(), self.width, &[pos_a]),
+            trace::grid_text(self.tape_b.as_slice(), self.width, &[pos_b]))
+  }
+
+  fn symbols_used(&self) -> uint {
+    distinct_symbols(&[self.tape_a.as_slice(), self.tape_b.as_slice()])
+  }
+
+  fn halted(&self) -> bool {
+    self.halted
+  }
+
+  // Side by side, tape B's head is on the right half.
+  fn head_cells(&self) -> Vec<(uint, uint, uint, uint)> {
+    let (pos_a, pos_b) = self.positions;
+    let offset = match self.display {
+      SideBySide => self.width,
+      Combined => 0,
+    };
+    let cell = |position: uint, offset: uint| {
+      ((position % self.width + offset) * self.cell_width, position / self.width * self.cell_height,
+       self.cell_width, self.cell_height)
+    };
+    vec!(cell(pos_a, 0), cell(pos_b, offset))
+  }
+
+  fn local_hash(&self) -> Option<u64> {
+    let (pos_a, pos_b) = self.positions;
+    Some(hash(&(pos_a, pos_b, self.state, self.neighbors.window(self.tape_a.as_slice(), pos_a),
+                self.neighbors.window(self.tape_b.as_slice(), pos_b))))
+  }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::{TwoTapeMachine, TwoTapeDisplay, SideBySide, Combined};
+  use super::super::{Machine, Cardinal, NORTH, EAST, SOUTH};
+  use rng::SessionRng;
+
+  fn machine(width: uint, height: uint, display: TwoTapeDisplay) -> Box<TwoTapeMachine> {
+    TwoTapeMachine::new(width, height, 2, 2, display, 1, 1, Cardinal, &mut SessionRng::new(0))
+  }
+
+  #[test]
+  fn both_heads_follow_the_table() {
+    let mut machine = machine(4, 3, SideBySide);
+    // Indexed by (state * symbols + read_a) * symbols + read_b.
+    machine.table = vec!((1, 1, 1, EAST, SOUTH), (0, 0, 0, NORTH, NORTH), (0, 0, 0, NORTH, NORTH),
+                         (0, 0, 0, NORTH, NORTH), (0, 1, 0, EAST, EAST), (0, 0, 0, NORTH, NORTH),
+                         (0, 0, 0, NORTH, NORTH), (0, 0, 0, NORTH, NORTH));
+    assert!(machine.step());
+    assert_eq!((machine.positions, machine.state), ((1, 4), 1));
+    assert!(machine.step());
+    assert_eq!((machine.positions, machine.state), ((2, 5), 0));
+    assert_eq!(machine.tape_a, vec!(1u8, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0));
+    assert_eq!(machine.tape_b, vec!(1u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0));
+  }
+
+  #[test]
+  fn tapes_are_drawn_side_by_side_or_combined() {
+    let palette = vec!([0, 0, 0], [100, 100, 100]);
+    let mut side_by_side = machine(2, 1, SideBySide);
+    side_by_side.tape_a = vec!(1, 0);
+    side_by_side.tape_b = vec!(0, 1);
+    side_by_side.set_palette(&palette);
+    assert_eq!(side_by_side.image_size(), (4, 1));
+    assert_eq!(side_by_side.image(), [100u8, 100, 100, 0, 0, 0, 0, 0, 0, 100, 100, 100].as_slice());
+
+    let mut combined = machine(2, 1, Combined);
+    combined.tape_a = vec!(1, 0);
+    combined.tape_b = vec!(1, 1);
+    combined.set_palette(&palette);
+    assert_eq!(combined.image_size(), (2, 1));
+    assert_eq!(combined.image(), [200u8, 200, 200, 100, 100, 100].as_slice());
+  }
+
+  #[test]
+  fn saved_states_load_back() {
+    let mut saved = machine(6, 4, SideBySide);
+    for _ in range(0u, 50) {
+      saved.step();
+    }
+    let text = saved.save_state();
+    let mut loaded = TwoTapeMachine::new(6, 4, 2, 2, SideBySide, 1, 1, Cardinal, &mut SessionRng::new(1));
+    loaded.load_state(&from_str(text.as_slice()).unwrap()).unwrap();
+    assert_eq!(loaded.save_state(), text);
+    assert_eq!(loaded.image(), saved.image());
+  }
+}
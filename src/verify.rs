@@ -0,0 +1,97 @@
+use turing::{Machine, Color, TuringMachine, NoFeature, Cardinal};
+use turing::rng::SessionRng;
+
+
+/// Runs 'machine' for 'steps' steps and returns the checksum of its frame.
+pub fn frame_checksum(machine: &mut Machine, steps: u64) -> u64 {
+  for _ in range(0, steps) {
+    machine.step();
+  }
+  fnv1a(machine.image())
+}
+
+
+/// The checksum of the frame of a single tape machine seeded with 'seed'
+/// after 'steps' steps.
+pub fn seeded_checksum(width: uint, height: uint, states: u8, symbols: u8, palette: &Vec<Color>,
+                       seed: u64, steps: u64) -> u64 {
+  let mut rng = SessionRng::new(seed);
+  let mut machine = TuringMachine::new(width, height, states, symbols, NoFeature, Cardinal, &mut rng);
+  machine.set_palette(palette);
+  frame_checksum(&mut *machine, steps)
+}
+
+
+/// 64 bit FNV-1a. Used instead of the standard library's hashing since the
+/// checksums are stored and must never change.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+  let mut hash = 0xcbf29ce484222325u64;
+  for &byte in bytes.iter() {
+    hash ^= byte as u64;
+    hash *= 0x100000001b3;
+  }
+  hash
+}
+
+
+#[cfg(test)]
+mod test {
+  use std::io::{BufferedReader, File};
+  use toml;
+
+  use super::{seeded_checksum, fnv1a};
+  use turing::Color;
+  use super::super::load_palette;
+
+  fn palette(symbols: u8) -> Vec<Color> {
+    let config: toml::Value = from_str(format!("[turing]\nsymbols = {}", symbols).as_slice()).unwrap();
+    load_palette(&config)
+  }
+
+  #[test]
+  fn fnv1a_known_values() {
+    assert_eq!(fnv1a(&[]), 0xcbf29ce484222325);
+    assert_eq!(fnv1a(b"a"), 0xaf63dc4c8601ec8c);
+  }
+
+  #[test]
+  fn same_seed_same_frame() {
+    let palette = palette(4);
+    assert_eq!(seeded_checksum(32, 24, 3, 4, &palette, 42, 10000),
+               seeded_checksum(32, 24, 3, 4, &palette, 42, 10000));
+  }
+
+  #[test]
+  fn different_seeds_different_frames() {
+    let palette = palette(4);
+    assert!(seeded_checksum(32, 24, 3, 4, &palette, 1, 10000) !=
+            seeded_checksum(32, 24, 3, 4, &palette, 2, 10000));
+  }
+
+  // Compares against the frames recorded in tests/golden.txt. Each line is
+  // "width height states symbols seed steps checksum"; add lines with the
+  // output of 'turing verify' before changing the step or render code.
+  #[test]
+  fn golden_frames() {
+    let mut file = BufferedReader::new(File::open(&Path::new("tests/golden.txt")));
+    let mut entries = 0u;
+    for line in file.lines() {
+      let line = line.unwrap();
+      let line = line.as_slice().trim();
+      if line.is_empty() || line.starts_with("#") {
+        continue;
+      }
+      let fields: Vec<&str> = line.split(' ').filter(|field| !field.is_empty()).collect();
+      assert_eq!(fields.len(), 7);
+      let number = |i: uint| from_str::<u64>(*fields.get(i)).unwrap();
+      let expected = ::std::num::from_str_radix::<u64>(*fields.get(6), 16).unwrap();
+      let symbols = number(3) as u8;
+      assert_eq!(seeded_checksum(number(0) as uint, number(1) as uint, number(2) as u8, symbols,
+                                 &palette(symbols), number(4), number(5)),
+                 expected);
+      entries += 1;
+    }
+    // An empty file would check nothing.
+    assert!(entries > 0, "tests/golden.txt has no entries");
+  }
+}
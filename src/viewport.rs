@@ -0,0 +1,192 @@
+use std::cmp::{max, min};
+
+
+// The most a viewport zooms in or out: 256 pixels a pixel either way.
+pub static MAX_ZOOM: int = 8;
+
+
+/// Shows a window of the machine's image at a fixed size, so huge tapes can
+/// run while frames stay at video resolution. The window is centred on a
+/// point of the image and wraps around its edges like the tape does. Each
+/// zoom level doubles the size things are shown at: at zoom 1 every image
+/// pixel covers 2x2 frame pixels, at zoom -1 every other pixel is shown.
+pub struct Viewport {
+  width: uint,
+  height: uint,
+  // The image point in the middle of the frame, or None to start in the
+  // middle of the image.
+  center: Option<(int, int)>,
+  zoom: int,
+  // When following the head: the size of the box in the middle of the frame
+  // the head moves freely in, as a percentage of the frame, and the frames
+  // the window takes to catch up with it.
+  follow: Option<(uint, uint)>,
+  frame: Vec<u8>,
+}
+
+impl Viewport {
+  pub fn new(width: uint, height: uint, center: Option<(int, int)>, zoom: int) -> Viewport {
+    Viewport { width: width, height: height, center: center, zoom: max(-MAX_ZOOM, min(MAX_ZOOM, zoom)),
+               follow: None, frame: Vec::new() }
+  }
+
+  /// Keeps the head in view from now on: while it is outside the middle
+  /// 'deadzone' percent of the frame, each frame moves the window a
+  /// 'smoothing'th of the way to bring it back in. A smoothing of 1 jumps.
+  pub fn follow(&mut self, deadzone: uint, smoothing: uint) {
+    self.follow = Some((deadzone, max(1, smoothing)));
+  }
+
+  /// Whether the window follows the head.
+  pub fn following(&self) -> bool {
+    self.follow.is_some()
+  }
+
+  /// Moves the window towards the head at image pixel ('x', 'y') of a
+  /// 'width' by 'height' image, if it follows the head.
+  pub fn track(&mut self, x: uint, y: uint, width: uint, height: uint) {
+    let (deadzone, smoothing) = match self.follow {
+      Some(follow) => follow,
+      None => return,
+    };
+    let (center_x, center_y) = self.center.unwrap_or((x as int, y as int));
+    let (free_x, free_y) = (self.image_pixels(self.width) * deadzone as int / 200,
+                            self.image_pixels(self.height) * deadzone as int / 200);
+    self.center = Some((chase(center_x, x, width, free_x, smoothing),
+                        chase(center_y, y, height, free_y, smoothing)));
+  }
+
+  /// The size of the frames shown.
+  pub fn size(&self) -> (uint, uint) {
+    (self.width, self.height)
+  }
+
+  /// Moves the window by 'dx' and 'dy' quarters of the frame. Does nothing
+  /// before the first frame, when the image isn't known yet.
+  pub fn pan(&mut self, dx: int, dy: int) {
+    match self.center {
+      Some((x, y)) => {
+        let step_x = max(1, self.image_pixels(self.width / 4));
+        let step_y = max(1, self.image_pixels(self.height / 4));
+        self.center = Some((x + dx * step_x, y + dy * step_y));
+      },
+      None => {},
+    }
+  }
+
+  /// Zooms in by 'levels', or out for negative levels, keeping the middle of
+  /// the frame where it is.
+  pub fn zoom(&mut self, levels: int) {
+    self.zoom = max(-MAX_ZOOM, min(MAX_ZOOM, self.zoom + levels));
+  }
+
+  /// The window of 'image', a 'width' by 'height' BGR image.
+  pub fn view<'a>(&'a mut self, image: &[u8], width: uint, height: uint) -> &'a [u8] {
+    let (center_x, center_y) = self.center.unwrap_or(((width / 2) as int, (height / 2) as int));
+    self.center = Some((center_x, center_y));
+    let columns: Vec<uint> = range(0, self.width).map(|x| self.source(center_x, x, self.width, width)).collect();
+    self.frame.clear();
+    for y in range(0, self.height) {
+      let row = self.source(center_y, y, self.height, height) * width;
+      for &x in columns.iter() {
+        let p = (row + x) * 3;
+        self.frame.push_all(image.slice(p, p + 3));
+      }
+    }
+    self.frame.as_slice()
+  }
+
+  // The image coordinate shown 'v' pixels along a frame 'view' pixels
+  // across, around 'center' of an image 'size' pixels across.
+  fn source(&self, center: int, v: uint, view: uint, size: uint) -> uint {
+    let offset = self.image_pixels(v) - self.image_pixels(view / 2);
+    let size = size as int;
+    (((center + offset) % size + size) % size) as uint
+  }
+
+  // The image pixels covered by 'pixels' frame pixels.
+  fn image_pixels(&self, pixels: uint) -> int {
+    if self.zoom >= 0 {
+      (pixels >> self.zoom as uint) as int
+    } else {
+      (pixels << (-self.zoom) as uint) as int
+    }
+  }
+}
+
+
+// Where 'center' moves to follow 'target' on an axis 'size' pixels around,
+// when the target may be 'free' pixels either side of it.
+fn chase(center: int, target: uint, size: uint, free: int, smoothing: uint) -> int {
+  // The shortest way round to the target.
+  let size = size as int;
+  let mut distance = ((target as int - center) % size + size) % size;
+  if distance > size / 2 {
+    distance -= size;
+  }
+  let outside = if distance > free {
+    distance - free
+  } else if distance < -free {
+    distance + free
+  } else {
+    return center;
+  };
+  let step = outside / smoothing as int;
+  center + if step != 0 { step } else if outside > 0 { 1 } else { -1 }
+}
+
+
+#[cfg(test)]
+mod test {
+  use super::Viewport;
+
+  // A 4x2 image whose pixels' blue bytes are their indexes.
+  fn image() -> Vec<u8> {
+    range(0u8, 8).flat_map(|i| vec!(i, 0, 0).into_iter()).collect()
+  }
+
+  fn blues(frame: &[u8]) -> Vec<u8> {
+    frame.chunks(3).map(|pixel| pixel[0]).collect()
+  }
+
+  #[test]
+  fn views_wrap_and_zoom() {
+    let image = image();
+    let mut viewport = Viewport::new(2, 2, None, 0);
+    assert_eq!(blues(viewport.view(image.as_slice(), 4, 2)), vec!(1, 2, 5, 6));
+    viewport.pan(-1, 0);
+    assert_eq!(blues(viewport.view(image.as_slice(), 4, 2)), vec!(0, 1, 4, 5));
+    viewport.pan(-1, 0);
+    assert_eq!(blues(viewport.view(image.as_slice(), 4, 2)), vec!(3, 0, 7, 4));
+    let mut zoomed = Viewport::new(4, 4, Some((1, 1)), 1);
+    assert_eq!(blues(zoomed.view(image.as_slice(), 4, 2)), vec!(0, 0, 1, 1, 0, 0, 1, 1, 4, 4, 5, 5, 4, 4, 5, 5));
+    zoomed.zoom(-1);
+    assert_eq!(blues(zoomed.view(image.as_slice(), 4, 2)), vec!(3, 0, 1, 2, 7, 4, 5, 6, 3, 0, 1, 2, 7, 4, 5, 6));
+    let mut shrunk = Viewport::new(2, 1, Some((2, 0)), -1);
+    assert_eq!(blues(shrunk.view(image.as_slice(), 4, 2)), vec!(0, 2));
+  }
+
+  #[test]
+  fn following_chases_the_head() {
+    let image = image();
+    // Jumps to the head at once.
+    let mut viewport = Viewport::new(2, 1, Some((0, 0)), 0);
+    viewport.follow(0, 1);
+    viewport.track(3, 1, 4, 2);
+    assert_eq!(blues(viewport.view(image.as_slice(), 4, 2)), vec!(6, 7));
+    // Catches up half the way each frame, going the short way round.
+    let mut smoothed = Viewport::new(100, 100, Some((10, 50)), 0);
+    smoothed.follow(0, 2);
+    smoothed.track(190, 50, 200, 100);
+    assert_eq!(smoothed.center, Some((0, 50)));
+    smoothed.track(190, 50, 200, 100);
+    assert_eq!(smoothed.center, Some((-5, 50)));
+    // Leaves the head be in the middle half of the frame.
+    let mut lazy = Viewport::new(100, 100, Some((50, 50)), 0);
+    lazy.follow(50, 1);
+    lazy.track(70, 30, 200, 200);
+    assert_eq!(lazy.center, Some((50, 50)));
+    lazy.track(80, 50, 200, 200);
+    assert_eq!(lazy.center, Some((55, 50)));
+  }
+}
@@ -0,0 +1,140 @@
+use output::ImageSink;
+
+
+/// A sink that shows frames in a window, for the "window" output. Only
+/// available when built with '--features window', which needs SDL2.
+#[cfg(feature = "window")]
+pub fn open(title: &str) -> Result<Box<ImageSink>, String> {
+  sdl::WindowSink::new(title).map(|sink| box sink as Box<ImageSink>)
+}
+
+#[cfg(not(feature = "window"))]
+pub fn open(_title: &str) -> Result<Box<ImageSink>, String> {
+  Err("The window output needs a build with --features window (SDL2)".to_string())
+}
+
+
+#[cfg(feature = "window")]
+mod sdl {
+  use std::io::{IoResult, IoError, BrokenPipe, OtherIoError};
+  use sdl2;
+  use sdl2::event::{poll_event, NoEvent, QuitEvent, KeyDownEvent};
+  use sdl2::keycode::{KeyCode, SpaceKey, NKey, SKey, EqualsKey, KpPlusKey, MinusKey, KpMinusKey};
+  use sdl2::keycode::{LeftKey, RightKey, UpKey, DownKey, HKey, LKey, KKey, JKey, ZKey, XKey};
+  use sdl2::pixels::BGR24;
+  use sdl2::render::{Renderer, Texture, DriverAuto, ACCELERATED, AccessStreaming};
+  use sdl2::video::{Window, PosCentered, SHOWN, RESIZABLE};
+
+  use keys;
+  use output::ImageSink;
+
+  fn error(desc: &'static str, why: String) -> IoError {
+    IoError { kind: OtherIoError, desc: desc, detail: Some(why) }
+  }
+
+  /// Streams frames into a texture the size of the frame, stretched over the
+  /// window. The window is opened with the first frame, when its size is
+  /// known.
+  pub struct WindowSink {
+    title: String,
+    renderer: Option<Renderer<Window>>,
+    texture: Option<Texture>,
+    size: (uint, uint),
+    // Set once the window has been closed.
+    closed: bool,
+  }
+
+  impl WindowSink {
+    pub fn new(title: &str) -> Result<WindowSink, String> {
+      if !sdl2::init(sdl2::INIT_VIDEO) {
+        return Err(format!("Unable to initialize SDL: {}", sdl2::get_error()));
+      }
+      Ok(WindowSink { title: title.to_string(), renderer: None, texture: None, size: (0, 0),
+                     closed: false })
+    }
+
+    // Opens the window, or makes a new texture when the frame size changed.
+    fn prepare(&mut self, width: uint, height: uint) -> IoResult<()> {
+      if self.texture.is_some() && self.size == (width, height) {
+        return Ok(());
+      }
+      if self.renderer.is_none() {
+        let window = try!(Window::new(self.title.as_slice(), PosCentered, PosCentered,
+                                      width as int, height as int, SHOWN | RESIZABLE)
+                          .map_err(|why| error("unable to open window", why)));
+        self.renderer = Some(try!(Renderer::from_window(window, DriverAuto, ACCELERATED)
+                                  .map_err(|why| error("unable to create renderer", why))));
+      }
+      let renderer = self.renderer.as_ref().unwrap();
+      self.texture = Some(try!(renderer.create_texture(BGR24, AccessStreaming, width as int, height as int)
+                               .map_err(|why| error("unable to create texture", why))));
+      self.size = (width, height);
+      Ok(())
+    }
+
+    // Handles the window's events: keys go to 'keys::press'.
+    fn poll_events(&mut self) {
+      loop {
+        match poll_event() {
+          NoEvent => break,
+          QuitEvent(..) => self.closed = true,
+          KeyDownEvent(_, _, key, _, _) => match key_command(key) {
+            Some(key) => keys::press(key),
+            None => {},
+          },
+          _ => {},
+        }
+      }
+    }
+  }
+
+  fn key_command(key: KeyCode) -> Option<keys::Key> {
+    match key {
+      SpaceKey => Some(keys::Pause),
+      NKey => Some(keys::NewMachine),
+      SKey => Some(keys::Save),
+      EqualsKey | KpPlusKey => Some(keys::Faster),
+      MinusKey | KpMinusKey => Some(keys::Slower),
+      LeftKey | HKey => Some(keys::PanLeft),
+      RightKey | LKey => Some(keys::PanRight),
+      UpKey | KKey => Some(keys::PanUp),
+      DownKey | JKey => Some(keys::PanDown),
+      ZKey => Some(keys::ZoomIn),
+      XKey => Some(keys::ZoomOut),
+      _ => None,
+    }
+  }
+
+  impl ImageSink for WindowSink {
+    fn write_frame(&mut self, image: &[u8], width: uint, height: uint) -> IoResult<()> {
+      // Closing the window ends the output like a player exiting.
+      self.poll_events();
+      if self.closed {
+        return Err(IoError { kind: BrokenPipe, desc: "window closed", detail: None });
+      }
+      try!(self.prepare(width, height));
+      let (renderer, texture) = (self.renderer.as_ref().unwrap(), self.texture.as_ref().unwrap());
+      try!(texture.update(None, image, (width * 3) as int).map_err(|why| error("unable to update texture", why)));
+      try!(renderer.clear().map_err(|why| error("unable to draw", why)));
+      try!(renderer.copy(texture, None, None).map_err(|why| error("unable to draw", why)));
+      renderer.present();
+      Ok(())
+    }
+
+    fn poll_input(&mut self) {
+      self.poll_events();
+    }
+
+    fn keep_alive(&self) -> bool {
+      true
+    }
+  }
+
+  impl Drop for WindowSink {
+    fn drop(&mut self) {
+      self.texture = None;
+      self.renderer = None;
+      sdl2::quit();
+    }
+  }
+}